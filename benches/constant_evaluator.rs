@@ -0,0 +1,38 @@
+//! Evaluation of fixed columns that are defined by functions of the row,
+//! like the lookup tables of bitwise operations, in the Goldilocks field.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use powdr::number::{with_named_field, FieldId};
+use powdr::{analyzer, constant_evaluator};
+
+const DEGREE: usize = 65536;
+
+fn lookup_tables_pil() -> String {
+    format!(
+        "namespace Main({DEGREE});
+            col fixed KEY(i) {{ i }};
+            col fixed A(i) {{ i & 255 }};
+            col fixed B(i) {{ (i >> 8) & 255 }};
+            col fixed AND(i) {{ (i & 255) & ((i >> 8) & 255) }};
+            col fixed XOR(i) {{ (i & 255) ^ ((i >> 8) & 255) }};
+            col fixed SQUARE(i) {{ i * i - 7 }};"
+    )
+}
+
+fn lookup_tables(c: &mut Criterion) {
+    let analyzed = analyzer::analyze_string(&lookup_tables_pil());
+    c.bench_function("fixed lookup tables", |b| {
+        b.iter(|| {
+            with_named_field(FieldId::Goldilocks, || {
+                constant_evaluator::generate(&analyzed)
+            })
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = lookup_tables
+}
+criterion_main!(benches);
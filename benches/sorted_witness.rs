@@ -0,0 +1,59 @@
+//! Witness generation for a wide lookup into a sorted witness machine.
+//! Every row stores a key with several values and reads them back,
+//! so the lookups are dominated by operations on affine expressions.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use powdr::{analyzer, commit_evaluator, compiler, constant_evaluator};
+
+const DEGREE: usize = 256;
+const WIDTH: usize = 16;
+
+fn wide_lookup_pil() -> String {
+    let values = (0..WIDTH).map(|i| format!("v{i}")).collect::<Vec<_>>();
+    let read = (0..WIDTH).map(|i| format!("r{i}")).collect::<Vec<_>>();
+    let stored = (0..WIDTH).map(|i| format!("m_v{i}")).collect::<Vec<_>>();
+    let value_constraints = (0..WIDTH)
+        .map(|i| format!("v{i} = KEY * {};", i + 1))
+        .collect::<Vec<_>>();
+    format!(
+        "namespace Main({DEGREE});
+            col fixed KEY(i) {{ i * 3 }};
+            col fixed POSITIVE(i) {{ i + 1 }};
+            col fixed FIRST = [1];
+            col fixed NOTLAST(i) {{ 1 - FIRST(i + 1) }};
+            col witness {values};
+            col witness {read};
+            col witness m_key, {stored};
+            {value_constraints}
+            NOTLAST {{ m_key' - m_key }} in POSITIVE;
+            {{ KEY, {values} }} in {{ m_key, {stored} }};
+            {{ KEY, {read} }} in {{ m_key, {stored} }};",
+        values = values.join(", "),
+        read = read.join(", "),
+        stored = stored.join(", "),
+        value_constraints = value_constraints.join("\n"),
+    )
+}
+
+fn sorted_witness_lookup(c: &mut Criterion) {
+    let analyzed = analyzer::analyze_string(&wide_lookup_pil());
+    let (constants, degree) = constant_evaluator::generate(&analyzed);
+    c.bench_function("wide sorted witness lookup", |b| {
+        b.iter(|| {
+            commit_evaluator::generate(
+                &analyzed,
+                degree,
+                &constants,
+                compiler::no_callback(),
+                false,
+            )
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = sorted_witness_lookup
+}
+criterion_main!(benches);
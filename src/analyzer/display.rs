@@ -1,5 +1,7 @@
 use std::fmt::{Display, Formatter, Result};
 
+use crate::number::display_number;
+
 use super::*;
 
 impl Display for Identity {
@@ -40,7 +42,7 @@ impl Display for Expression {
             Expression::Constant(name) => write!(f, "{name}"),
             Expression::PolynomialReference(reference) => write!(f, "{reference}"),
             Expression::PublicReference(name) => write!(f, "{name}"),
-            Expression::Number(value) => write!(f, "{value}"),
+            Expression::Number(value) => write!(f, "{}", display_number(value)),
             Expression::String(value) => write!(f, "\"{value}\""), // TODO quote?
             Expression::Tuple(items) => write!(f, "({})", format_expressions(items)),
             Expression::BinaryOperation(left, op, right) => write!(f, "({left} {op} {right})"),
@@ -0,0 +1,110 @@
+use itertools::Itertools;
+
+use crate::number::field_mod;
+use crate::utils::stable_hash;
+
+use super::{Analyzed, FunctionValueDefinition};
+
+/// Computes a hash of the constraint system that does not depend on the order
+/// in which identities, columns or constants are declared.
+pub fn constraint_system_hash(analyzed: &Analyzed) -> u64 {
+    stable_hash(canonical_form(analyzed).as_bytes())
+}
+
+/// Returns a textual representation of the constraint system (field, constants,
+/// column declarations, public declarations and identities) where all
+/// components are sorted.
+/// Prover queries are not part of the constraint system.
+fn canonical_form(analyzed: &Analyzed) -> String {
+    let constants = analyzed
+        .constants
+        .iter()
+        .map(|(name, value)| format!("constant {name} = {value};"))
+        .sorted();
+    let columns = analyzed
+        .definitions
+        .values()
+        .map(|(poly, definition)| {
+            let definition = match definition {
+                Some(FunctionValueDefinition::Mapping(e)) => format!(" = {e}"),
+                Some(FunctionValueDefinition::Array(items, padding)) => {
+                    format!(" = [{}]{padding}", items.iter().format(", "))
+                }
+                Some(FunctionValueDefinition::Query(_)) | None => String::new(),
+            };
+            format!(
+                "{:?} {}{}({}){definition};",
+                poly.poly_type,
+                poly.absolute_name,
+                poly.length.map(|l| format!("[{l}]")).unwrap_or_default(),
+                poly.degree
+            )
+        })
+        .sorted();
+    let publics = analyzed
+        .public_declarations
+        .values()
+        .map(|public| {
+            format!(
+                "public {} = {}({});",
+                public.name, public.polynomial, public.index
+            )
+        })
+        .sorted();
+    let identities = analyzed
+        .identities
+        .iter()
+        .map(|identity| format!("{identity}"))
+        .sorted();
+    std::iter::once(format!("field {};", field_mod()))
+        .chain(constants)
+        .chain(columns)
+        .chain(publics)
+        .chain(identities)
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+
+    use super::constraint_system_hash;
+
+    #[test]
+    fn reordered_identities() {
+        let hash = |src| constraint_system_hash(&analyze_string(src));
+        let original = hash(
+            r#"
+            namespace Main(8);
+                col fixed BYTE(i) { i & 0xff };
+                col witness x, y;
+                x' = x + 1;
+                { y } in { BYTE };
+                y = x * 2;
+        "#,
+        );
+        let reordered = hash(
+            r#"
+            namespace Main(8);
+                col witness x;
+                col fixed BYTE(i) { i & 0xff };
+                y = x * 2;
+                col witness y;
+                x' = x + 1;
+                { y } in { BYTE };
+        "#,
+        );
+        let changed = hash(
+            r#"
+            namespace Main(8);
+                col fixed BYTE(i) { i & 0xff };
+                col witness x, y;
+                x' = x + 1;
+                { y } in { BYTE };
+                y = x * 3;
+        "#,
+        );
+        assert_eq!(original, reordered);
+        assert_ne!(original, changed);
+    }
+}
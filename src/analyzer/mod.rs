@@ -1,11 +1,14 @@
 pub mod display;
+pub mod hash;
+pub mod optimizer;
 pub mod pil_analyzer;
+pub mod util;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::Path;
 
 use crate::number::{AbstractNumberType, DegreeType};
-pub use crate::parser::ast::{BinaryOperator, UnaryOperator};
+pub use crate::parser::ast::{BinaryOperator, PaddingStrategy, UnaryOperator};
 
 pub fn analyze(path: &Path) -> Analyzed {
     pil_analyzer::process_pil_file(path)
@@ -21,6 +24,9 @@ pub enum StatementIdentifier {
     Identity(usize),
 }
 
+/// The result of analyzing a PIL file: the polynomials, identities and constants
+/// with all macros expanded and all names resolved to absolute names.
+/// This is the input of the later stages and of backends.
 pub struct Analyzed {
     /// Constants are not namespaced!
     pub constants: HashMap<String, AbstractNumberType>,
@@ -76,6 +82,35 @@ impl Analyzed {
             .collect()
     }
 
+    /// @returns the polynomials of the given type in source order.
+    /// An array counts as a single polynomial, see `Polynomial::length`.
+    pub fn polys_in_source_order(&self, poly_type: PolynomialType) -> Vec<&Polynomial> {
+        self.definitions_in_source_order(poly_type)
+            .into_iter()
+            .map(|(poly, _)| poly)
+            .collect()
+    }
+
+    /// @returns the identities of the given kind in source order.
+    pub fn identities_of_kind(&self, kind: IdentityKind) -> impl Iterator<Item = &Identity> {
+        self.identities.iter().filter(move |i| i.kind == kind)
+    }
+
+    /// @returns the number of rows of the polynomials, or None if no polynomial is declared.
+    /// Panics if the polynomials do not all have the same degree.
+    pub fn degree(&self) -> Option<DegreeType> {
+        let degrees = self
+            .definitions
+            .values()
+            .map(|(poly, _)| poly.degree)
+            .collect::<BTreeSet<_>>();
+        assert!(
+            degrees.len() <= 1,
+            "Polynomials have different degrees: {degrees:?}"
+        );
+        degrees.into_iter().next()
+    }
+
     fn declaration_type_count(&self, poly_type: PolynomialType) -> usize {
         self.definitions
             .iter()
@@ -91,11 +126,15 @@ impl Analyzed {
 }
 
 pub struct Polynomial {
+    /// The ID is specific to the type.
     pub id: u64,
     pub source: SourceRef,
+    /// The name including the namespace, like `Main.x`.
     pub absolute_name: String,
     pub poly_type: PolynomialType,
+    /// The number of rows.
     pub degree: DegreeType,
+    /// The number of elements, if the polynomial is an array.
     pub length: Option<DegreeType>,
 }
 
@@ -107,7 +146,7 @@ impl Polynomial {
 
 pub enum FunctionValueDefinition {
     Mapping(Expression),
-    Array(Vec<Expression>),
+    Array(Vec<Expression>, PaddingStrategy),
     Query(Expression),
 }
 
@@ -126,6 +165,8 @@ pub struct Identity {
     pub id: u64,
     pub kind: IdentityKind,
     pub source: SourceRef,
+    /// The namespace the identity was declared in.
+    pub namespace: String,
     /// For a simple polynomial identity, the selector contains
     /// the actual expression.
     pub left: SelectedExpressions,
@@ -176,8 +217,45 @@ pub enum PolynomialType {
     Constant,
     Intermediate,
 }
+/// The location of a declaration or identity in the source.
 #[derive(Debug, Clone, PartialEq)]
 pub struct SourceRef {
     pub file: String, // TODO should maybe be a shared pointer
     pub line: usize,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{analyze_string, IdentityKind, PolynomialType};
+
+    #[test]
+    fn accessors() {
+        let analyzed = analyze_string(
+            r#"namespace Main(8);
+pol constant BYTE(i) { i & 0xff };
+pol commit x, y[2];
+x * (1 - x) = 0;
+{ y[0] } in { BYTE };
+y[1] = x;
+"#,
+        );
+        assert_eq!(analyzed.degree(), Some(8));
+        let committed = analyzed.polys_in_source_order(PolynomialType::Committed);
+        let committed = committed
+            .iter()
+            .map(|p| (p.absolute_name.as_str(), p.length))
+            .collect::<Vec<_>>();
+        assert_eq!(committed, [("Main.x", None), ("Main.y", Some(2))]);
+        let constants = analyzed.polys_in_source_order(PolynomialType::Constant);
+        assert_eq!(constants[0].absolute_name, "Main.BYTE");
+        let lines = |kind| {
+            analyzed
+                .identities_of_kind(kind)
+                .map(|i| i.source.line)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(IdentityKind::Polynomial), [4, 6]);
+        assert_eq!(lines(IdentityKind::Plookup), [5]);
+        assert!(lines(IdentityKind::Permutation).is_empty());
+    }
+}
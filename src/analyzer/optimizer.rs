@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use super::util::{expr_any, postvisit_expression_mut};
+use super::{
+    Analyzed, BinaryOperator, Expression, FunctionValueDefinition, Identity, IdentityKind,
+    PolynomialType, StatementIdentifier, UnaryOperator,
+};
+
+/// Removes witness columns that are fully defined by a linear polynomial identity
+/// of the form `w = e` (where `e` does not reference `w`) by substituting `e` for
+/// `w` everywhere and removing both the column and the identity.
+/// Columns that have a query, are arrays, are referenced with "next" or are
+/// used in public declarations are not eliminated.
+/// @returns the names of the eliminated columns in the order of elimination.
+pub fn eliminate_linearly_defined_witnesses(analyzed: &mut Analyzed) -> Vec<String> {
+    let mut eliminated = vec![];
+    while let Some((identity_index, name, value)) = find_linearly_defined_witness(analyzed) {
+        remove_identity(analyzed, identity_index);
+        remove_definition(analyzed, &name);
+        substitute_everywhere(analyzed, &name, &value);
+        eliminated.push(name);
+    }
+    if !eliminated.is_empty() {
+        renumber_committed_polys(analyzed);
+    }
+    eliminated
+}
+
+/// Replaces witness columns that are constrained exactly like a witness column in another
+/// namespace by that column, which is equivalent to adding an equality constraint between them
+/// and removing one of them. Two columns are only considered equal if the identities that reference
+/// them are identical apart from the column itself, so any witness for one column also satisfies
+/// the identities of the other. The identities of the replaced column are removed.
+/// Columns that have a query, are arrays or are used in public declarations are not replaced.
+/// @returns the pairs of replaced and remaining column in the order of replacement.
+pub fn deduplicate_witnesses_across_namespaces(analyzed: &mut Analyzed) -> Vec<(String, String)> {
+    let mut replaced = vec![];
+    while let Some((duplicate, original, identities)) = find_duplicate_witness(analyzed) {
+        for index in identities.into_iter().rev() {
+            remove_identity(analyzed, index);
+        }
+        remove_definition(analyzed, &duplicate);
+        rename_everywhere(analyzed, &duplicate, &original);
+        replaced.push((duplicate, original));
+    }
+    if !replaced.is_empty() {
+        renumber_committed_polys(analyzed);
+    }
+    replaced
+}
+
+/// @returns a witness column that can be replaced by a column declared earlier in another
+/// namespace, the name of that column and the indices of the identities of the replaced column.
+fn find_duplicate_witness(analyzed: &Analyzed) -> Option<(String, String, Vec<usize>)> {
+    let mut candidates = analyzed
+        .definitions
+        .values()
+        .filter(|(poly, definition)| {
+            poly.poly_type == PolynomialType::Committed
+                && !poly.is_array()
+                && definition.is_none()
+                && !analyzed
+                    .public_declarations
+                    .values()
+                    .any(|public| public.polynomial.name == poly.absolute_name)
+        })
+        .map(|(poly, _)| poly)
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|poly| poly.id);
+    let signatures = candidates
+        .iter()
+        .map(|poly| constraint_signature(analyzed, &poly.absolute_name))
+        .collect::<Vec<_>>();
+    let namespace = |name: &str| {
+        name.rsplit_once('.')
+            .map(|(namespace, _)| namespace.to_string())
+    };
+    for (i, original) in candidates.iter().enumerate() {
+        for (j, duplicate) in candidates.iter().enumerate().skip(i + 1) {
+            let ((indices, signature), (duplicate_indices, duplicate_signature)) =
+                (&signatures[i], &signatures[j]);
+            if !signature.is_empty()
+                && signature == duplicate_signature
+                && original.degree == duplicate.degree
+                && namespace(&original.absolute_name) != namespace(&duplicate.absolute_name)
+                && !indices.iter().any(|i| duplicate_indices.contains(i))
+            {
+                return Some((
+                    duplicate.absolute_name.clone(),
+                    original.absolute_name.clone(),
+                    duplicate_indices.clone(),
+                ));
+            }
+        }
+    }
+    None
+}
+
+/// @returns the indices of the identities that reference the column and the sorted identities
+/// themselves, with the column renamed to a placeholder.
+fn constraint_signature(analyzed: &Analyzed, name: &str) -> (Vec<usize>, Vec<String>) {
+    let (indices, mut identities): (Vec<_>, Vec<_>) = analyzed
+        .identities
+        .iter()
+        .enumerate()
+        .filter(|(_, identity)| identity_expressions(identity).any(|e| references(e, name)))
+        .map(|(index, identity)| {
+            let mut identity = identity.clone();
+            for e in identity_expressions_mut(&mut identity) {
+                postvisit_expression_mut(e, &mut renamer(name, "$"));
+            }
+            (index, identity.to_string())
+        })
+        .unzip();
+    identities.sort();
+    (indices, identities)
+}
+
+/// @returns the index of a polynomial identity that defines a witness column that can be
+/// eliminated, together with the name of the column and the expression it is equal to.
+fn find_linearly_defined_witness(analyzed: &Analyzed) -> Option<(usize, String, Expression)> {
+    analyzed
+        .identities
+        .iter()
+        .enumerate()
+        .filter(|(_, identity)| identity.kind == IdentityKind::Polynomial)
+        .find_map(|(index, identity)| {
+            let Some(Expression::BinaryOperation(left, BinaryOperator::Sub, right)) =
+                &identity.left.selector
+            else {
+                return None;
+            };
+            [(left, right), (right, left)]
+                .into_iter()
+                .find_map(|(column, value)| match column.as_ref() {
+                    Expression::PolynomialReference(poly)
+                        if poly.index.is_none()
+                            && !poly.next
+                            && is_eliminable(analyzed, &poly.name)
+                            && !references(value, &poly.name)
+                            && !references_next(value)
+                            && is_linear(value) =>
+                    {
+                        Some((index, poly.name.clone(), value.as_ref().clone()))
+                    }
+                    _ => None,
+                })
+        })
+}
+
+fn is_eliminable(analyzed: &Analyzed, name: &str) -> bool {
+    let Some((poly, definition)) = analyzed.definitions.get(name) else {
+        return false;
+    };
+    poly.poly_type == PolynomialType::Committed
+        && !poly.is_array()
+        && definition.is_none()
+        && !analyzed
+            .public_declarations
+            .values()
+            .any(|public| public.polynomial.name == name)
+        && !all_expressions(analyzed).any(|e| {
+            expr_any(e, &mut |e| {
+                matches!(e, Expression::PolynomialReference(poly) if poly.next && poly.name == name)
+            })
+        })
+}
+
+fn references(expr: &Expression, name: &str) -> bool {
+    expr_any(
+        expr,
+        &mut |e| matches!(e, Expression::PolynomialReference(poly) if poly.name == name),
+    )
+}
+
+fn references_next(expr: &Expression) -> bool {
+    expr_any(
+        expr,
+        &mut |e| matches!(e, Expression::PolynomialReference(poly) if poly.next),
+    )
+}
+
+fn references_polynomial(expr: &Expression) -> bool {
+    expr_any(expr, &mut |e| {
+        matches!(e, Expression::PolynomialReference(_))
+    })
+}
+
+/// @returns true if the expression is affine in the referenced polynomials.
+fn is_linear(expr: &Expression) -> bool {
+    match expr {
+        Expression::Constant(_) | Expression::Number(_) | Expression::PolynomialReference(_) => {
+            true
+        }
+        Expression::BinaryOperation(left, BinaryOperator::Add | BinaryOperator::Sub, right) => {
+            is_linear(left) && is_linear(right)
+        }
+        Expression::BinaryOperation(left, BinaryOperator::Mul, right) => {
+            (is_linear(left) && !references_polynomial(right))
+                || (!references_polynomial(left) && is_linear(right))
+        }
+        Expression::UnaryOperation(UnaryOperator::Plus | UnaryOperator::Minus, e) => is_linear(e),
+        _ => false,
+    }
+}
+
+fn identity_expressions(identity: &Identity) -> impl Iterator<Item = &Expression> {
+    identity
+        .left
+        .selector
+        .iter()
+        .chain(identity.left.expressions.iter())
+        .chain(identity.right.selector.iter())
+        .chain(identity.right.expressions.iter())
+}
+
+fn identity_expressions_mut(identity: &mut Identity) -> impl Iterator<Item = &mut Expression> {
+    identity
+        .left
+        .selector
+        .iter_mut()
+        .chain(identity.left.expressions.iter_mut())
+        .chain(identity.right.selector.iter_mut())
+        .chain(identity.right.expressions.iter_mut())
+}
+
+fn all_expressions(analyzed: &Analyzed) -> impl Iterator<Item = &Expression> {
+    analyzed
+        .identities
+        .iter()
+        .flat_map(identity_expressions)
+        .chain(
+            analyzed
+                .definitions
+                .values()
+                .filter_map(|(_, definition)| definition.as_ref())
+                .flat_map(|definition| match definition {
+                    FunctionValueDefinition::Mapping(e) | FunctionValueDefinition::Query(e) => {
+                        std::slice::from_ref(e).iter()
+                    }
+                    FunctionValueDefinition::Array(items, _) => items.iter(),
+                }),
+        )
+}
+
+fn substitute_everywhere(analyzed: &mut Analyzed, name: &str, value: &Expression) {
+    let substitute = &mut |e: &mut Expression| {
+        if matches!(e, Expression::PolynomialReference(poly) if poly.name == name) {
+            *e = value.clone();
+        }
+    };
+    visit_all_expressions_mut(analyzed, substitute);
+}
+
+/// Replaces all references to the polynomial `name` by references to `new_name`.
+fn rename_everywhere(analyzed: &mut Analyzed, name: &str, new_name: &str) {
+    visit_all_expressions_mut(analyzed, &mut renamer(name, new_name));
+}
+
+fn renamer<'a>(name: &'a str, new_name: &'a str) -> impl FnMut(&mut Expression) + 'a {
+    move |e| {
+        if let Expression::PolynomialReference(poly) = e {
+            if poly.name == name {
+                poly.name = new_name.to_string();
+            }
+        }
+    }
+}
+
+fn visit_all_expressions_mut(analyzed: &mut Analyzed, f: &mut impl FnMut(&mut Expression)) {
+    for identity in &mut analyzed.identities {
+        for e in identity_expressions_mut(identity) {
+            postvisit_expression_mut(e, f);
+        }
+    }
+    for (_, definition) in analyzed.definitions.values_mut() {
+        match definition {
+            Some(FunctionValueDefinition::Mapping(e) | FunctionValueDefinition::Query(e)) => {
+                postvisit_expression_mut(e, f)
+            }
+            Some(FunctionValueDefinition::Array(items, _)) => items
+                .iter_mut()
+                .for_each(|e| postvisit_expression_mut(e, f)),
+            None => {}
+        }
+    }
+}
+
+/// Removes the identity at the given index and re-assigns the kind-specific identity IDs.
+fn remove_identity(analyzed: &mut Analyzed, index: usize) {
+    analyzed.identities.remove(index);
+    analyzed
+        .source_order
+        .retain_mut(|statement| match statement {
+            StatementIdentifier::Identity(i) if *i == index => false,
+            StatementIdentifier::Identity(i) => {
+                if *i > index {
+                    *i -= 1;
+                }
+                true
+            }
+            _ => true,
+        });
+    let mut counters: HashMap<IdentityKind, u64> = HashMap::new();
+    for identity in &mut analyzed.identities {
+        let counter = counters.entry(identity.kind).or_default();
+        identity.id = *counter;
+        *counter += 1;
+    }
+}
+
+fn remove_definition(analyzed: &mut Analyzed, name: &str) {
+    analyzed.definitions.remove(name);
+    analyzed
+        .source_order
+        .retain(|statement| !matches!(statement, StatementIdentifier::Definition(n) if n == name));
+}
+
+/// Re-assigns the IDs of committed polynomials such that they are consecutive again.
+fn renumber_committed_polys(analyzed: &mut Analyzed) {
+    let mut committed = analyzed
+        .definitions
+        .values_mut()
+        .map(|(poly, _)| poly)
+        .filter(|poly| poly.poly_type == PolynomialType::Committed)
+        .collect::<Vec<_>>();
+    committed.sort_by_key(|poly| poly.id);
+    let mut id = 0;
+    for poly in committed {
+        poly.id = id;
+        id += poly.length.unwrap_or(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+    use crate::commit_evaluator;
+    use crate::compiler::no_callback;
+    use crate::constant_evaluator;
+
+    use super::*;
+
+    const FIBONACCI_WITH_SUM: &str = r#"
+        constant %N = 8;
+        namespace Fibonacci(%N);
+            col fixed ISLAST(i) { 1 - (i - (%N - 1)) / (i - (%N - 1)) };
+            col witness x, sum, y;
+            ISLAST * (y' - 1) = 0;
+            ISLAST * (x' - 1) = 0;
+            sum = x + y;
+            (1-ISLAST) * (x' - y) = 0;
+            (1-ISLAST) * (y' - sum) = 0;
+    "#;
+
+    #[test]
+    fn eliminate_sum_column() {
+        let original = analyze_string(FIBONACCI_WITH_SUM);
+        let (constants, degree) = constant_evaluator::generate(&original);
+        let original_witness =
+            commit_evaluator::generate(&original, degree, &constants, no_callback(), false);
+
+        let mut analyzed = analyze_string(FIBONACCI_WITH_SUM);
+        assert_eq!(
+            eliminate_linearly_defined_witnesses(&mut analyzed),
+            vec!["Fibonacci.sum".to_string()]
+        );
+        assert_eq!(analyzed.commitment_count(), 2);
+        assert_eq!(analyzed.definitions["Fibonacci.y"].0.id, 1);
+        assert_eq!(analyzed.identities.len(), 4);
+        assert_eq!(
+            analyzed.identities[3].to_string(),
+            "((1 - Fibonacci.ISLAST) * (Fibonacci.y' - (Fibonacci.x + Fibonacci.y))) = 0;"
+        );
+
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        assert_eq!(witness.len(), 2);
+        for (name, values) in &witness {
+            let (_, original_values) = original_witness.iter().find(|(n, _)| n == name).unwrap();
+            assert_eq!(values, original_values);
+        }
+    }
+
+    #[test]
+    fn deduplicate_counters() {
+        let src = r#"
+            constant %N = 8;
+            namespace Global(%N);
+                col fixed ISLAST(i) { 1 - (i - (%N - 1)) / (i - (%N - 1)) };
+            namespace A(%N);
+                col witness x;
+                (1 - Global.ISLAST) * (x' - x - 1) = 0;
+                Global.ISLAST * x' = 0;
+            namespace B(%N);
+                col witness y, w;
+                Global.ISLAST * y' = 0;
+                (1 - Global.ISLAST) * (y' - y - 1) = 0;
+                (1 - Global.ISLAST) * (w' - w - 2) = 0;
+                Global.ISLAST * w' = 0;
+        "#;
+        let original = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&original);
+        let original_witness =
+            commit_evaluator::generate(&original, degree, &constants, no_callback(), false);
+
+        let mut analyzed = analyze_string(src);
+        assert_eq!(
+            deduplicate_witnesses_across_namespaces(&mut analyzed),
+            vec![("B.y".to_string(), "A.x".to_string())]
+        );
+        assert_eq!(analyzed.commitment_count(), 2);
+        assert_eq!(analyzed.definitions["B.w"].0.id, 1);
+        assert_eq!(analyzed.identities.len(), 4);
+        assert_eq!(
+            analyzed.identities[2].to_string(),
+            "((1 - Global.ISLAST) * ((B.w' - B.w) - 2)) = 0;"
+        );
+
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        assert_eq!(witness.len(), 2);
+        for (name, values) in &witness {
+            let (_, original_values) = original_witness.iter().find(|(n, _)| n == name).unwrap();
+            assert_eq!(values, original_values);
+        }
+    }
+
+    #[test]
+    fn keep_columns_with_other_constraints() {
+        // A.x and A.y are in the same namespace, B.z has different constraints
+        // and B.w is also used by B.v.
+        let src = r#"
+            namespace A(4);
+                col witness x, y;
+                x * (1 - x) = 0;
+                y * (1 - y) = 0;
+            namespace B(4);
+                col witness z, w, v;
+                z * (2 - z) = 0;
+                w * (1 - w) = 0;
+                v = w + 1;
+        "#;
+        let mut analyzed = analyze_string(src);
+        assert!(deduplicate_witnesses_across_namespaces(&mut analyzed).is_empty());
+        assert_eq!(analyzed.commitment_count(), 5);
+    }
+
+    #[test]
+    fn keep_columns_referenced_with_next() {
+        let src = r#"
+            namespace Main(4);
+                col witness x, y;
+                y = x + 1;
+                y' = y;
+        "#;
+        let mut analyzed = analyze_string(src);
+        assert!(eliminate_linearly_defined_witnesses(&mut analyzed).is_empty());
+        assert_eq!(analyzed.commitment_count(), 2);
+    }
+}
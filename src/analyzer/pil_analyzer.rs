@@ -102,10 +102,10 @@ impl PILContext {
         // TOOD make this work for other line endings
         self.line_starts = utils::compute_line_starts(contents);
         self.current_file = path.to_path_buf();
-        let pil_file =
-            parser::parse(Some(path.to_str().unwrap()), contents).unwrap_or_else(|err| {
+        let pil_file = parser::parse_with_error_recovery(Some(path.to_str().unwrap()), contents)
+            .unwrap_or_else(|errors| {
                 eprintln!("Error parsing .pil file:");
-                err.output_to_stderr();
+                errors.iter().for_each(|err| err.output_to_stderr());
                 panic!();
             });
 
@@ -245,6 +245,7 @@ impl PILContext {
             id,
             kind,
             source: self.to_source_ref(*start),
+            namespace: self.namespace.clone(),
             left,
             right,
         };
@@ -341,8 +342,8 @@ impl PILContext {
                     _ => panic!(),
                 }
             }
-            ast::FunctionDefinition::Array(value) => {
-                FunctionValueDefinition::Array(self.process_expressions(value))
+            ast::FunctionDefinition::Array(value, padding) => {
+                FunctionValueDefinition::Array(self.process_expressions(value), *padding)
             }
         });
         let is_new = self
@@ -476,6 +477,13 @@ impl PILContext {
             ast::Expression::UnaryOperation(op, value) => {
                 if let Some(value) = self.evaluate_unary_operation(op, value) {
                     Expression::Number(value)
+                } else if *op == UnaryOperator::LogicalNot {
+                    // This assumes that the operand is boolean.
+                    Expression::BinaryOperation(
+                        Box::new(Expression::Number(1.into())),
+                        BinaryOperator::Sub,
+                        Box::new(self.process_expression(value)),
+                    )
                 } else {
                     Expression::UnaryOperation(*op, Box::new(self.process_expression(value)))
                 }
@@ -487,7 +495,7 @@ impl PILContext {
             ast::Expression::FunctionCall(name, arguments) => {
                 Expression::FunctionCall(self.namespaced(name), self.process_expressions(arguments))
             }
-            ast::Expression::FreeInput(_) => panic!(),
+            ast::Expression::FreeInput(..) => panic!(),
         }
     }
 
@@ -554,7 +562,7 @@ impl PILContext {
             }
             ast::Expression::UnaryOperation(op, value) => self.evaluate_unary_operation(op, value),
             ast::Expression::FunctionCall(_, _) => None,
-            ast::Expression::FreeInput(_) => panic!(),
+            ast::Expression::FreeInput(..) => panic!(),
         }
     }
 
@@ -580,8 +588,10 @@ impl PILContext {
                 BinaryOperator::Mod => left % right,
                 BinaryOperator::BinaryAnd => left & right,
                 BinaryOperator::BinaryOr => left | right,
+                BinaryOperator::BinaryXor => left ^ right,
                 BinaryOperator::ShiftLeft => left << abstract_to_degree(&right),
                 BinaryOperator::ShiftRight => left >> abstract_to_degree(&right),
+                op => op.compare(&left, &right),
             })
         } else {
             None
@@ -596,6 +606,13 @@ impl PILContext {
         self.evaluate_expression(value).map(|v| match op {
             UnaryOperator::Plus => v,
             UnaryOperator::Minus => -v,
+            UnaryOperator::LogicalNot => {
+                assert!(
+                    v == 0.into() || v == 1.into(),
+                    "The operand of ! has to be 0 or 1, but got {v}."
+                );
+                1 - v
+            }
         })
     }
 }
@@ -0,0 +1,43 @@
+use super::Expression;
+
+/// @returns true if `f` returns true for the expression or any of its sub-expressions.
+pub fn expr_any(expr: &Expression, f: &mut impl FnMut(&Expression) -> bool) -> bool {
+    if f(expr) {
+        true
+    } else {
+        match expr {
+            Expression::Tuple(items) => items.iter().any(|e| expr_any(e, f)),
+            Expression::BinaryOperation(l, _, r) => expr_any(l, f) || expr_any(r, f),
+            Expression::UnaryOperation(_, e) => expr_any(e, f),
+            Expression::FunctionCall(_, args) => args.iter().any(|e| expr_any(e, f)),
+            Expression::Constant(_)
+            | Expression::PolynomialReference(_)
+            | Expression::LocalVariableReference(_)
+            | Expression::PublicReference(_)
+            | Expression::Number(_)
+            | Expression::String(_) => false,
+        }
+    }
+}
+
+/// Calls `f` on all sub-expressions of the expression (children first)
+/// and then on the expression itself.
+pub fn postvisit_expression_mut(expr: &mut Expression, f: &mut impl FnMut(&mut Expression)) {
+    match expr {
+        Expression::Tuple(items) | Expression::FunctionCall(_, items) => items
+            .iter_mut()
+            .for_each(|e| postvisit_expression_mut(e, f)),
+        Expression::BinaryOperation(l, _, r) => {
+            postvisit_expression_mut(l, f);
+            postvisit_expression_mut(r, f);
+        }
+        Expression::UnaryOperation(_, e) => postvisit_expression_mut(e, f),
+        Expression::Constant(_)
+        | Expression::PolynomialReference(_)
+        | Expression::LocalVariableReference(_)
+        | Expression::PublicReference(_)
+        | Expression::Number(_)
+        | Expression::String(_) => {}
+    }
+    f(expr)
+}
@@ -0,0 +1,223 @@
+//! The control flow graph of an asm program, which can be written in the DOT format of Graphviz.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+
+use itertools::Itertools;
+
+use super::{collect_references, direct_reference, ASMPILConverter, LiteralArgument};
+use crate::parser::asm_ast::{ASMFile, ASMStatement};
+use crate::parser::{self, ParseError};
+
+/// The basic blocks of a program and the edges between them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Graph {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+}
+
+/// Consecutive lines of the program that are only entered at the first line
+/// and only left after the last line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// The index of the first line in the program.
+    pub first_line: usize,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Edge {
+    /// The index of the block the edge starts at.
+    pub from: usize,
+    /// The index of the block the edge leads to.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EdgeKind {
+    /// Execution continues with the next line, because the last line of
+    /// the block does not jump or the jump is not taken.
+    FallThrough,
+    /// The last line of the block always jumps, like `jmp`.
+    Jump,
+    /// The last line of the block jumps depending on a condition, like `jmpz`.
+    ConditionalJump,
+}
+
+/// Compiles the asm source and returns the control flow graph of the program.
+/// An instruction jumps if it updates the program counter. Its targets are the labels
+/// passed to it, and execution can continue with the next line if the update refers
+/// to the program counter. Jumps to computed targets, like `ret`, have no edges.
+pub fn control_flow_graph(file_name: Option<&str>, input: &str) -> Result<Graph, ParseError> {
+    let ast = parser::parse_asm(file_name, input)?;
+    let lines = program_lines(&ast);
+    let mut converter = ASMPILConverter::new();
+    converter
+        .convert(ast, true)
+        .map_err(|err| ParseError::new(file_name, input, err.start, err.start, err.message))?;
+    Ok(converter.control_flow_graph(lines))
+}
+
+/// Returns the source of the statements that are lines of the program.
+fn program_lines(ast: &ASMFile) -> Vec<String> {
+    ast.0
+        .iter()
+        .filter_map(|statement| match statement {
+            ASMStatement::Label(_, name) => Some(format!("{name}::")),
+            ASMStatement::Instruction(_, name, args) if args.is_empty() => Some(format!("{name};")),
+            ASMStatement::Instruction(_, name, args) => {
+                Some(format!("{name} {};", args.iter().format(", ")))
+            }
+            ASMStatement::Assignment(_, write_regs, assign_reg, value) => Some(format!(
+                "{} <={}= {value};",
+                write_regs.join(", "),
+                assign_reg.as_deref().unwrap_or_default()
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+impl ASMPILConverter {
+    fn control_flow_graph(&self, lines: Vec<String>) -> Graph {
+        let pc = self.pc_name().unwrap();
+        let label_positions = self.compute_label_positions();
+        // For each line the lines it jumps to, if it updates the
+        // program counter, and whether it can continue with the next line.
+        let jumps = self
+            .code_lines
+            .iter()
+            .map(|line| {
+                if line.write_regs.values().flatten().any(|reg| reg == pc) {
+                    return Some((vec![], false));
+                }
+                let flag = direct_reference(&format!("instr_{}", line.instruction.as_ref()?));
+                let (_, update) = self.registers[pc]
+                    .conditioned_updates
+                    .iter()
+                    .find(|(condition, _)| *condition == flag)?;
+                let targets = line
+                    .instruction_literal_args
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        Some(LiteralArgument::Label(label)) => Some(label_positions[label]),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let mut references = BTreeSet::new();
+                collect_references(update, &mut references);
+                Some((targets, references.contains(pc.as_str())))
+            })
+            .collect::<Vec<_>>();
+
+        // Blocks start at the first line, at labels and after jumps.
+        let block_starts = (0..self.code_lines.len())
+            .filter(|&i| i == 0 || self.code_lines[i].label.is_some() || jumps[i - 1].is_some())
+            .collect::<Vec<_>>();
+        let block_of = |line: usize| block_starts.partition_point(|&start| start <= line) - 1;
+        let blocks = block_starts
+            .iter()
+            .enumerate()
+            .map(|(i, &first_line)| {
+                let end = block_starts
+                    .get(i + 1)
+                    .copied()
+                    .unwrap_or(self.code_lines.len());
+                BasicBlock {
+                    first_line,
+                    lines: (first_line..end)
+                        .filter_map(|line| lines.get(line).cloned())
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut edges = vec![];
+        for from in 0..blocks.len() {
+            let last_line = block_starts
+                .get(from + 1)
+                .copied()
+                .unwrap_or(self.code_lines.len())
+                - 1;
+            let falls_through = match &jumps[last_line] {
+                Some((targets, falls_through)) => {
+                    let kind = if *falls_through {
+                        EdgeKind::ConditionalJump
+                    } else {
+                        EdgeKind::Jump
+                    };
+                    edges.extend(targets.iter().map(|&target| Edge {
+                        from,
+                        to: block_of(target),
+                        kind,
+                    }));
+                    *falls_through
+                }
+                None => true,
+            };
+            if falls_through && last_line + 1 < self.code_lines.len() {
+                edges.push(Edge {
+                    from,
+                    to: from + 1,
+                    kind: EdgeKind::FallThrough,
+                });
+            }
+        }
+        Graph { blocks, edges }
+    }
+}
+
+/// Writes the graph in the DOT format. Fall-through edges are dotted,
+/// conditional jumps dashed and unconditional jumps solid.
+impl Display for Graph {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "digraph control_flow {{")?;
+        writeln!(f, "    node [shape=box, fontname=monospace];")?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            let label = block
+                .lines
+                .iter()
+                .map(|line| format!("{}\\l", line.replace('\\', "\\\\").replace('"', "\\\"")))
+                .join("");
+            writeln!(f, "    b{i} [label=\"{label}\"];")?;
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::FallThrough => "dotted",
+                EdgeKind::Jump => "solid",
+                EdgeKind::ConditionalJump => "dashed",
+            };
+            writeln!(f, "    b{} -> b{} [style={style}];", edge.from, edge.to)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn simple_sum() {
+        let file_name = "tests/simple_sum.asm";
+        let contents = fs::read_to_string(file_name).unwrap();
+        let graph = control_flow_graph(Some(file_name), &contents).unwrap();
+        assert_eq!(
+            graph.to_string(),
+            r#"digraph control_flow {
+    node [shape=box, fontname=monospace];
+    b0 [label="CNT <=X= ${ (\"input\", 1) };\l"];
+    b1 [label="start::\ljmpz CNT, end;\l"];
+    b2 [label="A <=X= (A + ${ (\"input\", (CNT + 1)) });\ldec_CNT;\ljmp start;\l"];
+    b3 [label="end::\lA <=X= (A - ${ (\"input\", 0) });\lassert_zero A;\l"];
+    b0 -> b1 [style=dotted];
+    b1 -> b3 [style=dashed];
+    b1 -> b2 [style=dotted];
+    b2 -> b1 [style=solid];
+}"#
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! The gadget library: Inline PIL for common constraints, added to an assembly
+//! program with `gadget <name> <arguments>;`.
+
+use crate::number::{abstract_to_degree, AbstractNumberType};
+use crate::parser::ast::{BinaryOperator, Expression};
+
+/// The PIL of a gadget applied to some columns.
+pub struct GadgetPil {
+    /// Fixed columns the gadget looks up into, as pairs of name and declaration.
+    /// They do not depend on the arguments and are only declared once per program.
+    pub tables: Vec<(String, String)>,
+    /// The witness columns and constraints of the gadget.
+    pub pil: String,
+}
+
+type Gadget = fn(&[Expression]) -> Result<GadgetPil, String>;
+
+const GADGETS: [(&str, Gadget); 3] = [("is_zero", is_zero), ("lt", less_than), ("sign", sign)];
+
+/// Returns the PIL of the gadget `name` applied to `args`.
+pub fn expand(name: &str, args: &[Expression]) -> Result<GadgetPil, String> {
+    let (_, gadget) = GADGETS.iter().find(|(n, _)| *n == name).ok_or_else(|| {
+        format!(
+            "Unknown gadget {name}, available gadgets are: {}.",
+            GADGETS.map(|(n, _)| n).join(", ")
+        )
+    })?;
+    gadget(args)
+}
+
+/// `gadget is_zero v;` adds the columns `vIsZero`, which is 1 if `v` is zero and 0 otherwise,
+/// and `vInv`, the inverse of `v` if it is not zero.
+fn is_zero(args: &[Expression]) -> Result<GadgetPil, String> {
+    let [v] = args else {
+        return Err(arity("is_zero", "v"));
+    };
+    let v = column_name(v)?;
+    Ok(GadgetPil {
+        tables: vec![],
+        pil: format!(
+            "col witness {v}Inv;
+            col witness {v}IsZero;
+            {v}IsZero = 1 - {v} * {v}Inv;
+            {v}IsZero * {v} = 0;"
+        ),
+    })
+}
+
+/// `gadget lt a, b, bits;` adds the column `aLtb`, which is 1 if `a < b` and 0 otherwise.
+/// Both `a` and `b` have to be smaller than `2**bits` in all rows and the degree has to be
+/// at least `2**(bits + 1)`, since the result is looked up as bit `bits` of `a - b + 2**bits`.
+fn less_than(args: &[Expression]) -> Result<GadgetPil, String> {
+    let [a, b, bits] = args else {
+        return Err(arity("lt", "a, b, bits"));
+    };
+    let (a, b, bits) = (column_name(a)?, column_name(b)?, bit_count(bits)?);
+    let offset = AbstractNumberType::from(1) << bits;
+    Ok(GadgetPil {
+        tables: bit_tables(bits),
+        pil: format!(
+            "col witness {a}Lt{b};
+            {{ {a} - {b} + {offset}, 1 - {a}Lt{b} }} in {{ BIT{bits}_INPUT, BIT{bits} }};"
+        ),
+    })
+}
+
+/// `gadget sign v, bits;` adds the column `vSign`, the most significant bit of `v`
+/// as a `bits`-bit value, i.e. 1 if `v` is negative in two's complement.
+/// `v` has to be smaller than `2**bits` in all rows and the degree has to be at least `2**bits`.
+fn sign(args: &[Expression]) -> Result<GadgetPil, String> {
+    let [v, bits] = args else {
+        return Err(arity("sign", "v, bits"));
+    };
+    let (v, bits) = (column_name(v)?, bit_count(bits)?);
+    let bit = bits - 1;
+    Ok(GadgetPil {
+        tables: bit_tables(bit),
+        pil: format!(
+            "col witness {v}Sign;
+            {{ {v}, {v}Sign }} in {{ BIT{bit}_INPUT, BIT{bit} }};"
+        ),
+    })
+}
+
+/// The tables to look up bit `bit` of the values `0..2**(bit + 1)`.
+pub fn bit_tables(bit: u64) -> Vec<(String, String)> {
+    let mask = (AbstractNumberType::from(1) << (bit + 1)) - 1;
+    vec![
+        (
+            format!("BIT{bit}_INPUT"),
+            format!("col fixed BIT{bit}_INPUT(i) {{ i & {mask} }}"),
+        ),
+        (
+            format!("BIT{bit}"),
+            format!("col fixed BIT{bit}(i) {{ (i >> {bit}) & 1 }}"),
+        ),
+    ]
+}
+
+/// The tables to look up `a op b` for all operands `a` and `b` of `bits` bits, where
+/// `op` is `&`, `|` or `^`, and the names of the columns for `a + 2**bits * b`, `a`, `b`
+/// and the result. The first column is the key that the witness generator finds
+/// the row by. The tables have `2**(2 * bits)` rows.
+pub fn bitwise_tables(op: BinaryOperator, bits: u64) -> (Vec<(String, String)>, [String; 4]) {
+    let name = match op {
+        BinaryOperator::BinaryAnd => "AND",
+        BinaryOperator::BinaryOr => "OR",
+        BinaryOperator::BinaryXor => "XOR",
+        _ => panic!("No bitwise table for {op}."),
+    };
+    let mask = (AbstractNumberType::from(1) << bits) - 1;
+    let key = format!("BITWISE{bits}_KEY");
+    let a = format!("BITWISE{bits}_A");
+    let b = format!("BITWISE{bits}_B");
+    let result = format!("BITWISE{bits}_{name}");
+    let tables = vec![
+        (key.clone(), format!("col fixed {key}(i) {{ i }}")),
+        (a.clone(), format!("col fixed {a}(i) {{ i & {mask} }}")),
+        (
+            b.clone(),
+            format!("col fixed {b}(i) {{ (i >> {bits}) & {mask} }}"),
+        ),
+        (
+            result.clone(),
+            format!("col fixed {result}(i) {{ (i & {mask}) {op} ((i >> {bits}) & {mask}) }}"),
+        ),
+    ];
+    (tables, [key, a, b, result])
+}
+
+fn arity(name: &str, params: &str) -> String {
+    format!("Gadget {name} expects the arguments {params}.")
+}
+
+fn column_name(e: &Expression) -> Result<&str, String> {
+    match e {
+        Expression::PolynomialReference(r)
+            if r.namespace.is_none() && r.index.is_none() && !r.next =>
+        {
+            Ok(&r.name)
+        }
+        _ => Err(format!(
+            "Expected a column name as gadget argument, but got {e}."
+        )),
+    }
+}
+
+fn bit_count(e: &Expression) -> Result<u64, String> {
+    match e {
+        Expression::Number(n) if *n >= 1.into() && *n <= 64.into() => Ok(abstract_to_degree(n)),
+        _ => Err(format!(
+            "Expected a number of bits between 1 and 64 as gadget argument, but got {e}."
+        )),
+    }
+}
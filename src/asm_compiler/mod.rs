@@ -1,20 +1,117 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
 
-use crate::number::AbstractNumberType;
+use crate::number::{
+    abstract_to_degree, active_field_mod, clamp, field_mod, inv, is_zero, pow, reduce_signed,
+    AbstractNumberType, GOLDILOCKS_MOD,
+};
 use crate::parser::asm_ast::*;
 use crate::parser::ast::*;
 use crate::parser::{self, ParseError};
 
-pub fn compile<'a>(file_name: Option<&str>, input: &'a str) -> Result<PILFile, ParseError<'a>> {
-    let max_steps = 1024;
-    parser::parse_asm(file_name, input).map(|ast| ASMPILConverter::new().convert(ast, max_steps))
+mod control_flow;
+mod gadgets;
+pub mod query;
+mod simplify;
+
+pub use control_flow::{control_flow_graph, BasicBlock, Edge, EdgeKind, Graph};
+
+/// The channel free inputs are read from if the free input does not name one.
+pub const DEFAULT_INPUT_CHANNEL: &str = "input";
+
+/// The namespace of the generated PIL.
+const NAMESPACE: &str = "Assembly";
+/// The degree used if the program does not declare one.
+const DEFAULT_DEGREE: u64 = 1024;
+/// The PIL constant that holds the declared number of rows if the degree is padded.
+pub const LOGICAL_ROWS_CONSTANT: &str = "%LOGICAL_ROWS";
+/// The default bound for coefficients of assignment values if no field is active.
+const DEFAULT_COEFFICIENT_BOUND: u64 = GOLDILOCKS_MOD / 2;
+/// The number of bits of the operands of bitwise operations if the program does not declare it.
+const DEFAULT_BITWISE_WIDTH: u64 = 8;
+/// The largest number of bits of the operands of bitwise operations.
+const MAX_BITWISE_WIDTH: u64 = 32;
+/// Operands of bitwise operations wider than this are split into chunks of this many bits,
+/// so the lookup tables have at most `2**(2 * BITWISE_CHUNK_WIDTH)` rows.
+const BITWISE_CHUNK_WIDTH: u64 = 8;
+/// The number of bits of the operands of ordered comparisons if the program does not declare it.
+const DEFAULT_COMPARISON_WIDTH: u64 = 8;
+/// The largest number of bits of the operands of ordered comparisons, the lookup tables
+/// have `2**(bits + 1)` rows.
+const MAX_COMPARISON_WIDTH: u64 = 32;
+
+pub fn compile(file_name: Option<&str>, input: &str) -> Result<PILFile, ParseError> {
+    compile_with_auto_degree(file_name, input, false, false).map_err(|mut errors| errors.remove(0))
+}
+
+/// Like `compile`, but if `auto_degree` is set, the degree is the smallest power of two
+/// that fits the program, regardless of any degree declaration.
+/// If `verbose` is set, the number of merged program columns is printed.
+/// Returns all syntax errors of the file, but only the first error found in the conversion.
+pub fn compile_with_auto_degree(
+    file_name: Option<&str>,
+    input: &str,
+    auto_degree: bool,
+    verbose: bool,
+) -> Result<PILFile, Vec<ParseError>> {
+    let ast = parser::parse_asm_with_error_recovery(file_name, input)?;
+    let mut converter = ASMPILConverter::new();
+    let result = converter.convert(ast, auto_degree);
+    if verbose && result.is_ok() {
+        println!(
+            "Merged {} program columns into identical ones.",
+            converter.merged_columns
+        );
+    }
+    for warning in &converter.warnings {
+        ParseError::new(
+            file_name,
+            input,
+            warning.start,
+            warning.start,
+            warning.message.clone(),
+        )
+        .output_as_warning_to_stderr();
+    }
+    result.map_err(|err| {
+        vec![ParseError::new(
+            file_name,
+            input,
+            err.start,
+            err.start,
+            err.message,
+        )]
+    })
+}
+
+/// An error found while converting an asm file, located at
+/// an offset in the source.
+#[derive(Debug)]
+struct ConversionError {
+    start: usize,
+    message: String,
+}
+
+impl ConversionError {
+    fn new(start: usize, message: impl Into<String>) -> Self {
+        ConversionError {
+            start,
+            message: message.into(),
+        }
+    }
 }
 
 #[derive(Default)]
 struct ASMPILConverter {
     pil: Vec<Statement>,
-    pc_name: Option<String>,
-    default_assignment: Option<String>,
+    /// The program counters in the order of declaration. The first one
+    /// selects the line that is executed, the others are only changed by
+    /// instructions and can be used to jump back to a stored position.
+    pc_names: Vec<String>,
+    /// The program counters that can be read in assignments.
+    readable_pcs: BTreeSet<String>,
+    /// The assignment registers in the order of declaration.
+    assignment_regs: Vec<String>,
     registers: BTreeMap<String, Register>,
     instructions: BTreeMap<String, Instruction>,
     code_lines: Vec<CodeLine>,
@@ -22,6 +119,36 @@ struct ASMPILConverter {
     line_lookup: Vec<(String, String)>,
     /// Names of fixed columns that contain the program.
     program_constant_names: Vec<String>,
+    /// The number of program columns that were merged into an identical one.
+    merged_columns: usize,
+    /// Columns declared in inline PIL outside of the assembly namespace, by namespace.
+    external_columns: BTreeMap<String, BTreeSet<String>>,
+    /// Fixed columns declared in inline PIL of the assembly namespace.
+    fixed_columns: BTreeSet<String>,
+    /// The values of the named constants defined in inline PIL, by name including the `%`.
+    constants: BTreeMap<String, AbstractNumberType>,
+    /// Lookup tables of gadgets that have already been declared.
+    gadget_tables: BTreeSet<String>,
+    /// The number of bits of the operands of bitwise operations, if declared.
+    bitwise_width: Option<u64>,
+    /// The offset of each bitwise operation that was lowered to a lookup.
+    bitwise_operations: Vec<usize>,
+    /// The number of bits of the operands of ordered comparisons, if declared.
+    comparison_width: Option<u64>,
+    /// The offset and operator of each comparison that was lowered to witness columns.
+    comparison_operations: Vec<(usize, BinaryOperator)>,
+    /// Program constants that are one in a single line, by line index, like
+    /// the selectors of the lookups of bitwise operations.
+    line_flags: Vec<(usize, String)>,
+    /// The parameters and bodies of instruction macros.
+    macros: BTreeMap<String, (Vec<String>, Vec<InstructionBodyElement>)>,
+    /// For instructions that declare local columns, the statements generated
+    /// from their body, which are removed if the instruction is not used.
+    local_column_statements: BTreeMap<String, Range<usize>>,
+    /// If no field is active, coefficients whose absolute value exceeds
+    /// this bound are reported as warnings, if declared.
+    coefficient_bound: Option<AbstractNumberType>,
+    warnings: Vec<ConversionError>,
 }
 
 impl ASMPILConverter {
@@ -29,41 +156,83 @@ impl ASMPILConverter {
         Default::default()
     }
 
-    fn convert(&mut self, input: ASMFile, max_steps: usize) -> PILFile {
-        // TODO configure the degree
+    fn convert(&mut self, input: ASMFile, auto_degree: bool) -> Result<PILFile, ConversionError> {
+        let declared_degree = find_degree(&input.0)?;
+        // The degree is filled in once the length of the program is known.
         self.pil.push(Statement::Namespace(
             0,
-            "Assembly".to_string(),
-            Expression::Number(AbstractNumberType::from(max_steps)),
+            NAMESPACE.to_string(),
+            Expression::Number(0.into()),
         ));
         self.pil.push(Statement::PolynomialConstantDefinition(
             0,
             "first_step".to_string(),
-            FunctionDefinition::Array(vec![build_number(1.into())]),
+            FunctionDefinition::Array(vec![build_number(1.into())], PaddingStrategy::Zero),
         ));
 
         for statement in &input.0 {
             match statement {
+                ASMStatement::Degree(..) => {}
+                ASMStatement::BitwiseWidth(start, bits) => self.set_bitwise_width(*start, bits)?,
+                ASMStatement::ComparisonWidth(start, bits) => {
+                    self.set_comparison_width(*start, bits)?
+                }
+                ASMStatement::CoefficientBound(start, bound) => {
+                    self.set_coefficient_bound(*start, bound)?
+                }
                 ASMStatement::RegisterDeclaration(start, name, flags) => {
-                    self.handle_register_declaration(flags, name, start);
+                    self.handle_register_declaration(flags, name, start)?;
                 }
                 ASMStatement::InstructionDeclaration(start, name, params, body) => {
-                    self.handle_instruction_def(start, body, name, params);
+                    let body = self.expand_macros(*start, body, &mut vec![])?;
+                    self.handle_instruction_def(start, &body, name, params)?;
+                }
+                ASMStatement::MacroDefinition(start, name, params, body) => {
+                    if self.macros.contains_key(name) {
+                        return Err(ConversionError::new(
+                            *start,
+                            format!("Macro {name} is already declared."),
+                        ));
+                    }
+                    self.macros
+                        .insert(name.clone(), (params.clone(), body.clone()));
+                }
+                ASMStatement::InlinePil(start, statements) => {
+                    self.handle_inline_pil(*start, statements)
+                }
+                ASMStatement::Gadget(start, name, args) => {
+                    self.handle_gadget(*start, name, args)?
                 }
-                ASMStatement::InlinePil(_start, statements) => self.pil.extend(statements.clone()),
                 ASMStatement::Assignment(start, write_regs, assign_reg, value) => {
-                    self.handle_assignment(*start, write_regs, assign_reg, value.as_ref())
+                    self.handle_assignment(*start, write_regs, assign_reg, value.as_ref())?
                 }
-                ASMStatement::Instruction(_start, instr_name, args) => {
-                    self.handle_instruction(instr_name, args)
+                ASMStatement::Instruction(start, instr_name, args) => {
+                    self.handle_instruction(*start, instr_name, args)?
                 }
-                ASMStatement::Label(_start, name) => self.code_lines.push(CodeLine {
+                ASMStatement::Label(start, name) => self.code_lines.push(CodeLine {
+                    start: *start,
                     label: Some(name.clone()),
                     ..Default::default()
                 }),
             }
         }
-        self.create_constraints_for_assignment_reg();
+        self.remove_unused_local_columns();
+        if self.code_lines.is_empty() {
+            // Keep the program columns well-formed, like for a program that only has a label.
+            self.code_lines.push(CodeLine::default());
+        }
+        if self.assignment_regs.is_empty() {
+            return Err(ConversionError::new(0, "No assignment register declared."));
+        }
+        if self.pc_names.is_empty() {
+            return Err(ConversionError::new(
+                0,
+                "No program counter register declared.",
+            ));
+        }
+        for reg in self.assignment_regs.clone() {
+            self.create_constraints_for_assignment_reg(&reg);
+        }
 
         self.pil.extend(
             self.registers
@@ -74,7 +243,78 @@ impl ASMPILConverter {
                 }),
         );
 
-        self.translate_code_lines();
+        let (degree, padded) = match declared_degree {
+            _ if auto_degree => (self.code_lines.len().next_power_of_two().into(), false),
+            Some(declared) => declared,
+            None => (DEFAULT_DEGREE.into(), false),
+        };
+        if AbstractNumberType::from(self.code_lines.len()) > degree {
+            return Err(ConversionError::new(
+                0,
+                format!(
+                    "The program has {} lines, which does not fit the degree {degree}. \
+                     Declare a larger degree, like `degree {};`, or use --auto-degree.",
+                    self.code_lines.len(),
+                    self.code_lines.len().next_power_of_two()
+                ),
+            ));
+        }
+        // The columns have the padded length, the declared one is kept as a constant.
+        let degree = if padded {
+            self.pil.insert(
+                0,
+                Statement::ConstantDefinition(
+                    0,
+                    LOGICAL_ROWS_CONSTANT.to_string(),
+                    Expression::Number(degree.clone()),
+                ),
+            );
+            abstract_to_degree(&degree).next_power_of_two().into()
+        } else {
+            degree
+        };
+        if let Some(start) = self.bitwise_operations.first() {
+            let bits = self.bitwise_chunk_width();
+            let rows = AbstractNumberType::from(1) << (2 * bits);
+            if rows > degree {
+                return Err(ConversionError::new(
+                    *start,
+                    format!(
+                        "The lookup tables of bitwise operations on {bits} bits have {rows} rows, \
+                         which does not fit the degree {degree}."
+                    ),
+                ));
+            }
+        }
+        if let Some((start, _)) = self
+            .comparison_operations
+            .iter()
+            .find(|(_, op)| !matches!(op, BinaryOperator::Equal | BinaryOperator::NotEqual))
+        {
+            let bits = self.comparison_width.unwrap_or(DEFAULT_COMPARISON_WIDTH);
+            let rows = AbstractNumberType::from(1) << (bits + 1);
+            if rows > degree {
+                return Err(ConversionError::new(
+                    *start,
+                    format!(
+                        "The lookup tables of comparisons on {bits} bits have {rows} rows, \
+                         which does not fit the degree {degree}."
+                    ),
+                ));
+            }
+        }
+        for statement in &mut self.pil {
+            if let Statement::Namespace(_, name, value) = statement {
+                if name == NAMESPACE {
+                    *value = Expression::Number(degree.clone());
+                }
+            }
+        }
+
+        self.check_program_constants_matched()?;
+        let program = self.translate_code_lines()?;
+        self.check_update_conditions(&program)?;
+        self.warn_about_unused_declarations();
 
         self.pil.push(Statement::PlookupIdentity(
             0,
@@ -95,8 +335,108 @@ impl ASMPILConverter {
                     .collect(),
             },
         ));
+        // The other program counters have to point to a line as well.
+        for pc in self.pc_names.iter().skip(1) {
+            self.pil.push(Statement::PlookupIdentity(
+                self.registers[pc].start,
+                SelectedExpressions {
+                    selector: None,
+                    expressions: vec![direct_reference(pc)],
+                },
+                SelectedExpressions {
+                    selector: None,
+                    expressions: vec![direct_reference("line")],
+                },
+            ));
+        }
+
+        simplify::simplify_identities(&mut self.pil);
+        Ok(PILFile(std::mem::take(&mut self.pil)))
+    }
+
+    /// Adds warnings for instructions, registers and labels that are declared
+    /// but not used by any code line. A register is used if a code line reads or
+    /// writes it or if a used instruction updates it or reads it in an update.
+    /// Labels are not reported if the program assigns a computed value to the pc.
+    fn warn_about_unused_declarations(&mut self) {
+        let used_instructions = self
+            .code_lines
+            .iter()
+            .filter_map(|line| line.instruction.as_ref())
+            .collect::<BTreeSet<_>>();
+        let used_labels = self
+            .code_lines
+            .iter()
+            .flat_map(|line| &line.instruction_literal_args)
+            .filter_map(|arg| match arg {
+                Some(LiteralArgument::Label(label)) => Some(label),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        let instruction_flags = used_instructions
+            .iter()
+            .map(|instr| direct_reference(&format!("instr_{instr}")))
+            .collect::<Vec<_>>();
+        let mut used_registers = self
+            .pc_name()
+            .into_iter()
+            .chain(&self.assignment_regs)
+            .map(|reg| reg.as_str())
+            .collect::<BTreeSet<_>>();
+        for line in &self.code_lines {
+            used_registers.extend(line.write_regs.values().flatten().map(|reg| reg.as_str()));
+            for (_, component) in line.value.values().flatten() {
+                match component {
+                    AffineExpressionComponent::Register(reg) => {
+                        used_registers.insert(reg);
+                    }
+                    AffineExpressionComponent::FreeInput(_, _, Some(keys)) => {
+                        keys.iter()
+                            .for_each(|key| collect_references(key, &mut used_registers));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (name, register) in &self.registers {
+            for (condition, value) in &register.conditioned_updates {
+                if instruction_flags.contains(condition) {
+                    used_registers.insert(name);
+                    collect_references(value, &mut used_registers);
+                }
+            }
+        }
 
-        PILFile(std::mem::take(&mut self.pil))
+        let mut warnings = vec![];
+        for (name, instr) in &self.instructions {
+            if !used_instructions.contains(name) {
+                warnings.push((instr.start, format!("Instruction {name} is never used.")));
+            }
+        }
+        for (name, register) in &self.registers {
+            if !used_registers.contains(name.as_str()) {
+                warnings.push((register.start, format!("Register {name} is never used.")));
+            }
+        }
+        // A program that assigns a computed value to the pc can reach any label.
+        let computed_jumps = self.pc_name().is_some_and(|pc| {
+            self.code_lines
+                .iter()
+                .any(|line| line.write_regs.values().flatten().any(|reg| reg == pc))
+        });
+        for line in self.code_lines.iter().filter(|_| !computed_jumps) {
+            if let Some(label) = &line.label {
+                if !used_labels.contains(label) {
+                    warnings.push((line.start, format!("Label {label} is never used.")));
+                }
+            }
+        }
+        warnings.sort();
+        self.warnings.extend(
+            warnings
+                .into_iter()
+                .map(|(start, message)| ConversionError::new(start, message)),
+        );
     }
 
     fn handle_register_declaration(
@@ -104,15 +444,20 @@ impl ASMPILConverter {
         flags: &Option<RegisterFlag>,
         name: &str,
         start: &usize,
-    ) {
+    ) -> Result<(), ConversionError> {
+        if self.registers.contains_key(name) {
+            return Err(ConversionError::new(
+                *start,
+                format!("Register {name} is already declared."),
+            ));
+        }
         let mut conditioned_updates = vec![];
         let mut default_update = None;
         match flags {
-            Some(RegisterFlag::IsPC) => {
-                assert_eq!(self.pc_name, None);
-                self.pc_name = Some(name.to_string());
-                self.line_lookup
-                    .push((name.to_string(), "line".to_string()));
+            Some(RegisterFlag::IsPC | RegisterFlag::IsReadablePC) => {
+                if *flags == Some(RegisterFlag::IsReadablePC) {
+                    self.readable_pcs.insert(name.to_string());
+                }
                 // This might be superfluous but makes it easier to determine that the PC needs to
                 // be zero in the first row.
                 self.pil.push(Statement::PolynomialIdentity(
@@ -122,41 +467,114 @@ impl ASMPILConverter {
                 // The value here is actually irrelevant, it is only important
                 // that "first_step'" is included to compute the "default condition"
                 conditioned_updates.push((next_reference("first_step"), build_number(0.into())));
-                default_update = Some(build_add(direct_reference(name), build_number(1.into())));
+                if self.pc_names.is_empty() {
+                    self.line_lookup
+                        .push((name.to_string(), "line".to_string()));
+                    default_update =
+                        Some(build_add(direct_reference(name), build_number(1.into())));
+                } else {
+                    // The other program counters keep their value unless they are updated.
+                    default_update = Some(direct_reference(name));
+                }
+                self.pc_names.push(name.to_string());
             }
             Some(RegisterFlag::IsDefaultAssignment) => {
-                assert_eq!(self.default_assignment, None);
-                self.default_assignment = Some(name.to_string());
+                if self
+                    .registers
+                    .iter()
+                    .any(|(reg, r)| !r.is_pc && !self.assignment_regs.contains(reg))
+                {
+                    return Err(ConversionError::new(
+                        *start,
+                        "The assignment registers have to be declared before the other registers.",
+                    ));
+                }
+                self.assignment_regs.push(name.to_string());
             }
-            None => {
-                let write_flag = format!("reg_write_{name}");
-                self.create_witness_fixed_pair(*start, &write_flag);
+            Some(RegisterFlag::IsReturnAddress) | None => {
+                if self.assignment_regs.is_empty() {
+                    return Err(ConversionError::new(
+                        *start,
+                        "The assignment register has to be declared before the other registers.",
+                    ));
+                }
+                for assign_reg in self.assignment_regs.clone() {
+                    self.create_witness_fixed_pair(*start, &self.write_flag(&assign_reg, name));
+                }
                 // This might be superfluous but makes it easier to determine that the register needs to
                 // be zero in the first row.
                 self.pil.push(Statement::PolynomialIdentity(
                     *start,
                     build_mul(direct_reference("first_step"), direct_reference(name)),
                 ));
-                conditioned_updates = vec![
+                conditioned_updates = [
                     // The value here is actually irrelevant, it is only important
                     // that "first_step'" is included to compute the "default condition"
                     (next_reference("first_step"), build_number(0.into())),
+                ]
+                .into_iter()
+                .chain(self.assignment_regs.iter().map(|assign_reg| {
                     (
-                        direct_reference(&write_flag),
-                        direct_reference(self.default_assignment_reg()),
-                    ),
-                ];
+                        direct_reference(&self.write_flag(assign_reg, name)),
+                        direct_reference(assign_reg),
+                    )
+                }))
+                .collect();
                 default_update = Some(direct_reference(name));
             }
         };
         self.registers.insert(
             name.to_string(),
             Register {
+                start: *start,
+                is_pc: self.pc_names.iter().any(|pc| pc == name),
                 conditioned_updates,
                 default_update,
             },
         );
         self.pil.push(witness_column(*start, name, None));
+        if *flags == Some(RegisterFlag::IsReturnAddress) {
+            self.define_call_and_ret(start, name)?;
+        }
+        Ok(())
+    }
+
+    /// Defines the instructions of the calling convention that uses `return_reg`
+    /// to store the return address:
+    /// `instr call l: label { pc' = l, return_reg' = pc + 1 }` and
+    /// `instr ret { pc' = return_reg }`.
+    fn define_call_and_ret(
+        &mut self,
+        start: &usize,
+        return_reg: &str,
+    ) -> Result<(), ConversionError> {
+        let Some(pc) = self.pc_name().cloned() else {
+            return Err(ConversionError::new(
+                *start,
+                "The program counter has to be declared before the return address register.",
+            ));
+        };
+        let call_body = vec![
+            InstructionBodyElement::Expression(build_sub(
+                next_reference(&pc),
+                direct_reference("l"),
+            )),
+            InstructionBodyElement::Expression(build_sub(
+                next_reference(return_reg),
+                build_add(direct_reference(&pc), build_number(1.into())),
+            )),
+        ];
+        let call_params = vec![InstructionParam {
+            name: "l".to_string(),
+            param_type: Some("label".to_string()),
+            assignment_reg: (None, None),
+        }];
+        self.handle_instruction_def(start, &call_body, "call", &call_params)?;
+        let ret_body = vec![InstructionBodyElement::Expression(build_sub(
+            next_reference(&pc),
+            direct_reference(return_reg),
+        ))];
+        self.handle_instruction_def(start, &ret_body, "ret", &vec![])
     }
 
     fn handle_instruction_def(
@@ -165,7 +583,13 @@ impl ASMPILConverter {
         body: &Vec<InstructionBodyElement>,
         name: &str,
         params: &Vec<InstructionParam>,
-    ) {
+    ) -> Result<(), ConversionError> {
+        if self.instructions.contains_key(name) {
+            return Err(ConversionError::new(
+                *start,
+                format!("Instruction {name} is already declared."),
+            ));
+        }
         let instruction_flag = format!("instr_{name}");
         self.create_witness_fixed_pair(*start, &instruction_flag);
         // it's part of the lookup!
@@ -173,23 +597,77 @@ impl ASMPILConverter {
 
         let mut substitutions = HashMap::new();
         for p in params {
+            let [read_reg, write_reg] = [&p.assignment_reg.0, &p.assignment_reg.1].map(|reg| {
+                reg.as_ref()
+                    .map(|reg| self.resolve_assignment_register(*start, reg))
+                    .transpose()
+            });
+            // An in/out parameter reads a register and writes the result back to it.
+            if let (Some(read_reg), Some(write_reg)) = (read_reg?, write_reg?) {
+                if read_reg == write_reg {
+                    return Err(ConversionError::new(
+                        *start,
+                        format!(
+                            "In/out parameter {} of {name} reads and writes through the same assignment register {read_reg}.",
+                            p.name
+                        ),
+                    ));
+                }
+            }
             if p.assignment_reg.0.is_none() && p.assignment_reg.1.is_none() {
                 // literal argument
                 let param_col_name = format!("instr_{name}_param_{}", p.name);
                 self.create_witness_fixed_pair(*start, &param_col_name);
-                substitutions.insert(p.name.clone(), param_col_name);
+                substitutions.insert(p.name.clone(), direct_reference(&param_col_name));
+            }
+        }
+
+        let statements_start = self.pil.len();
+        let mut has_local_columns = false;
+        for expr in body {
+            if let InstructionBodyElement::LocalColumn(column) = expr {
+                if substitutions.contains_key(column) || self.registers.contains_key(column) {
+                    return Err(ConversionError::new(
+                        *start,
+                        format!("Local column {column} of {name} is already declared."),
+                    ));
+                }
+                let column_name = format!("instr_{name}_{column}");
+                self.pil.push(witness_column(*start, &column_name, None));
+                substitutions.insert(column.clone(), direct_reference(&column_name));
+                has_local_columns = true;
             }
         }
 
+        let mut updated_registers = BTreeSet::new();
         for expr in body {
             match expr {
+                InstructionBodyElement::LocalColumn(_) => {}
+                InstructionBodyElement::MacroCall(..) => {
+                    unreachable!("Macro calls are expanded before the instruction is converted.")
+                }
                 InstructionBodyElement::Expression(expr) => {
                     let expr = substitute(expr, &substitutions);
-                    match extract_update(expr) {
+                    match extract_update(expr).map_err(|e| ConversionError::new(*start, e))? {
                         (Some(var), expr) => {
+                            if !updated_registers.insert(var.clone()) {
+                                return Err(ConversionError::new(
+                                    *start,
+                                    format!(
+                                        "Instruction {name} updates register {var} more than once."
+                                    ),
+                                ));
+                            }
                             self.registers
                                 .get_mut(&var)
-                                .unwrap()
+                                .ok_or_else(|| {
+                                    ConversionError::new(
+                                        *start,
+                                        format!(
+                                            "Instruction {name} updates unknown register {var}."
+                                        ),
+                                    )
+                                })?
                                 .conditioned_updates
                                 .push((direct_reference(&instruction_flag), expr));
                         }
@@ -200,9 +678,17 @@ impl ASMPILConverter {
                     }
                 }
                 InstructionBodyElement::PlookupIdentity(left, op, right) => {
-                    assert!(left.selector.is_none(), "LHS selector not supported, could and-combine with instruction flag later.");
+                    // The lookup is only active if the instruction is executed
+                    // and the selector given in the body (if any) is non-zero.
+                    let selector = match &left.selector {
+                        Some(selector) => build_mul(
+                            direct_reference(&instruction_flag),
+                            substitute(selector, &substitutions),
+                        ),
+                        None => direct_reference(&instruction_flag),
+                    };
                     let left = SelectedExpressions {
-                        selector: Some(direct_reference(&instruction_flag)),
+                        selector: Some(selector),
                         expressions: substitute_vec(&left.expressions, &substitutions),
                     };
                     let right = substitute_selected_exprs(right, &substitutions);
@@ -213,320 +699,1686 @@ impl ASMPILConverter {
                 }
             }
         }
+        if has_local_columns {
+            self.local_column_statements
+                .insert(name.to_string(), statements_start..self.pil.len());
+        }
         let instr = Instruction {
+            start: *start,
             params: params.clone(),
         };
         self.instructions.insert(name.to_string(), instr);
+        Ok(())
+    }
+
+    /// Replaces the macro calls in the body of an instruction by the bodies of the macros,
+    /// with the parameters replaced by the arguments. `expanding` contains the macros
+    /// whose expansion is in progress, to report recursive macros.
+    fn expand_macros(
+        &self,
+        start: usize,
+        body: &[InstructionBodyElement],
+        expanding: &mut Vec<String>,
+    ) -> Result<Vec<InstructionBodyElement>, ConversionError> {
+        let mut result = vec![];
+        for element in body {
+            let InstructionBodyElement::MacroCall(name, args) = element else {
+                result.push(element.clone());
+                continue;
+            };
+            let (params, macro_body) = self
+                .macros
+                .get(name)
+                .ok_or_else(|| ConversionError::new(start, format!("Unknown macro {name}.")))?;
+            if params.len() != args.len() {
+                return Err(ConversionError::new(
+                    start,
+                    format!(
+                        "Macro {name} expects {} arguments, but got {}.",
+                        params.len(),
+                        args.len()
+                    ),
+                ));
+            }
+            if expanding.contains(name) {
+                return Err(ConversionError::new(
+                    start,
+                    format!(
+                        "Recursive expansion of macro {name}: {} -> {name}.",
+                        expanding.join(" -> ")
+                    ),
+                ));
+            }
+            let substitutions = params.iter().cloned().zip(args.iter().cloned()).collect();
+            let macro_body = macro_body
+                .iter()
+                .map(|element| match element {
+                    InstructionBodyElement::Expression(e) => {
+                        InstructionBodyElement::Expression(substitute(e, &substitutions))
+                    }
+                    InstructionBodyElement::PlookupIdentity(left, op, right) => {
+                        InstructionBodyElement::PlookupIdentity(
+                            substitute_selected_exprs(left, &substitutions),
+                            op.clone(),
+                            substitute_selected_exprs(right, &substitutions),
+                        )
+                    }
+                    InstructionBodyElement::LocalColumn(_) => element.clone(),
+                    InstructionBodyElement::MacroCall(name, args) => {
+                        InstructionBodyElement::MacroCall(
+                            name.clone(),
+                            substitute_vec(args, &substitutions),
+                        )
+                    }
+                })
+                .collect::<Vec<_>>();
+            expanding.push(name.clone());
+            result.extend(self.expand_macros(start, &macro_body, expanding)?);
+            expanding.pop();
+        }
+        Ok(result)
+    }
+
+    /// Removes the local columns of instructions that are not used in any line,
+    /// together with the identities and register updates of their bodies.
+    fn remove_unused_local_columns(&mut self) {
+        let mut unused = std::mem::take(&mut self.local_column_statements)
+            .into_iter()
+            .filter(|(name, _)| {
+                !self
+                    .code_lines
+                    .iter()
+                    .any(|line| line.instruction.as_ref() == Some(name))
+            })
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|(_, statements)| statements.start);
+        for (name, statements) in unused.into_iter().rev() {
+            self.pil.drain(statements);
+            let flag = direct_reference(&format!("instr_{name}"));
+            for register in self.registers.values_mut() {
+                register
+                    .conditioned_updates
+                    .retain(|(condition, _)| *condition != flag);
+            }
+        }
     }
 
     fn handle_assignment(
         &mut self,
-        _start: usize,
-        write_regs: &Vec<String>,
-        _assign_reg: &Option<String>,
+        start: usize,
+        write_regs: &[String],
+        assign_reg: &Option<String>,
         value: &Expression,
-    ) {
-        assert!(write_regs.len() <= 1);
-        let value = self.process_assignment_value(value);
-        // TODO handle assign register
+    ) -> Result<(), ConversionError> {
+        self.check_write_registers(start, write_regs)?;
+        if let Expression::Tuple(items) = value {
+            return self.handle_tuple_assignment(start, write_regs, assign_reg, items);
+        }
+        let assign_reg = self.resolve_assignment_register(start, assign_reg)?;
+        let value = self.process_assignment_value(start, value)?;
+        for reg in write_regs {
+            self.enable_pc_write(start, &assign_reg, reg);
+        }
         self.code_lines.push(CodeLine {
-            write_reg: write_regs.first().cloned(),
-            value,
+            start,
+            write_regs: [(assign_reg.clone(), write_regs.to_vec())].into(),
+            value: [(assign_reg, value)].into(),
             ..Default::default()
-        })
+        });
+        Ok(())
     }
 
-    fn handle_instruction(&mut self, instr_name: &str, args: &Vec<Expression>) {
-        let instr = &self.instructions[instr_name];
-        assert_eq!(instr.params.len(), args.len());
-        let mut value = vec![];
-        let mut instruction_literal_args = vec![];
-        let mut write_reg = None;
-        for (p, a) in instr.params.iter().zip(args) {
-            // TODO literal arguments can actually only be passed in.
-            if p.assignment_reg.0.is_some() {
-                // TODO this ignores which param it is, but it's ok
-                // TODO if we have  more than one assignment op, we cannot just use
-                // value here anymore. But I guess the same is for assignment.
-                // TODO check that we do not use the same assignment var twice
-                assert!(value.is_empty());
-                value = self.process_assignment_value(a);
-                instruction_literal_args.push(None);
-            } else if p.assignment_reg.1.is_some() {
-                // TODO this ignores which param it is, but it's ok
-                // TODO if we have  more than one assignment op, we cannot just use
-                // value here anymore. But I guess the same is for assignment.
-                if let Expression::PolynomialReference(r) = a {
-                    assert!(write_reg.is_none());
-                    write_reg = Some(r.name.clone());
-                } else {
-                    panic!("Expected direct register to assign to in instruction call.");
-                }
-                instruction_literal_args.push(None);
-            } else if p.param_type == Some("label".to_string()) {
-                if let Expression::PolynomialReference(r) = a {
-                    instruction_literal_args.push(Some(r.name.clone()))
-                } else {
-                    panic!();
-                }
-            } else {
-                todo!("Param type not supported.");
-            }
+    /// Handles `A, B <== (a, b);`, which assigns each item of the tuple to the
+    /// register at the same position. The items are passed through the assignment
+    /// registers in the order of their declaration.
+    fn handle_tuple_assignment(
+        &mut self,
+        start: usize,
+        write_regs: &[String],
+        assign_reg: &Option<String>,
+        items: &[Expression],
+    ) -> Result<(), ConversionError> {
+        if let Some(assign_reg) = assign_reg {
+            return Err(ConversionError::new(
+                start,
+                format!("Tuples are assigned through all assignment registers, but {assign_reg} is given explicitly."),
+            ));
         }
-        assert_eq!(instruction_literal_args.len(), instr.params.len());
-        self.code_lines.push(CodeLine {
-            write_reg,
-            instruction: Some(instr_name.to_string()),
-            value,
-            instruction_literal_args,
+        if items.len() != write_regs.len() {
+            return Err(ConversionError::new(
+                start,
+                format!(
+                    "Cannot assign a tuple of {} values to {} registers.",
+                    items.len(),
+                    write_regs.len()
+                ),
+            ));
+        }
+        if items.len() > self.assignment_regs.len() {
+            return Err(ConversionError::new(
+                start,
+                format!(
+                    "Assigning a tuple of {} values needs as many assignment registers, but only {} are declared.",
+                    items.len(),
+                    self.assignment_regs.len()
+                ),
+            ));
+        }
+        let mut line = CodeLine {
+            start,
             ..Default::default()
-        });
+        };
+        for ((assign_reg, reg), item) in self
+            .assignment_regs
+            .clone()
+            .into_iter()
+            .zip(write_regs)
+            .zip(items)
+        {
+            let value = self.process_assignment_value(start, item)?;
+            self.enable_pc_write(start, &assign_reg, reg);
+            line.write_regs
+                .insert(assign_reg.clone(), vec![reg.clone()]);
+            line.value.insert(assign_reg, value);
+        }
+        self.code_lines.push(line);
+        Ok(())
     }
 
-    fn process_assignment_value(
+    /// Checks that the registers can be assigned to and are distinct.
+    fn check_write_registers(
         &self,
-        value: &Expression,
-    ) -> Vec<(AbstractNumberType, AffineExpressionComponent)> {
-        match value {
-            Expression::Constant(_) => panic!(),
-            Expression::PublicReference(_) => panic!(),
-            Expression::FunctionCall(_, _) => panic!(),
-            Expression::PolynomialReference(reference) => {
-                assert!(reference.namespace.is_none());
-                assert!(reference.index.is_none());
-                assert!(!reference.next);
-                // TODO check it actually is a register
-                vec![(
-                    1.into(),
-                    AffineExpressionComponent::Register(reference.name.clone()),
-                )]
+        start: usize,
+        write_regs: &[String],
+    ) -> Result<(), ConversionError> {
+        for (i, reg) in write_regs.iter().enumerate() {
+            if !self.registers.contains_key(reg) {
+                return Err(ConversionError::new(
+                    start,
+                    format!("Assignment to unknown register {reg}."),
+                ));
             }
-            Expression::Number(value) => vec![(value.clone(), AffineExpressionComponent::Constant)],
-            Expression::String(_) => panic!(),
-            Expression::Tuple(_) => panic!(),
-            Expression::FreeInput(expr) => {
-                vec![(
-                    1.into(),
-                    AffineExpressionComponent::FreeInput(*expr.clone()),
-                )]
+            if self.assignment_regs.contains(reg) {
+                return Err(ConversionError::new(
+                    start,
+                    format!("Cannot assign to the special register {reg}."),
+                ));
             }
-            Expression::BinaryOperation(left, op, right) => match op {
-                BinaryOperator::Add => self.add_assignment_value(
-                    self.process_assignment_value(left),
-                    self.process_assignment_value(right),
-                ),
-                BinaryOperator::Sub => self.add_assignment_value(
-                    self.process_assignment_value(left),
-                    self.negate_assignment_value(self.process_assignment_value(right)),
-                ),
-                BinaryOperator::Mul => todo!(),
-                BinaryOperator::Div => panic!(),
-                BinaryOperator::Mod => panic!(),
-                BinaryOperator::Pow => panic!(),
-                BinaryOperator::BinaryAnd => panic!(),
-                BinaryOperator::BinaryOr => panic!(),
-                BinaryOperator::ShiftLeft => panic!(),
-                BinaryOperator::ShiftRight => panic!(),
-            },
-            Expression::UnaryOperation(op, expr) => {
-                assert!(*op == UnaryOperator::Minus);
-                self.negate_assignment_value(self.process_assignment_value(expr))
+            if write_regs[..i].contains(reg) {
+                return Err(ConversionError::new(
+                    start,
+                    format!("Register {reg} is assigned to more than once in the same line."),
+                ));
             }
         }
+        Ok(())
     }
 
-    fn add_assignment_value(
-        &self,
-        mut left: Vec<(AbstractNumberType, AffineExpressionComponent)>,
-        right: Vec<(AbstractNumberType, AffineExpressionComponent)>,
-    ) -> Vec<(AbstractNumberType, AffineExpressionComponent)> {
-        // TODO combine (or at leats check for) same components.
-        left.extend(right);
-        left
+    /// If `reg` is a program counter, lets `assign_reg` write to it, which is a jump
+    /// to a computed line. Program counters are declared before the assignment
+    /// registers, so the write flag is only created for the first such assignment.
+    fn enable_pc_write(&mut self, start: usize, assign_reg: &str, reg: &str) {
+        if !self.registers[reg].is_pc {
+            return;
+        }
+        let flag = self.write_flag(assign_reg, reg);
+        let update = (direct_reference(&flag), direct_reference(assign_reg));
+        if self.registers[reg].conditioned_updates.contains(&update) {
+            return;
+        }
+        self.create_witness_fixed_pair(start, &flag);
+        self.registers
+            .get_mut(reg)
+            .unwrap()
+            .conditioned_updates
+            .push(update);
     }
 
-    fn negate_assignment_value(
-        &self,
-        expr: Vec<(AbstractNumberType, AffineExpressionComponent)>,
-    ) -> Vec<(AbstractNumberType, AffineExpressionComponent)> {
-        expr.into_iter().map(|(v, c)| (-v, c)).collect()
+    /// Inserts the PIL of a gadget from the library, including its lookup tables
+    /// unless they have been declared by an earlier gadget.
+    fn handle_gadget(
+        &mut self,
+        start: usize,
+        name: &str,
+        args: &[Expression],
+    ) -> Result<(), ConversionError> {
+        let gadget = gadgets::expand(name, args).map_err(|e| ConversionError::new(start, e))?;
+        let pil = self.undeclared_tables(gadget.tables) + &gadget.pil;
+        let statements = parser::parse(None, &pil)
+            .unwrap_or_else(|err| panic!("Invalid PIL of gadget {name}: {}", err.message()))
+            .0;
+        self.handle_inline_pil(start, &statements);
+        Ok(())
     }
 
-    fn create_constraints_for_assignment_reg(&mut self) {
-        let assign_const = format!("{}_const", self.default_assignment_reg());
-        self.create_witness_fixed_pair(0, &assign_const);
-        let read_free = format!("{}_read_free", self.default_assignment_reg());
-        self.create_witness_fixed_pair(0, &read_free);
-        let free_value = format!("{}_free_value", self.default_assignment_reg());
-        let registers = self
-            .registers
-            .keys()
-            .filter(|name| **name != self.default_assignment_reg())
-            .cloned()
-            .collect::<Vec<_>>();
-        let assign_constraint = registers
-            .iter()
-            .map(|name| {
-                let read_coefficient = format!("read_{}_{name}", self.default_assignment_reg());
-                self.create_witness_fixed_pair(0, &read_coefficient);
-                build_mul(direct_reference(&read_coefficient), direct_reference(name))
-            })
-            .chain([
-                direct_reference(&assign_const),
-                build_mul(direct_reference(&read_free), direct_reference(&free_value)),
-            ])
-            .reduce(build_add);
-        self.pil.push(Statement::PolynomialIdentity(
-            0,
-            build_sub(
-                direct_reference(self.default_assignment_reg()),
-                assign_constraint.unwrap(),
-            ),
-        ));
+    /// @returns the declarations of the tables that have not been declared before
+    /// and marks them as declared.
+    fn undeclared_tables(&mut self, tables: Vec<(String, String)>) -> String {
+        tables
+            .into_iter()
+            .filter(|(table, _)| self.gadget_tables.insert(table.clone()))
+            .map(|(_, declaration)| format!("{declaration};\n"))
+            .collect()
     }
 
-    /// Translates the code lines to fixed column but also fills
-    /// the query hints for the free inputs.
-    fn translate_code_lines(&mut self) {
-        // TODO this should loop with the number of lines in the program, as should all the other program constants!
-        self.pil.push(Statement::PolynomialConstantDefinition(
-            0,
-            "line".to_string(),
-            FunctionDefinition::Mapping(vec!["i".to_string()], direct_reference("i")),
-        ));
-        // TODO check that all of them are matched against execution trace witnesses.
-        let mut program_constants = self
-            .program_constant_names
-            .iter()
-            .map(|n| (n, vec![AbstractNumberType::from(0); self.code_lines.len()]))
-            .collect::<BTreeMap<_, _>>();
-        let mut free_value_queries = vec![
-            direct_reference("i"),
-            direct_reference(self.pc_name.as_ref().unwrap()),
-        ];
-
-        let label_positions = self.compute_label_positions();
-        for (i, line) in self.code_lines.iter().enumerate() {
-            if let Some(reg) = &line.write_reg {
-                program_constants
-                    .get_mut(&format!("p_reg_write_{reg}"))
-                    .unwrap()[i] = 1.into();
+    /// Sets the number of bits of the operands of bitwise operations, which determines
+    /// the size of their lookup tables.
+    fn set_bitwise_width(
+        &mut self,
+        start: usize,
+        bits: &AbstractNumberType,
+    ) -> Result<(), ConversionError> {
+        let valid = *bits >= 1.into()
+            && *bits <= MAX_BITWISE_WIDTH.into()
+            && (*bits <= BITWISE_CHUNK_WIDTH.into()
+                || is_zero(&(bits.clone() % BITWISE_CHUNK_WIDTH)));
+        if !valid {
+            return Err(ConversionError::new(
+                start,
+                format!(
+                    "The bitwise width has to be between 1 and {BITWISE_CHUNK_WIDTH} or a multiple \
+                     of {BITWISE_CHUNK_WIDTH} up to {MAX_BITWISE_WIDTH}, but got {bits}."
+                ),
+            ));
+        }
+        let bits = abstract_to_degree(bits);
+        if !self.bitwise_operations.is_empty() {
+            return Err(ConversionError::new(
+                start,
+                "The bitwise width has to be declared before the first bitwise operation.",
+            ));
+        }
+        match self.bitwise_width {
+            Some(previous) if previous != bits => Err(ConversionError::new(
+                start,
+                format!("Conflicting bitwise width declaration: {bits} (previously declared as {previous})."),
+            )),
+            _ => {
+                self.bitwise_width = Some(bits);
+                Ok(())
             }
-            for (coeff, item) in &line.value {
-                match item {
-                    AffineExpressionComponent::Register(reg) => {
-                        program_constants
-                            .get_mut(&format!("p_read_{}_{reg}", self.default_assignment_reg()))
-                            .unwrap()[i] = coeff.clone();
-                    }
-                    AffineExpressionComponent::Constant => {
-                        program_constants
-                            .get_mut(&format!("p_{}_const", self.default_assignment_reg()))
-                            .unwrap()[i] = coeff.clone()
-                    }
-                    AffineExpressionComponent::FreeInput(expr) => {
-                        // The program just stores that we read a free input, the actual value
-                        // is part of the execution trace that generates the witness.
-                        program_constants
-                            .get_mut(&format!("p_{}_read_free", self.default_assignment_reg()))
-                            .unwrap()[i] = coeff.clone();
-                        free_value_queries.push(Expression::Tuple(vec![
-                            build_number(i.into()),
-                            expr.clone(),
-                        ]));
-                    }
+        }
+    }
+
+    /// The number of bits of the operands of the lookup tables of bitwise operations.
+    fn bitwise_chunk_width(&self) -> u64 {
+        self.bitwise_width
+            .unwrap_or(DEFAULT_BITWISE_WIDTH)
+            .min(BITWISE_CHUNK_WIDTH)
+    }
+
+    /// Lowers `left op right` for `op` being `&`, `|` or `^` to a lookup into a table
+    /// of all results for operands of the bitwise width, which also ensures that the
+    /// operands fit the width. The result is stored in a new witness column and the
+    /// lookup is only active in the current line. Constant operands are evaluated directly.
+    /// Wider operands and the result are split into bytes in new witness columns, and
+    /// each byte is looked up on its own.
+    fn bitwise_operation(
+        &mut self,
+        start: usize,
+        op: BinaryOperator,
+        left: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+        right: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+    ) -> Result<Vec<(AbstractNumberType, AffineExpressionComponent)>, ConversionError> {
+        if let (Some(left), Some(right)) = (constant_value(&left), constant_value(&right)) {
+            let value = match op {
+                BinaryOperator::BinaryAnd => clamp(left) & clamp(right),
+                BinaryOperator::BinaryOr => clamp(left) | clamp(right),
+                _ => clamp(left) ^ clamp(right),
+            };
+            return Ok(vec![(value, AffineExpressionComponent::Constant)]
+                .into_iter()
+                .filter(|(v, _)| !is_zero(v))
+                .collect());
+        }
+        let operands = [&left, &right]
+            .into_iter()
+            .map(|operand| {
+                affine_expression(operand).ok_or_else(|| {
+                    ConversionError::new(
+                        start,
+                        format!("The operands of {op} cannot contain free inputs."),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let bits = self.bitwise_chunk_width();
+        let chunks = self.bitwise_width.unwrap_or(DEFAULT_BITWISE_WIDTH) / bits;
+        let (tables, columns) = gadgets::bitwise_tables(op, bits);
+        let pil = self.undeclared_tables(tables);
+        let statements = parser::parse(None, &pil)
+            .unwrap_or_else(|err| panic!("Invalid PIL of bitwise tables: {}", err.message()))
+            .0;
+        self.handle_inline_pil(start, &statements);
+
+        let result = format!("bitwise_{}", self.bitwise_operations.len());
+        self.bitwise_operations.push(start);
+        let flag = format!("{result}_active");
+        self.create_witness_fixed_pair(start, &flag);
+        self.line_flags.push((self.code_lines.len(), flag.clone()));
+        self.pil.push(witness_column(start, &result, None));
+        let lookups = if chunks == 1 {
+            vec![[
+                operands[0].clone(),
+                operands[1].clone(),
+                direct_reference(&result),
+            ]]
+        } else {
+            // flag * (value - (value_0 + 2**bits * value_1 + ...)) = 0
+            let parts = ["a", "b", "r"].map(|part| {
+                (0..chunks)
+                    .map(|j| format!("{result}_{part}_{j}"))
+                    .collect::<Vec<_>>()
+            });
+            let values = [
+                operands[0].clone(),
+                operands[1].clone(),
+                direct_reference(&result),
+            ];
+            for (value, part) in values.into_iter().zip(&parts) {
+                let sum = (1..chunks).fold(direct_reference(&part[0]), |sum, j| {
+                    build_add(
+                        sum,
+                        build_mul(
+                            build_number(AbstractNumberType::from(1) << (bits * j)),
+                            direct_reference(&part[j as usize]),
+                        ),
+                    )
+                });
+                for name in part {
+                    self.pil.push(witness_column(start, name, None));
                 }
+                self.pil.push(Statement::PolynomialIdentity(
+                    start,
+                    build_mul(direct_reference(&flag), build_sub(value, sum)),
+                ));
             }
-            if let Some(instr) = &line.instruction {
-                if line.write_reg.is_some() {
-                    // If an instruction stores a value, we need to "read" it from the free input
-                    // because we assume that the assignment register is assigned in inline
-                    // pil. TODO This is horrible and needs to be fixed by a proper mechanism
-                    // that enforces that the assignment register is actually properly constrained.
-                    assert!(line.value.is_empty());
-                    program_constants
-                        .get_mut(&format!("p_{}_read_free", self.default_assignment_reg()))
-                        .unwrap()[i] = 1.into();
+            (0..chunks as usize)
+                .map(|j| {
+                    [&parts[0][j], &parts[1][j], &parts[2][j]].map(|name| direct_reference(name))
+                })
+                .collect()
+        };
+        for [left, right, result] in lookups {
+            self.pil.push(Statement::PlookupIdentity(
+                start,
+                SelectedExpressions {
+                    selector: Some(direct_reference(&flag)),
+                    expressions: [
+                        build_add(
+                            left.clone(),
+                            build_mul(
+                                build_number(AbstractNumberType::from(1) << bits),
+                                right.clone(),
+                            ),
+                        ),
+                        left,
+                        right,
+                        result,
+                    ]
+                    .into(),
+                },
+                SelectedExpressions {
+                    selector: None,
+                    expressions: columns.iter().map(|c| direct_reference(c)).collect(),
+                },
+            ));
+        }
+        Ok(vec![(
+            1.into(),
+            AffineExpressionComponent::LookupResult(result),
+        )])
+    }
+
+    /// Sets the bound above which coefficients are reported if no field is active.
+    fn set_coefficient_bound(
+        &mut self,
+        start: usize,
+        bound: &AbstractNumberType,
+    ) -> Result<(), ConversionError> {
+        if !self.code_lines.is_empty() {
+            return Err(ConversionError::new(
+                start,
+                "The coefficient bound has to be declared before the first assignment or instruction.",
+            ));
+        }
+        match &self.coefficient_bound {
+            Some(previous) if previous != bound => Err(ConversionError::new(
+                start,
+                format!("Conflicting coefficient bound declaration: {bound} (previously declared as {previous})."),
+            )),
+            _ => {
+                self.coefficient_bound = Some(bound.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the number of bits of the operands of ordered comparisons, which determines
+    /// the size of the lookup tables of their range checks.
+    fn set_comparison_width(
+        &mut self,
+        start: usize,
+        bits: &AbstractNumberType,
+    ) -> Result<(), ConversionError> {
+        if *bits < 1.into() || *bits > MAX_COMPARISON_WIDTH.into() {
+            return Err(ConversionError::new(
+                start,
+                format!("The comparison width has to be between 1 and {MAX_COMPARISON_WIDTH}, but got {bits}."),
+            ));
+        }
+        let bits = abstract_to_degree(bits);
+        if !self.comparison_operations.is_empty() {
+            return Err(ConversionError::new(
+                start,
+                "The comparison width has to be declared before the first comparison.",
+            ));
+        }
+        match self.comparison_width {
+            Some(previous) if previous != bits => Err(ConversionError::new(
+                start,
+                format!("Conflicting comparison width declaration: {bits} (previously declared as {previous})."),
+            )),
+            _ => {
+                self.comparison_width = Some(bits);
+                Ok(())
+            }
+        }
+    }
+
+    /// Lowers the comparison `left op right` to a new witness column that is 1 if it holds
+    /// and 0 otherwise. `<` is computed by a lookup of bit `bits` of `left - right + 2**bits`
+    /// that is only active in the current line, where both operands have to be smaller than
+    /// `2**bits` for the comparison width `bits`. `==` is computed by an is-zero gadget on the
+    /// difference, which is set to zero in the other lines. The other comparisons are derived
+    /// from these two. Constant operands are evaluated directly.
+    fn comparison(
+        &mut self,
+        start: usize,
+        op: BinaryOperator,
+        left: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+        right: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+    ) -> Result<Vec<(AbstractNumberType, AffineExpressionComponent)>, ConversionError> {
+        if let (Some(left), Some(right)) = (constant_value(&left), constant_value(&right)) {
+            return Ok(vec![(
+                op.compare(&clamp(left), &clamp(right)),
+                AffineExpressionComponent::Constant,
+            )]
+            .into_iter()
+            .filter(|(v, _)| !is_zero(v))
+            .collect());
+        }
+        let operands = [&left, &right]
+            .into_iter()
+            .map(|operand| {
+                affine_expression(operand).ok_or_else(|| {
+                    ConversionError::new(
+                        start,
+                        format!("The operands of {op} cannot contain free inputs."),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = format!("comparison_{}", self.comparison_operations.len());
+        self.comparison_operations.push((start, op));
+        let flag = format!("{result}_active");
+        self.create_witness_fixed_pair(start, &flag);
+        self.line_flags.push((self.code_lines.len(), flag.clone()));
+        self.pil.push(witness_column(start, &result, None));
+        let (a, b, negated) = match op {
+            BinaryOperator::Less | BinaryOperator::Equal => (&operands[0], &operands[1], false),
+            BinaryOperator::Greater => (&operands[1], &operands[0], false),
+            BinaryOperator::LessEqual => (&operands[1], &operands[0], true),
+            BinaryOperator::GreaterEqual | BinaryOperator::NotEqual => {
+                (&operands[0], &operands[1], true)
+            }
+            _ => panic!("{op} is not a comparison."),
+        };
+        if matches!(op, BinaryOperator::Equal | BinaryOperator::NotEqual) {
+            let diff = format!("{result}_diff");
+            let inv = format!("{result}_inv");
+            self.pil.push(witness_column(start, &diff, None));
+            self.pil.push(witness_column(start, &inv, None));
+            self.pil.push(Statement::PolynomialIdentity(
+                start,
+                build_sub(
+                    direct_reference(&diff),
+                    build_mul(direct_reference(&flag), build_sub(a.clone(), b.clone())),
+                ),
+            ));
+            self.pil.push(Statement::PolynomialIdentity(
+                start,
+                build_sub(
+                    direct_reference(&result),
+                    build_sub(
+                        build_number(1.into()),
+                        build_mul(direct_reference(&diff), direct_reference(&inv)),
+                    ),
+                ),
+            ));
+            self.pil.push(Statement::PolynomialIdentity(
+                start,
+                build_mul(direct_reference(&result), direct_reference(&diff)),
+            ));
+        } else {
+            let bits = self.comparison_width.unwrap_or(DEFAULT_COMPARISON_WIDTH);
+            let pil = self.undeclared_tables(gadgets::bit_tables(bits));
+            let statements = parser::parse(None, &pil)
+                .unwrap_or_else(|err| panic!("Invalid PIL of comparison tables: {}", err.message()))
+                .0;
+            self.handle_inline_pil(start, &statements);
+            self.pil.push(Statement::PlookupIdentity(
+                start,
+                SelectedExpressions {
+                    selector: Some(direct_reference(&flag)),
+                    expressions: [
+                        build_add(
+                            build_sub(a.clone(), b.clone()),
+                            build_number(AbstractNumberType::from(1) << bits),
+                        ),
+                        build_sub(build_number(1.into()), direct_reference(&result)),
+                    ]
+                    .into(),
+                },
+                SelectedExpressions {
+                    selector: None,
+                    expressions: vec![
+                        direct_reference(&format!("BIT{bits}_INPUT")),
+                        direct_reference(&format!("BIT{bits}")),
+                    ],
+                },
+            ));
+        }
+        let value = vec![(1.into(), AffineExpressionComponent::LookupResult(result))];
+        Ok(if negated {
+            self.add_assignment_value(
+                vec![(1.into(), AffineExpressionComponent::Constant)],
+                self.negate_assignment_value(value),
+            )
+        } else {
+            value
+        })
+    }
+
+    /// Inserts the inline PIL and records the columns it declares in other namespaces
+    /// and the values of the named constants it defines.
+    /// If it switches to another namespace, the assembly namespace is re-opened afterwards.
+    fn handle_inline_pil(&mut self, start: usize, statements: &[Statement]) {
+        let mut namespace = NAMESPACE.to_string();
+        for statement in statements {
+            if let Statement::ConstantDefinition(_, name, value) = statement {
+                // Definitions that are not constant are reported by the PIL analyzer,
+                // they just cannot be used in assignment values.
+                let mut references = BTreeSet::new();
+                collect_references(value, &mut references);
+                if references.is_empty() {
+                    if let Some(value) = self
+                        .process_assignment_value(start, value)
+                        .ok()
+                        .and_then(|value| constant_value(&value))
+                    {
+                        self.constants.insert(name.clone(), value);
+                    }
                 }
-                program_constants
-                    .get_mut(&format!("p_instr_{instr}"))
-                    .unwrap()[i] = 1.into();
-                for (arg, param) in line
-                    .instruction_literal_args
+            }
+            let names = match statement {
+                Statement::Namespace(_, name, _) => {
+                    namespace = name.clone();
+                    vec![]
+                }
+                Statement::PolynomialConstantDeclaration(_, polys)
+                | Statement::PolynomialCommitDeclaration(_, polys, _) => {
+                    polys.iter().map(|p| p.name.clone()).collect()
+                }
+                Statement::PolynomialDefinition(_, name, _)
+                | Statement::PolynomialConstantDefinition(_, name, _) => vec![name.clone()],
+                _ => vec![],
+            };
+            if namespace != NAMESPACE {
+                self.external_columns
+                    .entry(namespace.clone())
+                    .or_default()
+                    .extend(names);
+            } else if matches!(
+                statement,
+                Statement::PolynomialConstantDeclaration(..)
+                    | Statement::PolynomialConstantDefinition(..)
+            ) {
+                self.fixed_columns.extend(names);
+            }
+        }
+        self.pil.extend(statements.iter().cloned());
+        if namespace != NAMESPACE {
+            // The degree is filled in once the length of the program is known.
+            self.pil.push(Statement::Namespace(
+                start,
+                NAMESPACE.to_string(),
+                Expression::Number(0.into()),
+            ));
+        }
+    }
+
+    fn handle_instruction(
+        &mut self,
+        start: usize,
+        instr_name: &str,
+        args: &Vec<Expression>,
+    ) -> Result<(), ConversionError> {
+        let params = self
+            .instructions
+            .get(instr_name)
+            .ok_or_else(|| {
+                ConversionError::new(start, format!("Unknown instruction {instr_name}."))
+            })?
+            .params
+            .clone();
+        if params.len() != args.len() {
+            return Err(ConversionError::new(
+                start,
+                format!(
+                    "Instruction {instr_name} expects {} arguments, but got {}.",
+                    params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        let mut value = BTreeMap::new();
+        let mut instruction_literal_args = vec![];
+        let mut write_regs = BTreeMap::new();
+        for (p, a) in params.iter().zip(args) {
+            // TODO literal arguments can actually only be passed in.
+            if p.assignment_reg.0.is_some() && p.assignment_reg.1.is_some() {
+                // An in/out parameter reads the register and writes the result back to it.
+                if !matches!(a, Expression::PolynomialReference(r) if self.registers.contains_key(&r.name))
+                {
+                    return Err(ConversionError::new(
+                        start,
+                        format!(
+                            "Expected a register as in/out argument {} to {instr_name}, but got {a}.",
+                            p.name
+                        ),
+                    ));
+                }
+            }
+            if let Some(assign_reg) = &p.assignment_reg.0 {
+                let assign_reg = self.resolve_assignment_register(start, assign_reg)?;
+                if value.contains_key(&assign_reg) {
+                    return Err(ConversionError::new(
+                        start,
+                        format!("Instruction {instr_name} reads more than one value through the assignment register {assign_reg}."),
+                    ));
+                }
+                value.insert(assign_reg, self.process_assignment_value(start, a)?);
+            }
+            if let Some(assign_reg) = &p.assignment_reg.1 {
+                let assign_reg = self.resolve_assignment_register(start, assign_reg)?;
+                let Expression::PolynomialReference(r) = a else {
+                    return Err(ConversionError::new(
+                        start,
+                        format!("Expected direct register to assign to in instruction call, but got {a}."),
+                    ));
+                };
+                if write_regs.contains_key(&assign_reg) {
+                    return Err(ConversionError::new(
+                        start,
+                        format!("Instruction {instr_name} writes more than one value through the assignment register {assign_reg}."),
+                    ));
+                }
+                if write_regs.values().any(|reg| *reg == r.name) {
+                    return Err(ConversionError::new(
+                        start,
+                        format!(
+                            "Register {} is assigned to more than once in the same line.",
+                            r.name
+                        ),
+                    ));
+                }
+                write_regs.insert(assign_reg, r.name.clone());
+            }
+            if p.assignment_reg.0.is_some() || p.assignment_reg.1.is_some() {
+                instruction_literal_args.push(None);
+            } else if p.param_type == Some("label".to_string()) {
+                if let Expression::PolynomialReference(r) = a {
+                    instruction_literal_args.push(Some(LiteralArgument::Label(r.name.clone())))
+                } else {
+                    return Err(ConversionError::new(
+                        start,
+                        format!(
+                            "Expected a label as argument {} to {instr_name}, but got {a}.",
+                            p.name
+                        ),
+                    ));
+                }
+            } else if p.param_type.is_none() {
+                if let Expression::Number(n) = a {
+                    instruction_literal_args.push(Some(LiteralArgument::Number(n.clone())))
+                } else {
+                    return Err(ConversionError::new(
+                        start,
+                        format!(
+                            "Expected a number as argument {} to {instr_name}, but got {a}.",
+                            p.name
+                        ),
+                    ));
+                }
+            } else {
+                return Err(ConversionError::new(
+                    start,
+                    format!(
+                        "Type of parameter {} of {instr_name} not supported.",
+                        p.name
+                    ),
+                ));
+            }
+        }
+        for reg in write_regs.values() {
+            // If the instruction also reads through the assignment register, the register
+            // holds the value read in the current row and is written to the target register
+            // in the next row. This is only consistent if nothing else writes to the target.
+            let register = self.registers.get(reg).ok_or_else(|| {
+                ConversionError::new(
+                    start,
+                    format!("Instruction {instr_name} writes to unknown register {reg}."),
+                )
+            })?;
+            if register.is_pc || self.assignment_regs.contains(reg) {
+                return Err(ConversionError::new(
+                    start,
+                    format!("Instruction {instr_name} cannot write to the special register {reg}."),
+                ));
+            }
+            let instruction_flag = direct_reference(&format!("instr_{instr_name}"));
+            if register
+                .conditioned_updates
+                .iter()
+                .any(|(cond, _)| *cond == instruction_flag)
+            {
+                return Err(ConversionError::new(
+                    start,
+                    format!(
+                        "Instruction {instr_name} updates register {reg} in its body and also writes \
+                         to it through the assignment register."
+                    ),
+                ));
+            }
+        }
+        self.code_lines.push(CodeLine {
+            start,
+            write_regs: write_regs
+                .into_iter()
+                .map(|(assign_reg, reg)| (assign_reg, vec![reg]))
+                .collect(),
+            instruction: Some(instr_name.to_string()),
+            value,
+            instruction_literal_args,
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    fn process_assignment_value(
+        &mut self,
+        start: usize,
+        value: &Expression,
+    ) -> Result<Vec<(AbstractNumberType, AffineExpressionComponent)>, ConversionError> {
+        let unsupported = || {
+            Err(ConversionError::new(
+                start,
+                format!("Expression not supported in assignment value: {value}"),
+            ))
+        };
+        Ok(match value {
+            Expression::Constant(name) => {
+                let value = self.constants.get(name).ok_or_else(|| {
+                    ConversionError::new(
+                        start,
+                        format!("Unknown constant {name} in assignment value, constants have to be defined in inline PIL before they are used."),
+                    )
+                })?;
+                vec![(value.clone(), AffineExpressionComponent::Constant)]
+                    .into_iter()
+                    .filter(|(v, _)| !is_zero(v))
+                    .collect()
+            }
+            Expression::PublicReference(_)
+            | Expression::FunctionCall(_, _)
+            | Expression::String(_)
+            | Expression::Tuple(_) => return unsupported(),
+            Expression::PolynomialReference(reference) => {
+                if reference.index.is_some() || reference.next {
+                    return unsupported();
+                }
+                match &reference.namespace {
+                    Some(namespace) if namespace != NAMESPACE => {
+                        let columns = self.external_columns.get(namespace).ok_or_else(|| {
+                            ConversionError::new(
+                                start,
+                                format!("Unknown namespace {namespace} in {value}."),
+                            )
+                        })?;
+                        if !columns.contains(&reference.name) {
+                            return Err(ConversionError::new(
+                                start,
+                                format!(
+                                    "Column {} is not declared in namespace {namespace}.",
+                                    reference.name
+                                ),
+                            ));
+                        }
+                        return Ok(vec![(
+                            1.into(),
+                            AffineExpressionComponent::Column(
+                                namespace.clone(),
+                                reference.name.clone(),
+                            ),
+                        )]);
+                    }
+                    _ => {}
+                }
+                if self.is_fixed_column(&reference.name) {
+                    return Ok(vec![(
+                        1.into(),
+                        AffineExpressionComponent::FixedColumn(reference.name.clone()),
+                    )]);
+                }
+                if !self.registers.contains_key(&reference.name)
+                    || self.assignment_regs.contains(&reference.name)
+                {
+                    return Err(ConversionError::new(
+                        start,
+                        format!(
+                            "Cannot read from {} in an assignment value.",
+                            reference.name
+                        ),
+                    ));
+                }
+                if self.registers[&reference.name].is_pc
+                    && !self.readable_pcs.contains(&reference.name)
+                {
+                    return Err(ConversionError::new(
+                        start,
+                        format!(
+                            "The program counter {} can only be read in an assignment value if it is declared with [@pc_readable].",
+                            reference.name
+                        ),
+                    ));
+                }
+                vec![(
+                    1.into(),
+                    AffineExpressionComponent::Register(reference.name.clone()),
+                )]
+            }
+            Expression::Number(value) => vec![(value.clone(), AffineExpressionComponent::Constant)],
+            Expression::FreeInput(expr, keys) => {
+                let (channel, index) = free_input_channel(start, expr)?;
+                vec![(
+                    1.into(),
+                    AffineExpressionComponent::FreeInput(channel, index, keys.clone()),
+                )]
+            }
+            Expression::BinaryOperation(left, op, right) => {
+                match op {
+                    BinaryOperator::Add => {
+                        let left = self.process_assignment_value(start, left)?;
+                        let right = self.process_assignment_value(start, right)?;
+                        self.add_assignment_value(left, right)
+                    }
+                    BinaryOperator::Sub => {
+                        let left = self.process_assignment_value(start, left)?;
+                        let right = self.process_assignment_value(start, right)?;
+                        self.add_assignment_value(left, self.negate_assignment_value(right))
+                    }
+                    BinaryOperator::Mul => {
+                        let left = self.process_assignment_value(start, left)?;
+                        let right = self.process_assignment_value(start, right)?;
+                        self.multiply_assignment_value(start, left, right)?
+                    }
+                    BinaryOperator::Div => {
+                        let divisor = self.constant_divisor(start, right, "Division")?;
+                        let factor = inv(divisor, field_mod());
+                        let left = self.process_assignment_value(start, left)?;
+                        self.multiply_assignment_value(
+                            start,
+                            left,
+                            vec![(factor, AffineExpressionComponent::Constant)],
+                        )?
+                    }
+                    BinaryOperator::Mod => {
+                        let divisor = self.constant_divisor(start, right, "Modulo")?;
+                        let value = constant_value(&self.process_assignment_value(start, left)?)
+                        .ok_or_else(|| {
+                            ConversionError::new(
+                                start,
+                                format!("Modulo is only supported on constant values, but got {left}."),
+                            )
+                        })?;
+                        vec![(clamp(value) % divisor, AffineExpressionComponent::Constant)]
+                            .into_iter()
+                            .filter(|(v, _)| !is_zero(v))
+                            .collect()
+                    }
+                    BinaryOperator::Pow => {
+                        let base = self.process_assignment_value(start, left)?;
+                        let exponent = self.process_assignment_value(start, right)?;
+                        match (constant_value(&base), constant_value(&exponent)) {
+                            (Some(base), Some(exponent)) if exponent >= 0.into() => {
+                                let value = pow(clamp(base), exponent, field_mod());
+                                vec![(value, AffineExpressionComponent::Constant)]
+                                    .into_iter()
+                                    .filter(|(v, _)| !is_zero(v))
+                                    .collect()
+                            }
+                            (Some(_), Some(exponent)) => {
+                                return Err(ConversionError::new(
+                                    start,
+                                    format!("Negative exponent {exponent} in {value}."),
+                                ))
+                            }
+                            _ => {
+                                return Err(ConversionError::new(
+                                    start,
+                                    format!("Exponentiation is only supported if base and exponent are constant, but got {value}."),
+                                ))
+                            }
+                        }
+                    }
+                    BinaryOperator::BinaryAnd
+                    | BinaryOperator::BinaryOr
+                    | BinaryOperator::BinaryXor => {
+                        let left = self.process_assignment_value(start, left)?;
+                        let right = self.process_assignment_value(start, right)?;
+                        self.bitwise_operation(start, *op, left, right)?
+                    }
+                    BinaryOperator::Less
+                    | BinaryOperator::LessEqual
+                    | BinaryOperator::Greater
+                    | BinaryOperator::GreaterEqual
+                    | BinaryOperator::Equal
+                    | BinaryOperator::NotEqual => {
+                        let left = self.process_assignment_value(start, left)?;
+                        let right = self.process_assignment_value(start, right)?;
+                        self.comparison(start, *op, left, right)?
+                    }
+                    BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => {
+                        let factor = AbstractNumberType::from(1)
+                            << self.constant_shift_amount(start, right, *op)?;
+                        let left = self.process_assignment_value(start, left)?;
+                        match (op, constant_value(&left)) {
+                            (BinaryOperator::ShiftRight, Some(left)) => {
+                                vec![(clamp(left) / factor, AffineExpressionComponent::Constant)]
+                                    .into_iter()
+                                    .filter(|(v, _)| !is_zero(v))
+                                    .collect()
+                            }
+                            (BinaryOperator::ShiftRight, None) => self.multiply_assignment_value(
+                                start,
+                                left,
+                                vec![(
+                                    inv(factor, field_mod()),
+                                    AffineExpressionComponent::Constant,
+                                )],
+                            )?,
+                            _ => self.multiply_assignment_value(
+                                start,
+                                left,
+                                vec![(factor, AffineExpressionComponent::Constant)],
+                            )?,
+                        }
+                    }
+                }
+            }
+            Expression::UnaryOperation(UnaryOperator::Minus, expr) => {
+                let value = self.process_assignment_value(start, expr)?;
+                self.negate_assignment_value(value)
+            }
+            Expression::UnaryOperation(UnaryOperator::Plus, expr) => {
+                self.process_assignment_value(start, expr)?
+            }
+            Expression::UnaryOperation(UnaryOperator::LogicalNot, expr) => {
+                // !x is 1 - x, which assumes that x is boolean. This can only be
+                // checked if x is constant.
+                let value = self.process_assignment_value(start, expr)?;
+                if value
                     .iter()
-                    .zip(&self.instructions[instr].params)
+                    .all(|(_, c)| *c == AffineExpressionComponent::Constant)
                 {
-                    if let Some(arg) = arg {
-                        // TODO has to be label for now
-                        program_constants
-                            .get_mut(&format!("p_instr_{instr}_param_{}", param.name))
-                            .unwrap()[i] = (label_positions[arg] as i64).into();
+                    let constant = value
+                        .iter()
+                        .map(|(coeff, _)| coeff)
+                        .sum::<AbstractNumberType>();
+                    if constant != 0.into() && constant != 1.into() {
+                        return Err(ConversionError::new(
+                            start,
+                            format!("The operand of ! has to be 0 or 1, but {expr} is {constant}."),
+                        ));
                     }
                 }
+                self.add_assignment_value(
+                    vec![(1.into(), AffineExpressionComponent::Constant)],
+                    self.negate_assignment_value(value),
+                )
+            }
+        })
+    }
+
+    fn add_assignment_value(
+        &self,
+        mut left: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+        right: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+    ) -> Vec<(AbstractNumberType, AffineExpressionComponent)> {
+        for (coeff, component) in right {
+            if matches!(component, AffineExpressionComponent::FreeInput(..)) {
+                left.push((coeff, component));
+            } else if let Some((c, _)) = left.iter_mut().find(|(_, c)| *c == component) {
+                *c += coeff;
             } else {
-                assert!(line.instruction_literal_args.is_empty());
+                left.push((coeff, component));
             }
         }
-        let free_value = format!("{}_free_value", self.default_assignment_reg());
-        self.pil.push(witness_column(
-            0,
-            &free_value,
-            Some(FunctionDefinition::Query(
-                vec!["i".to_string()],
-                Expression::Tuple(free_value_queries),
-            )),
-        ));
-        for (name, values) in program_constants {
-            self.pil.push(Statement::PolynomialConstantDefinition(
-                0,
-                name.clone(),
-                FunctionDefinition::Array(values.into_iter().map(build_number).collect()),
-            ));
-        }
+        left.retain(|(coeff, _)| !is_zero(coeff));
+        left
     }
 
-    fn compute_label_positions(&self) -> HashMap<String, usize> {
-        self.code_lines
-            .iter()
-            .enumerate()
-            .filter_map(|(i, line)| line.label.as_ref().map(|l| (l.clone(), i)))
-            .collect()
+    /// Evaluates the right operand of a division or modulo operation, which has to be a non-zero constant.
+    fn constant_divisor(
+        &mut self,
+        start: usize,
+        divisor: &Expression,
+        operation: &str,
+    ) -> Result<AbstractNumberType, ConversionError> {
+        let value =
+            constant_value(&self.process_assignment_value(start, divisor)?).ok_or_else(|| {
+                ConversionError::new(
+                    start,
+                    format!(
+                        "{operation} is only supported with a constant divisor, but got {divisor}."
+                    ),
+                )
+            })?;
+        let value = clamp(value);
+        if is_zero(&value) {
+            return Err(ConversionError::new(start, format!("{operation} by zero.")));
+        }
+        Ok(value)
     }
 
-    /// Creates a pair of witness and fixed column and matches them in the lookup.
-    fn create_witness_fixed_pair(&mut self, start: usize, name: &str) {
-        let fixed_name = format!("p_{name}");
-        self.pil.push(witness_column(start, name, None));
-        self.line_lookup
-            .push((name.to_string(), fixed_name.clone()));
-        self.program_constant_names.push(fixed_name);
+    /// Evaluates the amount of a shift, which has to be a constant less than the
+    /// number of bits of the field modulus.
+    fn constant_shift_amount(
+        &mut self,
+        start: usize,
+        amount: &Expression,
+        op: BinaryOperator,
+    ) -> Result<u64, ConversionError> {
+        let value =
+            constant_value(&self.process_assignment_value(start, amount)?).ok_or_else(|| {
+                ConversionError::new(
+                    start,
+                    format!(
+                        "{op} is only supported with a constant shift amount, but got {amount}."
+                    ),
+                )
+            })?;
+        let bits = field_mod().bits();
+        if value < 0.into() || value >= bits.into() {
+            return Err(ConversionError::new(
+                start,
+                format!(
+                    "The shift amount has to be between 0 and {}, but got {value}.",
+                    bits - 1
+                ),
+            ));
+        }
+        Ok(abstract_to_degree(&value))
     }
 
-    fn default_assignment_reg(&self) -> &str {
-        self.default_assignment.as_ref().unwrap()
+    /// Multiplies two assignment values, one of which has to be constant.
+    /// If a field is active, the products are reduced immediately, otherwise
+    /// products that exceed the coefficient bound are reported as warnings.
+    fn multiply_assignment_value(
+        &mut self,
+        start: usize,
+        left: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+        right: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+    ) -> Result<Vec<(AbstractNumberType, AffineExpressionComponent)>, ConversionError> {
+        let (factor, value) = match (constant_value(&left), constant_value(&right)) {
+            (Some(factor), _) => (factor, right),
+            (None, Some(factor)) => (factor, left),
+            (None, None) => {
+                return Err(ConversionError::new(
+                    start,
+                    "Multiplication is only supported if one side is constant.",
+                ))
+            }
+        };
+        let modulus = active_field_mod();
+        let mut result = vec![];
+        for (coeff, component) in value {
+            let product = coeff * &factor;
+            let product = match &modulus {
+                Some(modulus) => reduce_signed(product, modulus),
+                None => {
+                    let bound = self.coefficient_bound.clone();
+                    let bound = bound.unwrap_or_else(|| DEFAULT_COEFFICIENT_BOUND.into());
+                    if product.magnitude() > bound.magnitude() {
+                        self.warnings.push(ConversionError::new(
+                            start,
+                            format!(
+                                "Coefficient {product} exceeds the bound {bound} and might wrap around in the field."
+                            ),
+                        ));
+                    }
+                    product
+                }
+            };
+            if !is_zero(&product) {
+                result.push((product, component));
+            }
+        }
+        Ok(result)
     }
-}
 
-struct Register {
-    /// Constraints to update this register, first item being the
-    /// condition, second item the value.
-    /// TODO check that condition is bool
-    conditioned_updates: Vec<(Expression, Expression)>,
-    default_update: Option<Expression>,
-}
+    fn negate_assignment_value(
+        &self,
+        expr: Vec<(AbstractNumberType, AffineExpressionComponent)>,
+    ) -> Vec<(AbstractNumberType, AffineExpressionComponent)> {
+        expr.into_iter().map(|(v, c)| (-v, c)).collect()
+    }
 
-impl Register {
-    /// Returns the expression assigned to this register in the next row.
-    pub fn update_expression(&self) -> Option<Expression> {
-        // TODO conditions need to be all boolean
-        let updates = self
-            .conditioned_updates
+    fn create_constraints_for_assignment_reg(&mut self, assign_reg: &str) {
+        let assign_const = format!("{assign_reg}_const");
+        self.create_witness_fixed_pair(0, &assign_const);
+        let read_free = format!("{assign_reg}_read_free");
+        self.create_witness_fixed_pair(0, &read_free);
+        let free_value = format!("{assign_reg}_free_value");
+        // Registers that are read through this assignment register. Only these get a read
+        // coefficient, the terms of the other registers would always be zero.
+        let read_registers = self
+            .code_lines
             .iter()
-            .map(|(cond, value)| build_mul(cond.clone(), value.clone()))
+            .filter_map(|line| line.value.get(assign_reg))
+            .flatten()
+            .filter_map(|(_, component)| match component {
+                AffineExpressionComponent::Register(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        // Program counters are only read if they are declared as readable.
+        let registers = self
+            .registers
+            .iter()
+            .filter(|(name, register)| {
+                read_registers.contains(name.as_str())
+                    && !self.assignment_regs.contains(name)
+                    && (!register.is_pc || self.readable_pcs.contains(*name))
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        // Columns of other namespaces that are read through this assignment register.
+        let columns = self
+            .code_lines
+            .iter()
+            .filter_map(|line| line.value.get(assign_reg))
+            .flatten()
+            .filter_map(|(_, component)| match component {
+                AffineExpressionComponent::Column(namespace, name) => {
+                    Some((namespace.clone(), name.clone()))
+                }
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        // Fixed columns that are read through this assignment register.
+        let fixed_columns = self
+            .code_lines
+            .iter()
+            .filter_map(|line| line.value.get(assign_reg))
+            .flatten()
+            .filter_map(|(_, component)| match component {
+                AffineExpressionComponent::FixedColumn(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        // Results of bitwise operations and comparisons that are read through this assignment register.
+        let lookup_results = self
+            .code_lines
+            .iter()
+            .filter_map(|line| line.value.get(assign_reg))
+            .flatten()
+            .filter_map(|(_, component)| match component {
+                AffineExpressionComponent::LookupResult(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        let assign_constraint = registers
+            .iter()
+            .map(|name| (format!("read_{assign_reg}_{name}"), direct_reference(name)))
+            .chain(columns.into_iter().map(|(namespace, name)| {
+                (
+                    format!("read_{assign_reg}_{namespace}_{name}"),
+                    namespaced_reference(namespace, name),
+                )
+            }))
+            .chain(fixed_columns.into_iter().map(|name| {
+                (
+                    format!("{assign_reg}_read_fixed_{name}"),
+                    direct_reference(&name),
+                )
+            }))
+            .chain(
+                lookup_results
+                    .into_iter()
+                    .map(|name| (format!("{assign_reg}_read_{name}"), direct_reference(&name))),
+            )
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|(read_coefficient, value)| {
+                self.create_witness_fixed_pair(0, &read_coefficient);
+                build_mul(direct_reference(&read_coefficient), value)
+            })
+            .chain([
+                direct_reference(&assign_const),
+                build_mul(direct_reference(&read_free), direct_reference(&free_value)),
+            ])
             .reduce(build_add);
+        self.pil.push(Statement::PolynomialIdentity(
+            0,
+            build_sub(direct_reference(assign_reg), assign_constraint.unwrap()),
+        ));
+    }
 
-        // TODO for computing the default condition, we need to ensure
-        // that the conditions all exclude each other
+    /// Translates the code lines to fixed column but also fills
+    /// the query hints for the free inputs.
+    /// @returns the values of the program constants.
+    fn translate_code_lines(
+        &mut self,
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, ConversionError> {
+        // TODO this should loop with the number of lines in the program, as should all the other program constants!
+        self.pil.push(Statement::PolynomialConstantDefinition(
+            0,
+            "line".to_string(),
+            FunctionDefinition::Mapping(vec!["i".to_string()], direct_reference("i")),
+        ));
+        let mut program_constants = self
+            .program_constant_names
+            .iter()
+            .map(|n| {
+                (
+                    n.clone(),
+                    vec![AbstractNumberType::from(0); self.code_lines.len()],
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        // The queries start with the keys given in the free inputs or, by default, the
+        // row and the program counter, followed by the free inputs of the program lines.
+        let default_keys = vec![
+            direct_reference("i"),
+            direct_reference(self.pc_name().unwrap()),
+        ];
+        let mut free_value_queries = self
+            .assignment_regs
+            .iter()
+            .map(|assign_reg| (assign_reg, (None, vec![])))
+            .collect::<BTreeMap<_, (Option<Vec<Expression>>, Vec<Expression>)>>();
+
+        for (i, flag) in &self.line_flags {
+            program_constants.get_mut(&format!("p_{flag}")).unwrap()[*i] = 1.into();
+        }
+        let label_positions = self.compute_label_positions();
+        for (i, line) in self.code_lines.iter().enumerate() {
+            for (assign_reg, regs) in &line.write_regs {
+                for reg in regs {
+                    program_constants
+                        .get_mut(&format!("p_{}", self.write_flag(assign_reg, reg)))
+                        .unwrap()[i] = 1.into();
+                }
+            }
+            for (assign_reg, value) in &line.value {
+                for (coeff, item) in value {
+                    match item {
+                        AffineExpressionComponent::Register(reg) => {
+                            program_constants
+                                .get_mut(&format!("p_read_{assign_reg}_{reg}"))
+                                .unwrap()[i] = coeff.clone();
+                        }
+                        AffineExpressionComponent::Column(namespace, name) => {
+                            program_constants
+                                .get_mut(&format!("p_read_{assign_reg}_{namespace}_{name}"))
+                                .unwrap()[i] = coeff.clone();
+                        }
+                        AffineExpressionComponent::FixedColumn(name) => {
+                            program_constants
+                                .get_mut(&format!("p_{assign_reg}_read_fixed_{name}"))
+                                .unwrap()[i] = coeff.clone();
+                        }
+                        AffineExpressionComponent::LookupResult(name) => {
+                            program_constants
+                                .get_mut(&format!("p_{assign_reg}_read_{name}"))
+                                .unwrap()[i] = coeff.clone();
+                        }
+                        AffineExpressionComponent::Constant => {
+                            program_constants
+                                .get_mut(&format!("p_{assign_reg}_const"))
+                                .unwrap()[i] = coeff.clone()
+                        }
+                        AffineExpressionComponent::FreeInput(channel, index, keys) => {
+                            // The program just stores that we read a free input, the actual value
+                            // is part of the execution trace that generates the witness.
+                            program_constants
+                                .get_mut(&format!("p_{assign_reg}_read_free"))
+                                .unwrap()[i] = coeff.clone();
+                            let (query_keys, entries) =
+                                free_value_queries.get_mut(assign_reg).unwrap();
+                            let keys = keys.as_ref().unwrap_or(&default_keys);
+                            match query_keys {
+                                Some(query_keys) if query_keys != keys => {
+                                    return Err(ConversionError::new(
+                                        line.start,
+                                        format!(
+                                            "Free inputs read through {assign_reg} have to use the same query keys, but got {} and {}.",
+                                            Expression::Tuple(query_keys.clone()),
+                                            Expression::Tuple(keys.clone())
+                                        ),
+                                    ));
+                                }
+                                Some(_) => {}
+                                None => *query_keys = Some(keys.clone()),
+                            }
+                            entries.push(Expression::Tuple(vec![
+                                build_number(i.into()),
+                                Expression::Tuple(vec![
+                                    Expression::String(channel.clone()),
+                                    index.clone(),
+                                ]),
+                            ]));
+                        }
+                    }
+                }
+            }
+            if let Some(instr) = &line.instruction {
+                for assign_reg in line.write_regs.keys() {
+                    if !line.value.contains_key(assign_reg) {
+                        // If an instruction stores a value, we need to "read" it from the free input
+                        // because we assume that the assignment register is assigned in inline
+                        // pil. TODO This is horrible and needs to be fixed by a proper mechanism
+                        // that enforces that the assignment register is actually properly constrained.
+                        // If the instruction also reads through the assignment register, the value
+                        // read in this row is the one written in the next row.
+                        program_constants
+                            .get_mut(&format!("p_{assign_reg}_read_free"))
+                            .unwrap()[i] = 1.into();
+                    }
+                }
+                program_constants
+                    .get_mut(&format!("p_instr_{instr}"))
+                    .unwrap()[i] = 1.into();
+                for (arg, param) in line
+                    .instruction_literal_args
+                    .iter()
+                    .zip(&self.instructions[instr].params)
+                {
+                    let value = match arg {
+                        Some(LiteralArgument::Label(label)) => {
+                            let position = label_positions.get(label).ok_or_else(|| {
+                                ConversionError::new(line.start, format!("Unknown label {label}."))
+                            })?;
+                            (*position as i64).into()
+                        }
+                        Some(LiteralArgument::Number(n)) => n.clone(),
+                        None => continue,
+                    };
+                    program_constants
+                        .get_mut(&format!("p_instr_{instr}_param_{}", param.name))
+                        .unwrap()[i] = value;
+                }
+            } else {
+                assert!(line.instruction_literal_args.is_empty());
+            }
+        }
+        for (assign_reg, (keys, entries)) in free_value_queries {
+            let query = keys
+                .unwrap_or_else(|| default_keys.clone())
+                .into_iter()
+                .chain(entries)
+                .collect();
+            self.pil.push(witness_column(
+                0,
+                &format!("{assign_reg}_free_value"),
+                Some(FunctionDefinition::Query(
+                    vec!["i".to_string()],
+                    Expression::Tuple(query),
+                )),
+            ));
+        }
+        // Columns with identical values are only declared once, the lookup
+        // uses the first of them in alphabetical order.
+        let mut canonical_names: BTreeMap<_, String> = BTreeMap::new();
+        let mut merged = HashMap::new();
+        for (name, values) in &program_constants {
+            if let Some(canonical) = canonical_names.get(values) {
+                merged.insert(name.clone(), canonical.clone());
+                continue;
+            }
+            canonical_names.insert(values, name.clone());
+            self.pil.push(Statement::PolynomialConstantDefinition(
+                0,
+                name.clone(),
+                // Rows after the end of the program do not execute any instruction.
+                FunctionDefinition::Array(
+                    values.iter().cloned().map(build_number).collect(),
+                    PaddingStrategy::Zero,
+                ),
+            ));
+        }
+        for (_, fixed) in &mut self.line_lookup {
+            if let Some(canonical) = merged.get(fixed) {
+                *fixed = canonical.clone();
+            }
+        }
+        self.merged_columns = merged.len();
+        Ok(program_constants)
+    }
+
+    /// Checks that the conditions of the register updates are boolean and that
+    /// the conditions of updates to the same register exclude each other.
+    /// Conditions that are matched against program columns are checked on the
+    /// program lines, for all other conditions a boolean constraint is added.
+    fn check_update_conditions(
+        &mut self,
+        program: &BTreeMap<String, Vec<AbstractNumberType>>,
+    ) -> Result<(), ConversionError> {
+        let program_column = |cond: &Expression| match cond {
+            Expression::PolynomialReference(r) if !r.next => self
+                .line_lookup
+                .iter()
+                .find(|(witness, _)| *witness == r.name)
+                .and_then(|(_, fixed)| program.get(fixed)),
+            _ => None,
+        };
+        let mut unchecked_conditions = vec![];
+        for (name, register) in &self.registers {
+            let conditions = register
+                .conditioned_updates
+                .iter()
+                .map(|(cond, _)| cond)
+                // "first_step'" is only one on the last row and it is only
+                // used to compute the default condition.
+                .filter(|cond| **cond != next_reference("first_step"))
+                .map(|cond| (cond, program_column(cond)))
+                .collect::<Vec<_>>();
+            for (cond, values) in &conditions {
+                match values {
+                    Some(values) => {
+                        if let Some(i) =
+                            values.iter().position(|v| *v != 0.into() && *v != 1.into())
+                        {
+                            return Err(ConversionError::new(
+                                self.code_lines[i].start,
+                                format!("Update condition {cond} of register {name} is not boolean in this line."),
+                            ));
+                        }
+                    }
+                    None => unchecked_conditions.push((*cond).clone()),
+                }
+            }
+            for (i, (cond1, values1)) in conditions.iter().enumerate() {
+                for (cond2, values2) in &conditions[i + 1..] {
+                    let (Some(values1), Some(values2)) = (values1, values2) else {
+                        continue;
+                    };
+                    if let Some(line) = values1
+                        .iter()
+                        .zip(values2.iter())
+                        .position(|(v1, v2)| !is_zero(v1) && !is_zero(v2))
+                    {
+                        return Err(ConversionError::new(
+                            self.code_lines[line].start,
+                            format!("Register {name} is updated under both {cond1} and {cond2} in the same line."),
+                        ));
+                    }
+                }
+            }
+        }
+        for cond in unchecked_conditions {
+            if !self.pil.iter().any(|s| {
+                matches!(s, Statement::PolynomialIdentity(_, e) if *e == build_boolean_constraint(&cond))
+            }) {
+                self.pil.push(Statement::PolynomialIdentity(
+                    0,
+                    build_boolean_constraint(&cond),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every program constant is matched against a declared witness column
+    /// in the connecting lookup and that every column of the lookup is a program constant.
+    fn check_program_constants_matched(&self) -> Result<(), ConversionError> {
+        for fixed in &self.program_constant_names {
+            if !self.line_lookup.iter().any(|(_, f)| f == fixed) {
+                return Err(ConversionError::new(
+                    0,
+                    format!("Program constant {fixed} is not matched against a witness column."),
+                ));
+            }
+        }
+        for (witness, fixed) in &self.line_lookup {
+            if fixed != "line" && !self.program_constant_names.contains(fixed) {
+                return Err(ConversionError::new(
+                    0,
+                    format!("Witness column {witness} is matched against {fixed}, which is not a program constant."),
+                ));
+            }
+            let declared = self.pil.iter().any(|s| {
+                matches!(s, Statement::PolynomialCommitDeclaration(_, names, _)
+                    if names.iter().any(|n| n.name == *witness))
+            });
+            if !declared {
+                return Err(ConversionError::new(
+                    0,
+                    format!("Program constant {fixed} is matched against {witness}, which is not a witness column."),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn compute_label_positions(&self) -> HashMap<String, usize> {
+        self.code_lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, line)| line.label.as_ref().map(|l| (l.clone(), i)))
+            .collect()
+    }
+
+    /// Creates a pair of witness and fixed column and matches them in the lookup.
+    fn create_witness_fixed_pair(&mut self, start: usize, name: &str) {
+        let fixed_name = format!("p_{name}");
+        self.pil.push(witness_column(start, name, None));
+        self.line_lookup
+            .push((name.to_string(), fixed_name.clone()));
+        self.program_constant_names.push(fixed_name);
+    }
+
+    /// @returns true if `name` is a fixed column of the assembly namespace that can be
+    /// read in assignment values, i.e. the generated column "line" or a fixed column
+    /// declared in inline PIL.
+    fn is_fixed_column(&self, name: &str) -> bool {
+        name == "line" || self.fixed_columns.contains(name)
+    }
+
+    /// Returns the program counter that selects the line that is executed.
+    fn pc_name(&self) -> Option<&String> {
+        self.pc_names.first()
+    }
+
+    fn assignment_registers(&self) -> impl Iterator<Item = &String> {
+        self.assignment_regs.iter()
+    }
+
+    /// Returns the name of the assignment register used in an assignment or instruction
+    /// parameter. If the register is not given explicitly, there has to be
+    /// exactly one assignment register.
+    fn resolve_assignment_register(
+        &self,
+        start: usize,
+        reg: &Option<String>,
+    ) -> Result<String, ConversionError> {
+        match reg {
+            Some(reg) if self.assignment_registers().any(|r| r == reg) => Ok(reg.clone()),
+            Some(reg) => Err(ConversionError::new(
+                start,
+                format!("{reg} is not an assignment register."),
+            )),
+            None => match self.assignment_registers().collect::<Vec<_>>()[..] {
+                [reg] => Ok(reg.clone()),
+                [] => Err(ConversionError::new(
+                    start,
+                    "Implicit assignment register used, but no assignment register declared.",
+                )),
+                _ => Err(ConversionError::new(
+                    start,
+                    "Implicit assignment register used, but there are multiple assignment registers. Please specify one explicitly.",
+                )),
+            },
+        }
+    }
+
+    /// Returns the name of the flag that writes the value of the assignment
+    /// register `assign_reg` to the register `reg`.
+    /// If there is only one assignment register, it is omitted from the name.
+    fn write_flag(&self, assign_reg: &str, reg: &str) -> String {
+        if self.assignment_regs.len() == 1 {
+            format!("reg_write_{reg}")
+        } else {
+            format!("reg_write_{assign_reg}_{reg}")
+        }
+    }
+}
+
+struct Register {
+    /// The offset of the declaration in the source.
+    start: usize,
+    is_pc: bool,
+    /// Constraints to update this register, first item being the
+    /// condition, second item the value.
+    /// TODO check that condition is bool
+    conditioned_updates: Vec<(Expression, Expression)>,
+    default_update: Option<Expression>,
+}
+
+impl Register {
+    /// Returns the expression assigned to this register in the next row.
+    pub fn update_expression(&self) -> Option<Expression> {
+        // TODO conditions need to be all boolean
+        let updates = self
+            .conditioned_updates
+            .iter()
+            .map(|(cond, value)| build_mul(cond.clone(), value.clone()))
+            .reduce(build_add);
+
+        // TODO for computing the default condition, we need to ensure
+        // that the conditions all exclude each other
         match (self.conditioned_updates.len(), &self.default_update) {
             (0, update) => update.clone(),
             (_, None) => Some(updates.unwrap()),
@@ -546,214 +2398,1793 @@ impl Register {
             }
         }
     }
-}
+}
+
+struct Instruction {
+    /// The offset of the declaration in the source.
+    start: usize,
+    params: Vec<InstructionParam>,
+}
+
+#[derive(Default)]
+struct CodeLine {
+    /// The offset of the line in the source.
+    start: usize,
+    /// The registers written to, by the assignment register they are written through.
+    write_regs: BTreeMap<String, Vec<String>>,
+    /// The values read, by the assignment register they are read through.
+    value: BTreeMap<String, Vec<(AbstractNumberType, AffineExpressionComponent)>>,
+    label: Option<String>,
+    instruction: Option<String>,
+    instruction_literal_args: Vec<Option<LiteralArgument>>,
+}
+
+/// An argument to an instruction that is stored in the program.
+enum LiteralArgument {
+    Label(String),
+    Number(AbstractNumberType),
+}
+
+#[derive(PartialEq)]
+enum AffineExpressionComponent {
+    Register(String),
+    /// A column in another namespace, given by namespace and name.
+    Column(String, String),
+    /// A fixed column of the assembly namespace.
+    FixedColumn(String),
+    /// A witness column of the assembly namespace that holds the result of a lookup,
+    /// like the result of a bitwise operation or a comparison.
+    LookupResult(String),
+    Constant,
+    /// A free input, given by channel name, index and the keys of its query, if any.
+    FreeInput(String, Expression, Option<Vec<Expression>>),
+}
+
+/// Splits the expression of a free input `${ ("channel", index) }` into channel name
+/// and index. A free input without channel `${ index }` reads from the default channel.
+fn free_input_channel(
+    start: usize,
+    expr: &Expression,
+) -> Result<(String, Expression), ConversionError> {
+    match expr {
+        Expression::Tuple(items) => match &items[..] {
+            [Expression::String(channel), index] => Ok((channel.clone(), index.clone())),
+            _ => Err(ConversionError::new(
+                start,
+                format!("Free input has to be of the form (\"channel\", index), but got {expr}."),
+            )),
+        },
+        _ => Ok((DEFAULT_INPUT_CHANNEL.to_string(), expr.clone())),
+    }
+}
+
+/// Returns the value of an assignment value that only consists of a constant.
+fn constant_value(
+    value: &[(AbstractNumberType, AffineExpressionComponent)],
+) -> Option<AbstractNumberType> {
+    match value {
+        [] => Some(0.into()),
+        [(coeff, AffineExpressionComponent::Constant)] => Some(coeff.clone()),
+        _ => None,
+    }
+}
+
+/// Returns the PIL expression of an affine value, or None if it reads a free input.
+fn affine_expression(
+    value: &[(AbstractNumberType, AffineExpressionComponent)],
+) -> Option<Expression> {
+    value
+        .iter()
+        .map(|(coeff, component)| {
+            let column = match component {
+                AffineExpressionComponent::Register(name)
+                | AffineExpressionComponent::FixedColumn(name)
+                | AffineExpressionComponent::LookupResult(name) => direct_reference(name),
+                AffineExpressionComponent::Column(namespace, name) => {
+                    namespaced_reference(namespace.clone(), name.clone())
+                }
+                AffineExpressionComponent::Constant => return Some(build_number(coeff.clone())),
+                AffineExpressionComponent::FreeInput(..) => return None,
+            };
+            Some(if *coeff == 1.into() {
+                column
+            } else {
+                build_mul(build_number(coeff.clone()), column)
+            })
+        })
+        .reduce(|left, right| Some(build_add(left?, right?)))
+        .unwrap_or_else(|| Some(build_number(0.into())))
+}
+
+/// Returns the degree declared in the program and whether it is padded, if any.
+/// The degree can be declared multiple times as long as the declarations agree,
+/// but not after the first code line.
+fn find_degree(
+    statements: &[ASMStatement],
+) -> Result<Option<(AbstractNumberType, bool)>, ConversionError> {
+    let mut degree = None;
+    let mut code_started = false;
+    for statement in statements {
+        match statement {
+            ASMStatement::Degree(start, value, padded) => {
+                if code_started {
+                    return Err(ConversionError {
+                        start: *start,
+                        message: "The degree has to be declared before the first code line."
+                            .to_string(),
+                    });
+                }
+                let format = |value, padded| {
+                    if padded {
+                        format!("{value} padded")
+                    } else {
+                        format!("{value}")
+                    }
+                };
+                match &degree {
+                    Some((previous, previous_padded))
+                        if (previous, previous_padded) != (value, padded) =>
+                    {
+                        return Err(ConversionError {
+                            start: *start,
+                            message: format!(
+                                "Conflicting degree declaration: {} (previously declared as {}).",
+                                format(value, *padded),
+                                format(previous, *previous_padded)
+                            ),
+                        })
+                    }
+                    _ => degree = Some((value.clone(), *padded)),
+                }
+            }
+            ASMStatement::Assignment(..)
+            | ASMStatement::Instruction(..)
+            | ASMStatement::Label(..) => code_started = true,
+            ASMStatement::RegisterDeclaration(..)
+            | ASMStatement::InstructionDeclaration(..)
+            | ASMStatement::MacroDefinition(..)
+            | ASMStatement::InlinePil(..)
+            | ASMStatement::Gadget(..)
+            | ASMStatement::BitwiseWidth(..)
+            | ASMStatement::ComparisonWidth(..)
+            | ASMStatement::CoefficientBound(..) => {}
+        }
+    }
+    Ok(degree)
+}
+
+fn witness_column(start: usize, name: &str, def: Option<FunctionDefinition>) -> Statement {
+    Statement::PolynomialCommitDeclaration(
+        start,
+        vec![PolynomialName {
+            name: name.to_string(),
+            array_size: None,
+        }],
+        def,
+    )
+}
+
+fn direct_reference(name: &str) -> Expression {
+    Expression::PolynomialReference(PolynomialReference {
+        namespace: None,
+        name: name.to_owned(),
+        index: None,
+        next: false,
+    })
+}
+
+fn namespaced_reference(namespace: String, name: String) -> Expression {
+    Expression::PolynomialReference(PolynomialReference {
+        namespace: Some(namespace),
+        name,
+        index: None,
+        next: false,
+    })
+}
+
+fn next_reference(name: &str) -> Expression {
+    Expression::PolynomialReference(PolynomialReference {
+        namespace: None,
+        name: name.to_owned(),
+        index: None,
+        next: true,
+    })
+}
+
+/// Returns the expression `cond * (1 - cond)`.
+fn build_boolean_constraint(cond: &Expression) -> Expression {
+    build_mul(
+        cond.clone(),
+        build_sub(build_number(1.into()), cond.clone()),
+    )
+}
+
+fn build_mul(left: Expression, right: Expression) -> Expression {
+    build_binary_expr(left, BinaryOperator::Mul, right)
+}
+
+fn build_sub(left: Expression, right: Expression) -> Expression {
+    build_binary_expr(left, BinaryOperator::Sub, right)
+}
+
+fn build_add(left: Expression, right: Expression) -> Expression {
+    build_binary_expr(left, BinaryOperator::Add, right)
+}
+
+fn build_binary_expr(left: Expression, op: BinaryOperator, right: Expression) -> Expression {
+    Expression::BinaryOperation(Box::new(left), op, Box::new(right))
+}
+
+fn build_unary_expr(op: UnaryOperator, exp: Expression) -> Expression {
+    Expression::UnaryOperation(op, Box::new(exp))
+}
+
+fn build_number(value: AbstractNumberType) -> Expression {
+    Expression::Number(value)
+}
+
+/// Splits an instruction body expression of the form `reg' - value` into the
+/// updated register and the value.
+/// Splits an expression of the form `reg' - expr` into the register name and `expr`.
+/// Other expressions are returned unchanged without register. Next references are allowed
+/// anywhere, except for a reference to `reg'` in the value of the update of `reg`.
+fn extract_update(expr: Expression) -> Result<(Option<String>, Expression), String> {
+    if let Expression::BinaryOperation(left, BinaryOperator::Sub, right) = expr {
+        if let Expression::PolynomialReference(PolynomialReference {
+            namespace,
+            name,
+            index,
+            next: true,
+        }) = *left
+        {
+            if namespace.is_some() || index.is_some() {
+                return Err(format!("Invalid register update: {name}"));
+            }
+            if references_next(&right, &name) {
+                return Err(format!(
+                    "The update of register {name} refers to the next value of {name}: {right}"
+                ));
+            }
+            Ok((Some(name), *right))
+        } else {
+            Ok((None, build_binary_expr(*left, BinaryOperator::Sub, *right)))
+        }
+    } else {
+        Ok((None, expr))
+    }
+}
+
+/// @returns true if `e` contains the next reference `name'`.
+fn references_next(e: &Expression, name: &str) -> bool {
+    match e {
+        Expression::PolynomialReference(r) => r.next && r.namespace.is_none() && r.name == name,
+        Expression::BinaryOperation(left, _, right) => {
+            references_next(left, name) || references_next(right, name)
+        }
+        Expression::UnaryOperation(_, e) => references_next(e, name),
+        Expression::FunctionCall(_, items) | Expression::Tuple(items) => {
+            items.iter().any(|e| references_next(e, name))
+        }
+        Expression::Constant(_)
+        | Expression::PublicReference(_)
+        | Expression::Number(_)
+        | Expression::String(_)
+        | Expression::FreeInput(..) => false,
+    }
+}
+
+/// Adds the names of all polynomials referenced in `e` to `names`.
+fn collect_references<'a>(e: &'a Expression, names: &mut BTreeSet<&'a str>) {
+    match e {
+        Expression::PolynomialReference(r) => {
+            names.insert(&r.name);
+        }
+        Expression::BinaryOperation(left, _, right) => {
+            collect_references(left, names);
+            collect_references(right, names);
+        }
+        Expression::UnaryOperation(_, e) => collect_references(e, names),
+        Expression::FunctionCall(_, items) | Expression::Tuple(items) => {
+            items.iter().for_each(|e| collect_references(e, names))
+        }
+        Expression::Constant(_)
+        | Expression::PublicReference(_)
+        | Expression::Number(_)
+        | Expression::String(_)
+        | Expression::FreeInput(..) => {}
+    }
+}
+
+/// Replaces references to the names in `substitution`. If the replacement is a
+/// reference, the next flag and the index of the original reference are kept.
+fn substitute(input: &Expression, substitution: &HashMap<String, Expression>) -> Expression {
+    match input {
+        // TODO namespace
+        Expression::PolynomialReference(r) => match substitution.get(&r.name) {
+            Some(Expression::PolynomialReference(replacement)) => {
+                Expression::PolynomialReference(PolynomialReference {
+                    name: replacement.name.clone(),
+                    ..r.clone()
+                })
+            }
+            Some(replacement) => replacement.clone(),
+            None => input.clone(),
+        },
+        Expression::BinaryOperation(left, op, right) => build_binary_expr(
+            substitute(left, substitution),
+            *op,
+            substitute(right, substitution),
+        ),
+        Expression::UnaryOperation(op, exp) => build_unary_expr(*op, substitute(exp, substitution)),
+        Expression::FunctionCall(name, args) => Expression::FunctionCall(
+            name.clone(),
+            args.iter().map(|e| substitute(e, substitution)).collect(),
+        ),
+        Expression::Tuple(items) => {
+            Expression::Tuple(items.iter().map(|e| substitute(e, substitution)).collect())
+        }
+        Expression::Constant(_)
+        | Expression::PublicReference(_)
+        | Expression::Number(_)
+        | Expression::String(_)
+        | Expression::FreeInput(..) => input.clone(),
+    }
+}
+
+fn substitute_selected_exprs(
+    input: &SelectedExpressions,
+    substitution: &HashMap<String, Expression>,
+) -> SelectedExpressions {
+    SelectedExpressions {
+        selector: input.selector.as_ref().map(|s| substitute(s, substitution)),
+        expressions: substitute_vec(&input.expressions, substitution),
+    }
+}
+
+fn substitute_vec(
+    input: &[Expression],
+    substitution: &HashMap<String, Expression>,
+) -> Vec<Expression> {
+    input.iter().map(|e| substitute(e, substitution)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{
+        build_number, compile, compile_with_auto_degree, direct_reference, ASMPILConverter,
+    };
+    use crate::number::{clamp, with_field_mod, with_number_format, NumberFormat};
+    use crate::parser;
+    use crate::parser::ast::{Expression, PILFile, Statement};
+
+    /// Returns the PIL followed by the program columns under the name `p_<column>`
+    /// of the witness column they are matched with, also for columns that were
+    /// merged into an identical one.
+    fn with_program_columns(pil: PILFile) -> String {
+        let (left, right) = pil
+            .0
+            .iter()
+            .find_map(|s| match s {
+                Statement::PlookupIdentity(_, left, right)
+                    if right.expressions.first() == Some(&direct_reference("line")) =>
+                {
+                    Some((&left.expressions, &right.expressions))
+                }
+                _ => None,
+            })
+            .unwrap();
+        let mut program_columns = vec![];
+        for (witness, fixed) in left.iter().zip(right) {
+            let (Expression::PolynomialReference(witness), Expression::PolynomialReference(fixed)) =
+                (witness, fixed)
+            else {
+                continue;
+            };
+            program_columns.extend(pil.0.iter().filter_map(|s| match s {
+                Statement::PolynomialConstantDefinition(_, name, definition)
+                    if *name == fixed.name =>
+                {
+                    Some(Statement::PolynomialConstantDefinition(
+                        0,
+                        format!("p_{}", witness.name),
+                        definition.clone(),
+                    ))
+                }
+                _ => None,
+            }));
+        }
+        format!("{pil}{}", PILFile(program_columns))
+    }
+
+    #[test]
+    pub fn compile_simple_sum() {
+        let expectation = r#"
+namespace Assembly(1024);
+pol constant first_step = [1];
+(first_step * pc) = 0;
+pol commit pc;
+pol commit X;
+pol commit reg_write_A;
+(first_step * A) = 0;
+pol commit A;
+pol commit reg_write_CNT;
+(first_step * CNT) = 0;
+pol commit CNT;
+pol commit XInv;
+pol commit XIsZero;
+XIsZero = (1 - (X * XInv));
+(XIsZero * X) = 0;
+(XIsZero * (1 - XIsZero)) = 0;
+pol commit instr_jmpz;
+pol commit instr_jmpz_param_l;
+pol commit instr_jmp;
+pol commit instr_jmp_param_l;
+pol commit instr_dec_CNT;
+pol commit instr_assert_zero;
+(instr_assert_zero * (XIsZero - 1)) = 0;
+pol commit X_const;
+pol commit X_read_free;
+pol commit read_X_A;
+pol commit read_X_CNT;
+X = ((((read_X_A * A) + (read_X_CNT * CNT)) + X_const) + (X_read_free * X_free_value));
+A' = ((reg_write_A * X) + ((1 - (first_step' + reg_write_A)) * A));
+CNT' = (((reg_write_CNT * X) + (instr_dec_CNT * (CNT - 1))) + ((1 - ((first_step' + reg_write_CNT) + instr_dec_CNT)) * CNT));
+pc' = (((instr_jmpz * ((XIsZero * instr_jmpz_param_l) + ((1 - XIsZero) * (pc + 1)))) + (instr_jmp * instr_jmp_param_l)) + ((1 - ((first_step' + instr_jmpz) + instr_jmp)) * (pc + 1)));
+pol constant line(i) { i };
+pol commit X_free_value(i) query (i, pc, (0, ("input", 1)), (3, ("input", (CNT + 1))), (7, ("input", 0)));
+pol constant p_X_const = [0, 0, 0, 0, 0, 0, 0, 0, 0];
+pol constant p_X_read_free = [1, 0, 0, 1, 0, 0, 0, -1, 0];
+pol constant p_instr_assert_zero = [0, 0, 0, 0, 0, 0, 0, 0, 1];
+pol constant p_instr_dec_CNT = [0, 0, 0, 0, 1, 0, 0, 0, 0];
+pol constant p_instr_jmp = [0, 0, 0, 0, 0, 1, 0, 0, 0];
+pol constant p_instr_jmpz = [0, 0, 1, 0, 0, 0, 0, 0, 0];
+pol constant p_instr_jmpz_param_l = [0, 0, 6, 0, 0, 0, 0, 0, 0];
+pol constant p_read_X_A = [0, 0, 0, 1, 0, 0, 0, 1, 1];
+pol constant p_reg_write_A = [0, 0, 0, 1, 0, 0, 0, 1, 0];
+pol constant p_reg_write_CNT = [1, 0, 0, 0, 0, 0, 0, 0, 0];
+{ pc, reg_write_A, reg_write_CNT, instr_jmpz, instr_jmpz_param_l, instr_jmp, instr_jmp_param_l, instr_dec_CNT, instr_assert_zero, X_const, X_read_free, read_X_A, read_X_CNT } in { line, p_reg_write_A, p_reg_write_CNT, p_instr_jmpz, p_instr_jmpz_param_l, p_instr_jmp, p_instr_jmp, p_instr_dec_CNT, p_instr_assert_zero, p_X_const, p_X_read_free, p_read_X_A, p_instr_jmpz };
+"#;
+        let file_name = "tests/simple_sum.asm";
+        let contents = fs::read_to_string(file_name).unwrap();
+        let pil = compile(Some(file_name), &contents).unwrap();
+        assert_eq!(format!("{pil}").trim(), expectation.trim());
+    }
+
+    #[test]
+    pub fn write_conflicts_with_instruction_body() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg B;
+instr pipe <=X= a, r <=X= { B' = X + 1 }
+pipe 1, B;
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "Instruction pipe updates register B in its body and also writes to it through the assignment register."
+        );
+    }
+
+    #[test]
+    pub fn write_to_multiple_registers() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+A, B <=X= 7;
+B <=X= A + 1;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        for line in [
+            "A' = ((reg_write_A * X) + ((1 - (first_step' + reg_write_A)) * A));",
+            "B' = ((reg_write_B * X) + ((1 - (first_step' + reg_write_B)) * B));",
+            "pol constant p_X_const = [7, 1];",
+            "pol constant p_read_X_A = [0, 1];",
+            "pol constant p_reg_write_A = [1, 0];",
+            "pol constant p_reg_write_B = [1, 1];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+    }
+
+    #[test]
+    pub fn tuple_assignment() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg Y[<=];
+reg A;
+reg B;
+A, B <== (7, A + 1);
+A, B <= (B, A);
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "pol constant p_X_const = [7, 0];",
+            "pol constant p_Y_const = [1, 0];",
+            "pol constant p_read_X_B = [0, 1];",
+            "pol constant p_read_Y_A = [1, 1];",
+            "pol constant p_reg_write_X_A = [1, 1];",
+            "pol constant p_reg_write_Y_B = [1, 1];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+
+        for (line, error) in [
+            (
+                "A, B <== (1, 2, 3);",
+                "Cannot assign a tuple of 3 values to 2 registers.",
+            ),
+            (
+                "A, B <=X= (1, 2);",
+                "Tuples are assigned through all assignment registers, but X is given explicitly.",
+            ),
+            (
+                "A <== (1, (2, 3));",
+                "Cannot assign a tuple of 2 values to 1 registers.",
+            ),
+            (
+                "A, B <== (1, (2, 3));",
+                "Expression not supported in assignment value: (2, 3)",
+            ),
+        ] {
+            let source = format!("reg pc[@pc];\nreg X[<=];\nreg Y[<=];\nreg A;\nreg B;\n{line}");
+            assert_eq!(compile(None, &source).unwrap_err().message(), error);
+        }
+        let source = "reg pc[@pc];\nreg X[<=];\nreg A;\nreg B;\nA, B <== (1, 2);";
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "Assigning a tuple of 2 values needs as many assignment registers, but only 1 are declared."
+        );
+    }
+
+    #[test]
+    pub fn implicit_assignment_register() {
+        let explicit = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr assert_zero <=X= a { X = 0 }
+A <=X= A + 1;
+A <=X= A + 2;
+assert_zero A;
+"#;
+        let implicit = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr assert_zero <== a { X = 0 }
+A <== A + 1;
+A <= A + 2;
+assert_zero A;
+"#;
+        assert_eq!(
+            format!("{}", compile(None, implicit).unwrap()),
+            format!("{}", compile(None, explicit).unwrap())
+        );
+    }
+
+    #[test]
+    pub fn combine_like_components() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+B <=X= 2 * A + 3 * A + 1 - 1;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        for line in [
+            "pol constant p_X_const = [0];",
+            "pol constant p_read_X_A = [5];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+    }
+
+    #[test]
+    pub fn division_by_constant() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+B <=X= A / 4 + 7 % 4;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        for line in [
+            "pol constant p_X_const = [3];",
+            "pol constant p_read_X_A = [13835058052060938241];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+    }
+
+    #[test]
+    pub fn namespaced_reference() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+pil{
+    namespace Bus(8);
+    col witness value;
+    value' = value + 1;
+}
+A <=X= Bus.value + 2;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        assert!(pil.contains("(read_X_Bus_value * Bus.value)"));
+        assert!(pil.contains("pol constant p_read_X_Bus_value = [1];"));
+        // The assembly namespace is re-opened after the inline PIL.
+        assert!(pil.contains("value' = (value + 1);\nnamespace Assembly(1024);"));
+        let analyzed = crate::analyzer::analyze_string(&pil);
+        assert!(analyzed
+            .definitions
+            .contains_key("Assembly.read_X_Bus_value"));
+
+        let unknown_namespace = source.replace("Bus.value + 2", "Other.value + 2");
+        assert_eq!(
+            compile(None, &unknown_namespace).unwrap_err().message(),
+            "Unknown namespace Other in Other.value."
+        );
+        let unknown_column = source.replace("Bus.value + 2", "Bus.other + 2");
+        assert_eq!(
+            compile(None, &unknown_column).unwrap_err().message(),
+            "Column other is not declared in namespace Bus."
+        );
+    }
+
+    #[test]
+    pub fn read_fixed_column() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+pil{
+    pol constant SQUARES(i) { i * i };
+}
+A <=X= line + 2 * SQUARES;
+A <=X= A - line;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        for line in [
+            "X = (((((read_X_A * A) + (X_read_fixed_SQUARES * SQUARES)) + (X_read_fixed_line * line)) + X_const) + (X_read_free * X_free_value));",
+            "pol constant p_X_read_fixed_SQUARES = [2, 0];",
+            "pol constant p_X_read_fixed_line = [1, -1];",
+            "pol constant p_read_X_A = [0, 1];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+    }
+
+    #[test]
+    pub fn read_pc() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=X= pc + 1;
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "The program counter pc can only be read in an assignment value if it is declared with [@pc_readable]."
+        );
+        let source = source.replace("@pc", "@pc_readable");
+        let pil = with_program_columns(compile(None, &source).unwrap());
+        for line in [
+            "X = (((read_X_pc * pc) + X_const) + (X_read_free * X_free_value));",
+            "pol constant p_read_X_pc = [1];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+    }
+
+    #[test]
+    pub fn write_pc() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr jmp l: label { pc' = l }
+pc <=X= A + 2;
+jmp end;
+end::
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "pc' = (((instr_jmp * instr_jmp_param_l) + (reg_write_pc * X)) + ((1 - ((first_step' + instr_jmp) + reg_write_pc)) * (pc + 1)));",
+            "pol constant p_reg_write_pc = [1, 0, 0];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+        assert_eq!(
+            compile(None, &source.replace("pc <=X=", "X <=X="))
+                .unwrap_err()
+                .message(),
+            "Cannot assign to the special register X."
+        );
+    }
+
+    #[test]
+    pub fn bitwise() {
+        let source = r#"
+degree 65536;
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+A <=X= 0x34;
+B <=X= A & 0xff;
+B <=X= (1 | 2 ^ 7) + B;
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "pol constant BITWISE8_AND(i) { ((i & 255) & ((i >> 8) & 255)) };",
+            "bitwise_0_active { (A + (256 * 255)), A, 255, bitwise_0 } in { BITWISE8_KEY, BITWISE8_A, BITWISE8_B, BITWISE8_AND };",
+            // A is only read by the bitwise operation, not through X.
+            "X = ((((read_X_B * B) + (X_read_bitwise_0 * bitwise_0)) + X_const) + (X_read_free * X_free_value));",
+            "pol constant p_bitwise_0_active = [0, 1, 0];",
+            // `^` binds stronger than `|`.
+            "pol constant p_X_const = [52, 0, 5];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+        assert!(!pil.contains("BITWISE8_OR"));
+        let xor = compile(None, &source.replace("A & 0xff", "A ^ B"))
+            .unwrap()
+            .to_string();
+        assert!(xor.contains(
+            "bitwise_0_active { (A + (256 * B)), A, B, bitwise_0 } in { BITWISE8_KEY, BITWISE8_A, BITWISE8_B, BITWISE8_XOR };"
+        ));
+
+        let error = |source: &str| compile(None, source).unwrap_err().message().to_string();
+        assert_eq!(
+            error(&source.replace("65536", "65535")),
+            "The lookup tables of bitwise operations on 8 bits have 65536 rows, which does not fit the degree 65535."
+        );
+        assert_eq!(
+            error(&source.replace("A & 0xff", "A & ${ (\"input\", 0) }")),
+            "The operands of & cannot contain free inputs."
+        );
+        assert_eq!(
+            error(&format!("{source}bitwise_width 4;")),
+            "The bitwise width has to be declared before the first bitwise operation."
+        );
+        assert_eq!(
+            error(&format!("bitwise_width 12;{source}")),
+            "The bitwise width has to be between 1 and 8 or a multiple of 8 up to 32, but got 12."
+        );
+        assert_eq!(
+            error(&format!("bitwise_width 40;{source}")),
+            "The bitwise width has to be between 1 and 8 or a multiple of 8 up to 32, but got 40."
+        );
+        assert!(compile(
+            None,
+            &format!("bitwise_width 4;{}", source.replace("65536", "256"))
+        )
+        .unwrap()
+        .to_string()
+        .contains("in { BITWISE4_KEY, BITWISE4_A, BITWISE4_B, BITWISE4_AND };"));
+
+        // Wider operands are split into bytes.
+        let pil = compile(None, &format!("bitwise_width 16;{source}"))
+            .unwrap()
+            .to_string();
+        for line in [
+            "pol constant BITWISE8_AND(i) { ((i & 255) & ((i >> 8) & 255)) };",
+            "(bitwise_0_active * (A - (bitwise_0_a_0 + (256 * bitwise_0_a_1)))) = 0;",
+            "(bitwise_0_active * (255 - (bitwise_0_b_0 + (256 * bitwise_0_b_1)))) = 0;",
+            "(bitwise_0_active * (bitwise_0 - (bitwise_0_r_0 + (256 * bitwise_0_r_1)))) = 0;",
+            "bitwise_0_active { (bitwise_0_a_0 + (256 * bitwise_0_b_0)), bitwise_0_a_0, bitwise_0_b_0, bitwise_0_r_0 } in { BITWISE8_KEY, BITWISE8_A, BITWISE8_B, BITWISE8_AND };",
+            "bitwise_0_active { (bitwise_0_a_1 + (256 * bitwise_0_b_1)), bitwise_0_a_1, bitwise_0_b_1, bitwise_0_r_1 } in { BITWISE8_KEY, BITWISE8_A, BITWISE8_B, BITWISE8_AND };",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+    }
+
+    #[test]
+    pub fn comparison() {
+        let source = r#"
+degree 512;
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+A <=X= 3 < 4;
+B <=X= A <= B;
+B <=X= A != 0;
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "pol constant BIT8_INPUT(i) { (i & 511) };",
+            "comparison_0_active { ((B - A) + 256), (1 - comparison_0) } in { BIT8_INPUT, BIT8 };",
+            "comparison_1_diff = (comparison_1_active * A);",
+            "comparison_1 = (1 - (comparison_1_diff * comparison_1_inv));",
+            "(comparison_1 * comparison_1_diff) = 0;",
+            "pol constant p_comparison_0_active = [0, 1, 0];",
+            "pol constant p_X_const = [1, 1, 1];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+
+        let error = |source: &str| compile(None, source).unwrap_err().message().to_string();
+        assert_eq!(
+            error(&source.replace("512", "511")),
+            "The lookup tables of comparisons on 8 bits have 512 rows, which does not fit the degree 511."
+        );
+        assert_eq!(
+            error(&source.replace("A <= B", "A <= ${ (\"input\", 0) }")),
+            "The operands of <= cannot contain free inputs."
+        );
+        assert_eq!(
+            error(&format!("{source}comparison_width 4;")),
+            "The comparison width has to be declared before the first comparison."
+        );
+        assert_eq!(
+            error(&format!("comparison_width 33;{source}")),
+            "The comparison width has to be between 1 and 32, but got 33."
+        );
+        assert!(error("reg pc[@pc]; reg X[<=]; reg A; A <=X= 1 < 2 < 3;")
+            .contains("Unrecognized token"));
+        assert!(compile(
+            None,
+            &format!("comparison_width 4;{}", source.replace("512", "32"))
+        )
+        .unwrap()
+        .to_string()
+        .contains("in { BIT4_INPUT, BIT4 };"));
+    }
+
+    #[test]
+    pub fn named_constants() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+pil{
+    constant %OFFSET = 7;
+    constant %SCALE = 2 * %OFFSET - 11;
+}
+A <=X= A + %OFFSET;
+A <=X= %SCALE * A - %OFFSET;
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "constant %SCALE = ((2 * %OFFSET) - 11);",
+            "X = (((read_X_A * A) + X_const) + (X_read_free * X_free_value));",
+            "pol constant p_X_const = [7, -7];",
+            "pol constant p_read_X_A = [1, 3];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+        assert_eq!(
+            compile(None, &source.replace("%OFFSET;", "%OFFST;"))
+                .unwrap_err()
+                .message(),
+            "Unknown constant %OFFST in assignment value, constants have to be defined in inline PIL before they are used."
+        );
+    }
+
+    #[test]
+    pub fn local_columns() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr jmpnz <=X= c, l: label { col witness XInv, X * (1 - X * XInv) = 0, pc' = X * XInv * l + (1 - X * XInv) * (pc + 1) }
+instr double <=X= a { col witness T, T = 2 * X, A' = T }
+start::
+jmpnz A, start;
+"#;
+        let pil = compile(None, source).unwrap().to_string();
+        for line in [
+            "pol commit instr_jmpnz_XInv;",
+            "(instr_jmpnz * (X * (1 - (X * instr_jmpnz_XInv)))) = 0;",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+        assert!(!pil.contains("instr_double_T"), "{pil}");
+        assert!(pil.contains("pol commit instr_double;"));
+
+        let pil = compile(None, &format!("{source}double A;"))
+            .unwrap()
+            .to_string();
+        for line in [
+            "pol commit instr_double_T;",
+            "(instr_double * (instr_double_T - (2 * X))) = 0;",
+            "A' = (((reg_write_A * X) + (instr_double * instr_double_T)) + ",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+
+        assert_eq!(
+            compile(None, &source.replace("col witness XInv", "col witness A"))
+                .unwrap_err()
+                .message(),
+            "Local column A of jmpnz is already declared."
+        );
+    }
+
+    #[test]
+    pub fn number_formats() {
+        let source = fs::read_to_string("tests/simple_sum.asm").unwrap();
+        let compile_as =
+            |format| with_number_format(format, || compile(None, &source).unwrap().to_string());
+        let signed = compile_as(NumberFormat::Signed);
+        let unsigned = compile_as(NumberFormat::Unsigned);
+        assert!(signed.contains("pol constant p_X_read_free = [1, 0, 0, 1, 0, 0, 0, -1, 0];"));
+        assert!(unsigned.contains(
+            "pol constant p_X_read_free = [1, 0, 0, 1, 0, 0, 0, 18446744069414584320, 0];"
+        ));
+        // Both forms are parsed to the same field elements.
+        let constants = |pil: &str| {
+            let analyzed = crate::analyzer::analyze_string(pil);
+            crate::constant_evaluator::generate(&analyzed)
+                .0
+                .into_iter()
+                .map(|(name, values)| {
+                    (
+                        name.to_string(),
+                        values.into_iter().map(clamp).collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(constants(&signed), constants(&unsigned));
+    }
+
+    #[test]
+    pub fn instruction_macros() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+macro add_const(c) { A' = A + c }
+macro add_twice(c) { add_const(2 * c) }
+instr add1 { add_const(1) }
+instr add6 { add_twice(3) }
+add1;
+add6;
+"#;
+        let pil = compile(None, source).unwrap().to_string();
+        let line = "A' = ((((reg_write_A * X) + (instr_add1 * (A + 1))) + (instr_add6 * (A + (2 * 3)))) + ((1 - (((first_step' + reg_write_A) + instr_add1) + instr_add6)) * A));";
+        assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+
+        let error = |source: &str| compile(None, source).unwrap_err().message().to_string();
+        assert_eq!(
+            error(&source.replace("add_const(1)", "add_const(1, 2)")),
+            "Macro add_const expects 1 arguments, but got 2."
+        );
+        assert_eq!(
+            error(&source.replace("add_const(1)", "add_three(1)")),
+            "Unknown macro add_three."
+        );
+        assert_eq!(
+            error(&source.replace("{ A' = A + c }", "{ add_twice(c) }")),
+            "Recursive expansion of macro add_const: add_const -> add_twice -> add_const."
+        );
+        assert_eq!(
+            error(&source.replace("macro add_twice", "macro add_const")),
+            "Macro add_const is already declared."
+        );
+    }
+
+    #[test]
+    pub fn multiple_pcs() {
+        let source = r#"
+reg pc[@pc];
+reg co[@pc];
+reg X[<=];
+reg A;
+instr start l: label { co' = l }
+instr yield { pc' = co, co' = pc + 1 }
+start end;
+yield;
+end::
+A <=X= co;
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "The program counter co can only be read in an assignment value if it is declared with [@pc_readable]."
+        );
+        let source = source.replace("reg co[@pc]", "reg co[@pc_readable]");
+        let pil = format!("{}", compile(None, &source).unwrap());
+        for line in [
+            // Only the first program counter is incremented by default.
+            "co' = (((instr_start * instr_start_param_l) + (instr_yield * (pc + 1))) + ((1 - ((first_step' + instr_start) + instr_yield)) * co));",
+            "pc' = ((instr_yield * co) + ((1 - (first_step' + instr_yield)) * (pc + 1)));",
+            "{ co } in { line };",
+            "X = (((read_X_co * co) + X_const) + (X_read_free * X_free_value));",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+        // Only the first program counter selects the executed line.
+        assert!(pil.contains("{ pc, reg_write_A, instr_start, "));
+    }
+
+    #[test]
+    pub fn gadgets() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+gadget is_zero A;
+gadget lt A, B, 4;
+gadget sign B, 4;
+gadget lt B, A, 4;
+A <=X= 3;
+B <=X= A + 2;
+"#;
+        let expectation = r#"
+namespace Assembly(1024);
+pol constant first_step = [1];
+(first_step * pc) = 0;
+pol commit pc;
+pol commit X;
+pol commit reg_write_A;
+(first_step * A) = 0;
+pol commit A;
+pol commit reg_write_B;
+(first_step * B) = 0;
+pol commit B;
+pol commit AInv;
+pol commit AIsZero;
+AIsZero = (1 - (A * AInv));
+(AIsZero * A) = 0;
+pol constant BIT4_INPUT(i) { (i & 31) };
+pol constant BIT4(i) { ((i >> 4) & 1) };
+pol commit ALtB;
+{ ((A - B) + 16), (1 - ALtB) } in { BIT4_INPUT, BIT4 };
+pol constant BIT3_INPUT(i) { (i & 15) };
+pol constant BIT3(i) { ((i >> 3) & 1) };
+pol commit BSign;
+{ B, BSign } in { BIT3_INPUT, BIT3 };
+pol commit BLtA;
+{ ((B - A) + 16), (1 - BLtA) } in { BIT4_INPUT, BIT4 };
+pol commit X_const;
+pol commit X_read_free;
+pol commit read_X_A;
+X = (((read_X_A * A) + X_const) + (X_read_free * X_free_value));
+A' = ((reg_write_A * X) + ((1 - (first_step' + reg_write_A)) * A));
+B' = ((reg_write_B * X) + ((1 - (first_step' + reg_write_B)) * B));
+pc' = ((1 - first_step') * (pc + 1));
+pol constant line(i) { i };
+pol commit X_free_value(i) query (i, pc);
+pol constant p_X_const = [3, 2];
+pol constant p_X_read_free = [0, 0];
+pol constant p_read_X_A = [0, 1];
+pol constant p_reg_write_A = [1, 0];
+{ pc, reg_write_A, reg_write_B, X_const, X_read_free, read_X_A } in { line, p_reg_write_A, p_read_X_A, p_X_const, p_X_read_free, p_read_X_A };
+"#;
+        let pil = compile(None, source).unwrap();
+        assert_eq!(format!("{pil}").trim(), expectation.trim());
+    }
+
+    #[test]
+    pub fn unknown_gadget() {
+        let source = r#"
+reg pc[@pc];
+gadget lt pc;
+gadget max pc;
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "Gadget lt expects the arguments a, b, bits."
+        );
+        let source = source.replace("gadget lt pc;\n", "");
+        assert_eq!(
+            compile(None, &source).unwrap_err().message(),
+            "Unknown gadget max, available gadgets are: is_zero, lt, sign."
+        );
+        let source = source.replace("max pc", "sign pc, 65");
+        assert_eq!(
+            compile(None, &source).unwrap_err().message(),
+            "Expected a number of bits between 1 and 64 as gadget argument, but got 65."
+        );
+    }
+
+    #[test]
+    pub fn constant_power() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=X= 2 ** 3 + 1;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        assert!(pil.contains("pol constant p_X_const = [9];"));
+
+        let register_base = source.replace("2 ** 3", "A ** 3");
+        assert_eq!(
+            compile(None, &register_base).unwrap_err().message(),
+            "Exponentiation is only supported if base and exponent are constant, but got (A ** 3)."
+        );
+        let negative_exponent = source.replace("2 ** 3", "2 ** (0 - 1)");
+        assert_eq!(
+            compile(None, &negative_exponent).unwrap_err().message(),
+            "Negative exponent -1 in (2 ** (0 - 1))."
+        );
+    }
+
+    #[test]
+    pub fn division_by_register() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+B <=X= 8 / A;
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "Division is only supported with a constant divisor, but got A."
+        );
+    }
+
+    #[test]
+    pub fn constant_shifts() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+B <=X= A << 3;
+B <=X= (1 << 4) + (0x35 >> 2);
+B <=X= A >> 3;
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "pol constant p_read_X_A = [8, 0, 16140901060737761281];",
+            "pol constant p_X_const = [0, 29, 0];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+        assert_eq!(
+            compile(None, &source.replace("A << 3", "3 << A"))
+                .unwrap_err()
+                .message(),
+            "<< is only supported with a constant shift amount, but got A."
+        );
+        assert_eq!(
+            compile(None, &source.replace("A >> 3", "A >> 64"))
+                .unwrap_err()
+                .message(),
+            "The shift amount has to be between 0 and 63, but got 64."
+        );
+    }
+
+    #[test]
+    pub fn degree_declaration() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+degree 256;
+reg A;
+degree 256;
+A <=X= 1;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        assert!(pil.starts_with("namespace Assembly(256);"));
+    }
+
+    #[test]
+    pub fn conflicting_degree_declarations() {
+        let source = r#"
+degree 256;
+reg pc[@pc];
+degree 512;
+"#;
+        let err = compile(None, source).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Conflicting degree declaration: 512 (previously declared as 256)."
+        );
+    }
+
+    #[test]
+    pub fn padded_degree() {
+        let source = r#"
+degree 5 padded;
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=X= 1;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        assert!(pil.starts_with("constant %LOGICAL_ROWS = 5;\nnamespace Assembly(8);"));
+        let analyzed = crate::analyzer::analyze_string(&pil);
+        let (constants, degree) = crate::constant_evaluator::generate(&analyzed);
+        assert_eq!(degree, 8);
+        assert!(constants.iter().all(|(_, values)| values.len() == 8));
+
+        // The program has to fit the declared number of rows.
+        let too_long = source.to_string() + "A <=X= 2;\n".repeat(5).as_str();
+        assert_eq!(
+            compile(None, &too_long).unwrap_err().message(),
+            "The program has 6 lines, which does not fit the degree 5. \
+             Declare a larger degree, like `degree 8;`, or use --auto-degree."
+        );
+
+        let conflicting = source.replace("reg A;", "reg A;\ndegree 5;");
+        assert_eq!(
+            compile(None, &conflicting).unwrap_err().message(),
+            "Conflicting degree declaration: 5 (previously declared as 5 padded)."
+        );
+    }
+
+    #[test]
+    pub fn lookup_with_selector() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg Y[<=];
+instr square_if c, <=X= a, b <=Y= { c { X, Y } in { SQUARE_IN, SQUARE } }
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        assert!(pil.contains(
+            "(instr_square_if * instr_square_if_param_c) { X, Y } in { SQUARE_IN, SQUARE };"
+        ));
+    }
+
+    #[test]
+    pub fn degree_after_code() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=X= 1;
+degree 256;
+"#;
+        let err = compile(None, source).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "The degree has to be declared before the first code line."
+        );
+    }
 
-struct Instruction {
-    params: Vec<InstructionParam>,
-}
+    #[test]
+    pub fn auto_degree() {
+        let source = r#"
+degree 4;
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=X= 1;
+A <=X= 2;
+A <=X= 3;
+A <=X= 4;
+A <=X= 5;
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "The program has 5 lines, which does not fit the degree 4. \
+             Declare a larger degree, like `degree 8;`, or use --auto-degree."
+        );
+        let pil = format!(
+            "{}",
+            compile_with_auto_degree(None, source, true, false).unwrap()
+        );
+        assert!(pil.starts_with("namespace Assembly(8);"));
 
-#[derive(Default)]
-struct CodeLine {
-    write_reg: Option<String>,
-    value: Vec<(AbstractNumberType, AffineExpressionComponent)>,
-    label: Option<String>,
-    instruction: Option<String>,
-    // TODO we only support labels for now.
-    instruction_literal_args: Vec<Option<String>>,
-}
+        // Without a declaration, the program has to fit the default degree.
+        let too_long = source.replace("degree 4;", "") + "A <=X= 6;\n".repeat(1020).as_str();
+        assert_eq!(
+            compile(None, &too_long).unwrap_err().message(),
+            "The program has 1025 lines, which does not fit the degree 1024. \
+             Declare a larger degree, like `degree 2048;`, or use --auto-degree."
+        );
+    }
 
-enum AffineExpressionComponent {
-    Register(String),
-    Constant,
-    FreeInput(Expression),
-}
+    #[test]
+    pub fn free_input_channels() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=X= ${ ("stdin", 0) };
+A <=X= ${ 1 };
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        let query = r#"query (i, pc, (0, ("stdin", 0)), (1, ("input", 1)))"#;
+        assert!(pil.contains(query), "Query {query} not found in:\n{pil}");
+    }
 
-fn witness_column(start: usize, name: &str, def: Option<FunctionDefinition>) -> Statement {
-    Statement::PolynomialCommitDeclaration(
-        start,
-        vec![PolynomialName {
-            name: name.to_string(),
-            array_size: None,
-        }],
-        def,
-    )
-}
+    #[test]
+    pub fn free_input_query_keys() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+A <=X= 9;
+B <=X= ${ ("sqrt", 0); A, B };
+B <=X= ${ ("sqrt", 1); A, B };
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        let query = r#"query (A, B, (1, ("sqrt", 0)), (2, ("sqrt", 1)))"#;
+        assert!(pil.contains(query), "Query {query} not found in:\n{pil}");
 
-fn direct_reference(name: &str) -> Expression {
-    Expression::PolynomialReference(PolynomialReference {
-        namespace: None,
-        name: name.to_owned(),
-        index: None,
-        next: false,
-    })
-}
+        let mixed = source.replace("${ (\"sqrt\", 1); A, B }", "${ (\"sqrt\", 1) }");
+        assert_eq!(
+            compile(None, &mixed).unwrap_err().message(),
+            "Free inputs read through X have to use the same query keys, but got (A, B) and (i, pc)."
+        );
+    }
 
-fn next_reference(name: &str) -> Expression {
-    Expression::PolynomialReference(PolynomialReference {
-        namespace: None,
-        name: name.to_owned(),
-        index: None,
-        next: true,
-    })
-}
+    #[test]
+    pub fn free_input_without_channel_name() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=X= ${ (0, 1) };
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "Free input has to be of the form (\"channel\", index), but got (0, 1)."
+        );
+    }
 
-fn build_mul(left: Expression, right: Expression) -> Expression {
-    build_binary_expr(left, BinaryOperator::Mul, right)
-}
+    #[test]
+    pub fn next_references_in_instruction_body() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+instr incr { A' = A + 1, B' = 2 * A', { A' } in { line } }
+incr;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        for line in [
+            "B' = (((reg_write_B * X) + (instr_incr * (2 * A'))) + ((1 - ((first_step' + reg_write_B) + instr_incr)) * B));",
+            "instr_incr { A' } in { line };",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
+    }
 
-fn build_sub(left: Expression, right: Expression) -> Expression {
-    build_binary_expr(left, BinaryOperator::Sub, right)
-}
+    #[test]
+    pub fn conflicting_register_updates() {
+        let twice = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr incr { A' = A + 1, A' = A + 2 }
+"#;
+        assert_eq!(
+            compile(None, twice).unwrap_err().message(),
+            "Instruction incr updates register A more than once."
+        );
+        let circular = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr incr { A' = A' + 1 }
+"#;
+        assert_eq!(
+            compile(None, circular).unwrap_err().message(),
+            "The update of register A refers to the next value of A: (A' + 1)"
+        );
+    }
 
-fn build_add(left: Expression, right: Expression) -> Expression {
-    build_binary_expr(left, BinaryOperator::Add, right)
-}
+    #[test]
+    pub fn unknown_assignment_register() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+A <=Y= 7;
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "Y is not an assignment register."
+        );
+    }
 
-fn build_binary_expr(left: Expression, op: BinaryOperator, right: Expression) -> Expression {
-    Expression::BinaryOperation(Box::new(left), op, Box::new(right))
-}
+    #[test]
+    pub fn return_address_before_pc() {
+        let source = r#"
+reg X[<=];
+reg RA[@ret];
+reg pc[@pc];
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "The program counter has to be declared before the return address register."
+        );
+    }
 
-fn build_unary_expr(op: UnaryOperator, exp: Expression) -> Expression {
-    Expression::UnaryOperation(op, Box::new(exp))
-}
+    #[test]
+    pub fn in_out_parameter() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg Y[<=];
+reg Z[<=];
+reg A;
+instr acc <=X= r <=Z=, <=Y= v { Z = X + Y }
+acc A, 2;
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            // A is read through X and only written through Z.
+            "pol constant p_read_X_A = [1];",
+            "pol constant p_reg_write_X_A = [0];",
+            "pol constant p_reg_write_Z_A = [1];",
+            "pol constant p_Y_const = [2];",
+            "pol constant p_Z_read_free = [1];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
+        }
 
-fn build_number(value: AbstractNumberType) -> Expression {
-    Expression::Number(value)
-}
+        let same_register = source.replace("<=X= r <=Z=", "<=X= r <=X=");
+        assert_eq!(
+            compile(None, &same_register).unwrap_err().message(),
+            "In/out parameter r of acc reads and writes through the same assignment register X."
+        );
+        let not_a_register = source.replace("acc A, 2;", "acc 7, 2;");
+        assert_eq!(
+            compile(None, &not_a_register).unwrap_err().message(),
+            "Expected a register as in/out argument r to acc, but got 7."
+        );
+    }
 
-fn extract_update(expr: Expression) -> (Option<String>, Expression) {
-    // TODO check that there are no other "next" references in the expression
-    if let Expression::BinaryOperation(left, BinaryOperator::Sub, right) = expr {
-        if let Expression::PolynomialReference(PolynomialReference {
-            namespace,
-            name,
-            index,
-            next: true,
-        }) = *left
-        {
-            assert_eq!(namespace, None);
-            assert_eq!(index, None);
-            (Some(name), *right)
-        } else {
-            (None, build_binary_expr(*left, BinaryOperator::Sub, *right))
+    #[test]
+    pub fn logical_not() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+instr jmpnz <=X= c, l: label { pc' = !X * l + X * (pc + 1) }
+start::
+A <=X= !B;
+jmpnz A, start;
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "pc' = ((instr_jmpnz * ((!X * instr_jmpnz_param_l) + (X * (pc + 1)))) + ((1 - (first_step' + instr_jmpnz)) * (pc + 1)));",
+            "pol constant p_X_const = [0, 1, 0];",
+            "pol constant p_read_X_B = [0, -1, 0];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
         }
-    } else {
-        (None, expr)
+
+        let not_boolean = source.replace("!B", "!(1 + 1)");
+        assert_eq!(
+            compile(None, &not_boolean).unwrap_err().message(),
+            "The operand of ! has to be 0 or 1, but (1 + 1) is 2."
+        );
     }
-}
 
-fn substitute(input: &Expression, substitution: &HashMap<String, String>) -> Expression {
-    match input {
-        // TODO namespace
-        Expression::PolynomialReference(r) => {
-            Expression::PolynomialReference(PolynomialReference {
-                name: substitute_string(&r.name, substitution),
-                ..r.clone()
-            })
-        }
-        Expression::BinaryOperation(left, op, right) => build_binary_expr(
-            substitute(left, substitution),
-            *op,
-            substitute(right, substitution),
-        ),
-        Expression::UnaryOperation(op, exp) => build_unary_expr(*op, substitute(exp, substitution)),
-        Expression::FunctionCall(name, args) => Expression::FunctionCall(
-            name.clone(),
-            args.iter().map(|e| substitute(e, substitution)).collect(),
-        ),
-        Expression::Tuple(items) => {
-            Expression::Tuple(items.iter().map(|e| substitute(e, substitution)).collect())
+    #[test]
+    pub fn multiple_outputs() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg Y[<=];
+reg Z[<=];
+reg A;
+reg Q;
+reg R;
+instr divmod <=X= a, q <=Y=, r <=Z= { X = Y * 4 + Z }
+A <=X= 11;
+divmod A, Q, R;
+"#;
+        let pil = with_program_columns(compile(None, source).unwrap());
+        for line in [
+            "Q' = ((((reg_write_X_Q * X) + (reg_write_Y_Q * Y)) + (reg_write_Z_Q * Z)) + ((1 - (((first_step' + reg_write_X_Q) + reg_write_Y_Q) + reg_write_Z_Q)) * Q));",
+            "pol constant p_read_X_A = [0, 1];",
+            "pol constant p_reg_write_X_A = [1, 0];",
+            "pol constant p_reg_write_Y_Q = [0, 1];",
+            "pol constant p_reg_write_Z_R = [0, 1];",
+            "pol constant p_Y_read_free = [0, 1];",
+            "pol constant p_Z_read_free = [0, 1];",
+        ] {
+            assert!(pil.contains(line), "Line {line} not found in:\n{pil}");
         }
-        Expression::Constant(_)
-        | Expression::PublicReference(_)
-        | Expression::Number(_)
-        | Expression::String(_)
-        | Expression::FreeInput(_) => input.clone(),
+
+        let wrong_arity = source.replace("divmod A, Q, R;", "divmod A, Q;");
+        assert_eq!(
+            compile(None, &wrong_arity).unwrap_err().message(),
+            "Instruction divmod expects 3 arguments, but got 2."
+        );
+        let same_output = source.replace("divmod A, Q, R;", "divmod A, Q, Q;");
+        assert_eq!(
+            compile(None, &same_output).unwrap_err().message(),
+            "Register Q is assigned to more than once in the same line."
+        );
     }
-}
 
-fn substitute_selected_exprs(
-    input: &SelectedExpressions,
-    substitution: &HashMap<String, String>,
-) -> SelectedExpressions {
-    SelectedExpressions {
-        selector: input.selector.as_ref().map(|s| substitute(s, substitution)),
-        expressions: substitute_vec(&input.expressions, substitution),
+    #[test]
+    pub fn assignment_registers_first() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg Y[<=];
+"#;
+        assert_eq!(
+            compile(None, source).unwrap_err().message(),
+            "The assignment registers have to be declared before the other registers."
+        );
     }
-}
 
-fn substitute_vec(input: &[Expression], substitution: &HashMap<String, String>) -> Vec<Expression> {
-    input.iter().map(|e| substitute(e, substitution)).collect()
-}
+    #[test]
+    pub fn immediate_argument() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr addi r <=X=, i { X = A + i }
+A <=X= 3;
+addi A, 5;
+addi A, 0x10;
+"#;
+        let pil = format!("{}", compile(None, source).unwrap());
+        assert!(pil.contains("(instr_addi * (X - (A + instr_addi_param_i))) = 0;"));
+        assert!(pil.contains("pol constant p_instr_addi_param_i = [0, 5, 16];"));
 
-fn substitute_string(input: &str, substitution: &HashMap<String, String>) -> String {
-    substitution
-        .get(input)
-        .cloned()
-        .unwrap_or_else(|| input.to_string())
-}
+        let wrong_argument = source.replace("addi A, 5;", "addi A, A;");
+        assert_eq!(
+            compile(None, &wrong_argument).unwrap_err().message(),
+            "Expected a number as argument i to addi, but got A."
+        );
+    }
 
-#[cfg(test)]
-mod test {
-    use std::fs;
+    #[test]
+    pub fn update_conditions() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+A, B <=X= 1;
+"#;
+        let mut converter = ASMPILConverter::new();
+        converter
+            .convert(parser::parse_asm(None, source).unwrap(), false)
+            .unwrap();
+        let program = converter.translate_code_lines().unwrap();
+        converter.check_update_conditions(&program).unwrap();
+
+        // A condition that is not a program column is constrained to be boolean.
+        let update = (direct_reference("C"), build_number(0.into()));
+        converter
+            .registers
+            .get_mut("A")
+            .unwrap()
+            .conditioned_updates
+            .push(update);
+        converter.check_update_conditions(&program).unwrap();
+        assert_eq!(
+            converter.pil.last().unwrap().to_string(),
+            "(C * (1 - C)) = 0;"
+        );
 
-    use super::compile;
+        let update = (direct_reference("reg_write_A"), direct_reference("X"));
+        converter
+            .registers
+            .get_mut("B")
+            .unwrap()
+            .conditioned_updates
+            .push(update);
+        let err = converter.check_update_conditions(&program).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Register B is updated under both reg_write_B and reg_write_A in the same line."
+        );
+        assert_eq!(err.start, source.find("A, B").unwrap());
+    }
 
     #[test]
-    pub fn compile_simple_sum() {
-        let expectation = r#"
-namespace Assembly(1024);
-pol constant first_step = [1];
-(first_step * pc) = 0;
-pol commit pc;
-pol commit X;
-pol commit reg_write_A;
-(first_step * A) = 0;
-pol commit A;
-pol commit reg_write_CNT;
-(first_step * CNT) = 0;
-pol commit CNT;
-pol commit XInv;
-pol commit XIsZero;
-XIsZero = (1 - (X * XInv));
-(XIsZero * X) = 0;
-(XIsZero * (1 - XIsZero)) = 0;
-pol commit instr_jmpz;
-pol commit instr_jmpz_param_l;
-pol commit instr_jmp;
-pol commit instr_jmp_param_l;
-pol commit instr_dec_CNT;
-pol commit instr_assert_zero;
-(instr_assert_zero * (XIsZero - 1)) = 0;
-pol commit X_const;
-pol commit X_read_free;
-pol commit read_X_A;
-pol commit read_X_CNT;
-pol commit read_X_pc;
-X = (((((read_X_A * A) + (read_X_CNT * CNT)) + (read_X_pc * pc)) + X_const) + (X_read_free * X_free_value));
-A' = (((first_step' * 0) + (reg_write_A * X)) + ((1 - (first_step' + reg_write_A)) * A));
-CNT' = ((((first_step' * 0) + (reg_write_CNT * X)) + (instr_dec_CNT * (CNT - 1))) + ((1 - ((first_step' + reg_write_CNT) + instr_dec_CNT)) * CNT));
-pc' = ((((first_step' * 0) + (instr_jmpz * ((XIsZero * instr_jmpz_param_l) + ((1 - XIsZero) * (pc + 1))))) + (instr_jmp * instr_jmp_param_l)) + ((1 - ((first_step' + instr_jmpz) + instr_jmp)) * (pc + 1)));
-pol constant line(i) { i };
-pol commit X_free_value(i) query (i, pc, (0, ("input", 1)), (3, ("input", (CNT + 1))), (7, ("input", 0)));
-pol constant p_X_const = [0, 0, 0, 0, 0, 0, 0, 0, 0];
-pol constant p_X_read_free = [1, 0, 0, 1, 0, 0, 0, -1, 0];
-pol constant p_instr_assert_zero = [0, 0, 0, 0, 0, 0, 0, 0, 1];
-pol constant p_instr_dec_CNT = [0, 0, 0, 0, 1, 0, 0, 0, 0];
-pol constant p_instr_jmp = [0, 0, 0, 0, 0, 1, 0, 0, 0];
-pol constant p_instr_jmp_param_l = [0, 0, 0, 0, 0, 1, 0, 0, 0];
-pol constant p_instr_jmpz = [0, 0, 1, 0, 0, 0, 0, 0, 0];
-pol constant p_instr_jmpz_param_l = [0, 0, 6, 0, 0, 0, 0, 0, 0];
-pol constant p_read_X_A = [0, 0, 0, 1, 0, 0, 0, 1, 1];
-pol constant p_read_X_CNT = [0, 0, 1, 0, 0, 0, 0, 0, 0];
-pol constant p_read_X_pc = [0, 0, 0, 0, 0, 0, 0, 0, 0];
-pol constant p_reg_write_A = [0, 0, 0, 1, 0, 0, 0, 1, 0];
-pol constant p_reg_write_CNT = [1, 0, 0, 0, 0, 0, 0, 0, 0];
-{ pc, reg_write_A, reg_write_CNT, instr_jmpz, instr_jmpz_param_l, instr_jmp, instr_jmp_param_l, instr_dec_CNT, instr_assert_zero, X_const, X_read_free, read_X_A, read_X_CNT, read_X_pc } in { line, p_reg_write_A, p_reg_write_CNT, p_instr_jmpz, p_instr_jmpz_param_l, p_instr_jmp, p_instr_jmp_param_l, p_instr_dec_CNT, p_instr_assert_zero, p_X_const, p_X_read_free, p_read_X_A, p_read_X_CNT, p_read_X_pc };
+    pub fn coefficient_overflow() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+A <=X= 6 * (5 * B);
 "#;
-        let file_name = "tests/simple_sum.asm";
-        let contents = fs::read_to_string(file_name).unwrap();
-        let pil = compile(Some(file_name), &contents).unwrap();
-        assert_eq!(format!("{pil}").trim(), expectation.trim());
+        let mut converter = ASMPILConverter::new();
+        let pil = converter
+            .convert(
+                parser::parse_asm(None, &format!("coefficient_bound 20;{source}")).unwrap(),
+                false,
+            )
+            .unwrap();
+        assert!(format!("{pil}").contains("pol constant p_read_X_B = [30];"));
+        assert_eq!(converter.warnings.len(), 1);
+        assert_eq!(
+            converter.warnings[0].message,
+            "Coefficient 30 exceeds the bound 20 and might wrap around in the field."
+        );
+        assert_eq!(
+            converter.warnings[0].start,
+            "coefficient_bound 20;".len() + source.find("A <=X=").unwrap()
+        );
+        // Products within the default bound are not reported.
+        let mut converter = ASMPILConverter::new();
+        converter
+            .convert(parser::parse_asm(None, source).unwrap(), false)
+            .unwrap();
+        assert!(converter.warnings.is_empty());
+
+        let error = |source: &str| compile(None, source).unwrap_err().message().to_string();
+        assert_eq!(
+            error(&format!("{source}coefficient_bound 20;")),
+            "The coefficient bound has to be declared before the first assignment or instruction."
+        );
+        assert_eq!(
+            error(&format!(
+                "coefficient_bound 20;coefficient_bound 10;{source}"
+            )),
+            "Conflicting coefficient bound declaration: 10 (previously declared as 20)."
+        );
+
+        // With an active field, the products are reduced.
+        let pil = with_field_mod(17.into(), || compile(None, source).unwrap());
+        assert!(format!("{pil}").contains("pol constant p_read_X_B = [-4];"));
+    }
+
+    #[test]
+    pub fn unused_declarations() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+reg C;
+reg D;
+instr inc_B { B' = B + 1 }
+instr copy_C { D' = C }
+instr stale { C' = 7 }
+instr jmp l: label { pc' = l }
+A <=X= 1;
+unused::
+loop::
+inc_B;
+copy_C;
+jmp loop;
+"#;
+        let mut converter = ASMPILConverter::new();
+        converter
+            .convert(parser::parse_asm(None, source).unwrap(), false)
+            .unwrap();
+        let warnings = converter
+            .warnings
+            .iter()
+            .map(|w| (w.start, w.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                (
+                    source.find("instr stale").unwrap(),
+                    "Instruction stale is never used."
+                ),
+                (
+                    source.find("unused::").unwrap(),
+                    "Label unused is never used."
+                ),
+            ]
+        );
+        let source = source
+            .replace("instr stale { C' = 7 }", "")
+            .replace("copy_C;\n", "");
+        let mut converter = ASMPILConverter::new();
+        converter
+            .convert(parser::parse_asm(None, &source).unwrap(), false)
+            .unwrap();
+        let warnings = converter
+            .warnings
+            .iter()
+            .map(|w| w.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                "Register C is never used.",
+                "Register D is never used.",
+                "Instruction copy_C is never used.",
+                "Label unused is never used.",
+            ]
+        );
+    }
+
+    #[test]
+    pub fn labels_reached_by_computed_jumps() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+reg A;
+instr jmp l: label { pc' = l }
+A <=X= ${ ("input", 0) };
+pc <=X= 3 * A + 2;
+zero::
+ A <=X= 10;
+ jmp end;
+one::
+ A <=X= 20;
+end::
+ jmp end;
+"#;
+        let mut converter = ASMPILConverter::new();
+        converter
+            .convert(parser::parse_asm(None, source).unwrap(), false)
+            .unwrap();
+        assert!(converter.warnings.is_empty());
+    }
+
+    #[test]
+    pub fn empty_program() {
+        assert_eq!(
+            compile(None, "").unwrap_err().message(),
+            "No assignment register declared."
+        );
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+"#;
+        let pil = compile(None, source).unwrap().to_string();
+        assert!(pil.contains("pol constant p_X_const = [0];"));
+        assert!(pil.contains("{ pc, X_const, X_read_free } in { line, p_X_const, p_X_const };"));
+    }
+
+    #[test]
+    pub fn error_location() {
+        let source = r#"
+reg pc[@pc];
+reg X[<=];
+instr jmp l: label { pc' = l }
+start::
+jmp start;
+jmp end;
+"#;
+        let err = ASMPILConverter::new()
+            .convert(parser::parse_asm(None, source).unwrap(), false)
+            .err()
+            .unwrap();
+        assert_eq!(err.message, "Unknown label end.");
+        assert_eq!(err.start, source.find("jmp end").unwrap());
+    }
+
+    #[test]
+    pub fn unmatched_program_constants() {
+        let check = |modify: fn(&mut ASMPILConverter)| {
+            let mut converter = ASMPILConverter::new();
+            converter.create_witness_fixed_pair(0, "X_const");
+            modify(&mut converter);
+            converter
+                .check_program_constants_matched()
+                .map_err(|e| e.message)
+        };
+        assert_eq!(check(|_| {}), Ok(()));
+        assert_eq!(
+            check(|c| c.program_constant_names.push("p_Y".to_string())),
+            Err("Program constant p_Y is not matched against a witness column.".to_string())
+        );
+        assert_eq!(
+            check(|c| c.line_lookup.push(("X".to_string(), "p_Y".to_string()))),
+            Err(
+                "Witness column X is matched against p_Y, which is not a program constant."
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(|c| c
+                .line_lookup
+                .push(("Y".to_string(), "p_X_const".to_string()))),
+            Err(
+                "Program constant p_X_const is matched against Y, which is not a witness column."
+                    .to_string()
+            )
+        );
     }
 }
@@ -0,0 +1,151 @@
+use std::str::FromStr;
+
+use crate::number::{abstract_to_degree, AbstractNumberType, DegreeType};
+
+/// A query for a free input of an assembly program, as issued by the witness generator
+/// for the `X_free_value` columns.
+/// The query string has the form `step, pc, pc_check_1, "channel_1", index_1, ...`,
+/// i.e. it lists the free input of each instruction that reads one.
+/// If the free inputs specify query keys as in `${ ("channel", index); key, ... }`,
+/// the values of the keys replace `step, pc` and the query has to be parsed by the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub step: DegreeType,
+    pub pc: AbstractNumberType,
+    pub entries: Vec<QueryEntry>,
+}
+
+/// The free input read by the instruction at `pc_check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryEntry {
+    pub pc_check: AbstractNumberType,
+    pub channel: String,
+    pub index: AbstractNumberType,
+}
+
+impl Query {
+    /// @returns the entry of the instruction at the current program counter, if any.
+    pub fn current_entry(&self) -> Option<&QueryEntry> {
+        self.entries.iter().find(|e| e.pc_check == self.pc)
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let items = split_items(s)?;
+        let number = |item: &QueryItem| match item {
+            QueryItem::Number(n) => Ok(n.clone()),
+            QueryItem::String(value) => Err(format!(
+                "Expected a number but got \"{value}\" in query {s}."
+            )),
+        };
+        let [step, pc, entries @ ..] = &items[..] else {
+            return Err(format!("Query needs at least a step and a pc: {s}"));
+        };
+        if entries.len() % 3 != 0 {
+            return Err(format!(
+                "Query entries need to consist of pc, channel and index: {s}"
+            ));
+        }
+        Ok(Query {
+            step: abstract_to_degree(&number(step)?),
+            pc: number(pc)?,
+            entries: entries
+                .chunks(3)
+                .map(|entry| {
+                    let QueryItem::String(channel) = &entry[1] else {
+                        return Err(format!("Expected a channel name in query {s}."));
+                    };
+                    Ok(QueryEntry {
+                        pc_check: number(&entry[0])?,
+                        channel: channel.clone(),
+                        index: number(&entry[2])?,
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Turns a callback that answers structured queries into a regular query callback
+/// that receives the query as a string.
+/// Panics if the witness generator issues a query that is not in the format of assembly queries.
+pub fn query_callback(
+    mut callback: impl FnMut(&Query) -> Option<AbstractNumberType>,
+) -> impl FnMut(&str) -> Option<AbstractNumberType> {
+    move |query| {
+        let query = query
+            .parse::<Query>()
+            .unwrap_or_else(|err| panic!("Invalid assembly query: {err}"));
+        callback(&query)
+    }
+}
+
+enum QueryItem {
+    Number(AbstractNumberType),
+    String(String),
+}
+
+/// Splits the query string at the commas that are not part of a string literal.
+fn split_items(s: &str) -> Result<Vec<QueryItem>, String> {
+    let mut items = vec![];
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next_if_eq(&'"').is_some() {
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => value.extend(chars.next()),
+                    Some(c) => value.push(c),
+                    None => return Err(format!("Unterminated string in query {s}")),
+                }
+            }
+            items.push(QueryItem::String(value));
+        } else {
+            let mut value = String::new();
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                value.push(c);
+            }
+            let value = value.trim();
+            items.push(QueryItem::Number(
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid number \"{value}\" in query {s}"))?,
+            ));
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some(',') => {}
+            None => return Ok(items),
+            Some(c) => return Err(format!("Unexpected character '{c}' in query {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_query() {
+        let query: Query = r#"3, 7, 0, "input", 1, 7, "in\"put", 5"#.parse().unwrap();
+        assert_eq!(query.step, 3);
+        assert_eq!(query.pc, 7.into());
+        assert_eq!(query.entries.len(), 2);
+        assert_eq!(
+            query.current_entry(),
+            Some(&QueryEntry {
+                pc_check: 7.into(),
+                channel: "in\"put".to_string(),
+                index: 5.into()
+            })
+        );
+        assert!("3".parse::<Query>().is_err());
+        assert!(r#"3, 7, 0, "input""#.parse::<Query>().is_err());
+        assert!(r#"3, 7, 0, 1, 2"#.parse::<Query>().is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use crate::number::is_zero;
+use crate::parser::ast::{BinaryOperator, Expression, Statement};
+
+/// Simplifies the expressions of all polynomial identities by removing
+/// multiplications by zero and one and additions and subtractions of zero.
+pub fn simplify_identities(statements: &mut [Statement]) {
+    for statement in statements {
+        if let Statement::PolynomialIdentity(_, expression) = statement {
+            *expression = simplify(std::mem::replace(expression, Expression::Number(0.into())));
+        }
+    }
+}
+
+fn simplify(e: Expression) -> Expression {
+    match e {
+        Expression::BinaryOperation(left, op, right) => {
+            let left = simplify(*left);
+            let right = simplify(*right);
+            match (op, number(&left), number(&right)) {
+                (BinaryOperator::Mul, Some(0), _) | (BinaryOperator::Mul, _, Some(0)) => {
+                    Expression::Number(0.into())
+                }
+                (BinaryOperator::Mul, Some(1), _) | (BinaryOperator::Add, Some(0), _) => right,
+                (BinaryOperator::Mul, _, Some(1))
+                | (BinaryOperator::Add, _, Some(0))
+                | (BinaryOperator::Sub, _, Some(0)) => left,
+                _ => Expression::BinaryOperation(Box::new(left), op, Box::new(right)),
+            }
+        }
+        Expression::UnaryOperation(op, e) => Expression::UnaryOperation(op, Box::new(simplify(*e))),
+        e => e,
+    }
+}
+
+/// Returns the value of a number literal if it is zero or one.
+fn number(e: &Expression) -> Option<u8> {
+    match e {
+        Expression::Number(n) if is_zero(n) => Some(0),
+        Expression::Number(n) if *n == 1.into() => Some(1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::parse;
+
+    use super::*;
+
+    fn simplified(input: &str) -> String {
+        let mut pil = parse(None, input).unwrap();
+        simplify_identities(&mut pil.0);
+        format!("{pil}").trim().to_string()
+    }
+
+    #[test]
+    fn neutral_elements() {
+        assert_eq!(simplified("x * 1 + 0 * y = 1 * z - 0;"), "x = z;");
+        assert_eq!(simplified("(x + 0) * (y - 0) = 0;"), "(x * y) = 0;");
+        // Subtraction from zero is not simplified.
+        assert_eq!(simplified("(0 - x) * y = 0;"), "((0 - x) * y) = 0;");
+    }
+
+    #[test]
+    fn only_identities() {
+        assert_eq!(simplified("pol x = y * 1;"), "pol x = (y * 1);");
+    }
+}
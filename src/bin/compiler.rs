@@ -1,13 +1,28 @@
 use clap::{Parser, Subcommand};
-use powdr::compiler::no_callback;
-use powdr::number::AbstractNumberType;
-use std::{fs, path::Path};
+use powdr::asm_compiler::DEFAULT_INPUT_CHANNEL;
+use powdr::compiler::{no_callback, ColumnFormat, CompileOptions, InputChannels};
+use powdr::number::{
+    parse_field_element, with_named_field, with_number_format, AbstractNumberType, FieldId,
+    NumberFormat,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Number of threads to evaluate fixed columns with (defaults to the number of CPUs).
+    /// Use 1 for sequential evaluation, e.g. for debugging.
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+
+    /// Write field elements in PIL and column files as unsigned numbers in [0, p)
+    /// or as signed numbers in (-p/2, p/2]. By default they are written as computed.
+    #[arg(long, global = true)]
+    number_format: Option<NumberFormat>,
 }
 
 #[derive(Subcommand)]
@@ -17,9 +32,29 @@ enum Commands {
         /// Input file
         file: String,
 
-        /// Comma-separated list of free inputs (numbers).
+        /// Comma-separated list of free inputs (decimal, 0x hexadecimal or 0b binary numbers,
+        /// negative numbers are field elements in signed form). Their absolute values have to
+        /// be less than the field modulus.
         #[arg(short, long)]
-        inputs: String,
+        #[arg(required_unless_present_any = ["inputs_file", "channel", "interactive"])]
+        inputs: Option<String>,
+
+        /// File containing the free inputs (numbers), separated by commas or newlines.
+        #[arg(long)]
+        #[arg(conflicts_with = "inputs")]
+        inputs_file: Option<String>,
+
+        /// Free inputs of a named channel, given as NAME=comma-separated numbers.
+        /// Can be repeated. The other input options fill the channel "input".
+        #[arg(long)]
+        channel: Vec<String>,
+
+        /// Ask for each free input on stdin, one number per line, instead of
+        /// taking them from the other input options.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        #[arg(conflicts_with_all = ["inputs", "inputs_file", "channel"])]
+        interactive: bool,
 
         /// Output directory for PIL file, json file and fixed and witness column data.
         #[arg(short, long)]
@@ -35,12 +70,109 @@ enum Commands {
         #[arg(short, long)]
         #[arg(default_value_t = false)]
         verbose: bool,
+
+        /// Report rows of fixed lookup tables that are never matched.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        lookup_coverage: bool,
+
+        /// Use the smallest power of two that fits the program as degree,
+        /// instead of the declared degree or the default of 1024.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        auto_degree: bool,
+
+        /// Write the execution trace (the values of the witness columns in each row)
+        /// as CSV to this file.
+        #[arg(long)]
+        #[arg(conflicts_with = "lookup_coverage")]
+        trace_file: Option<String>,
+
+        /// Skip the stages whose inputs did not change since the last compilation
+        /// into the output directory, as recorded in its manifest.json.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        incremental: bool,
+
+        /// Also write the fixed and witness columns together with their names
+        /// to fixed_columns.<FORMAT> and witness_columns.<FORMAT> (bin or csv).
+        #[arg(long)]
+        format: Option<ColumnFormat>,
+
+        /// The field to compute in.
+        #[arg(long)]
+        #[arg(default_value_t = FieldId::Goldilocks)]
+        field: FieldId,
+
+        /// Remove the witness columns that are defined by a linear identity,
+        /// substituting their definition.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        eliminate_witnesses: bool,
+
+        /// Replace witness columns that are constrained like a column in another
+        /// namespace by that column.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        deduplicate_witnesses: bool,
+
+        /// Group the identities in the json file by the machine (namespace)
+        /// they are declared in.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        group_by_machine: bool,
+    },
+
+    /// Checks that the witness can be generated for a .asm or .pil file,
+    /// without writing any files. Reports the first row where it fails.
+    Check {
+        /// Input file
+        file: String,
+
+        /// Comma-separated list of free inputs (numbers) for .asm files.
+        #[arg(short, long)]
+        inputs: Option<String>,
+
+        /// File containing the free inputs (numbers) for .asm files,
+        /// separated by commas or newlines.
+        #[arg(long)]
+        #[arg(conflicts_with = "inputs")]
+        inputs_file: Option<String>,
+
+        /// Free inputs of a named channel, given as NAME=comma-separated numbers.
+        /// Can be repeated. The other input options fill the channel "input".
+        #[arg(long)]
+        channel: Vec<String>,
+
+        /// The field to compute in.
+        #[arg(long)]
+        #[arg(default_value_t = FieldId::Goldilocks)]
+        field: FieldId,
+    },
+
+    /// Writes the control flow graph of the assembly program as a Graphviz .dot file.
+    /// Fall-through edges are dotted, conditional jumps dashed and other jumps solid.
+    Cfg {
+        /// Input file
+        file: String,
+
+        /// Output directory for the .dot file.
+        #[arg(short, long)]
+        #[arg(default_value_t = String::from("."))]
+        output_directory: String,
     },
 
-    /// Parses and prints the PIL file on stdout.
+    /// Parses and prints the PIL file on stdout, with included files inlined.
+    /// Expressions only keep the parentheses their precedence requires.
     Reformat {
         /// Input file
         file: String,
+
+        /// The maximum line width. Longer chains of binary operations are
+        /// broken into one operand per line.
+        #[arg(long)]
+        #[arg(default_value_t = 100)]
+        width: usize,
     },
 
     /// Compiles the PIL file to json and generates fixed and witness columns.
@@ -51,48 +183,238 @@ enum Commands {
         #[arg(short, long)]
         #[arg(default_value_t = String::from("."))]
         output_directory: String,
+
+        /// Report rows of fixed lookup tables that are never matched.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        lookup_coverage: bool,
+
+        /// Skip the stages whose inputs did not change since the last compilation
+        /// into the output directory, as recorded in its manifest.json.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        incremental: bool,
+
+        /// Also write the fixed and witness columns together with their names
+        /// to fixed_columns.<FORMAT> and witness_columns.<FORMAT> (bin or csv).
+        #[arg(long)]
+        format: Option<ColumnFormat>,
+
+        /// The field to compute in.
+        #[arg(long)]
+        #[arg(default_value_t = FieldId::Goldilocks)]
+        field: FieldId,
+
+        /// Report the time the witness generation spends on each identity,
+        /// split into the generic solver and the machines.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        #[arg(conflicts_with = "lookup_coverage")]
+        profile: bool,
+
+        /// Remove the witness columns that are defined by a linear identity,
+        /// substituting their definition.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        eliminate_witnesses: bool,
+
+        /// Replace witness columns that are constrained like a column in another
+        /// namespace by that column.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        deduplicate_witnesses: bool,
+
+        /// Group the identities in the json file by the machine (namespace)
+        /// they are declared in.
+        #[arg(long)]
+        #[arg(default_value_t = false)]
+        group_by_machine: bool,
     },
 }
 
 fn main() {
-    match Cli::parse().command {
+    let cli = Cli::parse();
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+    match cli.number_format {
+        Some(format) => with_number_format(format, || run(cli.command)),
+        None => run(cli.command),
+    }
+}
+
+fn run(command: Commands) {
+    match command {
         Commands::Asm {
             file,
             inputs,
+            inputs_file,
+            channel,
+            interactive,
             output_directory,
             force,
             verbose,
+            lookup_coverage,
+            auto_degree,
+            trace_file,
+            incremental,
+            format,
+            field,
+            eliminate_witnesses,
+            deduplicate_witnesses,
+            group_by_machine,
         } => {
-            let inputs = inputs
-                .split(',')
-                .map(|x| x.trim())
-                .filter(|x| !x.is_empty())
-                .map(|x| x.parse().unwrap())
-                .collect::<Vec<AbstractNumberType>>();
-            powdr::compiler::compile_asm(
-                &file,
-                inputs,
-                Path::new(&output_directory),
-                force,
-                verbose,
-            );
+            let inputs = with_named_field(field, || read_inputs(inputs, inputs_file, channel))
+                .unwrap_or_else(|err| {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                });
+            let result = with_named_field(field, || {
+                powdr::compiler::compile_asm(
+                    &file,
+                    inputs,
+                    Path::new(&output_directory),
+                    &CompileOptions {
+                        verbose,
+                        lookup_coverage,
+                        incremental,
+                        format,
+                        trace_file: trace_file.map(PathBuf::from),
+                        auto_degree,
+                        interactive,
+                        force_overwrite: force,
+                        eliminate_witnesses,
+                        deduplicate_witnesses,
+                        group_by_machine,
+                        ..Default::default()
+                    },
+                )
+            });
+            if let Err(err) = result {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        }
+        Commands::Check {
+            file,
+            inputs,
+            inputs_file,
+            channel,
+            field,
+        } => {
+            // .pil files are checked without query callback.
+            if !file.ends_with(".asm")
+                && (inputs.is_some() || inputs_file.is_some() || !channel.is_empty())
+            {
+                eprintln!("Free inputs can only be given for .asm files.");
+                std::process::exit(1);
+            }
+            let inputs = with_named_field(field, || read_inputs(inputs, inputs_file, channel))
+                .unwrap_or_else(|err| {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                });
+            match with_named_field(field, || powdr::compiler::check_witness(&file, inputs)) {
+                Ok(()) => println!("Witness generation succeeded."),
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+            }
         }
-        Commands::Reformat { file } => {
+        Commands::Cfg {
+            file,
+            output_directory,
+        } => {
             let contents = fs::read_to_string(&file).unwrap();
-            match powdr::parser::parse(Some(&file), &contents) {
-                Ok(ast) => println!("{ast}"),
+            match powdr::asm_compiler::control_flow_graph(Some(&file), &contents) {
+                Ok(graph) => {
+                    let dot_file = Path::new(&output_directory).join(format!(
+                        "{}.dot",
+                        Path::new(&file).file_stem().unwrap().to_str().unwrap()
+                    ));
+                    fs::write(&dot_file, format!("{graph}\n")).unwrap();
+                    println!("Wrote {}.", dot_file.display());
+                }
+                Err(err) => err.output_to_stderr(),
+            }
+        }
+        Commands::Reformat { file, width } => {
+            match powdr::parser::parse_file_with_includes_and_comments(Path::new(&file)) {
+                Ok(ast) => print!("{}", powdr::parser::pretty::format_pil(&ast, width)),
                 Err(err) => err.output_to_stderr(),
             }
         }
         Commands::Compile {
             file,
             output_directory,
+            lookup_coverage,
+            incremental,
+            format,
+            field,
+            profile,
+            eliminate_witnesses,
+            deduplicate_witnesses,
+            group_by_machine,
         } => {
-            powdr::compiler::compile_pil(
-                Path::new(&file),
-                Path::new(&output_directory),
-                no_callback(),
-            );
+            let result = with_named_field(field, || {
+                powdr::compiler::compile_pil(
+                    Path::new(&file),
+                    Path::new(&output_directory),
+                    no_callback(),
+                    &CompileOptions {
+                        lookup_coverage,
+                        incremental,
+                        format,
+                        profile,
+                        eliminate_witnesses,
+                        deduplicate_witnesses,
+                        group_by_machine,
+                        ..Default::default()
+                    },
+                )
+            });
+            if let Err(err) = result {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
         }
     }
 }
+
+/// Returns the free inputs by channel, as elements of the active field. The inputs of the
+/// default channel are given on the command line or, alternatively, in a file.
+fn read_inputs(
+    inputs: Option<String>,
+    inputs_file: Option<String>,
+    channels: Vec<String>,
+) -> Result<InputChannels, String> {
+    let default_inputs = match inputs_file {
+        Some(path) => parse_inputs(
+            &fs::read_to_string(&path)
+                .map_err(|err| format!("Could not read inputs file {path}: {err}"))?,
+        ),
+        None => parse_inputs(&inputs.unwrap_or_default()),
+    }?;
+    let mut result = InputChannels::from([(DEFAULT_INPUT_CHANNEL.to_string(), default_inputs)]);
+    for channel in channels {
+        let (name, values) = channel
+            .split_once('=')
+            .ok_or_else(|| format!("Expected NAME=VALUES for channel, but got {channel}"))?;
+        result.insert(name.to_string(), parse_inputs(values)?);
+    }
+    Ok(result)
+}
+
+/// Parses a list of field elements separated by commas or newlines.
+fn parse_inputs(inputs: &str) -> Result<Vec<AbstractNumberType>, String> {
+    inputs
+        .split([',', '\n'])
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(parse_field_element)
+        .collect()
+}
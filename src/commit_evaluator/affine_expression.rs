@@ -1,19 +1,27 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
 // TODO this should probably rather be a finite field element.
-use crate::number::{format_number, is_zero, AbstractNumberType, GOLDILOCKS_MOD};
+use crate::number::{clamp, field_mod, format_number, inv, is_zero, AbstractNumberType};
 
 use super::util::WitnessColumnNamer;
 
 /// An expression affine in the committed polynomials.
-#[derive(Debug, Clone)]
+/// It is kept in a canonical form: Only nonzero coefficients are stored and
+/// all values are reduced modulo the field, so that the variables an
+/// expression depends on are available without scanning all coefficients
+/// and structurally equal expressions compare equal.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AffineExpression {
-    pub coefficients: Vec<AbstractNumberType>,
-    pub offset: AbstractNumberType,
+    /// The nonzero coefficients by variable ID.
+    coefficients: BTreeMap<usize, AbstractNumberType>,
+    offset: AbstractNumberType,
 }
 
 impl From<AbstractNumberType> for AffineExpression {
     fn from(value: AbstractNumberType) -> Self {
         AffineExpression {
-            coefficients: Vec::new(),
+            coefficients: BTreeMap::new(),
             offset: clamp(value),
         }
     }
@@ -21,23 +29,20 @@ impl From<AbstractNumberType> for AffineExpression {
 
 impl From<u32> for AffineExpression {
     fn from(value: u32) -> Self {
-        AffineExpression {
-            coefficients: Vec::new(),
-            offset: clamp(value.into()),
-        }
+        AbstractNumberType::from(value).into()
     }
 }
 
 impl AffineExpression {
     pub fn from_wittness_poly_value(poly_id: usize) -> AffineExpression {
         AffineExpression {
-            coefficients: [vec![0.into(); poly_id], vec![1.into()]].concat(),
+            coefficients: [(poly_id, 1.into())].into(),
             offset: 0.into(),
         }
     }
 
     pub fn is_constant(&self) -> bool {
-        self.nonzero_coefficients().next().is_none()
+        self.coefficients.is_empty()
     }
 
     pub fn constant_value(&self) -> Option<AbstractNumberType> {
@@ -48,21 +53,22 @@ impl AffineExpression {
         }
     }
 
-    pub fn nonzero_variables(&self) -> Vec<usize> {
-        self.nonzero_coefficients().map(|(i, _)| i).collect()
+    /// @returns the IDs of the variables with nonzero coefficient in ascending order.
+    pub fn nonzero_variables(&self) -> impl Iterator<Item = usize> + '_ {
+        self.coefficients.keys().copied()
     }
 
     /// @returns an iterator of the nonzero coefficients and their variable IDs (but not the offset).
     pub fn nonzero_coefficients(&self) -> impl Iterator<Item = (usize, &AbstractNumberType)> {
-        self.coefficients
-            .iter()
-            .enumerate()
-            .filter(|(_, c)| !is_zero(c))
+        self.coefficients.iter().map(|(i, c)| (*i, c))
     }
 
     pub fn mul(mut self, factor: AbstractNumberType) -> AffineExpression {
         let fac = clamp(factor);
-        for f in &mut self.coefficients {
+        if is_zero(&fac) {
+            return 0.into();
+        }
+        for f in self.coefficients.values_mut() {
             *f = clamp(f.clone() * fac.clone());
         }
         self.offset = clamp(self.offset.clone() * fac);
@@ -73,28 +79,60 @@ impl AffineExpression {
     /// returns the index of the variable and the assignment that evaluates the
     /// affine expression to zero.
     pub fn solve(&self) -> Option<(usize, AbstractNumberType)> {
-        let mut nonzero = self.nonzero_coefficients();
-        nonzero.next().and_then(|(i, c)| {
-            if nonzero.next().is_none() {
-                // c * a + o = 0 <=> a = -o/c
-                if *c == 1.into() {
-                    Some((i, clamp(-self.offset.clone())))
-                } else if *c == (-1).into() || *c == (GOLDILOCKS_MOD - 1).into() {
-                    Some((i, self.offset.clone()))
-                } else {
-                    Some((
-                        i,
-                        clamp(-clamp(
-                            self.offset.clone() * inv(c.clone(), GOLDILOCKS_MOD.into()),
-                        )),
-                    ))
-                }
-            } else {
-                None
-            }
+        if self.coefficients.len() != 1 {
+            return None;
+        }
+        let (&i, c) = self.coefficients.first_key_value().unwrap();
+        // c * a + o = 0 <=> a = -o/c
+        Some(if *c == 1.into() {
+            (i, clamp(-self.offset.clone()))
+        } else if *c == field_mod() - 1 {
+            (i, self.offset.clone())
+        } else {
+            (
+                i,
+                clamp(-clamp(self.offset.clone() * inv(c.clone(), field_mod()))),
+            )
         })
     }
 
+    /// Solves expressions like `a + 256 * b - 0x1234` with several variables, given the
+    /// maximal number of bits of the variables: If all coefficients are distinct powers of two
+    /// (or all are their negations) such that the bits of the variables do not overlap,
+    /// each variable is assigned its bits of the value.
+    /// @returns None if the expression does not have this form or if the value has bits
+    /// that are not covered by any variable.
+    pub fn solve_with_bit_constraints(
+        &self,
+        bit_constraints: impl Fn(usize) -> Option<u64>,
+    ) -> Option<Vec<(usize, AbstractNumberType)>> {
+        let is_power_of_two =
+            |c: &AbstractNumberType| !is_zero(c) && (c.clone() & (c - 1)) == 0.into();
+        // c_1 * x_1 + ... + c_n * x_n + o = 0 <=> c_1 * x_1 + ... + c_n * x_n = -o
+        let expression = if self.coefficients.values().all(is_power_of_two) {
+            self.clone()
+        } else {
+            -self.clone()
+        };
+        let value = clamp(-expression.offset.clone());
+        let mut covered = AbstractNumberType::from(0);
+        let mut assignments = vec![];
+        for (&i, c) in &expression.coefficients {
+            if !is_power_of_two(c) {
+                return None;
+            }
+            let shift = c.bits() - 1;
+            let mask = ((AbstractNumberType::from(1) << bit_constraints(i)?) - 1) << shift;
+            if !is_zero(&(covered.clone() & &mask)) {
+                return None;
+            }
+            covered |= &mask;
+            assignments.push((i, (value.clone() & mask) >> shift));
+        }
+        // The sum must not wrap around the modulus, so that the solution is unique.
+        (covered < field_mod() && (value.clone() & &covered) == value).then_some(assignments)
+    }
+
     /// Returns true if it can be determined that this expression can never be zero.
     pub fn is_invalid(&self) -> bool {
         self.constant_value().map(|v| v != 0.into()) == Some(true)
@@ -106,7 +144,7 @@ impl AffineExpression {
                 let name = namer.name(i);
                 if *c == 1.into() {
                     name
-                } else if *c == (-1).into() {
+                } else if *c == clamp((-1).into()) {
                     format!("-{name}")
                 } else {
                     format!("{} * {name}", format_number(c))
@@ -118,53 +156,29 @@ impl AffineExpression {
     }
 }
 
-fn clamp(mut x: AbstractNumberType) -> AbstractNumberType {
-    while x < 0.into() {
-        x += GOLDILOCKS_MOD
-    }
-    x % GOLDILOCKS_MOD
-}
-
-fn pow(
-    mut x: AbstractNumberType,
-    mut y: AbstractNumberType,
-    m: AbstractNumberType,
-) -> AbstractNumberType {
-    assert!(y >= 0.into());
-    if y == 0.into() {
-        return 1.into();
-    }
-    let mut r: AbstractNumberType = 1.into();
-    while y >= 2.into() {
-        if y.bit(0) {
-            r = (r * x.clone()) % m.clone();
-        }
-        x = (x.clone() * x) % m.clone();
-        y = y.clone() >> 1;
-    }
-    (r * x) % m
-}
-
-fn inv(x: AbstractNumberType, m: AbstractNumberType) -> AbstractNumberType {
-    pow(x, m.clone() - 2, m)
-}
-
-impl PartialEq for AffineExpression {
-    fn eq(&self, other: &Self) -> bool {
-        self.offset == other.offset && self.nonzero_coefficients().eq(other.nonzero_coefficients())
-    }
-}
-
 impl std::ops::Add for AffineExpression {
     type Output = AffineExpression;
 
     fn add(self, rhs: Self) -> Self::Output {
-        let mut coefficients = rhs.coefficients;
-        if self.coefficients.len() > coefficients.len() {
-            coefficients.resize(self.coefficients.len(), 0.into());
-        }
-        for (i, v) in self.coefficients.iter().enumerate() {
-            coefficients[i] = clamp(coefficients[i].clone() + v);
+        let (mut coefficients, other) = if self.coefficients.len() >= rhs.coefficients.len() {
+            (self.coefficients, rhs.coefficients)
+        } else {
+            (rhs.coefficients, self.coefficients)
+        };
+        for (i, v) in other {
+            match coefficients.entry(i) {
+                Entry::Vacant(entry) => {
+                    entry.insert(v);
+                }
+                Entry::Occupied(mut entry) => {
+                    let sum = clamp(entry.get().clone() + v);
+                    if is_zero(&sum) {
+                        entry.remove();
+                    } else {
+                        *entry.get_mut() = sum;
+                    }
+                }
+            }
         }
         AffineExpression {
             coefficients,
@@ -178,7 +192,7 @@ impl std::ops::Neg for AffineExpression {
 
     fn neg(mut self) -> Self::Output {
         self.coefficients
-            .iter_mut()
+            .values_mut()
             .for_each(|v| *v = clamp(-v.clone()));
         self.offset = clamp(-self.offset);
         self
@@ -196,12 +210,18 @@ impl std::ops::Sub for AffineExpression {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::number::AbstractNumberType;
+    use crate::number::{pow, AbstractNumberType, GOLDILOCKS_MOD};
 
-    use super::{AffineExpression, GOLDILOCKS_MOD};
+    use super::AffineExpression;
 
-    fn convert(input: Vec<i32>) -> Vec<AbstractNumberType> {
-        input.into_iter().map(|x| x.into()).collect()
+    /// Converts dense coefficients to the canonical form.
+    fn convert(input: Vec<i32>) -> BTreeMap<usize, AbstractNumberType> {
+        input
+            .into_iter()
+            .enumerate()
+            .filter(|(_, x)| *x != 0)
+            .map(|(i, x)| (i, clamp(x.into())))
+            .collect()
     }
 
     #[test]
@@ -213,11 +233,11 @@ mod test {
         assert_eq!(
             -a,
             AffineExpression {
-                coefficients: vec![
-                    (GOLDILOCKS_MOD - 1).into(),
-                    0.into(),
-                    (GOLDILOCKS_MOD - 2).into()
-                ],
+                coefficients: [
+                    (0, (GOLDILOCKS_MOD - 1).into()),
+                    (2, (GOLDILOCKS_MOD - 2).into())
+                ]
+                .into(),
                 offset: (GOLDILOCKS_MOD - 9).into(),
             },
         );
@@ -243,6 +263,72 @@ mod test {
         assert_eq!(b.clone() + a.clone(), a + b,);
     }
 
+    #[test]
+    pub fn canonical_form() {
+        let x = AffineExpression::from_wittness_poly_value(0);
+        let y = AffineExpression::from_wittness_poly_value(3);
+        let e = x.clone() + y.clone() + 5.into();
+        assert_eq!(e.nonzero_variables().collect::<Vec<_>>(), [0, 3]);
+        // Cancelling a variable removes it.
+        let e = e - y.clone();
+        assert_eq!(e.nonzero_variables().collect::<Vec<_>>(), [0]);
+        assert_eq!(e, x.clone() + 5.into());
+        assert_eq!(e.solve(), Some((0, clamp((-5).into()))));
+        assert_eq!((x.clone() - x.clone()).constant_value(), Some(0.into()));
+        assert!(y.mul(0.into()).is_constant());
+        assert_eq!((-x).mul(2.into()).solve(), Some((0, 0.into())),);
+    }
+
+    #[test]
+    pub fn bit_constraints() {
+        let x = AffineExpression::from_wittness_poly_value(0);
+        let y = AffineExpression::from_wittness_poly_value(1);
+        let bytes = |_| Some(8);
+        // 0x1234 = x + 256 * y
+        let e = x.clone() + y.clone().mul(256.into()) - 0x1234.into();
+        assert_eq!(
+            e.solve_with_bit_constraints(bytes),
+            Some(vec![(0, 0x34.into()), (1, 0x12.into())])
+        );
+        assert_eq!(
+            (-e).solve_with_bit_constraints(bytes),
+            Some(vec![(0, 0x34.into()), (1, 0x12.into())])
+        );
+        // The value does not fit two bytes.
+        let e = x.clone() + y.clone().mul(256.into()) - 0x12345.into();
+        assert_eq!(e.solve_with_bit_constraints(bytes), None);
+        // The bits of x and y overlap.
+        let e = x.clone() + y.clone().mul(16.into()) - 0x12.into();
+        assert_eq!(e.solve_with_bit_constraints(bytes), None);
+        // No bit constraint for y.
+        let e = x.clone() + y.clone().mul(256.into()) - 0x12.into();
+        assert_eq!(
+            e.solve_with_bit_constraints(|i| (i == 0).then_some(8)),
+            None
+        );
+        // The coefficients are not powers of two.
+        let e = x + y.mul(3.into()) - 0x12.into();
+        assert_eq!(e.solve_with_bit_constraints(bytes), None);
+    }
+
+    struct Names;
+
+    impl WitnessColumnNamer for Names {
+        fn name(&self, i: usize) -> String {
+            format!("x{i}")
+        }
+    }
+
+    #[test]
+    pub fn format() {
+        let e = AffineExpression {
+            coefficients: convert(vec![1, -1, 0, -2, 3]),
+            offset: 0.into(),
+        };
+        assert_eq!(e.format(&Names), "x0 + -x1 + -2 * x3 + 3 * x4");
+        assert_eq!((-e).format(&Names), "-x0 + x1 + 2 * x3 + -3 * x4");
+    }
+
     #[test]
     pub fn mod_arith() {
         assert_eq!(pow(7.into(), 0.into(), GOLDILOCKS_MOD.into()), 1.into());
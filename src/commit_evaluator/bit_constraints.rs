@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use crate::analyzer::{Identity, IdentityKind};
+use crate::number::clamp;
+
+use super::util::column_name;
+use super::FixedData;
+
+/// Determines the maximal number of bits of witness columns from lookups of the form
+/// `s { ..., x, ... } in { ..., BYTE, ... }`, where the fixed column `BYTE` only contains
+/// values of at most that many bits.
+/// The bits are used to solve constraints like `v = x + 256 * y`. The lookups are
+/// still checked, so a bound that does not hold in a row where the lookup is not
+/// active (because of the selector `s`) can only make the witness generation fail.
+/// @returns the number of bits by witness column ID.
+pub fn determine_bit_constraints(
+    fixed_data: &FixedData,
+    identities: &[&Identity],
+) -> BTreeMap<usize, u64> {
+    let mut bit_constraints = BTreeMap::new();
+    for identity in identities {
+        if identity.kind != IdentityKind::Plookup || identity.right.selector.is_some() {
+            continue;
+        }
+        for (left, right) in identity
+            .left
+            .expressions
+            .iter()
+            .zip(&identity.right.expressions)
+        {
+            let (Some(witness), Some(fixed)) = (column_name(left), column_name(right)) else {
+                continue;
+            };
+            let (Some(&id), Some(values)) = (
+                fixed_data.witness_ids.get(witness),
+                fixed_data.fixed_cols.get(fixed),
+            ) else {
+                continue;
+            };
+            let bits = values
+                .iter()
+                .map(|v| clamp(v.clone()).bits())
+                .max()
+                .unwrap_or_default();
+            bit_constraints
+                .entry(id)
+                .and_modify(|b: &mut u64| *b = (*b).min(bits))
+                .or_insert(bits);
+        }
+    }
+    bit_constraints
+}
@@ -0,0 +1,483 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use itertools::{Either, Itertools};
+
+use crate::analyzer::{Expression, Identity, IdentityKind, SelectedExpressions};
+use crate::commit_evaluator::eval_error;
+use crate::commit_evaluator::machine::LookupReturn;
+use crate::number::{clamp, AbstractNumberType, DegreeType};
+
+use super::affine_expression::AffineExpression;
+use super::eval_error::EvalError;
+use super::expression_evaluator::{ExpressionEvaluator, SymbolicVariables};
+use super::machine::{LookupResult, Machine};
+use super::symbolic_evaluator::SymbolicEvaluator;
+use super::util::{column_name, contains_next_ref};
+use super::FixedData;
+
+/// A machine that computes its witness columns in blocks of a fixed number of rows,
+/// one block per call. Its identities only reference its own witness columns and fixed columns.
+/// The latch is a fixed column in the namespace of the machine that is 1 in the last row
+/// of each block and 0 otherwise, e.g. `col fixed LATCH = [0, 0, 0, 1, 0, 0, 0, 1]`.
+///
+/// The machine is called through lookups or permutations of the form
+/// `{ a, b, c } is LATCH { x, y, z }`: The known values on the LHS are placed
+/// in the last row of a new block, the rest of the block is derived from the
+/// identities and the values of the last row are returned for the unknown values on the LHS.
+///
+/// Blocks that are not used by any call are filled with copies of the last block.
+/// This does not work for permutations, since every block has to correspond to a call,
+/// so the witness generation fails if a machine that is called through a permutation
+/// has unused blocks.
+pub struct BlockMachine {
+    latch: String,
+    block_size: usize,
+    /// The witness columns of the machine.
+    witness_cols: Vec<String>,
+    /// The polynomial identities of the machine (as expressions that have to be zero).
+    identities: Vec<Expression>,
+    /// The values of the blocks, indexed by block, row in the block and column.
+    blocks: Vec<Vec<Vec<AbstractNumberType>>>,
+    /// Maps the calls, identified by the kind and ID of the identity and the row,
+    /// to the block they use.
+    calls: HashMap<(IdentityKind, u64, DegreeType), usize>,
+    /// Whether the machine is called through a permutation.
+    permutation: bool,
+}
+
+impl BlockMachine {
+    pub fn try_new(
+        fixed_data: &FixedData,
+        identities: &[&Identity],
+        witness_names: &HashSet<&str>,
+    ) -> Option<Box<Self>> {
+        if identities.is_empty()
+            || identities
+                .iter()
+                .any(|id| id.kind != IdentityKind::Polynomial)
+        {
+            return None;
+        }
+        let namespaces = witness_names
+            .iter()
+            .map(|name| namespace(name))
+            .collect::<HashSet<_>>();
+        let (latch, block_size) = match &fixed_data
+            .fixed_cols
+            .iter()
+            .filter(|(name, _)| namespaces.contains(namespace(name)))
+            .filter_map(|(name, values)| Some((*name, latch_block_size(values)?)))
+            .collect::<Vec<_>>()[..]
+        {
+            [(latch, block_size)] => (latch.to_string(), *block_size),
+            _ => return None,
+        };
+        Some(Box::new(BlockMachine {
+            latch,
+            block_size,
+            witness_cols: witness_names
+                .iter()
+                .sorted()
+                .map(|n| n.to_string())
+                .collect(),
+            identities: identities
+                .iter()
+                .map(|id| id.left.selector.clone().unwrap())
+                .collect(),
+            blocks: vec![],
+            calls: Default::default(),
+            permutation: false,
+        }))
+    }
+
+    fn column_index(&self, name: &str) -> Option<usize> {
+        self.witness_cols.iter().position(|c| c == name)
+    }
+
+    /// Computes the values of a block whose last row contains the given values.
+    fn compute_block(
+        &self,
+        fixed_data: &FixedData,
+        start: DegreeType,
+        inputs: &[(usize, AbstractNumberType)],
+    ) -> Result<Vec<Vec<AbstractNumberType>>, EvalError> {
+        let mut values = vec![vec![None; self.witness_cols.len()]; self.block_size];
+        for (col, value) in inputs {
+            values[self.block_size - 1][*col] = Some(value.clone());
+        }
+        let witness_count = fixed_data.witness_ids.len();
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for identity in &self.identities {
+                // Identities with next references are only evaluated inside the block.
+                let rows = if contains_next_ref(identity, fixed_data) {
+                    self.block_size - 1
+                } else {
+                    self.block_size
+                };
+                for row in 0..rows {
+                    let evaluated = ExpressionEvaluator::new(BlockRow {
+                        fixed_data,
+                        machine: self,
+                        values: &values,
+                        start,
+                        row,
+                    })
+                    .evaluate(identity)?;
+                    if let Some(value) = evaluated.constant_value() {
+                        if value != 0.into() {
+                            return Err(EvalError::Conflict(format!(
+                                "Identity {identity} = 0 is violated in row {row} of a block of the machine with latch {}.",
+                                self.latch
+                            )));
+                        }
+                    } else if let Some((id, value)) = evaluated.solve() {
+                        let (row, id) = if id >= witness_count {
+                            (row + 1, id - witness_count)
+                        } else {
+                            (row, id)
+                        };
+                        let col = self.column_index(fixed_data.witness_cols[id].name).unwrap();
+                        values[row][col] = Some(clamp(value));
+                        progress = true;
+                    }
+                }
+            }
+        }
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(row, values)| {
+                values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(col, v)| {
+                        v.ok_or_else(|| {
+                            EvalError::Incomplete(format!(
+                                "Could not determine {} in row {row} of a block of the machine with latch {}.",
+                                self.witness_cols[col], self.latch
+                            ))
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Machine for BlockMachine {
+    fn process_plookup(
+        &mut self,
+        fixed_data: &FixedData,
+        row: DegreeType,
+        identity_id: u64,
+        kind: IdentityKind,
+        left: &[Result<AffineExpression, EvalError>],
+        right: &SelectedExpressions,
+    ) -> LookupResult {
+        if kind != IdentityKind::Plookup && kind != IdentityKind::Permutation {
+            return Ok(LookupReturn::NotApplicable);
+        }
+        if right.selector.as_ref().and_then(column_name) != Some(&self.latch) {
+            return Ok(LookupReturn::NotApplicable);
+        }
+        let Some(rhs) = right
+            .expressions
+            .iter()
+            .map(|e| column_name(e).and_then(|name| self.column_index(name)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(LookupReturn::NotApplicable);
+        };
+
+        // Fail if the LHS has an error.
+        let (left, errors): (Vec<_>, Vec<_>) = left.iter().partition_map(|x| match x {
+            Ok(x) => Either::Left(x),
+            Err(x) => Either::Right(x),
+        });
+        if !errors.is_empty() {
+            return Err(errors
+                .into_iter()
+                .cloned()
+                .reduce(eval_error::combine)
+                .unwrap());
+        }
+
+        let call = (kind, identity_id, row);
+        let block = match self.calls.get(&call) {
+            Some(block) => *block,
+            None => {
+                let block = self.blocks.len();
+                let start = (block * self.block_size) as DegreeType;
+                if start + self.block_size as DegreeType > fixed_data.degree {
+                    return Err(EvalError::Conflict(format!(
+                        "The machine with latch {} has no rows left for another block.",
+                        self.latch
+                    )));
+                }
+                let inputs = left
+                    .iter()
+                    .zip(&rhs)
+                    .filter_map(|(l, col)| Some((*col, l.constant_value()?)))
+                    .collect::<Vec<_>>();
+                let values = self.compute_block(fixed_data, start, &inputs)?;
+                if fixed_data.verbose {
+                    println!(
+                        "Block {block} of the machine with latch {} for the call in row {row}.",
+                        self.latch
+                    );
+                }
+                self.blocks.push(values);
+                self.calls.insert(call, block);
+                self.permutation |= kind == IdentityKind::Permutation;
+                block
+            }
+        };
+
+        let last_row = &self.blocks[block][self.block_size - 1];
+        let mut assignments = vec![];
+        for (l, col) in left.iter().zip(&rhs) {
+            let value = last_row[*col].clone();
+            match l.constant_value() {
+                Some(v) if clamp(v.clone()) != value => {
+                    return Err(EvalError::Conflict(format!(
+                        "Value {v} does not match the value {value} of {} computed by the machine.",
+                        self.witness_cols[*col]
+                    )))
+                }
+                Some(_) => {}
+                None => assignments.extend(((*l).clone() - value.into()).solve()),
+            }
+        }
+        Ok(LookupReturn::Assignments(assignments))
+    }
+
+    fn witness_col_values(
+        &mut self,
+        fixed_data: &FixedData,
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError> {
+        let block_count = fixed_data.degree as usize / self.block_size;
+        if self.permutation && self.blocks.len() < block_count {
+            return Err(EvalError::Conflict(format!(
+                "The machine with latch {} is called through a permutation, but only {} of its {block_count} blocks are used. \
+                Unused blocks cannot be filled without violating the permutation.",
+                self.latch,
+                self.blocks.len()
+            )));
+        }
+        let padding = self
+            .blocks
+            .last()
+            .cloned()
+            .unwrap_or_else(|| vec![vec![0.into(); self.witness_cols.len()]; self.block_size]);
+        let rows = self
+            .blocks
+            .iter()
+            .flatten()
+            .chain(padding.iter().cycle())
+            .take(fixed_data.degree as usize)
+            .collect::<Vec<_>>();
+        Ok(self
+            .witness_cols
+            .iter()
+            .enumerate()
+            .map(|(col, name)| {
+                (
+                    name.clone(),
+                    rows.iter().map(|row| row[col].clone()).collect(),
+                )
+            })
+            .collect())
+    }
+}
+
+/// Evaluates expressions on a row of a block, where unknown values in the
+/// current row and the next row are different variables (as in the SymbolicEvaluator).
+struct BlockRow<'a> {
+    fixed_data: &'a FixedData<'a>,
+    machine: &'a BlockMachine,
+    values: &'a [Vec<Option<AbstractNumberType>>],
+    /// The first row of the block.
+    start: DegreeType,
+    /// The row inside the block.
+    row: usize,
+}
+
+impl<'a> SymbolicVariables for BlockRow<'a> {
+    fn constant(&self, name: &str) -> Result<AffineExpression, EvalError> {
+        Ok(self.fixed_data.constants[name].clone().into())
+    }
+
+    fn value(&self, name: &str, next: bool) -> Result<AffineExpression, EvalError> {
+        let row = self.row + next as usize;
+        if let Some(col) = self.machine.column_index(name) {
+            Ok(match &self.values[row][col] {
+                Some(value) => value.clone().into(),
+                None => {
+                    let id = self.fixed_data.witness_ids[name];
+                    let witness_count = self.fixed_data.witness_ids.len();
+                    AffineExpression::from_wittness_poly_value(
+                        id + if next { witness_count } else { 0 },
+                    )
+                }
+            })
+        } else if let Some(values) = self.fixed_data.fixed_cols.get(name) {
+            Ok(values[(self.start as usize + row) % values.len()]
+                .clone()
+                .into())
+        } else {
+            Err(EvalError::Unsupported(format!(
+                "Column {name} is not part of the machine."
+            )))
+        }
+    }
+
+    fn format(&self, expr: AffineExpression) -> String {
+        expr.format(&SymbolicEvaluator::new(self.fixed_data))
+    }
+}
+
+/// Returns the block size if the values are 1 in the last row of each block and 0 otherwise.
+fn latch_block_size(values: &[AbstractNumberType]) -> Option<usize> {
+    let block_size = values.iter().position(|v| *v == 1.into())? + 1;
+    (block_size > 1
+        && values.len().is_multiple_of(block_size)
+        && values.iter().enumerate().all(|(i, v)| {
+            *v == if (i + 1).is_multiple_of(block_size) {
+                1.into()
+            } else {
+                0.into()
+            }
+        }))
+    .then_some(block_size)
+}
+
+fn namespace(name: &str) -> &str {
+    name.split_once('.').map(|(ns, _)| ns).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+    use crate::commit_evaluator;
+    use crate::compiler::no_callback;
+    use crate::constant_evaluator;
+    use crate::number::AbstractNumberType;
+
+    #[test]
+    fn double_three_times() {
+        let src = r#"
+            namespace Main(8);
+                col fixed CALL = [1, 0, 0, 0, 1, 0, 0, 0];
+                col fixed INPUT(i) { i + 1 };
+                col witness a, b;
+                a = CALL * INPUT;
+                (1 - CALL) * b = 0;
+                CALL { a, b } is Double.LATCH { Double.x, Double.acc };
+            namespace Double(8);
+                col fixed FIRST = [1, 0, 0, 0, 1, 0, 0, 0];
+                col fixed LATCH = [0, 0, 0, 1, 0, 0, 0, 1];
+                col witness x, acc;
+                (1 - LATCH) * (x' - x) = 0;
+                FIRST * (acc - x) = 0;
+                (1 - LATCH) * (acc' - 2 * acc) = 0;
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let column = |name| {
+            witness
+                .iter()
+                .find(|(n, _)| *n == name)
+                .unwrap()
+                .1
+                .iter()
+                .map(|v| v.try_into().unwrap())
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(column("Main.b"), vec![8, 0, 0, 0, 40, 0, 0, 0]);
+        assert_eq!(column("Double.x"), vec![1, 1, 1, 1, 5, 5, 5, 5]);
+        assert_eq!(column("Double.acc"), vec![1, 2, 4, 8, 5, 10, 20, 40]);
+    }
+
+    #[test]
+    fn two_calls_in_the_same_row() {
+        let src = r#"
+            namespace Main(8);
+                col fixed CALL = [1, 0, 0, 0, 0, 0, 0, 0];
+                col witness a, b, c, d;
+                a = 3 * CALL;
+                c = 5 * CALL;
+                (1 - CALL) * b = 0;
+                (1 - CALL) * d = 0;
+                CALL { a, b } is Double.LATCH { Double.x, Double.acc };
+                CALL { c, d } is Double.LATCH { Double.x, Double.acc };
+            namespace Double(8);
+                col fixed FIRST = [1, 0, 0, 0, 1, 0, 0, 0];
+                col fixed LATCH = [0, 0, 0, 1, 0, 0, 0, 1];
+                col witness x, acc;
+                (1 - LATCH) * (x' - x) = 0;
+                FIRST * (acc - x) = 0;
+                (1 - LATCH) * (acc' - 2 * acc) = 0;
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let column = |name| &witness.iter().find(|(n, _)| *n == name).unwrap().1;
+        assert_eq!(column("Main.b")[0], 24.into());
+        assert_eq!(column("Main.d")[0], 40.into());
+        assert_eq!(
+            column("Double.acc"),
+            &[3, 6, 12, 24, 5, 10, 20, 40].map(AbstractNumberType::from)
+        );
+    }
+
+    fn double_once(operator: &str) -> String {
+        format!(
+            r#"
+            namespace Main(8);
+                col fixed CALL = [1, 0, 0, 0, 0, 0, 0, 0];
+                col witness a, b;
+                a = 3 * CALL;
+                (1 - CALL) * b = 0;
+                CALL {{ a, b }} {operator} Double.LATCH {{ Double.x, Double.acc }};
+            namespace Double(8);
+                col fixed FIRST = [1, 0, 0, 0, 1, 0, 0, 0];
+                col fixed LATCH = [0, 0, 0, 1, 0, 0, 0, 1];
+                col witness x, acc;
+                (1 - LATCH) * (x' - x) = 0;
+                FIRST * (acc - x) = 0;
+                (1 - LATCH) * (acc' - 2 * acc) = 0;
+        "#
+        )
+    }
+
+    #[test]
+    fn lookup_with_unused_block() {
+        let analyzed = analyze_string(&double_once("in"));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let acc = witness.iter().find(|(n, _)| *n == "Double.acc").unwrap();
+        assert_eq!(
+            acc.1,
+            [3, 6, 12, 24, 3, 6, 12, 24].map(AbstractNumberType::from)
+        );
+    }
+
+    #[test]
+    fn permutation_with_unused_block() {
+        let analyzed = analyze_string(&double_once("is"));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let err =
+            commit_evaluator::check(&analyzed, degree, &constants, no_callback()).unwrap_err();
+        assert_eq!(
+            err.reasons,
+            ["The machine with latch Double.LATCH is called through a permutation, but only 1 of its 2 blocks are used. \
+            Unused blocks cannot be filled without violating the permutation."]
+        );
+    }
+}
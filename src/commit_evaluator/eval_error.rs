@@ -4,10 +4,40 @@ use std::fmt::{Display, Formatter, Result};
 pub enum EvalError {
     /// Previous value of witness column not known when trying to derive a value in the next row.
     PreviousValueUnknown(String),
-    Generic(String),
+    /// Not enough values are known (yet) to derive new values. This might change
+    /// once other identities or machines made progress.
+    Incomplete(String),
+    /// The known values violate a constraint or a lookup. Evaluating the same
+    /// identity again in the same row cannot help.
+    Conflict(String),
+    /// The expression or lookup cannot be handled by the evaluator or machine.
+    Unsupported(String),
     Multiple(Vec<EvalError>),
 }
 
+impl EvalError {
+    /// @returns true if the error (or one of the combined errors) is a conflict.
+    pub fn is_conflict(&self) -> bool {
+        match self {
+            EvalError::Conflict(_) => true,
+            EvalError::Multiple(errors) => errors.iter().any(EvalError::is_conflict),
+            _ => false,
+        }
+    }
+
+    /// Returns an error of the same kind with a new message. Combined errors
+    /// are a conflict if one of them is and incomplete otherwise.
+    pub fn with_message(&self, message: String) -> EvalError {
+        match self {
+            EvalError::Unsupported(_) => EvalError::Unsupported(message),
+            _ if self.is_conflict() => EvalError::Conflict(message),
+            _ => EvalError::Incomplete(message),
+        }
+    }
+}
+
+/// Combines two errors into one, so that all reasons are reported.
+/// The combination is a conflict if one of the errors is.
 pub fn combine(left: EvalError, right: EvalError) -> EvalError {
     match (left, right) {
         (EvalError::Multiple(l), EvalError::Multiple(r)) => {
@@ -20,16 +50,12 @@ pub fn combine(left: EvalError, right: EvalError) -> EvalError {
     }
 }
 
-impl From<String> for EvalError {
-    fn from(value: String) -> Self {
-        EvalError::Generic(value)
-    }
-}
-
 impl Display for EvalError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
-            EvalError::Generic(reason) => write!(f, "{reason}"),
+            EvalError::Incomplete(reason)
+            | EvalError::Conflict(reason)
+            | EvalError::Unsupported(reason) => write!(f, "{reason}"),
             EvalError::PreviousValueUnknown(names) => write!(
                 f,
                 "Previous value of the following column(s) is not (yet) known: {names}.",
@@ -57,3 +83,29 @@ impl Display for EvalError {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kinds_of_combined_errors() {
+        let incomplete = EvalError::Incomplete("x unknown".to_string());
+        let conflict = EvalError::Conflict("1 != 2".to_string());
+        let unknown_previous = EvalError::PreviousValueUnknown("y".to_string());
+        assert!(!combine(incomplete.clone(), unknown_previous.clone()).is_conflict());
+        let combined = combine(combine(incomplete, conflict), unknown_previous);
+        assert!(combined.is_conflict());
+        assert_eq!(
+            format!("{combined}"),
+            "x unknown\n1 != 2\nPrevious value of the following column(s) is not (yet) known: y."
+        );
+        let wrapped = combined.with_message("No progress".to_string());
+        assert!(matches!(wrapped, EvalError::Conflict(m) if m == "No progress"));
+        let unsupported = EvalError::Unsupported("Tuple".to_string());
+        assert!(matches!(
+            unsupported.with_message("No progress".to_string()),
+            EvalError::Unsupported(_)
+        ));
+    }
+}
@@ -1,15 +1,20 @@
 use crate::analyzer::{Expression, Identity, IdentityKind};
-use crate::number::format_number;
+use crate::number::{clamp, format_number};
 use crate::utils::indent;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
+use std::time::Instant;
 // TODO should use finite field instead of abstract number
 use crate::number::{AbstractNumberType, DegreeType};
 
 use super::affine_expression::AffineExpression;
+use super::bit_constraints::determine_bit_constraints;
 use super::eval_error::EvalError;
 use super::expression_evaluator::{ExpressionEvaluator, SymbolicVariables};
+use super::is_zero_gadget::{find_is_zero_gadgets, IsZeroGadget};
 use super::machine::{LookupReturn, Machine};
+use super::profile::Profile;
 use super::util::contains_next_ref;
+use super::witness_error::WitnessError;
 use super::{EvalResult, FixedData, WitnessColumn};
 
 pub struct Evaluator<'a, QueryCallback>
@@ -18,6 +23,10 @@ where
 {
     fixed_data: &'a FixedData<'a>,
     identities: Vec<&'a Identity>,
+    /// Is-zero gadgets among the identities, used to explain failures.
+    is_zero_gadgets: Vec<IsZeroGadget<'a>>,
+    /// The maximal number of bits of witness columns by ID, if known from lookups.
+    bit_constraints: BTreeMap<usize, u64>,
     machines: Vec<Box<dyn Machine>>,
     query_callback: Option<QueryCallback>,
     /// Maps the witness polynomial names to optional parameter and query string.
@@ -29,6 +38,8 @@ where
     next_row: DegreeType,
     failure_reasons: Vec<String>,
     progress: bool,
+    /// If set, the time spent on each identity is recorded here.
+    profile: Option<&'a mut Profile>,
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -53,6 +64,8 @@ where
 
         Evaluator {
             fixed_data,
+            is_zero_gadgets: find_is_zero_gadgets(&identities),
+            bit_constraints: determine_bit_constraints(fixed_data, &identities),
             identities,
             machines,
             query_callback,
@@ -62,18 +75,34 @@ where
             next_row: 0,
             failure_reasons: vec![],
             progress: true,
+            profile: None,
         }
     }
 
-    pub fn compute_next_row(&mut self, next_row: DegreeType) -> Vec<AbstractNumberType> {
+    /// Records the time spent by the generic solver and the machines
+    /// on each identity in `profile`.
+    pub fn with_profile(mut self, profile: &'a mut Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Computes the values of the witness columns in the row `next_row`.
+    pub fn compute_next_row(
+        &mut self,
+        next_row: DegreeType,
+    ) -> Result<Vec<AbstractNumberType>, WitnessError> {
         self.next_row = next_row;
 
         // TODO maybe better to generate a dependency graph than looping multiple times.
         // TODO at least we could cache the affine expressions between loops.
 
         let mut identity_failed;
+        let mut conflict;
+        let mut failed_identities = vec![];
         loop {
             identity_failed = false;
+            conflict = false;
+            failed_identities.clear();
             self.progress = false;
             self.failure_reasons.clear();
 
@@ -81,22 +110,29 @@ where
             for identity in &self.identities.clone() {
                 let result = match identity.kind {
                     IdentityKind::Polynomial => {
-                        self.process_polynomial_identity(identity.left.selector.as_ref().unwrap())
+                        let start = self.start_timer();
+                        let result = self
+                            .process_polynomial_identity(identity.left.selector.as_ref().unwrap());
+                        self.record_time(identity, "solver", start);
+                        result
                     }
                     IdentityKind::Plookup | IdentityKind::Permutation => {
                         self.process_plookup(identity)
                     }
-                    _ => Err("Unsupported lookup type".to_string().into()),
+                    _ => Err(EvalError::Unsupported(
+                        "Unsupported lookup type".to_string(),
+                    )),
                 }
                 .map_err(|err| {
-                    format!(
+                    err.with_message(format!(
                         "No progress on {identity}:\n{}",
                         indent(&format!("{err}"), "    ")
-                    )
-                    .into()
+                    ))
                 });
-                if result.is_err() {
+                if let Err(err) = &result {
                     identity_failed = true;
+                    conflict |= err.is_conflict();
+                    failed_identities.push(*identity);
                 }
                 self.handle_eval_result(result);
             }
@@ -111,7 +147,9 @@ where
                     }
                 }
             }
-            if !self.progress {
+            // Further iterations cannot resolve a conflict, so we stop early unless
+            // we are in the first row, where failures are not fatal.
+            if !self.progress || (conflict && next_row != 0) {
                 break;
             }
             if self.next.iter().all(|v| v.is_some()) {
@@ -121,25 +159,40 @@ where
         // Identity check failure on the first row is not fatal. We will proceed with
         // "unknown", report zero and re-check the wrap-around against the zero values at the end.
         if identity_failed && next_row != 0 {
-            eprintln!(
-                "\nError: Row {next_row}: Identity check failer or unable to derive values for witness polynomials: {}\n",
-                self.next
+            let mut reasons = self
+                .is_zero_gadgets
+                .iter()
+                .filter_map(|gadget| {
+                    let value = self
+                        .fixed_data
+                        .witness_ids
+                        .get(gadget.value)
+                        .and_then(|id| self.next[*id].as_ref());
+                    gadget.explain_failure(next_row, value, &failed_identities)
+                })
+                .collect::<Vec<_>>();
+            reasons.extend(self.failure_reasons.iter().cloned());
+            Err(WitnessError {
+                row: next_row,
+                failed_identities: failed_identities
                     .iter()
-                    .enumerate()
-                    .filter_map(|(i, v)| if v.is_none() {
-                        Some(self.fixed_data.witness_cols[i].name.to_string())
-                    } else {
-                        None
+                    .map(|identity| {
+                        format!(
+                            "{}:{}: {identity}",
+                            identity.source.file, identity.source.line
+                        )
                     })
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            );
-            eprintln!("Reasons:\n{}\n", self.failure_reasons.join("\n\n"));
-            eprintln!(
-                "Current values:\n{}",
-                indent(&self.format_next_values().join("\n"), "    ")
-            );
-            panic!();
+                    .collect(),
+                unknown_columns: self
+                    .next
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, v)| v.is_none())
+                    .map(|(i, _)| self.fixed_data.witness_cols[i].name.to_string())
+                    .collect(),
+                reasons,
+                values: self.format_next_values(),
+            })
         } else {
             if self.fixed_data.verbose {
                 println!(
@@ -151,19 +204,27 @@ where
             self.next = vec![None; self.current.len()];
             // TODO check a bit better that "None" values do not
             // violate constraints.
-            self.current
+            Ok(self
+                .current
                 .iter()
                 .map(|v| v.clone().unwrap_or_default())
-                .collect()
+                .collect())
         }
     }
 
-    pub fn machine_witness_col_values(&mut self) -> HashMap<String, Vec<AbstractNumberType>> {
-        let mut result: HashMap<_, _> = Default::default();
+    /// @returns the values of the row computed last, `None` if a value could not be derived.
+    pub fn current_values(&self) -> &[Option<AbstractNumberType>] {
+        &self.current
+    }
+
+    pub fn machine_witness_col_values(
+        &mut self,
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError> {
+        let mut result = BTreeMap::new();
         for m in &mut self.machines {
-            result.extend(m.witness_col_values(self.fixed_data));
+            result.extend(m.witness_col_values(self.fixed_data)?);
         }
-        result
+        Ok(result)
     }
 
     fn format_next_values(&self) -> Vec<String> {
@@ -186,11 +247,16 @@ where
         &mut self,
         column: &&WitnessColumn,
     ) -> Result<Vec<(usize, AbstractNumberType)>, EvalError> {
-        let query = self.interpolate_query(column.query.unwrap())?;
+        let query = self
+            .interpolate_query(column.query.unwrap())
+            .map_err(EvalError::Incomplete)?;
         if let Some(value) = self.query_callback.as_mut().and_then(|c| (c)(&query)) {
             Ok(vec![(column.id, value)])
         } else {
-            Err(format!("No query answer for {} query: {query}.", column.name).into())
+            Err(EvalError::Incomplete(format!(
+                "No query answer for {} query: {query}.",
+                column.name
+            )))
         }
     }
 
@@ -231,14 +297,22 @@ where
         if evaluated.constant_value() == Some(0.into()) {
             Ok(vec![])
         } else {
+            // Variables of the next row have IDs offset by the number of witness columns.
+            let witness_count = self.fixed_data.witness_cols.len();
+            let bits = |id| self.bit_constraints.get(&(id % witness_count)).copied();
             match evaluated.solve() {
                 Some((id, value)) => Ok(vec![(id, value)]),
                 None => {
+                    if let Some(assignments) = evaluated.solve_with_bit_constraints(bits) {
+                        return Ok(assignments);
+                    }
                     let formatted = evaluated.format(self.fixed_data);
                     Err(if evaluated.is_invalid() {
-                        format!("Constraint is invalid ({formatted} != 0).").into()
+                        EvalError::Conflict(format!("Constraint is invalid ({formatted} != 0)."))
                     } else {
-                        format!("Could not solve expression {formatted} = 0.").into()
+                        EvalError::Incomplete(format!(
+                            "Could not solve expression {formatted} = 0."
+                        ))
                     })
                 }
             }
@@ -246,19 +320,83 @@ where
     }
 
     fn process_plookup(&mut self, identity: &Identity) -> EvalResult {
+        let start = self.start_timer();
+        let left = self.evaluate_plookup_left(identity);
+        self.record_time(identity, "solver", start);
+        let Some((row, left)) = left? else {
+            return Ok(vec![]);
+        };
+
+        // Now query the machines.
+        // Note that we should always query all machines that match, because they might
+        // update their internal data, even if all values are already known.
+        // TODO could it be that multiple machines match?
+        for m in &mut self.machines {
+            let start = self.profile.is_some().then(Instant::now);
+            let result = m.process_plookup(
+                self.fixed_data,
+                row,
+                identity.id,
+                identity.kind,
+                &left,
+                &identity.right,
+            );
+            if let (Some(profile), Some(start)) = (&mut self.profile, start) {
+                profile.record(identity, m.name(), start.elapsed());
+            }
+            // TODO also consider the reasons above.
+            if let LookupReturn::Assignments(assignments) = result? {
+                return Ok(assignments);
+            }
+        }
+
+        Err(EvalError::Unsupported(
+            "Could not find a matching machine for the lookup.".to_string(),
+        ))
+    }
+
+    /// Evaluates the left hand side of a plookup.
+    /// @returns the row the lookup is issued in and the values on the left hand side,
+    /// or `None` if the selector is zero.
+    #[allow(clippy::type_complexity)]
+    fn evaluate_plookup_left(
+        &self,
+        identity: &Identity,
+    ) -> Result<Option<(DegreeType, Vec<Result<AffineExpression, EvalError>>)>, EvalError> {
+        // Like polynomial identities, lookups that reference the "next" row
+        // are evaluated on the current row.
+        let (evaluate_row, row) = if identity
+            .left
+            .selector
+            .iter()
+            .chain(&identity.left.expressions)
+            .any(|e| contains_next_ref(e, self.fixed_data))
+        {
+            let degree = self.fixed_data.degree;
+            (
+                EvaluationRow::Current,
+                (self.next_row + degree - 1) % degree,
+            )
+        } else {
+            (EvaluationRow::Next, self.next_row)
+        };
         if let Some(left_selector) = &identity.left.selector {
-            let value = self.evaluate(left_selector, EvaluationRow::Next)?;
+            let value = self.evaluate(left_selector, evaluate_row)?;
             match value.constant_value() {
                 Some(v) if v == 0.into() => {
-                    return Ok(vec![]);
+                    return Ok(None);
                 }
                 Some(v) if v == 1.into() => {}
-                _ => {
-                    return Err(format!(
-                        "Value of the selector on the left hand side unknown or not boolean: {}",
+                Some(v) => {
+                    return Err(EvalError::Conflict(format!(
+                        "Value of the selector on the left hand side is not boolean: {v}",
+                    )))
+                }
+                None => {
+                    return Err(EvalError::Incomplete(format!(
+                        "Value of the selector on the left hand side unknown: {}",
                         value.format(self.fixed_data)
-                    )
-                    .into())
+                    )))
                 }
             };
         }
@@ -267,32 +405,33 @@ where
             .left
             .expressions
             .iter()
-            .map(|e| self.evaluate(e, EvaluationRow::Next))
+            .map(|e| self.evaluate(e, evaluate_row))
             .collect::<Vec<_>>();
+        Ok(Some((row, left)))
+    }
 
-        // Now query the machines.
-        // Note that we should always query all machines that match, because they might
-        // update their internal data, even if all values are already known.
-        // TODO could it be that multiple machines match?
-        for m in &mut self.machines {
-            // TODO also consider the reasons above.
-            if let LookupReturn::Assignments(assignments) =
-                m.process_plookup(self.fixed_data, identity.kind, &left, &identity.right)?
-            {
-                return Ok(assignments);
-            }
-        }
+    fn start_timer(&self) -> Option<Instant> {
+        self.profile.is_some().then(Instant::now)
+    }
 
-        Err("Could not find a matching machine for the lookup."
-            .to_string()
-            .into())
+    fn record_time(
+        &mut self,
+        identity: &Identity,
+        component: &'static str,
+        start: Option<Instant>,
+    ) {
+        if let (Some(profile), Some(start)) = (&mut self.profile, start) {
+            profile.record(identity, component, start.elapsed());
+        }
     }
 
     fn handle_eval_result(&mut self, result: EvalResult) {
         match result {
             Ok(assignments) => {
                 for (id, value) in assignments {
-                    self.next[id] = Some(value);
+                    // Values can come from outside (e.g. the query callback),
+                    // so we make sure to only store canonical field elements.
+                    self.next[id] = Some(clamp(value));
                     self.progress = true;
                 }
             }
@@ -365,10 +504,9 @@ impl<'a> SymbolicVariables for EvaluationData<'a> {
                 }
                 (true, EvaluationRow::Next) => {
                     // "double next" or evaluation of a witness on a specific row
-                    Err(format!(
+                    Err(EvalError::Unsupported(format!(
                         "{name}' references the next-next row when evaluating on the current row.",
-                    )
-                    .into())
+                    )))
                 }
             }
         } else {
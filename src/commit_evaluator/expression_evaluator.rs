@@ -34,19 +34,21 @@ impl<SV: SymbolicVariables> ExpressionEvaluator<SV> {
                 self.evaluate_binary_operation(left, op, right)
             }
             Expression::UnaryOperation(op, expr) => self.evaluate_unary_operation(op, expr),
-            Expression::Tuple(_) => Err("Tuple not implemented.".to_string().into()),
-            Expression::String(_) => Err("String not implemented.".to_string().into()),
-            Expression::LocalVariableReference(_) => {
-                Err("Local variable references not implemented."
-                    .to_string()
-                    .into())
-            }
-            Expression::PublicReference(_) => {
-                Err("Public references not implemented.".to_string().into())
-            }
-            Expression::FunctionCall(_, _) => {
-                Err("Function calls not implemented.".to_string().into())
+            Expression::Tuple(_) => {
+                Err(EvalError::Unsupported("Tuple not implemented.".to_string()))
             }
+            Expression::String(_) => Err(EvalError::Unsupported(
+                "String not implemented.".to_string(),
+            )),
+            Expression::LocalVariableReference(_) => Err(EvalError::Unsupported(
+                "Local variable references not implemented.".to_string(),
+            )),
+            Expression::PublicReference(_) => Err(EvalError::Unsupported(
+                "Public references not implemented.".to_string(),
+            )),
+            Expression::FunctionCall(_, _) => Err(EvalError::Unsupported(
+                "Function calls not implemented.".to_string(),
+            )),
         }
     }
 
@@ -66,12 +68,11 @@ impl<SV: SymbolicVariables> ExpressionEvaluator<SV> {
                     } else if let Some(f) = right.constant_value() {
                         Ok(left.mul(f))
                     } else {
-                        Err(format!(
+                        Err(EvalError::Incomplete(format!(
                             "Multiplication of two non-constants: ({}) * ({})",
                             self.variables.format(left),
                             self.variables.format(right),
-                        )
-                        .into())
+                        )))
                     }
                 }
                 BinaryOperator::Div => {
@@ -84,31 +85,36 @@ impl<SV: SymbolicVariables> ExpressionEvaluator<SV> {
                             Ok((l / r).into())
                         }
                     } else {
-                        Err(format!(
+                        Err(EvalError::Incomplete(format!(
                             "Division of two non-constants: ({}) / ({})",
                             self.variables.format(left),
                             self.variables.format(right),
-                        )
-                        .into())
+                        )))
                     }
                 }
                 BinaryOperator::Pow => {
                     if let (Some(l), Some(r)) = (left.constant_value(), right.constant_value()) {
                         Ok(l.pow(abstract_to_degree(&r) as u32).into())
                     } else {
-                        Err(format!(
+                        Err(EvalError::Incomplete(format!(
                             "Pow of two non-constants: ({}) ** ({})",
                             self.variables.format(left),
                             self.variables.format(right),
-                        )
-                        .into())
+                        )))
                     }
                 }
                 BinaryOperator::Mod
                 | BinaryOperator::BinaryAnd
                 | BinaryOperator::BinaryOr
+                | BinaryOperator::BinaryXor
                 | BinaryOperator::ShiftLeft
-                | BinaryOperator::ShiftRight => {
+                | BinaryOperator::ShiftRight
+                | BinaryOperator::Less
+                | BinaryOperator::LessEqual
+                | BinaryOperator::Greater
+                | BinaryOperator::GreaterEqual
+                | BinaryOperator::Equal
+                | BinaryOperator::NotEqual => {
                     if let (Some(left), Some(right)) =
                         (left.constant_value(), right.constant_value())
                     {
@@ -116,9 +122,10 @@ impl<SV: SymbolicVariables> ExpressionEvaluator<SV> {
                             BinaryOperator::Mod => left % right,
                             BinaryOperator::BinaryAnd => left & right,
                             BinaryOperator::BinaryOr => left | right,
+                            BinaryOperator::BinaryXor => left ^ right,
                             BinaryOperator::ShiftLeft => left << abstract_to_degree(&right),
                             BinaryOperator::ShiftRight => left >> abstract_to_degree(&right),
-                            _ => panic!(),
+                            op => op.compare(&left, &right),
                         };
                         Ok(result.into())
                     } else {
@@ -139,6 +146,7 @@ impl<SV: SymbolicVariables> ExpressionEvaluator<SV> {
         self.evaluate(expr).map(|v| match op {
             UnaryOperator::Plus => v,
             UnaryOperator::Minus => -v,
+            UnaryOperator::LogicalNot => unreachable!(),
         })
     }
 }
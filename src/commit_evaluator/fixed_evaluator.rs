@@ -31,9 +31,9 @@ impl<'a> SymbolicVariables for FixedEvaluator<'a> {
             };
             Ok(col_data[row].clone().into())
         } else {
-            Err("Can only accesss fixed columns in the fixed evaluator."
-                .to_string()
-                .into())
+            Err(EvalError::Unsupported(
+                "Can only accesss fixed columns in the fixed evaluator.".to_string(),
+            ))
         }
     }
 
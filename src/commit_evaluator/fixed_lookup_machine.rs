@@ -1,4 +1,6 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use itertools::Itertools;
 
 use crate::analyzer::{Expression, Identity, IdentityKind, SelectedExpressions};
 use crate::commit_evaluator::eval_error;
@@ -14,8 +16,21 @@ use super::machine::{LookupResult, Machine};
 use super::{EvalResult, FixedData};
 
 /// Machine to perform a lookup in fixed columns only.
-/// It only supports lookup in the first column of the query and will use the first match.
-pub struct FixedLookup {}
+/// For plookups, it only supports lookup in the first column of the query and will use the first match.
+/// For permutations, it uses the first row that matches all known values on the LHS
+/// and has not been used by another row.
+#[derive(Default)]
+pub struct FixedLookup {
+    /// The rows of the table used by permutations, by the RHS of the permutation.
+    permutations: HashMap<String, PermutationRows>,
+}
+
+#[derive(Default)]
+struct PermutationRows {
+    /// The table row used by each row of the LHS.
+    assigned: HashMap<DegreeType, DegreeType>,
+    used: HashSet<DegreeType>,
+}
 
 impl FixedLookup {
     pub fn try_new(
@@ -24,7 +39,7 @@ impl FixedLookup {
         witness_names: &HashSet<&str>,
     ) -> Option<Box<Self>> {
         if identities.is_empty() && witness_names.is_empty() {
-            Some(Box::new(FixedLookup {}))
+            Some(Box::default())
         } else {
             None
         }
@@ -35,12 +50,14 @@ impl Machine for FixedLookup {
     fn process_plookup(
         &mut self,
         fixed_data: &FixedData,
+        row: DegreeType,
+        _identity_id: u64,
         kind: IdentityKind,
         left: &[Result<AffineExpression, EvalError>],
         right: &SelectedExpressions,
     ) -> LookupResult {
-        // This is a matching machine if it is a plookup and the RHS is fully constant.
-        if kind != IdentityKind::Plookup
+        // This is a matching machine if it is a plookup or permutation and the RHS is fully constant.
+        if !matches!(kind, IdentityKind::Plookup | IdentityKind::Permutation)
             || right.selector.is_some()
             || right
                 .expressions
@@ -50,6 +67,10 @@ impl Machine for FixedLookup {
             return Ok(LookupReturn::NotApplicable);
         }
 
+        if kind == IdentityKind::Permutation {
+            return self.process_permutation(fixed_data, row, left, right);
+        }
+
         // If we already know the LHS, skip it.
         if left
             .iter()
@@ -61,12 +82,14 @@ impl Machine for FixedLookup {
         let left_key = match left[0].clone() {
             Ok(v) => match v.constant_value() {
                 Some(v) => Ok(v),
-                None => Err(format!(
+                None => Err(EvalError::Incomplete(format!(
                     "First expression needs to be constant but is not: {}.",
                     v.format(fixed_data)
-                )),
+                ))),
             },
-            Err(err) => Err(format!("First expression on the LHS is unknown: {err}")),
+            Err(err) => {
+                Err(err.with_message(format!("First expression on the LHS is unknown: {err}")))
+            }
         }?;
 
         let right_key = right.expressions.first().unwrap();
@@ -76,13 +99,15 @@ impl Machine for FixedLookup {
                 .get(poly.name.as_str())
                 .and_then(|values| values.iter().position(|v| *v == left_key))
                 .ok_or_else(|| {
-                    format!(
+                    EvalError::Conflict(format!(
                         "Unable to find matching row on the RHS where the first element is {left_key} - only fixed columns supported there."
-                    )
+                    ))
                 })
                 .map(|i| i as DegreeType)
         } else {
-            Err("First item on the RHS must be a polynomial reference.".to_string())
+            Err(EvalError::Unsupported(
+                "First item on the RHS must be a polynomial reference.".to_string(),
+            ))
         }?;
 
         // TODO we only support the following case:
@@ -90,16 +115,100 @@ impl Machine for FixedLookup {
         // - The first component on the RHS has to be a direct fixed column reference
         // - The first match of those uniquely determines the rest of the RHS.
 
+        self.equate_to_rhs_row(fixed_data, left, right, rhs_row, 1)
+    }
+    fn witness_col_values(
+        &mut self,
+        _fixed_data: &FixedData,
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError> {
+        Ok(Default::default())
+    }
+}
+
+impl FixedLookup {
+    /// Assigns an unused row of the table to the LHS row `row`, unless one is assigned already,
+    /// and equates the LHS to it. All values on the LHS that are known have to match the table row.
+    fn process_permutation(
+        &mut self,
+        fixed_data: &FixedData,
+        row: DegreeType,
+        left: &[Result<AffineExpression, EvalError>],
+        right: &SelectedExpressions,
+    ) -> LookupResult {
+        let rows = self.permutations.entry(right.to_string()).or_default();
+        let rhs_row = match rows.assigned.get(&row) {
+            Some(rhs_row) => *rhs_row,
+            None => {
+                let known = left
+                    .iter()
+                    .zip(&right.expressions)
+                    .filter_map(|(l, r)| {
+                        l.as_ref()
+                            .ok()
+                            .and_then(|l| l.constant_value())
+                            .map(|l| (l, r))
+                    })
+                    .collect::<Vec<_>>();
+                if known.is_empty() {
+                    return Err(EvalError::Incomplete(
+                        "No value on the LHS of the permutation is known.".to_string(),
+                    ));
+                }
+                let rhs_row = (0..fixed_data.degree)
+                    .filter(|r| !rows.used.contains(r))
+                    .find(|r| {
+                        let rhs_evaluator = ExpressionEvaluator::new(EvaluateFixedOnRow {
+                            fixed_data,
+                            row: *r,
+                        });
+                        known.iter().all(|(l, r)| {
+                            rhs_evaluator
+                                .evaluate(r)
+                                .map(|r| r.constant_value().as_ref() == Some(l))
+                                .unwrap_or(false)
+                        })
+                    })
+                    .ok_or_else(|| {
+                        EvalError::Conflict(format!(
+                            "No unused row of the permutation table matches the known values {}.",
+                            known.iter().map(|(l, r)| format!("{r} = {l}")).join(", ")
+                        ))
+                    })?;
+                rows.used.insert(rhs_row);
+                rows.assigned.insert(row, rhs_row);
+                rhs_row
+            }
+        };
+        if left
+            .iter()
+            .all(|v| v.is_ok() && v.as_ref().unwrap().is_constant())
+        {
+            return Ok(LookupReturn::Assignments(vec![]));
+        }
+        self.equate_to_rhs_row(fixed_data, left, right, rhs_row, 0)
+    }
+
+    /// Equates the LHS components starting at `skip` to the RHS evaluated on `rhs_row`.
+    fn equate_to_rhs_row(
+        &self,
+        fixed_data: &FixedData,
+        left: &[Result<AffineExpression, EvalError>],
+        right: &SelectedExpressions,
+        rhs_row: DegreeType,
+        skip: usize,
+    ) -> LookupResult {
         let mut reasons = vec![];
         let mut result = vec![];
-        for (l, r) in left.iter().zip(right.expressions.iter()).skip(1) {
+        for (l, r) in left.iter().zip(right.expressions.iter()).skip(skip) {
             match l {
                 Ok(l) => match self.equate_to_constant_rhs(l, r, fixed_data, rhs_row) {
                     Ok(assignments) => result.extend(assignments),
                     Err(err) => reasons.push(err),
                 },
                 Err(err) => {
-                    reasons.push(format!("Value of LHS component too complex: {err}").into());
+                    reasons.push(
+                        err.with_message(format!("Value of LHS component too complex: {err}")),
+                    );
                 }
             }
         }
@@ -109,15 +218,7 @@ impl Machine for FixedLookup {
             Ok(LookupReturn::Assignments(result))
         }
     }
-    fn witness_col_values(
-        &mut self,
-        _fixed_data: &FixedData,
-    ) -> HashMap<String, Vec<AbstractNumberType>> {
-        Default::default()
-    }
-}
 
-impl FixedLookup {
     fn equate_to_constant_rhs(
         &self,
         l: &AffineExpression,
@@ -133,8 +234,9 @@ impl FixedLookup {
         // This needs to be a costant because symbolic variables
         // would reference a different row!
         let r = rhs_evaluator.evaluate(r).and_then(|r| {
-            r.constant_value()
-                .ok_or_else(|| format!("Constant value required: {}", r.format(fixed_data)).into())
+            r.constant_value().ok_or_else(|| {
+                EvalError::Unsupported(format!("Constant value required: {}", r.format(fixed_data)))
+            })
         })?;
 
         let evaluated = l.clone() - r.clone().into();
@@ -143,9 +245,9 @@ impl FixedLookup {
             None => {
                 let formatted = l.format(fixed_data);
                 Err(if evaluated.is_invalid() {
-                    format!("Constraint is invalid ({formatted} != {r}).",).into()
+                    EvalError::Conflict(format!("Constraint is invalid ({formatted} != {r}).",))
                 } else {
-                    format!("Could not solve expression {formatted} = {r}.",).into()
+                    EvalError::Incomplete(format!("Could not solve expression {formatted} = {r}.",))
                 })
             }
         }
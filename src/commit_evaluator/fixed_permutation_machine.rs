@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::analyzer::{Expression, Identity, IdentityKind, SelectedExpressions};
+use crate::commit_evaluator::machine::LookupReturn;
+use crate::number::{clamp, AbstractNumberType, DegreeType};
+
+use super::affine_expression::AffineExpression;
+use super::eval_error::EvalError;
+use super::machine::{LookupResult, Machine};
+use super::util::column_name;
+use super::{EvalResult, FixedData};
+
+/// Machine to perform two-column lookups `{a, b} in {A, B}` where the fixed columns
+/// `A` and `B` contain distinct values and `B` is a permutation of `A` (like a rotation
+/// or shift table). The mapping is bijective, so it can be evaluated in both directions:
+/// If `a` is known, `b` is derived and vice-versa.
+pub struct FixedPermutation {
+    /// The row of each (reduced) value, for all fixed columns whose values are distinct.
+    rows: HashMap<String, HashMap<AbstractNumberType, DegreeType>>,
+    /// Columns with the same value set have the same group.
+    groups: HashMap<String, usize>,
+}
+
+impl FixedPermutation {
+    pub fn try_new(
+        fixed_data: &FixedData,
+        identities: &[&Identity],
+        witness_names: &HashSet<&str>,
+    ) -> Option<Box<Self>> {
+        if !identities.is_empty() || !witness_names.is_empty() {
+            return None;
+        }
+        let rows = fixed_data
+            .fixed_cols
+            .iter()
+            .filter_map(|(name, values)| {
+                let rows = values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (clamp(v.clone()), i as DegreeType))
+                    .collect::<HashMap<_, _>>();
+                (rows.len() == values.len()).then(|| (name.to_string(), rows))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut value_sets: BTreeMap<BTreeSet<&AbstractNumberType>, usize> = BTreeMap::new();
+        let mut groups = HashMap::new();
+        for (name, rows) in &rows {
+            let group_count = value_sets.len();
+            let group = *value_sets
+                .entry(rows.keys().collect())
+                .or_insert(group_count);
+            groups.insert(name.clone(), group);
+        }
+        // Only keep columns that are a permutation of another column.
+        let group_sizes = groups.values().fold(HashMap::new(), |mut acc, g| {
+            *acc.entry(*g).or_insert(0) += 1;
+            acc
+        });
+        groups.retain(|_, g| group_sizes[g] > 1);
+        if groups.is_empty() {
+            None
+        } else {
+            let rows = rows
+                .into_iter()
+                .filter(|(name, _)| groups.contains_key(name))
+                .collect();
+            Some(Box::new(FixedPermutation { rows, groups }))
+        }
+    }
+
+    fn column_name<'b>(&self, expr: &'b Expression) -> Option<&'b str> {
+        column_name(expr).filter(|name| self.groups.contains_key(*name))
+    }
+}
+
+impl Machine for FixedPermutation {
+    fn process_plookup(
+        &mut self,
+        fixed_data: &FixedData,
+        _row: DegreeType,
+        _identity_id: u64,
+        kind: IdentityKind,
+        left: &[Result<AffineExpression, EvalError>],
+        right: &SelectedExpressions,
+    ) -> LookupResult {
+        let (from, to) = match (kind, &right.selector, &right.expressions[..]) {
+            (IdentityKind::Plookup, None, [from, to]) => {
+                match (self.column_name(from), self.column_name(to)) {
+                    (Some(from), Some(to)) if self.groups[from] == self.groups[to] => (from, to),
+                    _ => return Ok(LookupReturn::NotApplicable),
+                }
+            }
+            _ => return Ok(LookupReturn::NotApplicable),
+        };
+
+        let known = |v: &Result<AffineExpression, EvalError>| {
+            v.as_ref().ok().and_then(|v| v.constant_value())
+        };
+        let columns = [from, to];
+        let (known_index, value) = match (known(&left[0]), known(&left[1])) {
+            (Some(value), _) => (0, value),
+            (None, Some(value)) => (1, value),
+            (None, None) => {
+                return Err(EvalError::Incomplete(format!(
+                    "Neither side of the lookup into the permutation {from} -> {to} is known."
+                )))
+            }
+        };
+        let rhs_row = self.rows[columns[known_index]].get(&value).ok_or_else(|| {
+            EvalError::Conflict(format!(
+                "Value {value} does not occur in the fixed column {}.",
+                columns[known_index]
+            ))
+        })?;
+        let other = 1 - known_index;
+        let expected = fixed_data.fixed_cols[columns[other]][*rhs_row as usize].clone();
+        match &left[other] {
+            Ok(l) => equate(l, expected, fixed_data).map(LookupReturn::Assignments),
+            Err(err) => Err(err.with_message(format!("Value of LHS component too complex: {err}"))),
+        }
+    }
+
+    fn witness_col_values(
+        &mut self,
+        _fixed_data: &FixedData,
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError> {
+        Ok(Default::default())
+    }
+}
+
+/// Solves `l = value`, or checks it if `l` is constant.
+fn equate(l: &AffineExpression, value: AbstractNumberType, fixed_data: &FixedData) -> EvalResult {
+    let evaluated = l.clone() - value.clone().into();
+    if evaluated.is_constant() && !evaluated.is_invalid() {
+        return Ok(vec![]);
+    }
+    match evaluated.solve() {
+        Some((id, value)) => Ok(vec![(id, value)]),
+        None => {
+            let formatted = l.format(fixed_data);
+            Err(if evaluated.is_invalid() {
+                EvalError::Conflict(format!("Constraint is invalid ({formatted} != {value})."))
+            } else {
+                EvalError::Incomplete(format!("Could not solve expression {formatted} = {value}."))
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::analyzer::{PolynomialReference, SelectedExpressions};
+    use crate::commit_evaluator::FixedData;
+
+    use super::*;
+
+    fn lookup_into(from: &str, to: &str) -> SelectedExpressions {
+        let reference = |name: &str| {
+            Expression::PolynomialReference(PolynomialReference {
+                name: name.to_string(),
+                index: None,
+                next: false,
+            })
+        };
+        SelectedExpressions {
+            selector: None,
+            expressions: vec![reference(from), reference(to)],
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        let constants = HashMap::new();
+        let values = (0..8).map(AbstractNumberType::from).collect::<Vec<_>>();
+        let rotated = (0..8)
+            .map(|i| AbstractNumberType::from((i + 3) % 8))
+            .collect::<Vec<_>>();
+        let other = (0..8)
+            .map(|i| AbstractNumberType::from(i % 3))
+            .collect::<Vec<_>>();
+        let fixed_cols = [
+            ("Main.IN", &values),
+            ("Main.OUT", &rotated),
+            ("Main.X", &other),
+        ]
+        .into_iter()
+        .collect();
+        let witness_cols = vec![];
+        let fixed_data = FixedData::new(
+            8,
+            &constants,
+            fixed_cols,
+            &witness_cols,
+            HashMap::new(),
+            false,
+        );
+        let mut machine = FixedPermutation::try_new(&fixed_data, &[], &HashSet::new()).unwrap();
+
+        let unknown = || AffineExpression::from_wittness_poly_value(0);
+        let known = |v: i32| AffineExpression::from(AbstractNumberType::from(v));
+        let mut lookup = |left: [AffineExpression; 2], from: &str, to: &str| {
+            machine.process_plookup(
+                &fixed_data,
+                0,
+                0,
+                IdentityKind::Plookup,
+                &left.map(Ok),
+                &lookup_into(from, to),
+            )
+        };
+        assert!(matches!(
+            lookup([known(6), unknown()], "Main.IN", "Main.OUT"),
+            Ok(LookupReturn::Assignments(a)) if a == [(0, 1.into())]
+        ));
+        assert!(matches!(
+            lookup([unknown(), known(6)], "Main.IN", "Main.OUT"),
+            Ok(LookupReturn::Assignments(a)) if a == [(0, 3.into())]
+        ));
+        assert!(matches!(
+            lookup([known(6), known(1)], "Main.IN", "Main.OUT"),
+            Ok(LookupReturn::Assignments(a)) if a.is_empty()
+        ));
+        assert!(lookup([known(6), known(2)], "Main.IN", "Main.OUT").is_err());
+        assert!(lookup([known(9), unknown()], "Main.IN", "Main.OUT").is_err());
+        assert!(matches!(
+            lookup([known(1), unknown()], "Main.IN", "Main.X"),
+            Ok(LookupReturn::NotApplicable)
+        ));
+    }
+}
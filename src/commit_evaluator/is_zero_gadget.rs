@@ -0,0 +1,197 @@
+use crate::analyzer::{BinaryOperator, Expression, Identity, IdentityKind};
+use crate::number::{field_mod, inv, is_zero, AbstractNumberType, DegreeType};
+
+use super::util::{column_name, expr_any};
+
+/// The "is zero" gadget
+///     is_zero = 1 - value * inverse;
+///     is_zero * value = 0;
+/// where all three are witness columns. It forces `is_zero` to be 1 if `value` is zero
+/// and 0 otherwise. It is recognized to explain failures of the witness generation
+/// that are caused by other constraints on `is_zero`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IsZeroGadget<'a> {
+    pub is_zero: &'a str,
+    pub value: &'a str,
+    pub inverse: &'a str,
+}
+
+/// Finds all is-zero gadgets among the polynomial identities.
+pub fn find_is_zero_gadgets<'a>(identities: &[&'a Identity]) -> Vec<IsZeroGadget<'a>> {
+    let polynomial_identities = identities
+        .iter()
+        .filter(|id| id.kind == IdentityKind::Polynomial)
+        .filter_map(|id| id.left.selector.as_ref())
+        .collect::<Vec<_>>();
+    let products = polynomial_identities
+        .iter()
+        .filter_map(|e| zero_product(e))
+        .collect::<Vec<_>>();
+    polynomial_identities
+        .iter()
+        .filter_map(|e| inverse_definition(e))
+        .filter_map(|(is_zero, a, b)| {
+            // The product identity determines which factor is the value.
+            [(a, b), (b, a)]
+                .into_iter()
+                .find(|(value, _)| {
+                    products.contains(&(is_zero, value)) || products.contains(&(value, is_zero))
+                })
+                .map(|(value, inverse)| IsZeroGadget {
+                    is_zero,
+                    value,
+                    inverse,
+                })
+        })
+        .collect()
+}
+
+impl<'a> IsZeroGadget<'a> {
+    /// Explains why the witness generation failed in `row` if any of the `failed`
+    /// identities references a column of the gadget. `value` is the value of
+    /// the `value` column in that row, if known.
+    pub fn explain_failure(
+        &self,
+        row: DegreeType,
+        value: Option<&AbstractNumberType>,
+        failed: &[&Identity],
+    ) -> Option<String> {
+        let related = failed
+            .iter()
+            .filter(|identity| self.is_referenced_by(identity))
+            .map(|identity| format!("    {identity}"))
+            .collect::<Vec<_>>();
+        if related.is_empty() {
+            return None;
+        }
+        let gadget = format!(
+            "the is-zero gadget {} = 1 - {} * {}",
+            self.is_zero, self.value, self.inverse
+        );
+        Some(match value {
+            None => format!(
+                "Row {row}: The value of {} is unknown, so {gadget} cannot be solved. Failed constraints:\n{}",
+                self.value,
+                related.join("\n")
+            ),
+            Some(v) if is_zero(v) => format!(
+                "Row {row}: {} is zero, so {gadget} requires {} = 1, which conflicts with:\n{}",
+                self.value,
+                self.is_zero,
+                related.join("\n")
+            ),
+            Some(v) => format!(
+                "Row {row}: {} = {v} is not zero, so {gadget} requires {} = 0 and {} = {}, which conflicts with:\n{}",
+                self.value,
+                self.is_zero,
+                self.inverse,
+                inv(v.clone(), field_mod()),
+                related.join("\n")
+            ),
+        })
+    }
+
+    fn is_referenced_by(&self, identity: &Identity) -> bool {
+        [&identity.left, &identity.right]
+            .into_iter()
+            .flat_map(|selected| selected.selector.iter().chain(&selected.expressions))
+            .any(|e| {
+                expr_any(e, &mut |e| match e {
+                    Expression::PolynomialReference(poly) => {
+                        [self.is_zero, self.value, self.inverse].contains(&poly.name.as_str())
+                    }
+                    _ => false,
+                })
+            })
+    }
+}
+
+/// Matches `is_zero - (1 - a * b)` and returns the three column names.
+fn inverse_definition(e: &Expression) -> Option<(&str, &str, &str)> {
+    let Expression::BinaryOperation(is_zero, BinaryOperator::Sub, right) = e else {
+        return None;
+    };
+    let Expression::BinaryOperation(one, BinaryOperator::Sub, product) = right.as_ref() else {
+        return None;
+    };
+    if !matches!(one.as_ref(), Expression::Number(n) if *n == 1.into()) {
+        return None;
+    }
+    let (a, b) = product_of_columns(product)?;
+    Some((column_name(is_zero)?, a, b))
+}
+
+/// Matches `a * b` or `a * b - 0` and returns the two column names.
+fn zero_product(e: &Expression) -> Option<(&str, &str)> {
+    match e {
+        Expression::BinaryOperation(left, BinaryOperator::Sub, right) if matches!(right.as_ref(), Expression::Number(n) if is_zero(n)) => {
+            product_of_columns(left)
+        }
+        _ => product_of_columns(e),
+    }
+}
+
+fn product_of_columns(e: &Expression) -> Option<(&str, &str)> {
+    match e {
+        Expression::BinaryOperation(a, BinaryOperator::Mul, b) => {
+            Some((column_name(a)?, column_name(b)?))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+
+    use super::*;
+
+    const GADGET: &str = r#"
+        namespace Main(4);
+            col witness X;
+            col witness XInv;
+            col witness XIsZero;
+            col witness flag;
+            XIsZero = 1 - X * XInv;
+            XIsZero * X = 0;
+            flag * (XIsZero - 1) = 0;
+    "#;
+
+    #[test]
+    fn find_gadget() {
+        let analyzed = analyze_string(GADGET);
+        let identities = analyzed.identities.iter().collect::<Vec<_>>();
+        assert_eq!(
+            find_is_zero_gadgets(&identities),
+            vec![IsZeroGadget {
+                is_zero: "Main.XIsZero",
+                value: "Main.X",
+                inverse: "Main.XInv"
+            }]
+        );
+        // Without the product identity, X and XInv cannot be told apart.
+        assert!(find_is_zero_gadgets(&[&analyzed.identities[0]]).is_empty());
+    }
+
+    #[test]
+    fn explain_failure() {
+        let analyzed = analyze_string(GADGET);
+        let identities = analyzed.identities.iter().collect::<Vec<_>>();
+        let gadget = &find_is_zero_gadgets(&identities)[0];
+        let failed = [&analyzed.identities[2]];
+        assert_eq!(
+            gadget.explain_failure(5, Some(&7.into()), &failed).unwrap(),
+            format!(
+                "Row 5: Main.X = 7 is not zero, so the is-zero gadget Main.XIsZero = 1 - Main.X * Main.XInv \
+                requires Main.XIsZero = 0 and Main.XInv = {}, which conflicts with:\n    \
+                (Main.flag * (Main.XIsZero - 1)) = 0;",
+                inv(7.into(), field_mod())
+            )
+        );
+        assert!(gadget
+            .explain_failure(5, None, &failed)
+            .unwrap()
+            .starts_with("Row 5: The value of Main.X is unknown"));
+        assert_eq!(gadget.explain_failure(5, Some(&7.into()), &[]), None);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::analyzer::{
+    Analyzed, Expression, FunctionValueDefinition, Identity, IdentityKind, PolynomialType,
+    SelectedExpressions,
+};
+use crate::number::{is_zero, AbstractNumberType, DegreeType};
+
+use super::affine_expression::AffineExpression;
+use super::eval_error::EvalError;
+use super::expression_evaluator::{ExpressionEvaluator, SymbolicVariables};
+
+/// The rows of the right-hand side of a lookup into fixed columns
+/// that are not matched by any row of the left-hand side.
+pub struct UnusedLookupRows<'a> {
+    pub identity: &'a Identity,
+    /// The number of rows of the table (i.e. rows where the RHS selector is non-zero).
+    pub table_size: usize,
+    pub rows: Vec<DegreeType>,
+    /// The rows of the left-hand side that cannot be evaluated to constants, for example
+    /// because they reference unsupported expressions. They are skipped, so they might
+    /// match some of the unused rows.
+    pub unevaluated_rows: Vec<DegreeType>,
+}
+
+/// Compares the keys matched by the left-hand sides of all lookups into fixed columns
+/// against the table contents and reports the table rows that are never used.
+/// Needs to be called after witness generation.
+pub fn unused_lookup_rows<'a>(
+    analyzed: &'a Analyzed,
+    degree: DegreeType,
+    fixed_cols: &[(&str, Vec<AbstractNumberType>)],
+    witness_cols: &[(&str, Vec<AbstractNumberType>)],
+) -> Vec<UnusedLookupRows<'a>> {
+    let fixed: HashMap<&str, &Vec<AbstractNumberType>> =
+        fixed_cols.iter().map(|(n, v)| (*n, v)).collect();
+    let columns: HashMap<&str, &Vec<AbstractNumberType>> = fixed
+        .iter()
+        .map(|(n, v)| (*n, *v))
+        .chain(witness_cols.iter().map(|(n, v)| (*n, v)))
+        .collect();
+    // @returns the keys of the selected rows and the rows that cannot be evaluated.
+    let evaluate_rows = |selected: &SelectedExpressions, columns| {
+        let mut keys = vec![];
+        let mut unevaluated = vec![];
+        for row in 0..degree {
+            let evaluator = ExpressionEvaluator::new(EvaluateOnRow::new(analyzed, columns, row));
+            let evaluate = |e| {
+                evaluator
+                    .evaluate(e)
+                    .ok()
+                    .and_then(|value| value.constant_value())
+            };
+            let selected_row = match &selected.selector {
+                Some(selector) => evaluate(selector).map(|value| !is_zero(&value)),
+                None => Some(true),
+            };
+            let key = selected
+                .expressions
+                .iter()
+                .map(evaluate)
+                .collect::<Option<Vec<_>>>();
+            match (selected_row, key) {
+                (Some(false), _) => {}
+                (Some(true), Some(key)) => keys.push((row, key)),
+                _ => unevaluated.push(row),
+            }
+        }
+        (keys, unevaluated)
+    };
+
+    analyzed
+        .identities
+        .iter()
+        .filter(|identity| {
+            identity.kind == IdentityKind::Plookup
+                && references_only(&identity.right, &|name| fixed.contains_key(name))
+        })
+        .filter_map(|identity| {
+            let (matched, unevaluated_rows) = evaluate_rows(&identity.left, &columns);
+            let matched = matched
+                .into_iter()
+                .map(|(_, key)| key)
+                .collect::<HashSet<_>>();
+            let (table, _) = evaluate_rows(&identity.right, &fixed);
+            let rows = table
+                .iter()
+                .filter(|(_, key)| !matched.contains(key))
+                .map(|(row, _)| *row)
+                .collect::<Vec<_>>();
+            (!rows.is_empty()).then_some(UnusedLookupRows {
+                identity,
+                table_size: table.len(),
+                rows,
+                unevaluated_rows,
+            })
+        })
+        .collect()
+}
+
+fn references_only(selected: &SelectedExpressions, allowed: &impl Fn(&str) -> bool) -> bool {
+    selected
+        .selector
+        .iter()
+        .chain(selected.expressions.iter())
+        .all(|e| {
+            !super::util::expr_any(e, &mut |e| match e {
+                Expression::PolynomialReference(poly) => !allowed(&poly.name),
+                _ => false,
+            })
+        })
+}
+
+/// Evaluates references to columns with fully known values on a specific row.
+/// References to intermediate polynomials are evaluated through their definition.
+pub(super) struct EvaluateOnRow<'a> {
+    analyzed: &'a Analyzed,
+    columns: &'a HashMap<&'a str, &'a Vec<AbstractNumberType>>,
+    row: DegreeType,
+}
+
+impl<'a> EvaluateOnRow<'a> {
+    pub(super) fn new(
+        analyzed: &'a Analyzed,
+        columns: &'a HashMap<&'a str, &'a Vec<AbstractNumberType>>,
+        row: DegreeType,
+    ) -> Self {
+        EvaluateOnRow {
+            analyzed,
+            columns,
+            row,
+        }
+    }
+}
+
+impl<'a> SymbolicVariables for EvaluateOnRow<'a> {
+    fn constant(&self, name: &str) -> Result<AffineExpression, EvalError> {
+        Ok(self.analyzed.constants[name].clone().into())
+    }
+
+    fn value(&self, name: &str, next: bool) -> Result<AffineExpression, EvalError> {
+        // TODO arrays
+        if let Some(values) = self.columns.get(name) {
+            let degree = values.len() as DegreeType;
+            let row = if next {
+                (self.row + 1) % degree
+            } else {
+                self.row
+            };
+            return Ok(values[row as usize].clone().into());
+        }
+        match self.analyzed.definitions.get(name) {
+            Some((poly, Some(FunctionValueDefinition::Mapping(e))))
+                if poly.poly_type == PolynomialType::Intermediate =>
+            {
+                let row = if next {
+                    (self.row + 1) % poly.degree
+                } else {
+                    self.row
+                };
+                ExpressionEvaluator::new(EvaluateOnRow { row, ..*self }).evaluate(e)
+            }
+            _ => Err(EvalError::Unsupported(format!("Unknown column: {name}"))),
+        }
+    }
+
+    fn format(&self, expr: AffineExpression) -> String {
+        format!("{expr:?}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+    use crate::commit_evaluator;
+    use crate::compiler::no_callback;
+    use crate::constant_evaluator;
+
+    use super::*;
+
+    #[test]
+    fn over_sized_table() {
+        let src = r#"
+            constant %N = 8;
+            namespace Main(%N);
+                col fixed BYTE(i) { i };
+                col fixed X(i) { i % 3 };
+                col witness y;
+                y = X;
+                { y } in { BYTE };
+                { X } in { X };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let unused = unused_lookup_rows(&analyzed, degree, &constants, &witness);
+        assert_eq!(unused.len(), 1);
+        assert_eq!(unused[0].identity.id, 0);
+        assert_eq!(unused[0].table_size, 8);
+        assert_eq!(unused[0].rows, vec![3, 4, 5, 6, 7]);
+        assert!(unused[0].unevaluated_rows.is_empty());
+    }
+
+    #[test]
+    fn unevaluated_rows() {
+        let src = r#"
+            constant %N = 8;
+            namespace Main(%N);
+                col fixed BYTE(i) { i };
+                col fixed X(i) { i % 4 };
+                col witness y;
+                y = X;
+                { y } in { BYTE };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        // Without the witness, the left-hand side cannot be evaluated in any row.
+        let unused = unused_lookup_rows(&analyzed, degree, &constants, &[]);
+        assert_eq!(unused[0].rows, (0..8).collect::<Vec<_>>());
+        assert_eq!(unused[0].unevaluated_rows, (0..8).collect::<Vec<_>>());
+        let unused = unused_lookup_rows(&analyzed, degree, &constants, &witness);
+        assert_eq!(unused[0].rows, vec![4, 5, 6, 7]);
+        assert!(unused[0].unevaluated_rows.is_empty());
+    }
+
+    #[test]
+    fn selected_table_rows() {
+        let src = r#"
+            constant %N = 8;
+            namespace Main(%N);
+                col fixed BYTE(i) { i };
+                col fixed SEL(i) { i % 2 };
+                col fixed X(i) { (i * 3) % 8 };
+                SEL { X } in SEL { BYTE };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        // X on the odd rows is 3, 1, 7, 5, which covers all selected rows of BYTE.
+        assert!(unused_lookup_rows(&analyzed, degree, &constants, &[]).is_empty());
+    }
+}
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::analyzer::{IdentityKind, SelectedExpressions};
-use crate::number::AbstractNumberType;
+use crate::number::{AbstractNumberType, DegreeType};
 
 use super::{affine_expression::AffineExpression, eval_error::EvalError, FixedData};
 
@@ -17,21 +17,31 @@ pub trait Machine {
     //     witness_names: HashSet<&'a str>,
     // ) -> Option<Box<Self>>;
 
-    /// Process a plookup. Not all values on the LHS need to be available.
-    /// Can update internal data.
+    /// Process a plookup issued in the given row. Not all values on the LHS need to be available.
+    /// Can update internal data. The same lookup can be processed multiple times for the same row,
+    /// lookups of the same kind are distinguished by `identity_id`.
     fn process_plookup(
         &mut self,
         fixed_data: &FixedData,
+        row: DegreeType,
+        identity_id: u64,
         kind: IdentityKind,
         left: &[Result<AffineExpression, EvalError>],
         right: &SelectedExpressions,
     ) -> LookupResult;
 
-    /// Returns the final values of the witness columns.
+    /// The name of the machine type, used in reports.
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// Returns the final values of the witness columns, or an error if the
+    /// calls processed so far do not allow to fill all rows.
     fn witness_col_values(
         &mut self,
         fixed_data: &FixedData,
-    ) -> HashMap<String, Vec<AbstractNumberType>>;
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError>;
 }
 
 pub type LookupResult = Result<LookupReturn, EvalError>;
@@ -2,9 +2,12 @@ use std::collections::HashSet;
 
 use crate::analyzer::{Expression, Identity, SelectedExpressions};
 
-use super::double_sorted_witness_machine::DoubleSortedWitnesses;
+use super::block_machine::BlockMachine;
 use super::fixed_lookup_machine::FixedLookup;
+use super::fixed_permutation_machine::FixedPermutation;
 use super::machine::Machine;
+use super::memory_machine::MemoryMachine;
+use super::range_check_machine::RangeCheck;
 
 use super::sorted_witness_machine::SortedWitnesses;
 use super::{FixedData, WitnessColumn};
@@ -20,9 +23,17 @@ pub fn split_out_machines<'a>(
     // TODO we only split out one machine for now.
     // We could also split the machine into independent sub-machines.
 
-    // The lookup-in-fixed-columns machine, it always exists with an empty set of witnesses.
-    let mut machines: Vec<Box<dyn Machine>> =
-        vec![FixedLookup::try_new(fixed, &[], &Default::default()).unwrap()];
+    // The range-check and fixed permutation machines handle lookups into range and
+    // permutation columns before they reach the general lookup-in-fixed-columns machine,
+    // which always exists with an empty set of witnesses.
+    let mut machines: Vec<Box<dyn Machine>> = vec![];
+    if let Some(machine) = RangeCheck::try_new(fixed, &[], &Default::default()) {
+        machines.push(machine);
+    }
+    if let Some(machine) = FixedPermutation::try_new(fixed, &[], &Default::default()) {
+        machines.push(machine);
+    }
+    machines.push(FixedLookup::try_new(fixed, &[], &Default::default()).unwrap());
 
     let witness_names = witness_cols.iter().map(|c| c.name).collect::<HashSet<_>>();
     let all_witnesses = ReferenceExtractor::new(witness_names.clone());
@@ -53,7 +64,11 @@ pub fn split_out_machines<'a>(
     {
         machines.push(machine);
     } else if let Some(machine) =
-        DoubleSortedWitnesses::try_new(fixed, &machine_identities, &machine_witnesses)
+        MemoryMachine::try_new(fixed, &machine_identities, &machine_witnesses)
+    {
+        machines.push(machine);
+    } else if let Some(machine) =
+        BlockMachine::try_new(fixed, &machine_identities, &machine_witnesses)
     {
         machines.push(machine);
     }
@@ -0,0 +1,479 @@
+use std::collections::{BTreeMap, HashSet};
+use std::iter::once;
+
+use itertools::{Either, Itertools};
+
+use crate::analyzer::{BinaryOperator, Expression, Identity, IdentityKind, SelectedExpressions};
+use crate::commit_evaluator::eval_error;
+use crate::commit_evaluator::machine::LookupReturn;
+use crate::number::{is_zero, AbstractNumberType, DegreeType};
+
+use super::affine_expression::AffineExpression;
+use super::eval_error::EvalError;
+use super::machine::{LookupResult, Machine};
+use super::util::column_name;
+use super::FixedData;
+
+/// A read-write memory whose columns are sorted by address and then by step.
+/// The roles of the columns are detected from the identities of the machine:
+///  - `(m_addr' - m_addr) * (1 - m_change) = 0`
+///  - `(1 - m_is_write') * (1 - m_change) * (m_value' - m_value) = 0`
+///  - a lookup that references `m_change`, `m_addr` and the step column `m_step`
+///    (used to enforce the sorting)
+///  - optionally `m_is_write * (1 - m_op) = 0` and `m_is_read * m_is_write = 0`
+///
+/// The memory is accessed through lookups or permutations of the form
+/// `{ ADDR, STEP, VALUE } is m_is_write { m_addr, m_step, m_value }`,
+/// `{ ADDR, STEP, VALUE } is m_is_read { m_addr, m_step, m_value }` or
+/// `{ ADDR, STEP, VALUE, IS_WRITE } is m_op { m_addr, m_step, m_value, m_is_write }`.
+/// Lookups with three columns are only recognized with one of these two selectors, since
+/// the selector determines the type of the operation. Lookups with four columns take the
+/// type from `IS_WRITE` and are recognized with any selector or without one.
+pub struct MemoryMachine {
+    columns: MemoryColumns,
+    /// (addr, step) -> operation
+    trace: BTreeMap<(AbstractNumberType, AbstractNumberType), Operation>,
+    /// The current value for each address.
+    data: BTreeMap<AbstractNumberType, AbstractNumberType>,
+}
+
+struct MemoryColumns {
+    addr: String,
+    step: String,
+    value: String,
+    change: String,
+    is_write: String,
+    op: Option<String>,
+    is_read: Option<String>,
+}
+
+struct Operation {
+    is_write: bool,
+    value: AbstractNumberType,
+}
+
+impl MemoryMachine {
+    pub fn try_new(
+        _fixed_data: &FixedData,
+        identities: &[&Identity],
+        witness_names: &HashSet<&str>,
+    ) -> Option<Box<Self>> {
+        let is_witness = |name: &str| witness_names.contains(name);
+        let polynomial_identities = identities
+            .iter()
+            .filter(|id| id.kind == IdentityKind::Polynomial)
+            .filter_map(|id| id.left.selector.as_ref())
+            .map(|e| factors(strip_zero(e)))
+            .collect::<Vec<_>>();
+
+        let (is_write, change, value) =
+            polynomial_identities.iter().find_map(|f| match &f[..] {
+                [a, b, c] => [a, b, c].into_iter().permutations(3).find_map(|p| {
+                    Some((
+                        one_minus(p[0], true)?,
+                        one_minus(p[1], false)?,
+                        next_minus_current(p[2])?,
+                    ))
+                }),
+                _ => None,
+            })?;
+        let addr = polynomial_identities.iter().find_map(|f| match &f[..] {
+            [a, b] => [(a, b), (b, a)].into_iter().find_map(|(a, b)| {
+                (one_minus(b, false)? == change)
+                    .then(|| next_minus_current(a))
+                    .flatten()
+                    .filter(|addr| *addr != value)
+            }),
+            _ => None,
+        })?;
+        let step = identities
+            .iter()
+            .filter(|id| id.kind == IdentityKind::Plookup)
+            .find_map(|id| {
+                let referenced = referenced_columns(&id.left);
+                if !referenced.contains(change) || !referenced.contains(addr) {
+                    return None;
+                }
+                match &referenced
+                    .into_iter()
+                    .filter(|c| is_witness(c) && *c != change && *c != addr)
+                    .collect::<Vec<_>>()[..]
+                {
+                    [step] => Some(*step),
+                    _ => None,
+                }
+            })?;
+        let op = polynomial_identities.iter().find_map(|f| match &f[..] {
+            [a, b] => [(a, b), (b, a)].into_iter().find_map(|(a, b)| {
+                (column_name(a)? == is_write)
+                    .then(|| one_minus(b, false))
+                    .flatten()
+                    .filter(|op| *op != is_write)
+            }),
+            _ => None,
+        });
+        let is_read = polynomial_identities.iter().find_map(|f| match &f[..] {
+            [a, b] => [(a, b), (b, a)].into_iter().find_map(|(a, b)| {
+                (column_name(a)? == is_write)
+                    .then(|| column_name(b))
+                    .flatten()
+                    .filter(|r| *r != is_write)
+            }),
+            _ => None,
+        });
+
+        let columns = [
+            Some(addr),
+            Some(step),
+            Some(value),
+            Some(change),
+            Some(is_write),
+            op,
+            is_read,
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<HashSet<_>>();
+        if columns != *witness_names {
+            return None;
+        }
+        Some(Box::new(MemoryMachine {
+            columns: MemoryColumns {
+                addr: addr.to_string(),
+                step: step.to_string(),
+                value: value.to_string(),
+                change: change.to_string(),
+                is_write: is_write.to_string(),
+                op: op.map(|c| c.to_string()),
+                is_read: is_read.map(|c| c.to_string()),
+            },
+            trace: Default::default(),
+            data: Default::default(),
+        }))
+    }
+
+    /// Determines whether a lookup with the given right-hand side is a write operation,
+    /// from the selector `m_is_write` or `m_is_read` or from the fourth column `m_is_write`.
+    /// Returns None if the lookup is not directed at this machine.
+    fn is_write_lookup(
+        &self,
+        fixed_data: &FixedData,
+        left: &[&AffineExpression],
+        right: &SelectedExpressions,
+    ) -> Option<Result<bool, EvalError>> {
+        let columns = &self.columns;
+        let rhs = right
+            .expressions
+            .iter()
+            .map(column_name)
+            .collect::<Option<Vec<_>>>()?;
+        let selector = match &right.selector {
+            Some(selector) => Some(column_name(selector)?),
+            None => None,
+        };
+        match rhs[..] {
+            [addr, step, value]
+                if addr == columns.addr && step == columns.step && value == columns.value =>
+            {
+                if selector == Some(&columns.is_write) {
+                    Some(Ok(true))
+                } else if selector.is_some() && selector == columns.is_read.as_deref() {
+                    Some(Ok(false))
+                } else {
+                    None
+                }
+            }
+            [addr, step, value, is_write]
+                if addr == columns.addr
+                    && step == columns.step
+                    && value == columns.value
+                    && is_write == columns.is_write =>
+            {
+                Some(match left[3].constant_value() {
+                    Some(v) => Ok(!is_zero(&v)),
+                    None => Err(EvalError::Incomplete(format!(
+                        "Memory operation type must be known: {}",
+                        left[3].format(fixed_data)
+                    ))),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Machine for MemoryMachine {
+    fn process_plookup(
+        &mut self,
+        fixed_data: &FixedData,
+        _row: DegreeType,
+        _identity_id: u64,
+        kind: IdentityKind,
+        left: &[Result<AffineExpression, EvalError>],
+        right: &SelectedExpressions,
+    ) -> LookupResult {
+        if kind != IdentityKind::Permutation && kind != IdentityKind::Plookup {
+            return Ok(LookupReturn::NotApplicable);
+        }
+
+        // Fail if the LHS has an error.
+        let (left, errors): (Vec<_>, Vec<_>) = left.iter().partition_map(|x| match x {
+            Ok(x) => Either::Left(x),
+            Err(x) => Either::Right(x),
+        });
+        if !errors.is_empty() {
+            return Err(errors
+                .into_iter()
+                .cloned()
+                .reduce(eval_error::combine)
+                .unwrap());
+        }
+
+        let is_write = match self.is_write_lookup(fixed_data, &left, right) {
+            None => return Ok(LookupReturn::NotApplicable),
+            Some(is_write) => is_write?,
+        };
+        let addr = left[0].constant_value().ok_or_else(|| {
+            EvalError::Incomplete(format!(
+                "Address must be known: {} = {}",
+                left[0].format(fixed_data),
+                right.expressions[0]
+            ))
+        })?;
+        let step = left[1].constant_value().ok_or_else(|| {
+            EvalError::Incomplete(format!(
+                "Step must be known: {} = {}",
+                left[1].format(fixed_data),
+                right.expressions[1]
+            ))
+        })?;
+
+        let mut assignments = vec![];
+        if is_write {
+            let value = match left[2].constant_value() {
+                Some(v) => v,
+                None => return Ok(LookupReturn::Assignments(vec![])),
+            };
+            if fixed_data.verbose {
+                println!("Memory write: addr={addr}, step={step}, value={value}");
+            }
+            self.data.insert(addr.clone(), value.clone());
+            self.trace
+                .insert((addr, step), Operation { is_write, value });
+        } else {
+            let value = self.data.entry(addr.clone()).or_default().clone();
+            if fixed_data.verbose {
+                println!("Memory read: addr={addr}, step={step}, value={value}");
+            }
+            match (left[2].clone() - value.clone().into()).solve() {
+                Some(assignment) => assignments.push(assignment),
+                None => return Ok(LookupReturn::Assignments(vec![])),
+            }
+            self.trace
+                .insert((addr, step), Operation { is_write, value });
+        }
+        Ok(LookupReturn::Assignments(assignments))
+    }
+
+    fn witness_col_values(
+        &mut self,
+        fixed_data: &FixedData,
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError> {
+        let mut addr = vec![];
+        let mut step = vec![];
+        let mut value = vec![];
+        let mut op = vec![];
+        let mut is_write = vec![];
+        let mut is_read = vec![];
+
+        for ((a, s), o) in std::mem::take(&mut self.trace) {
+            addr.push(a);
+            step.push(s);
+            value.push(o.value);
+            op.push(1.into());
+            is_write.push((o.is_write as u32).into());
+            is_read.push((!o.is_write as u32).into());
+        }
+        if addr.is_empty() {
+            // Start at address zero and step zero, the padding below does the rest.
+            addr.push(0.into());
+            step.push(0.into());
+            value.push(0.into());
+            op.push(0.into());
+            is_write.push(0.into());
+            is_read.push(0.into());
+        }
+        while addr.len() < fixed_data.degree as usize {
+            addr.push(addr.last().unwrap().clone());
+            step.push(step.last().unwrap().clone() + 1);
+            value.push(value.last().unwrap().clone());
+            op.push(0.into());
+            is_write.push(0.into());
+            is_read.push(0.into());
+        }
+
+        let change = addr
+            .iter()
+            .tuple_windows()
+            .map(|(a, a_next)| if a == a_next { 0.into() } else { 1.into() })
+            .chain(once(1.into()))
+            .collect::<Vec<_>>();
+
+        let columns = &self.columns;
+        Ok([
+            (Some(&columns.value), value),
+            (Some(&columns.addr), addr),
+            (Some(&columns.step), step),
+            (Some(&columns.change), change),
+            (columns.op.as_ref(), op),
+            (Some(&columns.is_write), is_write),
+            (columns.is_read.as_ref(), is_read),
+        ]
+        .into_iter()
+        .filter_map(|(name, values)| name.map(|n| (n.clone(), values)))
+        .collect())
+    }
+}
+
+/// Turns `e - 0` into `e`.
+fn strip_zero(e: &Expression) -> &Expression {
+    match e {
+        Expression::BinaryOperation(left, BinaryOperator::Sub, right) if matches!(right.as_ref(), Expression::Number(n) if is_zero(n)) => {
+            left
+        }
+        _ => e,
+    }
+}
+
+/// Returns the factors of a product.
+fn factors(e: &Expression) -> Vec<&Expression> {
+    match e {
+        Expression::BinaryOperation(left, BinaryOperator::Mul, right) => {
+            factors(left).into_iter().chain(factors(right)).collect()
+        }
+        _ => vec![e],
+    }
+}
+
+/// Returns the name of the column `x` if the expression is of the form `1 - x` (or `1 - x'` if `next` is true).
+fn one_minus(e: &Expression, next: bool) -> Option<&str> {
+    match e {
+        Expression::BinaryOperation(left, BinaryOperator::Sub, right)
+            if **left == Expression::Number(1.into()) =>
+        {
+            match right.as_ref() {
+                Expression::PolynomialReference(poly)
+                    if poly.next == next && poly.index.is_none() =>
+                {
+                    Some(&poly.name)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the name of the column `x` if the expression is of the form `x' - x`.
+fn next_minus_current(e: &Expression) -> Option<&str> {
+    match e {
+        Expression::BinaryOperation(left, BinaryOperator::Sub, right) => {
+            match (left.as_ref(), right.as_ref()) {
+                (
+                    Expression::PolynomialReference(next),
+                    Expression::PolynomialReference(current),
+                ) if next.next
+                    && !current.next
+                    && next.name == current.name
+                    && next.index.is_none()
+                    && current.index.is_none() =>
+                {
+                    Some(&next.name)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn referenced_columns(selected: &SelectedExpressions) -> HashSet<&str> {
+    fn collect<'a>(e: &'a Expression, columns: &mut HashSet<&'a str>) {
+        match e {
+            Expression::PolynomialReference(poly) => {
+                columns.insert(&poly.name);
+            }
+            Expression::Tuple(items) | Expression::FunctionCall(_, items) => {
+                items.iter().for_each(|e| collect(e, columns))
+            }
+            Expression::BinaryOperation(left, _, right) => {
+                collect(left, columns);
+                collect(right, columns);
+            }
+            Expression::UnaryOperation(_, e) => collect(e, columns),
+            Expression::Constant(_)
+            | Expression::LocalVariableReference(_)
+            | Expression::PublicReference(_)
+            | Expression::Number(_)
+            | Expression::String(_) => {}
+        }
+    }
+    let mut columns = HashSet::new();
+    for e in selected.selector.iter().chain(selected.expressions.iter()) {
+        collect(e, &mut columns);
+    }
+    columns
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::analyzer;
+    use crate::commit_evaluator;
+    use crate::compiler::no_callback;
+    use crate::constant_evaluator;
+
+    #[test]
+    fn write_then_read() {
+        let analyzed = analyzer::analyze(Path::new("tests/memory.pil"));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let column = |name| {
+            witness
+                .iter()
+                .find(|(n, _)| *n == name)
+                .unwrap()
+                .1
+                .iter()
+                .map(|v| v.try_into().unwrap())
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(column("Main.value"), vec![0, 7, 0, 0, 7, 0, 0, 0]);
+        assert_eq!(column("Memory.addr"), vec![3; 8]);
+        assert_eq!(column("Memory.step"), vec![1, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(column("Memory.value"), vec![7; 8]);
+        assert_eq!(column("Memory.op"), vec![1, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(column("Memory.is_write"), vec![1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(column("Memory.change"), vec![0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn unselected_lookup_with_operation_type() {
+        // The fourth column on the right-hand side determines the type of the operation,
+        // so the lookup does not need a selector.
+        let src = fs::read_to_string("tests/memory.pil")
+            .unwrap()
+            .replace("is Memory.op { Memory.addr", "in { Memory.addr");
+        assert!(src.contains("in { Memory.addr, Memory.step, Memory.value, Memory.is_write }"));
+        let analyzed = analyzer::analyze_string(&src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let (_, values) = witness.iter().find(|(n, _)| *n == "Main.value").unwrap();
+        assert_eq!(values[4], 7.into());
+        assert!(commit_evaluator::verify(&analyzed, degree, &constants, &witness).is_empty());
+    }
+}
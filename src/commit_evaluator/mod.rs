@@ -1,23 +1,50 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-use crate::analyzer::{Analyzed, Expression, FunctionValueDefinition};
+use crate::analyzer::util::postvisit_expression_mut;
+use crate::analyzer::{Analyzed, Expression, FunctionValueDefinition, Identity};
 use crate::number::{AbstractNumberType, DegreeType};
 
 use self::eval_error::EvalError;
 use self::util::WitnessColumnNamer;
 
 mod affine_expression;
-mod double_sorted_witness_machine;
+mod bit_constraints;
+mod block_machine;
 mod eval_error;
 mod evaluator;
 mod expression_evaluator;
 pub mod fixed_evaluator;
 mod fixed_lookup_machine;
+mod fixed_permutation_machine;
+mod is_zero_gadget;
+pub mod lookup_coverage;
 mod machine;
 mod machine_extractor;
+mod memory_machine;
+mod profile;
+mod range_check_machine;
 mod sorted_witness_machine;
 pub mod symbolic_evaluator;
+mod trace;
 mod util;
+mod verifier;
+mod witness_error;
+
+pub use profile::Profile;
+pub use trace::{read_trace, TraceWriter};
+pub use verifier::{verify, ConstraintViolation};
+pub use witness_error::WitnessError;
+
+/// What the witness generation reports besides the values.
+#[derive(Default)]
+pub struct Reports<'a, 'w> {
+    /// Prints the values of each row and explains failures in more detail.
+    pub verbose: bool,
+    /// The execution trace is written to it row by row.
+    pub trace: Option<&'a mut TraceWriter<'w>>,
+    /// The time spent on each identity is added to it.
+    pub profile: Option<&'a mut Profile>,
+}
 
 /// Generates the committed polynomial values
 /// @returns the values (in source order) and the degree of the polynomials.
@@ -28,6 +55,87 @@ pub fn generate<'a>(
     query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
     verbose: bool,
 ) -> Vec<(&'a str, Vec<AbstractNumberType>)> {
+    let mut values: Vec<(&str, Vec<AbstractNumberType>)> = analyzed
+        .committed_polys_in_source_order()
+        .iter()
+        .map(|(poly, _)| (poly.absolute_name.as_str(), Vec::new()))
+        .collect();
+    let machine_values = generate_rows(
+        analyzed,
+        degree,
+        fixed_cols,
+        query_callback,
+        Reports {
+            verbose,
+            ..Default::default()
+        },
+        |row_values| {
+            for (col, v) in row_values.into_iter().enumerate() {
+                values[col].1.push(v);
+            }
+        },
+    );
+    for (col, data) in machine_values {
+        values[col].1 = data;
+    }
+    values
+}
+
+/// Like `generate`, but passes the values of each row (in source order) to `process_row`
+/// instead of collecting them, so that the memory needed does not grow with the degree.
+/// The values of the witness columns of machines are only known after the last row.
+/// They are returned as pairs of column index and values and replace whatever
+/// was passed to `process_row` for these columns.
+/// The trace and the profile requested in `reports` are written while the rows are generated.
+pub fn generate_rows(
+    analyzed: &Analyzed,
+    degree: DegreeType,
+    fixed_cols: &[(&str, Vec<AbstractNumberType>)],
+    query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
+    reports: Reports,
+    process_row: impl FnMut(Vec<AbstractNumberType>),
+) -> Vec<(usize, Vec<AbstractNumberType>)> {
+    try_generate_rows(
+        analyzed,
+        degree,
+        fixed_cols,
+        query_callback,
+        reports,
+        process_row,
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("\n{err}");
+        panic!();
+    })
+}
+
+/// Runs the witness generation without keeping the values and
+/// returns the first row in which it fails, if any.
+pub fn check(
+    analyzed: &Analyzed,
+    degree: DegreeType,
+    fixed_cols: &[(&str, Vec<AbstractNumberType>)],
+    query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
+) -> Result<(), WitnessError> {
+    try_generate_rows(
+        analyzed,
+        degree,
+        fixed_cols,
+        query_callback,
+        Reports::default(),
+        |_| {},
+    )
+    .map(|_| ())
+}
+
+fn try_generate_rows(
+    analyzed: &Analyzed,
+    degree: DegreeType,
+    fixed_cols: &[(&str, Vec<AbstractNumberType>)],
+    query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
+    reports: Reports,
+    mut process_row: impl FnMut(Vec<AbstractNumberType>),
+) -> Result<Vec<(usize, Vec<AbstractNumberType>)>, WitnessError> {
     let witness_cols: Vec<WitnessColumn> = analyzed
         .committed_polys_in_source_order()
         .iter()
@@ -45,31 +153,104 @@ pub fn generate<'a>(
         fixed_cols: fixed_cols.iter().map(|(n, v)| (*n, v)).collect(),
         witness_cols: &witness_cols,
         witness_ids: witness_cols.iter().map(|w| (w.name, w.id)).collect(),
-        verbose,
+        verbose: reports.verbose,
     };
+    let mut trace = reports.trace;
+    let all_identities = substitute_constants(&analyzed.identities, &analyzed.constants);
     let (machines, identities) =
-        machine_extractor::split_out_machines(&fixed, &analyzed.identities, &witness_cols);
+        machine_extractor::split_out_machines(&fixed, &all_identities, &witness_cols);
     let mut evaluator = evaluator::Evaluator::new(&fixed, identities, machines, query_callback);
+    if let Some(profile) = reports.profile {
+        evaluator = evaluator.with_profile(profile);
+    }
 
-    let mut values: Vec<(&str, Vec<AbstractNumberType>)> =
-        witness_cols.iter().map(|p| (p.name, Vec::new())).collect();
+    if let Some(trace) = &mut trace {
+        trace.write_header(witness_cols.iter().map(|c| c.name));
+    }
+    let mut first_row = vec![];
     for row in 0..degree as DegreeType {
-        let row_values = evaluator.compute_next_row(row);
-        for (col, v) in row_values.into_iter().enumerate() {
-            values[col].1.push(v);
+        let row_values = evaluator.compute_next_row(row)?;
+        if let Some(trace) = &mut trace {
+            trace.write_row(row, evaluator.current_values());
+        }
+        if row == 0 {
+            first_row = row_values.clone();
         }
+        process_row(row_values);
     }
-    for (col, v) in evaluator.compute_next_row(0).into_iter().enumerate() {
-        if v != values[col].1[0] {
+    // Failures in row 0 are not fatal, so this does not return an error.
+    for (col, v) in evaluator.compute_next_row(0)?.into_iter().enumerate() {
+        if v != first_row[col] {
             eprintln!("Wrap-around value for column {} does not match: {} (wrap-around) vs. {} (first row).",
-            witness_cols[col].name, v, values[col].1[0]);
+            witness_cols[col].name, v, first_row[col]);
         }
     }
-    for (name, data) in evaluator.machine_witness_col_values() {
-        let (_, col) = values.iter_mut().find(|(n, _)| *n == name).unwrap();
-        *col = data;
+    // The machines fill their remaining rows after the last row has been processed,
+    // so their errors are reported for the last row.
+    let mut machine_values = evaluator
+        .machine_witness_col_values()
+        .map_err(|err| WitnessError {
+            row: degree - 1,
+            failed_identities: vec![],
+            unknown_columns: vec![],
+            reasons: vec![err.to_string()],
+            values: vec![],
+        })?
+        .into_iter()
+        .map(|(name, data)| (fixed.witness_ids[name.as_str()], data))
+        .collect::<Vec<_>>();
+    machine_values.sort_by_key(|(col, _)| *col);
+    Ok(machine_values)
+}
+
+/// Replaces the references to constants in the identities by their values,
+/// so that they are not looked up each time an identity is evaluated.
+fn substitute_constants(
+    identities: &[Identity],
+    constants: &HashMap<String, AbstractNumberType>,
+) -> Vec<Identity> {
+    let mut identities = identities.to_vec();
+    let substitute = &mut |e: &mut Expression| {
+        if let Expression::Constant(name) = e {
+            *e = Expression::Number(constants[name.as_str()].clone());
+        }
+    };
+    for identity in &mut identities {
+        for e in identity
+            .left
+            .selector
+            .iter_mut()
+            .chain(identity.left.expressions.iter_mut())
+            .chain(identity.right.selector.iter_mut())
+            .chain(identity.right.expressions.iter_mut())
+        {
+            postvisit_expression_mut(e, substitute);
+        }
+    }
+    identities
+}
+
+/// Turns a query callback that answers a query with a batch of values into
+/// a regular query callback.
+///
+/// The first value of a batch answers the query that triggered the call, the remaining
+/// values are cached and answer the subsequent queries in the order in which the witness
+/// generator issues them, independently of the content of these queries.
+/// Only once the cache is exhausted, the batched callback is called again.
+/// Queries are issued row by row and, inside a row, in the order in which the
+/// witness columns with queries are declared. After the last row, the queries
+/// of the first row are issued again to check the wrap-around.
+/// An empty batch is treated like `None`.
+pub fn batched_query_callback(
+    mut callback: impl FnMut(&str) -> Option<Vec<AbstractNumberType>>,
+) -> impl FnMut(&str) -> Option<AbstractNumberType> {
+    let mut remaining = VecDeque::new();
+    move |query| {
+        if remaining.is_empty() {
+            remaining.extend(callback(query)?);
+        }
+        remaining.pop_front()
     }
-    values
 }
 
 /// Result of evaluating an expression / lookup:
@@ -132,3 +313,472 @@ impl<'a> WitnessColumn<'a> {
         WitnessColumn { id, name, query }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+    use crate::number::{with_named_field, FieldId, GOLDILOCKS_MOD};
+    use crate::{asm_compiler, constant_evaluator};
+
+    use super::*;
+
+    /// Compiles the asm source and generates the witness, returning the values
+    /// of the given columns on the given row.
+    fn asm_witness_row(
+        src: &str,
+        query_callback: impl FnMut(&str) -> Option<AbstractNumberType>,
+        row: usize,
+        columns: &[&str],
+    ) -> Vec<AbstractNumberType> {
+        let pil = asm_compiler::compile(None, src).unwrap();
+        let analyzed = analyze_string(&format!("{pil}"));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness = generate(&analyzed, degree, &constants, Some(query_callback), false);
+        columns
+            .iter()
+            .map(|name| {
+                let (_, values) = witness.iter().find(|(n, _)| n == name).unwrap();
+                values[row].clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn constants_in_identities() {
+        let src = r#"
+            constant %N = 8;
+            constant %OFFSET = 3;
+            namespace Main(%N);
+                col fixed BYTE(i) { i };
+                col witness x, y;
+                x = BYTE;
+                y = x * %OFFSET;
+                { y - %OFFSET * x } in { BYTE };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness = generate(
+            &analyzed,
+            degree,
+            &constants,
+            crate::compiler::no_callback(),
+            false,
+        );
+        assert_eq!(witness[1].1[5], 15.into());
+        assert!(verify(&analyzed, degree, &constants, &witness).is_empty());
+    }
+
+    #[test]
+    fn bit_decomposition() {
+        // The lookups bound `lo` and `hi` to two bits, so they are determined by `x`.
+        let src = r#"
+            namespace Main(16);
+                col fixed X(i) { i };
+                col fixed BITS2(i) { i & 3 };
+                col witness lo, hi;
+                X = lo + 4 * hi;
+                { lo } in { BITS2 };
+                { hi } in { BITS2 };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness = generate(
+            &analyzed,
+            degree,
+            &constants,
+            crate::compiler::no_callback(),
+            false,
+        );
+        assert_eq!(witness[0].1[6], 2.into());
+        assert_eq!(witness[1].1[6], 1.into());
+        assert!(verify(&analyzed, degree, &constants, &witness).is_empty());
+    }
+
+    #[test]
+    fn profile() {
+        let src = r#"
+            namespace Main(8);
+                col fixed BYTE(i) { i };
+                col fixed DOUBLE(i) { 2 * i };
+                col witness x, y;
+                x = BYTE;
+                { x, y } in { BYTE, DOUBLE };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let mut profile = Profile::default();
+        generate_rows(
+            &analyzed,
+            degree,
+            &constants,
+            crate::compiler::no_callback(),
+            Reports {
+                profile: Some(&mut profile),
+                ..Default::default()
+            },
+            |_| {},
+        );
+        let mut components = profile
+            .sorted_entries()
+            .into_iter()
+            .map(|(location, component, _, calls)| {
+                assert!(calls > 0);
+                (location.to_string(), component)
+            })
+            .collect::<Vec<_>>();
+        components.sort();
+        // Machines are also timed if the lookup is not applicable to them.
+        assert_eq!(
+            components,
+            [
+                ("input:6".to_string(), "solver"),
+                ("input:7".to_string(), "FixedLookup"),
+                ("input:7".to_string(), "RangeCheck"),
+                ("input:7".to_string(), "solver"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fixtures_are_unchanged() {
+        // The witness is checked against the original identities, which still
+        // contain the references to the constants.
+        for file in [
+            "tests/fibonacci.pil",
+            "tests/fib_macro.pil",
+            "tests/global.pil",
+            "tests/independent_constants.pil",
+            "tests/memory.pil",
+        ] {
+            let analyzed = crate::analyzer::analyze(std::path::Path::new(file));
+            let (constants, degree) = constant_evaluator::generate(&analyzed);
+            let witness = generate(
+                &analyzed,
+                degree,
+                &constants,
+                crate::compiler::no_callback(),
+                false,
+            );
+            assert!(
+                verify(&analyzed, degree, &constants, &witness).is_empty(),
+                "{file}"
+            );
+        }
+    }
+
+    #[test]
+    fn negative_values_are_canonical() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            reg B;
+            A <=X= ${ ("input", 0) };
+            B <=X= A - 5;
+        "#;
+        let p: AbstractNumberType = GOLDILOCKS_MOD.into();
+        let values = asm_witness_row(src, |_| Some((-2).into()), 2, &["Assembly.A", "Assembly.B"]);
+        assert_eq!(values, vec![p.clone() - 2, p - 7]);
+
+        let p = FieldId::Bn254.modulus();
+        let values = with_named_field(FieldId::Bn254, || {
+            asm_witness_row(src, |_| Some((-2).into()), 2, &["Assembly.A", "Assembly.B"])
+        });
+        assert_eq!(values, vec![p.clone() - 2, p - 7]);
+    }
+
+    #[test]
+    fn gadgets() {
+        let src = r#"
+            degree 32;
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            reg B;
+            gadget is_zero A;
+            gadget lt A, B, 4;
+            gadget sign B, 4;
+            A <=X= 3;
+            B <=X= 9;
+            A <=X= 12;
+        "#;
+        let columns = ["Assembly.AIsZero", "Assembly.ALtB", "Assembly.BSign"];
+        let no_query = |_: &str| None;
+        assert_eq!(
+            asm_witness_row(src, no_query, 0, &columns),
+            [1, 0, 0].map(Into::into)
+        );
+        assert_eq!(
+            asm_witness_row(src, no_query, 2, &columns),
+            [0, 1, 1].map(Into::into)
+        );
+        assert_eq!(
+            asm_witness_row(src, no_query, 3, &columns),
+            [0, 0, 1].map(Into::into)
+        );
+    }
+
+    #[test]
+    fn tuple_assignment() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg Y[<=];
+            reg A;
+            reg B;
+            A, B <== (3, 4);
+            A, B <== (B, A + B);
+        "#;
+        let columns = ["Assembly.A", "Assembly.B"];
+        let no_query = |_: &str| None;
+        assert_eq!(
+            asm_witness_row(src, no_query, 2, &columns),
+            [4, 7].map(Into::into)
+        );
+    }
+
+    #[test]
+    fn free_input_query_keys() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            reg B;
+            A <=X= 16;
+            B <=X= ${ ("sqrt", 0); A };
+        "#;
+        // The query only starts with the value of A instead of the row and the pc.
+        let sqrt = |query: &str| {
+            let key: u64 = query.split(',').next().unwrap().trim().parse().unwrap();
+            Some(((key as f64).sqrt() as u64).into())
+        };
+        assert_eq!(asm_witness_row(src, sqrt, 2, &["Assembly.B"]), [4.into()]);
+    }
+
+    #[test]
+    fn acc() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg Y[<=];
+            reg Z[<=];
+            reg A;
+            instr acc <=X= r <=Z=, <=Y= v { Z = X + Y }
+            A <=X= 1;
+            acc A, 2;
+            acc A, A + 3;
+        "#;
+        let no_query = |_: &str| None;
+        let accumulated = (1..=3)
+            .map(|row| asm_witness_row(src, no_query, row, &["Assembly.A"]).remove(0))
+            .collect::<Vec<_>>();
+        assert_eq!(accumulated, [1, 3, 9].map(Into::into));
+    }
+
+    #[test]
+    fn logical_not() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            reg B;
+            pil{
+                col witness XInv;
+                col witness XIsZero;
+                XIsZero = 1 - X * XInv;
+                XIsZero * X = 0;
+                XIsZero * (1 - XIsZero) = 0;
+            }
+            instr jmpnz <=X= c, l: label { pc' = !XIsZero * l + XIsZero * (pc + 1) }
+            instr dec_A { A' = A - 1 }
+            A <=X= 3;
+            loop::
+            dec_A;
+            B <=X= !B;
+            jmpnz A, loop;
+        "#;
+        let columns = ["Assembly.pc", "Assembly.A", "Assembly.B"];
+        let no_query = |_: &str| None;
+        // Three iterations of four rows each, B is toggled in each of them.
+        assert_eq!(
+            asm_witness_row(src, no_query, 8, &columns),
+            [4, 1, 0].map(Into::into)
+        );
+        assert_eq!(
+            asm_witness_row(src, no_query, 13, &columns),
+            [5, 0, 1].map(Into::into)
+        );
+    }
+
+    #[test]
+    fn read_and_write_through_assignment_register() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            reg B;
+            instr pipe <=X= a, r <=X= { B' = X + 1 }
+            A <=X= 3;
+            pipe A + 2, A;
+            pipe A, A;
+        "#;
+        let columns = ["Assembly.X", "Assembly.A", "Assembly.B"];
+        let no_query = |_: &str| None;
+        assert_eq!(
+            asm_witness_row(src, no_query, 1, &columns),
+            [5, 3, 0].map(Into::into)
+        );
+        assert_eq!(
+            asm_witness_row(src, no_query, 2, &columns),
+            [5, 5, 6].map(Into::into)
+        );
+        assert_eq!(
+            asm_witness_row(src, no_query, 3, &columns)[1..],
+            [5, 6].map(Into::into)
+        );
+    }
+
+    #[test]
+    fn immediate_argument() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            instr addi r <=X=, i { X = A + i }
+            A <=X= 3;
+            addi A, 5;
+            addi A, 16;
+        "#;
+        let no_query = |_: &str| None;
+        assert_eq!(
+            asm_witness_row(src, no_query, 3, &["Assembly.A"]),
+            [24.into()]
+        );
+    }
+
+    #[test]
+    fn call_and_return() {
+        let src = include_str!("../../tests/call_ret.asm");
+        let columns = ["Assembly.pc", "Assembly.A", "Assembly.RA"];
+        let input = |_: &str| Some(3.into());
+        // First call returned to the second call.
+        assert_eq!(
+            asm_witness_row(src, input, 5, &columns),
+            [2, 6, 2].map(Into::into)
+        );
+        // Second call returned to the assertion.
+        assert_eq!(
+            asm_witness_row(src, input, 9, &columns),
+            [3, 12, 3].map(Into::into)
+        );
+    }
+
+    #[test]
+    fn read_fixed_column() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            pil{
+                pol constant SQUARES(i) { i * i };
+            }
+            A <=X= 7;
+            A <=X= A + line + 2 * SQUARES;
+            A <=X= line;
+        "#;
+        let columns = ["Assembly.A"];
+        let no_input = |_: &str| None;
+        assert_eq!(asm_witness_row(src, no_input, 2, &columns), [10.into()]);
+        assert_eq!(asm_witness_row(src, no_input, 3, &columns), [2.into()]);
+    }
+
+    #[test]
+    fn next_references_in_instruction() {
+        let src = r#"
+            reg pc[@pc];
+            reg X[<=];
+            reg A;
+            reg B;
+            pil{
+                pol constant SQUARES(i) { i * i };
+                col witness square;
+            }
+            instr incr { A' = A + 1, B' = 2 * A', { A', square' } in { line, SQUARES } }
+            A <=X= 4;
+            incr;
+        "#;
+        let columns = ["Assembly.A", "Assembly.B", "Assembly.square"];
+        let no_input = |_: &str| None;
+        assert_eq!(
+            asm_witness_row(src, no_input, 2, &columns),
+            [5, 10, 25].map(Into::into)
+        );
+    }
+
+    #[test]
+    fn permutation_with_fixed_table() {
+        let src = r#"
+            namespace Main(4);
+                col fixed KEY = [1, 1, 2, 2];
+                col fixed VALUE = [10, 11, 20, 21];
+                col fixed INPUT = [2, 1, 1, 2];
+                col witness x;
+                col witness y;
+                x = INPUT;
+                { x, y } is { KEY, VALUE };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness = generate(&analyzed, degree, &constants, None::<fn(&str) -> _>, false);
+        // Each row of the table is used exactly once.
+        assert_eq!(witness[1].1, [20, 10, 11, 21].map(Into::into));
+
+        // A plookup uses the first matching row.
+        let analyzed = analyze_string(&src.replace(" is ", " in "));
+        let witness = generate(&analyzed, degree, &constants, None::<fn(&str) -> _>, false);
+        assert_eq!(witness[1].1, [20, 10, 10, 20].map(Into::into));
+    }
+
+    #[test]
+    #[should_panic]
+    fn permutation_with_exhausted_table() {
+        let src = r#"
+            namespace Main(4);
+                col fixed KEY = [1, 1, 2, 2];
+                col fixed VALUE = [10, 11, 20, 21];
+                col fixed INPUT = [1, 1, 1, 2];
+                col witness x;
+                col witness y;
+                x = INPUT;
+                { x, y } is { KEY, VALUE };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        // The key 1 is needed three times, but only occurs twice in the table.
+        generate(&analyzed, degree, &constants, None::<fn(&str) -> _>, false);
+    }
+
+    #[test]
+    fn batched_query() {
+        let src = r#"
+            namespace Main(4);
+                col fixed FIRST = [1];
+                col witness input(i) query ("in", i);
+                col witness sum;
+                FIRST * sum = 0;
+                sum' = sum + input;
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let mut calls = vec![];
+        let callback = batched_query_callback(|query| {
+            calls.push(query.to_string());
+            Some([7, 8, 2, 5].map(Into::into).to_vec())
+        });
+        let witness = generate(&analyzed, degree, &constants, Some(callback), false);
+        assert_eq!(witness[0].1, [7, 8, 2, 5].map(Into::into));
+        assert_eq!(witness[1].1, [0, 7, 15, 17].map(Into::into));
+        // The second call is the query for the wrap-around check of the first row.
+        assert_eq!(calls, ["\"in\", 0", "\"in\", 0"]);
+    }
+}
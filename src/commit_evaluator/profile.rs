@@ -0,0 +1,112 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result};
+use std::time::Duration;
+
+use crate::analyzer::Identity;
+
+/// Collects the time the witness generation spends on each identity, split into
+/// the generic solver and the machines that process the lookups.
+/// Identities are named by their source location, so identities on the same
+/// line are accumulated together.
+#[derive(Default)]
+pub struct Profile {
+    /// Total time and number of calls by source location and component.
+    entries: BTreeMap<(String, &'static str), (Duration, u64)>,
+}
+
+impl Profile {
+    /// Adds a call of `component` (the solver or a machine) on `identity`.
+    pub fn record(&mut self, identity: &Identity, component: &'static str, duration: Duration) {
+        let location = format!("{}:{}", identity.source.file, identity.source.line);
+        let (total, calls) = self.entries.entry((location, component)).or_default();
+        *total += duration;
+        *calls += 1;
+    }
+
+    pub fn total(&self) -> Duration {
+        self.entries.values().map(|(total, _)| *total).sum()
+    }
+
+    /// @returns the source location, component, total time and number of calls,
+    /// sorted by decreasing total time.
+    pub fn sorted_entries(&self) -> Vec<(&str, &'static str, Duration, u64)> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|((location, component), (total, calls))| {
+                (location.as_str(), *component, *total, *calls)
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, _, total, _)| Reverse(*total));
+        entries
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let total = self.total();
+        writeln!(f, "Witness generation profile (total {total:.3?}):")?;
+        writeln!(
+            f,
+            "{:>12} {:>7} {:>10}  location (component)",
+            "time", "share", "calls"
+        )?;
+        for (location, component, time, calls) in self.sorted_entries() {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / total.as_secs_f64()
+            };
+            writeln!(
+                f,
+                "{:>12} {share:>6.1}% {calls:>10}  {location} ({component})",
+                format!("{time:.3?}")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::{IdentityKind, SelectedExpressions, SourceRef};
+
+    use super::*;
+
+    fn identity(line: usize) -> Identity {
+        Identity {
+            id: 0,
+            kind: IdentityKind::Polynomial,
+            source: SourceRef {
+                file: "input.pil".to_string(),
+                line,
+            },
+            namespace: "Main".to_string(),
+            left: SelectedExpressions::default(),
+            right: SelectedExpressions::default(),
+        }
+    }
+
+    #[test]
+    fn sorted_by_time() {
+        let mut profile = Profile::default();
+        let ms = Duration::from_millis;
+        profile.record(&identity(3), "solver", ms(2));
+        profile.record(&identity(7), "solver", ms(1));
+        profile.record(&identity(7), "FixedLookup", ms(4));
+        profile.record(&identity(3), "solver", ms(2));
+        assert_eq!(profile.total(), ms(9));
+        assert_eq!(
+            profile.sorted_entries(),
+            [
+                ("input.pil:3", "solver", ms(4), 2),
+                ("input.pil:7", "FixedLookup", ms(4), 1),
+                ("input.pil:7", "solver", ms(1), 1),
+            ]
+        );
+        let report = format!("{profile}");
+        assert!(report.contains("44.4%          2  input.pil:3 (solver)"));
+        assert!(report.contains("input.pil:7 (FixedLookup)"));
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::analyzer::{Expression, Identity, IdentityKind, SelectedExpressions};
+use crate::commit_evaluator::machine::LookupReturn;
+use crate::number::{clamp, AbstractNumberType, DegreeType};
+
+use super::affine_expression::AffineExpression;
+use super::eval_error::EvalError;
+use super::machine::{LookupResult, Machine};
+use super::FixedData;
+
+/// Machine to perform single-column lookups into fixed columns
+/// that contain the values 0, 1, 2, ..., N-1 in that order (like a BYTE column).
+/// Instead of searching the column, it only checks that the value on the LHS
+/// is smaller than N.
+pub struct RangeCheck {
+    /// The range columns and their sizes.
+    ranges: HashMap<String, usize>,
+}
+
+impl RangeCheck {
+    pub fn try_new(
+        fixed_data: &FixedData,
+        identities: &[&Identity],
+        witness_names: &HashSet<&str>,
+    ) -> Option<Box<Self>> {
+        if !identities.is_empty() || !witness_names.is_empty() {
+            return None;
+        }
+        let ranges = fixed_data
+            .fixed_cols
+            .iter()
+            .filter(|(_, values)| {
+                values
+                    .iter()
+                    .enumerate()
+                    .all(|(i, v)| *v == AbstractNumberType::from(i))
+            })
+            .map(|(name, values)| (name.to_string(), values.len()))
+            .collect::<HashMap<_, _>>();
+        if ranges.is_empty() {
+            None
+        } else {
+            Some(Box::new(RangeCheck { ranges }))
+        }
+    }
+}
+
+impl Machine for RangeCheck {
+    fn process_plookup(
+        &mut self,
+        fixed_data: &FixedData,
+        _row: DegreeType,
+        _identity_id: u64,
+        kind: IdentityKind,
+        left: &[Result<AffineExpression, EvalError>],
+        right: &SelectedExpressions,
+    ) -> LookupResult {
+        let (name, size) = match (kind, &right.selector, &right.expressions[..]) {
+            (IdentityKind::Plookup, None, [Expression::PolynomialReference(poly)])
+                if !poly.next && poly.index.is_none() =>
+            {
+                match self.ranges.get_key_value(&poly.name) {
+                    Some((name, size)) => (name, *size),
+                    None => return Ok(LookupReturn::NotApplicable),
+                }
+            }
+            _ => return Ok(LookupReturn::NotApplicable),
+        };
+
+        let value = match &left[0] {
+            Ok(v) => v.constant_value().ok_or_else(|| {
+                EvalError::Incomplete(format!(
+                    "Value to range-check against {name} is not constant: {}.",
+                    v.format(fixed_data)
+                ))
+            }),
+            Err(err) => Err(err.with_message(format!(
+                "Value to range-check against {name} is unknown: {err}"
+            ))),
+        }?;
+
+        if clamp(value.clone()) < AbstractNumberType::from(size) {
+            Ok(LookupReturn::Assignments(vec![]))
+        } else {
+            Err(EvalError::Conflict(format!(
+                "Value {value} is out of range for lookup into {name} (0..{size})."
+            )))
+        }
+    }
+
+    fn witness_col_values(
+        &mut self,
+        _fixed_data: &FixedData,
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError> {
+        Ok(Default::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::analyzer::{PolynomialReference, SelectedExpressions};
+    use crate::commit_evaluator::FixedData;
+
+    use super::*;
+
+    fn lookup_into(name: &str) -> SelectedExpressions {
+        SelectedExpressions {
+            selector: None,
+            expressions: vec![Expression::PolynomialReference(PolynomialReference {
+                name: name.to_string(),
+                index: None,
+                next: false,
+            })],
+        }
+    }
+
+    #[test]
+    fn bounds_check() {
+        let constants = HashMap::new();
+        let byte = (0..8).map(AbstractNumberType::from).collect::<Vec<_>>();
+        let other = (0..8)
+            .map(|i| AbstractNumberType::from(i % 3))
+            .collect::<Vec<_>>();
+        let fixed_cols = [("Main.BYTE", &byte), ("Main.X", &other)]
+            .into_iter()
+            .collect();
+        let witness_cols = vec![];
+        let fixed_data = FixedData::new(
+            8,
+            &constants,
+            fixed_cols,
+            &witness_cols,
+            HashMap::new(),
+            false,
+        );
+        let mut machine = RangeCheck::try_new(&fixed_data, &[], &HashSet::new()).unwrap();
+
+        let mut check = |value: i32, rhs: &str| {
+            machine.process_plookup(
+                &fixed_data,
+                0,
+                0,
+                IdentityKind::Plookup,
+                &[Ok(AffineExpression::from(AbstractNumberType::from(value)))],
+                &lookup_into(rhs),
+            )
+        };
+        assert!(matches!(
+            check(7, "Main.BYTE"),
+            Ok(LookupReturn::Assignments(a)) if a.is_empty()
+        ));
+        assert!(matches!(
+            check(2, "Main.X"),
+            Ok(LookupReturn::NotApplicable)
+        ));
+        match check(8, "Main.BYTE") {
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "Value 8 is out of range for lookup into Main.BYTE (0..8)."
+            ),
+            _ => panic!("Expected an out-of-range error."),
+        }
+        assert!(check(-1, "Main.BYTE").is_err());
+    }
+}
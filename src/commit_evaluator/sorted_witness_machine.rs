@@ -2,10 +2,10 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 
 use itertools::{Either, Itertools};
 
-use crate::analyzer::{Expression, Identity, IdentityKind, SelectedExpressions};
+use crate::analyzer::{BinaryOperator, Expression, Identity, IdentityKind, SelectedExpressions};
 use crate::commit_evaluator::eval_error;
 use crate::commit_evaluator::machine::LookupReturn;
-use crate::number::AbstractNumberType;
+use crate::number::{AbstractNumberType, DegreeType};
 
 use super::affine_expression::AffineExpression;
 use super::eval_error::EvalError;
@@ -77,21 +77,67 @@ fn check_identity<'a>(fixed_data: &'a FixedData, id: &Identity) -> Option<&'a st
     let notlast = id.left.selector.as_ref()?;
     let positive = id.right.expressions.first().unwrap();
 
-    // TODO this could be rather slow. We should check the code for identity instead
-    // of evaluating it.
     let degree = fixed_data.degree as usize;
-    for row in 0..(degree) {
-        let ev = ExpressionEvaluator::new(FixedEvaluator::new(fixed_data, row));
-        let nl = ev.evaluate(notlast).ok()?.constant_value()?;
-        if (row == degree - 1 && nl != 0.into()) || (row < degree - 1 && nl != 1.into()) {
-            return None;
+    let is_notlast = |row: usize, value: &AbstractNumberType| {
+        *value == AbstractNumberType::from((row < degree - 1) as u32)
+    };
+    let is_positive = |row: usize, value: &AbstractNumberType| *value == (row + 1).into();
+    if !check_values(fixed_data, notlast, is_notlast)
+        || !check_values(fixed_data, positive, is_positive)
+    {
+        return None;
+    }
+    Some(key_column)
+}
+
+/// Checks `check(row, value)` for the values of `e` in all rows.
+/// If `e` is a fixed column `C` or `1 - C`, the generated values of `C` are compared
+/// directly, other expressions are evaluated in each row.
+fn check_values(
+    fixed_data: &FixedData,
+    e: &Expression,
+    check: impl Fn(usize, &AbstractNumberType) -> bool,
+) -> bool {
+    let degree = fixed_data.degree as usize;
+    let all_rows = |values: &Vec<AbstractNumberType>, map: fn(&AbstractNumberType) -> _| {
+        values.len() == degree
+            && values
+                .iter()
+                .enumerate()
+                .all(|(row, v)| check(row, &map(v)))
+    };
+    if let Some(values) = fixed_column(fixed_data, e) {
+        return all_rows(values, |v| v.clone());
+    }
+    if let Expression::BinaryOperation(one, BinaryOperator::Sub, column) = e {
+        if let (Expression::Number(n), Some(values)) =
+            (one.as_ref(), fixed_column(fixed_data, column))
+        {
+            if *n == 1.into() {
+                return all_rows(values, |v| AbstractNumberType::from(1) - v);
+            }
         }
-        let pos = ev.evaluate(positive).ok()?.constant_value()?;
-        if pos != (row + 1).into() {
-            return None;
+    }
+    (0..degree).all(|row| {
+        ExpressionEvaluator::new(FixedEvaluator::new(fixed_data, row))
+            .evaluate(e)
+            .ok()
+            .and_then(|value| value.constant_value())
+            .is_some_and(|value| check(row, &value))
+    })
+}
+
+/// @returns the values of the fixed column if `e` is a reference to it in the current row.
+fn fixed_column<'a>(
+    fixed_data: &FixedData<'a>,
+    e: &Expression,
+) -> Option<&'a Vec<AbstractNumberType>> {
+    match e {
+        Expression::PolynomialReference(r) if !r.next && r.index.is_none() => {
+            fixed_data.fixed_cols.get(r.name.as_str()).copied()
         }
+        _ => None,
     }
-    Some(key_column)
 }
 
 /// Checks that the identity has a constraint of the form `a' - a` as the first expression
@@ -102,10 +148,7 @@ fn check_constraint<'a>(fixed_data: &'a FixedData, constraint: &Expression) -> O
         Ok(c) => c,
         Err(_) => return None,
     };
-    let key_column_id = match sort_constraint.nonzero_variables().as_slice() {
-        [key, _] => *key,
-        _ => return None,
-    };
+    let (key_column_id, _) = sort_constraint.nonzero_variables().collect_tuple()?;
     let witness_count = fixed_data.witness_cols.len();
     let pattern = AffineExpression::from_wittness_poly_value(key_column_id + witness_count)
         - AffineExpression::from_wittness_poly_value(key_column_id);
@@ -120,6 +163,8 @@ impl Machine for SortedWitnesses {
     fn process_plookup(
         &mut self,
         fixed_data: &FixedData,
+        _row: DegreeType,
+        _identity_id: u64,
         kind: IdentityKind,
         left: &[Result<AffineExpression, EvalError>],
         right: &SelectedExpressions,
@@ -163,11 +208,11 @@ impl Machine for SortedWitnesses {
         let key_index = rhs.iter().position(|&x| x == &self.key_col).unwrap();
 
         let key_value = left[key_index].constant_value().ok_or_else(|| {
-            format!(
+            EvalError::Incomplete(format!(
                 "Value of unique key must be known: {} = {}",
                 left[key_index].format(fixed_data),
                 right.expressions[key_index]
-            )
+            ))
         })?;
 
         let mut assignments = vec![];
@@ -183,13 +228,12 @@ impl Machine for SortedWitnesses {
                     let constraint = l.clone() - (*v).clone().into();
                     if constraint.is_invalid() {
                         // The LHS value is known and it is differetn from the stored one.
-                        return Err(format!(
+                        return Err(EvalError::Conflict(format!(
                             "Lookup mismatch: There is already a unique row with {} = \
                             {key_value} and {r} = {v}, but wanted to store {r} = {}",
                             self.key_col,
                             l.format(fixed_data),
-                        )
-                        .into());
+                        )));
                     } else if constraint.constant_value() == Some(0.into()) {
                         // Just a repeated lookup.
                     } else {
@@ -201,9 +245,10 @@ impl Machine for SortedWitnesses {
                                 assignments.push(assignment);
                             }
                             None => {
-                                return Err(
-                                    format!("Cannot solve {} = {v}", l.format(fixed_data)).into()
-                                )
+                                return Err(EvalError::Incomplete(format!(
+                                    "Cannot solve {} = {v}",
+                                    l.format(fixed_data)
+                                )))
                             }
                         }
                     }
@@ -217,11 +262,10 @@ impl Machine for SortedWitnesses {
                         *stored_value = Some(v);
                     }
                     None => {
-                        return Err(format!(
+                        return Err(EvalError::Incomplete(format!(
                             "Value {r} for key {} = {key_value} not known",
                             self.key_col,
-                        )
-                        .into())
+                        )))
                     }
                 },
             }
@@ -232,8 +276,8 @@ impl Machine for SortedWitnesses {
     fn witness_col_values(
         &mut self,
         fixed_data: &FixedData,
-    ) -> HashMap<String, Vec<AbstractNumberType>> {
-        let mut result = HashMap::new();
+    ) -> Result<BTreeMap<String, Vec<AbstractNumberType>>, EvalError> {
+        let mut result = BTreeMap::new();
 
         let (mut keys, mut values): (Vec<_>, Vec<_>) =
             std::mem::take(&mut self.data).into_iter().unzip();
@@ -254,6 +298,65 @@ impl Machine for SortedWitnesses {
             result.insert(col_name.clone(), col_values);
         }
 
-        result
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+    use crate::commit_evaluator::{FixedData, WitnessColumn};
+    use crate::constant_evaluator;
+
+    use super::check_identity;
+
+    /// @returns the key column if the first identity of the PIL is the constraint of a sorted machine.
+    fn key_column(identity: &str) -> Option<String> {
+        let analyzed = analyze_string(&format!(
+            "namespace Main(8);
+col fixed STEP(i) {{ i }};
+col fixed POSITIVE(i) {{ i + 1 }};
+col fixed LAST = [0, 0, 0, 0, 0, 0, 0, 1];
+col fixed NOTLAST = [1, 1, 1, 1, 1, 1, 1, 0];
+col witness key;
+{identity}"
+        ));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness_cols = analyzed
+            .committed_polys_in_source_order()
+            .iter()
+            .enumerate()
+            .map(|(i, (poly, value))| WitnessColumn::new(i, &poly.absolute_name, value))
+            .collect::<Vec<_>>();
+        let fixed_data = FixedData::new(
+            degree,
+            &analyzed.constants,
+            constants
+                .iter()
+                .map(|(name, values)| (*name, values))
+                .collect(),
+            &witness_cols,
+            witness_cols.iter().map(|w| (w.name, w.id)).collect(),
+            false,
+        );
+        check_identity(&fixed_data, &analyzed.identities[0]).map(str::to_string)
+    }
+
+    #[test]
+    fn sorted_identity() {
+        let key = Some("Main.key".to_string());
+        assert_eq!(key_column("NOTLAST { key' - key } in { POSITIVE };"), key);
+        assert_eq!(
+            key_column("(1 - LAST) { key' - key } in { POSITIVE };"),
+            key
+        );
+        // Evaluated in every row.
+        assert_eq!(
+            key_column("(1 - LAST) { key' - key } in { STEP + 1 };"),
+            key
+        );
+        assert_eq!(key_column("LAST { key' - key } in { POSITIVE };"), None);
+        assert_eq!(key_column("NOTLAST { key' - key } in { STEP };"), None);
+        assert_eq!(key_column("NOTLAST { key' - key } in { STEP + 2 };"), None);
     }
 }
@@ -32,9 +32,9 @@ impl<'a> SymbolicVariables for SymbolicEvaluator<'a> {
                 *id + if next { witness_count } else { 0 },
             ))
         } else {
-            Err("Cannot access fixed columns in the symoblic evaluator."
-                .to_string()
-                .into())
+            Err(EvalError::Unsupported(
+                "Cannot access fixed columns in the symoblic evaluator.".to_string(),
+            ))
         }
     }
 
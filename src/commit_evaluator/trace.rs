@@ -0,0 +1,111 @@
+use std::io::Write;
+
+use crate::number::{display_number, parse_number, AbstractNumberType, DegreeType};
+
+/// Writes the execution trace of the witness generation as CSV: A header line
+/// `row,<column>,...` with the names of all witness columns in source order,
+/// followed by one line per row.
+/// Values the witness generator could not derive in a row are left empty. This is
+/// always the case for the columns of machines, because they are only determined
+/// after the last row.
+pub struct TraceWriter<'a> {
+    writer: &'a mut dyn Write,
+}
+
+impl<'a> TraceWriter<'a> {
+    pub fn new(writer: &'a mut dyn Write) -> Self {
+        TraceWriter { writer }
+    }
+
+    pub fn write_header<'b>(&mut self, columns: impl IntoIterator<Item = &'b str>) {
+        let header = ["row"].into_iter().chain(columns).collect::<Vec<_>>();
+        writeln!(self.writer, "{}", header.join(",")).unwrap();
+    }
+
+    pub fn write_row(&mut self, row: DegreeType, values: &[Option<AbstractNumberType>]) {
+        let values = values
+            .iter()
+            .map(|v| v.as_ref().map(display_number).unwrap_or_default());
+        let line = [row.to_string()]
+            .into_iter()
+            .chain(values)
+            .collect::<Vec<_>>();
+        writeln!(self.writer, "{}", line.join(",")).unwrap();
+    }
+}
+
+/// Reads an execution trace in the format written by `TraceWriter`
+/// and returns the values by column. Values can be in signed or unsigned format.
+/// Panics on missing or invalid values.
+pub fn read_trace(csv: &str) -> Vec<(String, Vec<AbstractNumberType>)> {
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().expect("Empty trace.");
+    let mut columns = header
+        .split(',')
+        .skip(1)
+        .map(|name| (name.trim().to_string(), vec![]))
+        .collect::<Vec<_>>();
+    for (row, line) in lines.enumerate() {
+        let mut cells = line.split(',').map(|cell| cell.trim());
+        assert_eq!(
+            cells.next(),
+            Some(row.to_string().as_str()),
+            "Expected row {row} in line: {line}"
+        );
+        let values = cells.collect::<Vec<_>>();
+        assert_eq!(
+            values.len(),
+            columns.len(),
+            "Expected {} values in row {row}, but got {}.",
+            columns.len(),
+            values.len()
+        );
+        for ((name, column), value) in columns.iter_mut().zip(values) {
+            column.push(parse_number(value).unwrap_or_else(|_| {
+                panic!("Invalid or missing value of {name} in row {row}: \"{value}\"")
+            }));
+        }
+    }
+    columns
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_csv() {
+        let mut out = vec![];
+        let mut trace = TraceWriter::new(&mut out);
+        trace.write_header(["Main.x", "Main.y"]);
+        trace.write_row(0, &[Some(1.into()), None]);
+        trace.write_row(1, &[Some(2.into()), Some(3.into())]);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "row,Main.x,Main.y\n0,1,\n1,2,3\n"
+        );
+    }
+
+    #[test]
+    fn read_csv() {
+        let mut out = vec![];
+        let mut trace = TraceWriter::new(&mut out);
+        trace.write_header(["Main.x", "Main.y"]);
+        trace.write_row(0, &[Some(1.into()), Some((-2).into())]);
+        trace.write_row(1, &[Some(2.into()), Some(3.into())]);
+        let columns = read_trace(&String::from_utf8(out).unwrap());
+        assert_eq!(
+            columns,
+            [
+                ("Main.x".to_string(), vec![1.into(), 2.into()]),
+                ("Main.y".to_string(), vec![(-2).into(), 3.into()]),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic = "Invalid or missing value of Main.y in row 0: \"\""]
+    fn read_incomplete_csv() {
+        read_trace("row,Main.x,Main.y\n0,1,\n");
+    }
+}
@@ -1,7 +1,18 @@
+pub use crate::analyzer::util::expr_any;
 use crate::analyzer::Expression;
 
 use super::FixedData;
 
+/// @returns the name of the column if the expression is a reference to the current row of a column.
+pub fn column_name(e: &Expression) -> Option<&str> {
+    match e {
+        Expression::PolynomialReference(poly) if !poly.next && poly.index.is_none() => {
+            Some(&poly.name)
+        }
+        _ => None,
+    }
+}
+
 pub trait WitnessColumnNamer {
     fn name(&self, i: usize) -> String;
 }
@@ -25,22 +36,3 @@ pub fn contains_witness_ref(expr: &Expression, fixed_data: &FixedData) -> bool {
         _ => false,
     })
 }
-
-pub fn expr_any(expr: &Expression, f: &mut impl FnMut(&Expression) -> bool) -> bool {
-    if f(expr) {
-        true
-    } else {
-        match expr {
-            Expression::Tuple(items) => items.iter().any(|e| expr_any(e, f)),
-            Expression::BinaryOperation(l, _, r) => expr_any(l, f) || expr_any(r, f),
-            Expression::UnaryOperation(_, e) => expr_any(e, f),
-            Expression::FunctionCall(_, args) => args.iter().any(|e| expr_any(e, f)),
-            Expression::Constant(_)
-            | Expression::PolynomialReference(_)
-            | Expression::LocalVariableReference(_)
-            | Expression::PublicReference(_)
-            | Expression::Number(_)
-            | Expression::String(_) => false,
-        }
-    }
-}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::analyzer::{Analyzed, BinaryOperator, Expression, IdentityKind, SelectedExpressions};
+use crate::number::{format_number, is_zero, AbstractNumberType, DegreeType};
+
+use super::expression_evaluator::ExpressionEvaluator;
+use super::lookup_coverage::EvaluateOnRow;
+
+/// An identity that does not hold for given column values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintViolation {
+    /// The violated identity, prefixed by its source location.
+    pub identity: String,
+    pub row: DegreeType,
+    /// The values of the left-hand side in the row. For polynomial identities `a = b`,
+    /// these are the values of `a` and `b`.
+    pub left: Vec<AbstractNumberType>,
+    /// The values of the right-hand side. For lookups and permutations, only one
+    /// of the sides is given: The one of the row that has no match on the other side.
+    pub right: Vec<AbstractNumberType>,
+}
+
+impl Display for ConstraintViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let format = |values: &[AbstractNumberType]| {
+            values
+                .iter()
+                .map(format_number)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "Row {}: {}\n    left: [{}], right: [{}]",
+            self.row,
+            self.identity,
+            format(&self.left),
+            format(&self.right)
+        )
+    }
+}
+
+/// Evaluates all identities on all rows of the given fixed and witness columns and
+/// returns the violated ones. Polynomial identities are checked row by row, the
+/// rows of lookups and permutations that have no match on the other side are reported.
+/// Connect identities are not checked.
+pub fn verify(
+    analyzed: &Analyzed,
+    degree: DegreeType,
+    fixed_cols: &[(&str, Vec<AbstractNumberType>)],
+    witness_cols: &[(&str, Vec<AbstractNumberType>)],
+) -> Vec<ConstraintViolation> {
+    let columns: HashMap<&str, &Vec<AbstractNumberType>> = fixed_cols
+        .iter()
+        .chain(witness_cols)
+        .map(|(n, v)| (*n, v))
+        .collect();
+    let evaluate = |e: &Expression, row| {
+        ExpressionEvaluator::new(EvaluateOnRow::new(analyzed, &columns, row))
+            .evaluate(e)
+            .unwrap()
+            .constant_value()
+            .unwrap_or_else(|| {
+                panic!("Expression {e} does not evaluate to a constant on row {row}.")
+            })
+    };
+    // The values of the expressions on all rows where the selector is non-zero.
+    let selected_rows = |selected: &SelectedExpressions| {
+        (0..degree)
+            .filter(|row| {
+                selected
+                    .selector
+                    .as_ref()
+                    .map(|selector| !is_zero(&evaluate(selector, *row)))
+                    .unwrap_or(true)
+            })
+            .map(|row| {
+                let values = selected
+                    .expressions
+                    .iter()
+                    .map(|e| evaluate(e, row))
+                    .collect::<Vec<_>>();
+                (row, values)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut violations = vec![];
+    for identity in &analyzed.identities {
+        let mut violation = |row, left, right| {
+            violations.push(ConstraintViolation {
+                identity: format!(
+                    "{}:{}: {identity}",
+                    identity.source.file, identity.source.line
+                ),
+                row,
+                left,
+                right,
+            })
+        };
+        match identity.kind {
+            IdentityKind::Polynomial => {
+                let expression = identity.left.selector.as_ref().unwrap();
+                for row in 0..degree {
+                    if is_zero(&evaluate(expression, row)) {
+                        continue;
+                    }
+                    match expression {
+                        Expression::BinaryOperation(left, BinaryOperator::Sub, right) => {
+                            violation(row, vec![evaluate(left, row)], vec![evaluate(right, row)])
+                        }
+                        _ => violation(row, vec![evaluate(expression, row)], vec![0.into()]),
+                    }
+                }
+            }
+            IdentityKind::Plookup => {
+                let table = selected_rows(&identity.right)
+                    .into_iter()
+                    .map(|(_, values)| values)
+                    .collect::<HashSet<_>>();
+                for (row, values) in selected_rows(&identity.left) {
+                    if !table.contains(&values) {
+                        violation(row, values, vec![]);
+                    }
+                }
+            }
+            IdentityKind::Permutation => {
+                let mut counts: HashMap<Vec<AbstractNumberType>, usize> = HashMap::new();
+                let right = selected_rows(&identity.right);
+                for (_, values) in &right {
+                    *counts.entry(values.clone()).or_default() += 1;
+                }
+                for (row, values) in selected_rows(&identity.left) {
+                    match counts.get_mut(&values) {
+                        Some(count) if *count > 0 => *count -= 1,
+                        _ => violation(row, values, vec![]),
+                    }
+                }
+                // The rows of the right-hand side that are not matched by the left-hand side.
+                for (row, values) in right.into_iter().rev() {
+                    let count = counts.get_mut(&values).unwrap();
+                    if *count > 0 {
+                        *count -= 1;
+                        violation(row, vec![], values);
+                    }
+                }
+            }
+            IdentityKind::Connect => {}
+        }
+    }
+    violations.sort_by_key(|v| v.row);
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::analyze_string;
+    use crate::constant_evaluator;
+
+    use super::*;
+
+    fn verify_columns(src: &str, witness: &[(&str, Vec<i32>)]) -> Vec<ConstraintViolation> {
+        let analyzed = analyze_string(src);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let witness = witness
+            .iter()
+            .map(|(name, values)| (*name, values.iter().map(|&v| v.into()).collect()))
+            .collect::<Vec<_>>();
+        verify(&analyzed, degree, &constants, &witness)
+    }
+
+    #[test]
+    fn polynomial_identities() {
+        let src = r#"
+            namespace Main(4);
+                col fixed FIRST = [1, 0, 0, 0];
+                col witness x, y;
+                pol s = x + y;
+                FIRST * x = 0;
+                x' = s;
+        "#;
+        assert_eq!(
+            verify_columns(
+                src,
+                &[("Main.x", vec![0, 1, 2, 4]), ("Main.y", vec![1, 1, 2, -4])]
+            ),
+            []
+        );
+        let violations = verify_columns(
+            src,
+            &[("Main.x", vec![1, 1, 2, 4]), ("Main.y", vec![1, 1, 2, 3])],
+        );
+        assert_eq!(violations.len(), 3);
+        assert_eq!(violations[0].row, 0);
+        assert_eq!(
+            violations[0].identity,
+            "input:6: (Main.FIRST * Main.x) = 0;"
+        );
+        assert_eq!(violations[0].left, [1.into()]);
+        assert_eq!(violations[0].right, [0.into()]);
+        // x' = s does not hold on row 0 (1 != 2) and in the wrap-around (1 != 7).
+        assert_eq!(violations[1].row, 0);
+        assert_eq!(violations[1].left, [1.into()]);
+        assert_eq!(violations[1].right, [2.into()]);
+        assert_eq!(violations[2].row, 3);
+        assert_eq!(violations[2].right, [7.into()]);
+    }
+
+    #[test]
+    fn lookups_and_permutations() {
+        let src = r#"
+            namespace Main(4);
+                col fixed BYTE(i) { i };
+                col witness x, y;
+                { x } in { BYTE };
+                { x } is { y };
+        "#;
+        assert_eq!(
+            verify_columns(
+                src,
+                &[("Main.x", vec![0, 3, 3, 1]), ("Main.y", vec![3, 1, 0, 3])]
+            ),
+            []
+        );
+        let violations = verify_columns(
+            src,
+            &[("Main.x", vec![0, 5, 3, 3]), ("Main.y", vec![3, 1, 0, 3])],
+        );
+        let summary = violations
+            .iter()
+            .map(|v| (v.row, v.left.clone(), v.right.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (1, vec![5.into()], vec![]),
+                (1, vec![5.into()], vec![]),
+                (1, vec![], vec![1.into()]),
+            ]
+        );
+        assert!(violations[0]
+            .identity
+            .ends_with("{ Main.x } in { Main.BYTE };"));
+        assert!(violations[1]
+            .identity
+            .ends_with("{ Main.x } is { Main.y };"));
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt::{Display, Formatter, Result};
+
+use crate::number::DegreeType;
+use crate::utils::indent;
+
+/// The reason why the witness generation failed in a row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessError {
+    pub row: DegreeType,
+    /// The identities that were violated or on which no progress could be made,
+    /// prefixed by their source location.
+    pub failed_identities: Vec<String>,
+    /// The witness columns whose values could not be derived.
+    pub unknown_columns: Vec<String>,
+    /// Why the generic solver or the machines could not make progress.
+    pub reasons: Vec<String>,
+    /// The values of the witness columns in the row, `<unknown>` if not derived.
+    pub values: Vec<String>,
+}
+
+impl Display for WitnessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        writeln!(
+            f,
+            "Error: Row {}: Identity check failer or unable to derive values for witness polynomials: {}",
+            self.row,
+            self.unknown_columns.join(", ")
+        )?;
+        writeln!(
+            f,
+            "Failed identities:\n{}",
+            indent(&self.failed_identities.join("\n"), "    ")
+        )?;
+        writeln!(f, "Reasons:\n{}", self.reasons.join("\n\n"))?;
+        write!(
+            f,
+            "Current values:\n{}",
+            indent(&self.values.join("\n"), "    ")
+        )
+    }
+}
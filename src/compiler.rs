@@ -1,143 +1,809 @@
+use std::cell::{LazyCell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::io::{BufRead, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
+use crate::asm_compiler::query::{query_callback, Query};
+use crate::commit_evaluator::lookup_coverage::{self, UnusedLookupRows};
+use crate::commit_evaluator::{Profile, Reports, TraceWriter};
+use crate::number::{
+    field_byte_size, field_mod, from_field_bytes, number_format, parse_number, to_field_bytes,
+    AbstractNumberType, DegreeType,
+};
+use crate::parser::ast::PILFile;
+use crate::parser::ParseError;
+use crate::utils::stable_hash;
+use crate::{analyzer, asm_compiler, commit_evaluator, constant_evaluator, json_exporter, parser};
 use itertools::Itertools;
-use num_bigint::Sign;
+use manifest::{combine_hashes, Manifest, Stage};
 
-use crate::number::{abstract_to_degree, AbstractNumberType, DegreeType};
-use crate::parser::ast::PILFile;
-use crate::{analyzer, asm_compiler, commit_evaluator, constant_evaluator, json_exporter};
+pub use column_file::ColumnFormat;
+
+mod column_file;
+mod manifest;
+
+/// The free inputs of an assembly program by channel name.
+/// Free inputs that do not name a channel are read from `asm_compiler::DEFAULT_INPUT_CHANNEL`.
+pub type InputChannels = HashMap<String, Vec<AbstractNumberType>>;
 
 pub fn no_callback() -> Option<fn(&str) -> Option<AbstractNumberType>> {
     None
 }
 
+/// The options of `compile_pil` and `compile_asm`.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Prints the PIL and the values of each row of the witness.
+    pub verbose: bool,
+    /// Reports rows of fixed lookup tables that are not used by the generated witness.
+    pub lookup_coverage: bool,
+    /// Skips stages whose inputs did not change since the last compilation
+    /// into the output directory. The witness columns of .pil files are always
+    /// generated if there is a query callback.
+    pub incremental: bool,
+    /// Also writes the columns to fixed_columns.<format> and witness_columns.<format>,
+    /// together with their names.
+    pub format: Option<ColumnFormat>,
+    /// Reports the time the witness generation spends on each identity.
+    /// This cannot be combined with `lookup_coverage`.
+    pub profile: bool,
+    /// Writes the execution trace to this file as CSV (see `commit_evaluator::TraceWriter`).
+    /// This cannot be combined with `lookup_coverage`.
+    pub trace_file: Option<PathBuf>,
+    /// Lets the asm compiler choose the degree. Only used for .asm files.
+    pub auto_degree: bool,
+    /// Reads the free inputs from stdin instead of the given inputs.
+    /// Only used for .asm files.
+    pub interactive: bool,
+    /// Overwrites the PIL file generated from a .asm file if it exists.
+    pub force_overwrite: bool,
+    /// Removes the witness columns that are defined by a linear identity
+    /// (see `analyzer::optimizer::eliminate_linearly_defined_witnesses`).
+    pub eliminate_witnesses: bool,
+    /// Replaces witness columns that are constrained like a column in another namespace
+    /// by that column (see `analyzer::optimizer::deduplicate_witnesses_across_namespaces`).
+    pub deduplicate_witnesses: bool,
+    /// Groups the identities in the json file by the machine they are declared in
+    /// (see `json_exporter::export_grouped_by_machine`).
+    pub group_by_machine: bool,
+}
+
+impl CompileOptions {
+    /// Checks that the options can be combined: The lookup coverage report
+    /// keeps the witness in memory, while the trace and the profile are created
+    /// when the witness is written.
+    fn check(&self) -> Result<(), String> {
+        if self.lookup_coverage && self.trace_file.is_some() {
+            Err("The trace cannot be written together with the lookup coverage report.".to_string())
+        } else if self.lookup_coverage && self.profile {
+            Err(
+                "The profile cannot be created together with the lookup coverage report."
+                    .to_string(),
+            )
+        } else {
+            Ok(())
+        }
+    }
+}
+
 /// Compiles a .pil file to its json form and also tries to generate
 /// constants and committed polynomials.
 /// @returns true if all committed/witness and constant/fixed polynomials
-/// could be generated.
+/// could be generated, or an error if the options cannot be combined.
 pub fn compile_pil(
     pil_file: &Path,
     output_dir: &Path,
     query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
-) -> bool {
-    compile(
-        &analyzer::analyze(pil_file),
+    options: &CompileOptions,
+) -> Result<bool, String> {
+    options.check()?;
+    let keys = StageKeys {
+        pil: parser::parse_file_with_includes(pil_file)
+            .ok()
+            .map(|pil| pil_hash(&format!("{pil}"))),
+        inputs: query_callback
+            .is_none()
+            .then(|| inputs_hash(&InputChannels::new())),
+    };
+    Ok(compile(
+        || analyzer::analyze(pil_file),
         pil_file.file_name().unwrap().to_str().unwrap(),
         output_dir,
         query_callback,
-        false,
-    )
+        options,
+        keys,
+        &mut Manifest::load(output_dir, options.incremental),
+    ))
 }
 
+/// Like `compile_pil`, but for PIL that is already parsed.
 pub fn compile_pil_ast(
     pil: &PILFile,
     file_name: &str,
     output_dir: &Path,
     query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
-    verbose: bool,
-) -> bool {
+    options: &CompileOptions,
+) -> Result<bool, String> {
+    options.check()?;
     // TODO exporting this to string as a hack because the parser
     // is tied into the analyzer due to imports.
-    compile(
-        &analyzer::analyze_string(&format!("{pil}")),
+    let pil = format!("{pil}");
+    let keys = StageKeys {
+        pil: Some(pil_hash(&pil)),
+        inputs: None,
+    };
+    Ok(compile(
+        || analyzer::analyze_string(&pil),
         file_name,
         output_dir,
         query_callback,
-        verbose,
-    )
+        options,
+        keys,
+        &mut Manifest::load(output_dir, options.incremental),
+    ))
+}
+
+/// The artifacts of compiling a .asm file, kept in memory.
+pub struct CompilationResult {
+    pub pil: PILFile,
+    pub analyzed: analyzer::Analyzed,
+    pub degree: DegreeType,
+    /// The values of the fixed columns in source order.
+    pub constants: Vec<(String, Vec<AbstractNumberType>)>,
+    /// The values of the witness columns in source order.
+    /// Empty if not all fixed columns are defined.
+    pub commits: Vec<(String, Vec<AbstractNumberType>)>,
+    /// The indices of the inputs that were never read, by channel.
+    pub unused_inputs: Vec<(String, Vec<usize>)>,
+}
+
+/// Compiles a .asm file and generates the fixed and witness columns
+/// without writing any files.
+/// @returns the errors of the asm compiler if the file cannot be compiled.
+pub fn compile_asm_to_memory(
+    file_name: &str,
+    inputs: InputChannels,
+    verbose: bool,
+    auto_degree: bool,
+) -> Result<CompilationResult, Vec<ParseError>> {
+    let pil = compile_asm_to_pil(file_name, auto_degree, verbose)?;
+    let analyzed = analyzer::analyze_string(&format!("{pil}"));
+    let (constants, degree) = constant_evaluator::generate(&analyzed);
+    let usage = InputUsage::new(&inputs);
+    let commits = if analyzed.constant_count() == constants.len() {
+        commit_evaluator::generate(
+            &analyzed,
+            degree,
+            &constants,
+            Some(inputs_query_callback(inputs, usage.clone())),
+            verbose,
+        )
+        .into_iter()
+        .map(|(name, values)| (name.to_string(), values))
+        .collect()
+    } else {
+        vec![]
+    };
+    let constants = constants
+        .into_iter()
+        .map(|(name, values)| (name.to_string(), values))
+        .collect();
+    let unused_inputs = usage.borrow().unused();
+    Ok(CompilationResult {
+        pil,
+        analyzed,
+        degree,
+        constants,
+        commits,
+        unused_inputs,
+    })
 }
 
 /// Compiles a .asm file, outputs the PIL on stdout and tries to generate
 /// fixed and witness columns.
+/// Unlike `compile_asm_to_memory`, this writes the witness columns while
+/// they are generated, so the trace is never kept in memory as a whole.
+/// Inputs that were never read during witness generation are reported on stderr.
+/// @returns an error if the options cannot be combined, the file cannot be compiled
+/// or the PIL file exists and may not be overwritten.
 pub fn compile_asm(
     file_name: &str,
-    inputs: Vec<AbstractNumberType>,
+    inputs: InputChannels,
     output_dir: &Path,
-    force_overwrite: bool,
-    verbose: bool,
-) {
-    let contents = fs::read_to_string(file_name).unwrap();
-    let pil = asm_compiler::compile(Some(file_name), &contents).unwrap_or_else(|err| {
-        eprintln!("Error parsing .asm file:");
-        err.output_to_stderr();
-        panic!();
-    });
+    options: &CompileOptions,
+) -> Result<(), String> {
+    options.check()?;
+    let CompileOptions {
+        verbose,
+        auto_degree,
+        interactive,
+        force_overwrite,
+        ..
+    } = *options;
+    let mut manifest = Manifest::load(output_dir, options.incremental);
     let pil_file_name = output_dir.join(format!(
         "{}.pil",
         Path::new(file_name).file_stem().unwrap().to_str().unwrap()
     ));
-    if pil_file_name.exists() && !force_overwrite {
-        eprint!(
-            "Target file {} already exists. Not overwriting.",
-            pil_file_name.to_str().unwrap()
-        );
-        return;
-    }
-    fs::write(pil_file_name.clone(), format!("{pil}")).unwrap();
-
-    let query_callback = |query: &str| -> Option<AbstractNumberType> {
-        let items = query.split(',').map(|s| s.trim()).collect::<Vec<_>>();
-        let mut it = items.iter();
-        let _current_step = it.next().unwrap();
-        let current_pc = it.next().unwrap();
-        assert!(it.clone().len() % 3 == 0);
-        for (pc_check, input, index) in it.tuples() {
-            if pc_check == current_pc {
-                assert_eq!(*input, "\"input\"");
-                let index: usize = index.parse().unwrap();
-                return inputs.get(index).cloned();
-            }
+    let asm_key = Some(combine_hashes([
+        manifest.file_hash(Path::new(file_name)),
+        auto_degree as u64,
+        field_hash(),
+    ]));
+    let pil = if manifest.is_up_to_date(Stage::Asm, asm_key, &[&pil_file_name]) {
+        println!("{} is up to date.", pil_file_name.display());
+        fs::read_to_string(&pil_file_name).unwrap()
+    } else {
+        let pil = compile_asm_to_pil(file_name, auto_degree, verbose)
+            .map_err(|errors| errors.iter().join("\n"))?;
+        let pil = format!("{pil}");
+        if pil_file_name.exists() && !force_overwrite {
+            return Err(format!(
+                "Target file {} already exists. Not overwriting.",
+                pil_file_name.to_str().unwrap()
+            ));
         }
-        None
+        manifest.start(Stage::Asm);
+        fs::write(pil_file_name.clone(), &pil).unwrap();
+        manifest.record(Stage::Asm, asm_key);
+        pil
+    };
+
+    // The answers on stdin are not known in advance, so the witness is always regenerated.
+    let keys = StageKeys {
+        pil: Some(pil_hash(&pil)),
+        inputs: (!interactive).then(|| inputs_hash(&inputs)),
     };
-    compile_pil_ast(
-        &pil,
+    let usage = InputUsage::new(&inputs);
+    let callback = if interactive {
+        let stdin = std::io::stdin().lock();
+        Box::new(interactive_query_callback(stdin, std::io::stderr())) as Box<dyn FnMut(&str) -> _>
+    } else {
+        Box::new(inputs_query_callback(inputs, usage.clone()))
+    };
+    let success = compile(
+        || analyzer::analyze_string(&pil),
         pil_file_name.to_str().unwrap(),
         output_dir,
-        Some(query_callback),
-        verbose,
+        Some(callback),
+        options,
+        keys,
+        &mut manifest,
+    );
+    if success && !interactive {
+        usage.borrow().report_unused();
+    }
+    Ok(())
+}
+
+/// Checks that the witness can be generated for a .asm or .pil file, without writing
+/// any files. For .asm files, the inputs answer the queries for free inputs,
+/// .pil files are checked without query callback.
+/// Inputs that were never read are reported on stderr. If the check fails, the
+/// inputs that were read but not given are added to the reasons of the error.
+/// Panics if the .asm file cannot be compiled or not all fixed columns are defined.
+pub fn check_witness(
+    file_name: &str,
+    inputs: InputChannels,
+) -> Result<(), commit_evaluator::WitnessError> {
+    let analyzed = if file_name.ends_with(".asm") {
+        let pil = compile_asm_to_pil(file_name, false, false).unwrap_or_else(|errors| {
+            eprintln!("Error parsing .asm file:");
+            errors.iter().for_each(|err| err.output_to_stderr());
+            panic!();
+        });
+        analyzer::analyze_string(&format!("{pil}"))
+    } else {
+        analyzer::analyze(Path::new(file_name))
+    };
+    let (constants, degree) = constant_evaluator::generate(&analyzed);
+    assert_eq!(
+        analyzed.constant_count(),
+        constants.len(),
+        "Not all fixed columns are defined."
+    );
+    if file_name.ends_with(".asm") {
+        let usage = InputUsage::new(&inputs);
+        let result = commit_evaluator::check(
+            &analyzed,
+            degree,
+            &constants,
+            Some(inputs_query_callback(inputs, usage.clone())),
+        );
+        let usage = usage.borrow();
+        match result {
+            Ok(()) => {
+                usage.report_unused();
+                Ok(())
+            }
+            Err(mut err) => {
+                err.reasons.extend(usage.missing());
+                Err(err)
+            }
+        }
+    } else {
+        commit_evaluator::check(&analyzed, degree, &constants, no_callback())
+    }
+}
+
+/// Checks a witness that was generated elsewhere against the constraints of a .pil file.
+/// The witness is read from a file in one of the formats of `ColumnFormat`, which
+/// has to contain all witness columns. The fixed columns are read from the
+/// constants.bin in `fixed_dir`. The degree is the number of rows of the witness.
+/// @returns the violated identities by row.
+pub fn verify_witness(
+    pil_file: &Path,
+    fixed_dir: &Path,
+    witness_file: &Path,
+) -> Result<(), Vec<commit_evaluator::ConstraintViolation>> {
+    let analyzed = analyzer::analyze(pil_file);
+    let witness = column_file::read_columns(&fs::read(witness_file).unwrap());
+    let degree = witness
+        .first()
+        .map(|(_, values)| values.len() as DegreeType)
+        .unwrap_or_default();
+    for (poly, _) in analyzed.committed_polys_in_source_order() {
+        assert!(
+            witness.iter().any(|(name, _)| *name == poly.absolute_name),
+            "Witness column {} is missing in {}.",
+            poly.absolute_name,
+            witness_file.display()
+        );
+    }
+    let constant_names = analyzed
+        .constant_polys_in_source_order()
+        .into_iter()
+        .map(|(poly, _)| poly.absolute_name.as_str())
+        .collect::<Vec<_>>();
+    let constants = read_polys_file(
+        &fs::read(fixed_dir.join("constants.bin")).unwrap(),
+        degree,
+        &constant_names,
     );
+    let witness = witness
+        .iter()
+        .map(|(name, values)| (name.as_str(), values.clone()))
+        .collect::<Vec<_>>();
+    let violations = commit_evaluator::verify(&analyzed, degree, &constants, &witness);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// Compiles a .asm file to PIL, the front end shared by all ways to compile .asm files.
+fn compile_asm_to_pil(
+    file_name: &str,
+    auto_degree: bool,
+    verbose: bool,
+) -> Result<PILFile, Vec<ParseError>> {
+    let contents = fs::read_to_string(file_name).unwrap();
+    asm_compiler::compile_with_auto_degree(Some(file_name), &contents, auto_degree, verbose)
+}
+
+/// Returns a query callback that answers the queries of an asm program
+/// from the inputs of the respective channel, recording the inputs it reads in `usage`.
+/// Queries for channels that are not in `inputs` or for negative indices are answered
+/// with None and recorded as missing.
+fn inputs_query_callback(
+    inputs: InputChannels,
+    usage: Rc<RefCell<InputUsage>>,
+) -> impl FnMut(&str) -> Option<AbstractNumberType> {
+    query_callback(move |query: &Query| {
+        let mut usage = usage.borrow_mut();
+        usage.queried = true;
+        let entry = query.current_entry()?;
+        // Negative indices and channels without inputs are reported like any other missing input.
+        let value = usize::try_from(&entry.index)
+            .ok()
+            .and_then(|index| Some((index, inputs.get(&entry.channel)?.get(index)?.clone())));
+        match value {
+            Some((index, value)) => {
+                usage.read.insert((entry.channel.clone(), index));
+                Some(value)
+            }
+            None => {
+                usage
+                    .missing
+                    .insert((entry.channel.clone(), entry.index.clone()));
+                None
+            }
+        }
+    })
+}
+
+/// The free inputs that were read by `inputs_query_callback`.
+struct InputUsage {
+    /// The number of inputs by channel.
+    sizes: BTreeMap<String, usize>,
+    /// Whether the callback was called at all. If not, the witness was not generated
+    /// or the program has no free inputs, and nothing is reported.
+    queried: bool,
+    read: BTreeSet<(String, usize)>,
+    /// Inputs that were read, but not given, by channel and index.
+    missing: BTreeSet<(String, AbstractNumberType)>,
+}
+
+impl InputUsage {
+    fn new(inputs: &InputChannels) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(InputUsage {
+            sizes: inputs
+                .iter()
+                .map(|(channel, values)| (channel.clone(), values.len()))
+                .collect(),
+            queried: false,
+            read: Default::default(),
+            missing: Default::default(),
+        }))
+    }
+
+    /// The indices of the inputs that were never read, by channel.
+    fn unused(&self) -> Vec<(String, Vec<usize>)> {
+        if !self.queried {
+            return vec![];
+        }
+        self.sizes
+            .iter()
+            .map(|(channel, &size)| {
+                let unused = (0..size)
+                    .filter(|&i| !self.read.contains(&(channel.clone(), i)))
+                    .collect::<Vec<_>>();
+                (channel.clone(), unused)
+            })
+            .filter(|(_, unused)| !unused.is_empty())
+            .collect()
+    }
+
+    fn report_unused(&self) {
+        for (channel, unused) in self.unused() {
+            eprintln!(
+                "Warning: {} of {} inputs of channel \"{channel}\" were never read: {}",
+                unused.len(),
+                self.sizes[&channel],
+                unused.iter().format(", ")
+            );
+        }
+    }
+
+    /// Describes the inputs that were read, but not given.
+    fn missing(&self) -> Vec<String> {
+        self.missing
+            .iter()
+            .map(|(channel, index)| match self.sizes.get(channel) {
+                Some(size) => format!(
+                    "Input {index} of channel \"{channel}\" was read, but only {size} inputs were given."
+                ),
+                None => format!(
+                    "Input {index} of channel \"{channel}\" was read, but no inputs were given for the channel."
+                ),
+            })
+            .collect()
+    }
+}
+
+/// Returns a query callback that asks for the free inputs of an asm program on `input`,
+/// after writing the step, pc, channel and index of the query to `output`.
+/// Each input is only asked for once. After the end of `input`, all queries are
+/// answered with None, as if the inputs were exhausted.
+fn interactive_query_callback(
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> impl FnMut(&str) -> Option<AbstractNumberType> {
+    let mut answers = HashMap::<(String, AbstractNumberType), AbstractNumberType>::new();
+    let mut at_end = false;
+    query_callback(move |query: &Query| {
+        let entry = query.current_entry()?;
+        let key = (entry.channel.clone(), entry.index.clone());
+        if let Some(value) = answers.get(&key) {
+            return Some(value.clone());
+        }
+        while !at_end {
+            write!(
+                output,
+                "Free input at step {}, pc {}: channel \"{}\", index {}: ",
+                query.step, query.pc, entry.channel, entry.index
+            )
+            .unwrap();
+            output.flush().unwrap();
+            let mut line = String::new();
+            if input.read_line(&mut line).unwrap() == 0 {
+                writeln!(output).unwrap();
+                at_end = true;
+            } else {
+                match parse_number(line.trim()) {
+                    Ok(value) => {
+                        answers.insert(key, value.clone());
+                        return Some(value);
+                    }
+                    Err(_) => writeln!(output, "Not a number: {}", line.trim()).unwrap(),
+                }
+            }
+        }
+        None
+    })
 }
 
+/// The hashes of the inputs of the stages after compiling to PIL.
+/// Stages without hash are never skipped.
+struct StageKeys {
+    /// The hash of the PIL source and the field.
+    pil: Option<u64>,
+    /// The hash of the free inputs, if the witness generation only depends on them.
+    inputs: Option<u64>,
+}
+
+/// Expects that the options were checked with `CompileOptions::check`.
 fn compile(
-    analyzed: &analyzer::Analyzed,
+    analyze: impl FnOnce() -> analyzer::Analyzed,
     file_name: &str,
     output_dir: &Path,
     query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
-    verbose: bool,
+    options: &CompileOptions,
+    keys: StageKeys,
+    manifest: &mut Manifest,
 ) -> bool {
+    let CompileOptions {
+        verbose,
+        lookup_coverage,
+        format,
+        profile,
+        eliminate_witnesses,
+        deduplicate_witnesses,
+        group_by_machine,
+        ..
+    } = *options;
+    let trace_file = options.trace_file.as_deref();
+    // The optimizations change the analyzed PIL and thus all outputs.
+    let keys = StageKeys {
+        pil: keys.pil.map(|pil| {
+            combine_hashes([
+                pil,
+                eliminate_witnesses as u64,
+                deduplicate_witnesses as u64,
+            ])
+        }),
+        ..keys
+    };
+    // The PIL is only analyzed if a stage needs to run.
+    let analyzed = LazyCell::new(|| {
+        let mut analyzed = analyze();
+        if eliminate_witnesses {
+            let eliminated =
+                analyzer::optimizer::eliminate_linearly_defined_witnesses(&mut analyzed);
+            if !eliminated.is_empty() {
+                println!("Eliminated witness columns: {}", eliminated.join(", "));
+            }
+        }
+        if deduplicate_witnesses {
+            for (replaced, remaining) in
+                analyzer::optimizer::deduplicate_witnesses_across_namespaces(&mut analyzed)
+            {
+                println!("Replaced witness column {replaced} by {remaining}.");
+            }
+        }
+        analyzed
+    });
     let mut success = true;
-    let (constants, degree) = constant_evaluator::generate(analyzed);
-    if analyzed.constant_count() == constants.len() {
-        write_polys_file(
-            &mut BufWriter::new(&mut fs::File::create(output_dir.join("constants.bin")).unwrap()),
-            degree,
-            &constants,
-        );
-        println!("Wrote constants.bin.");
-        let commits =
-            commit_evaluator::generate(analyzed, degree, &constants, query_callback, verbose);
-        write_polys_file(
-            &mut BufWriter::new(&mut fs::File::create(output_dir.join("commits.bin")).unwrap()),
-            degree,
-            &commits,
+    let constants_file = output_dir.join("constants.bin");
+    let commits_file = output_dir.join("commits.bin");
+    // The columns are also written in the requested format, together with their names.
+    let fixed_columns_file =
+        format.map(|format| output_dir.join(format!("fixed_columns.{format}")));
+    let witness_columns_file =
+        format.map(|format| output_dir.join(format!("witness_columns.{format}")));
+    let commits_key = keys
+        .pil
+        .zip(keys.inputs)
+        .map(|(pil, inputs)| combine_hashes([pil, inputs]));
+    let constants_up_to_date = manifest.is_up_to_date(
+        Stage::Constants,
+        keys.pil,
+        &outputs(&constants_file, &fixed_columns_file),
+    );
+    // Verbose output, the coverage report, the trace and the profile need the witness generation.
+    let commits_up_to_date = !verbose
+        && !lookup_coverage
+        && trace_file.is_none()
+        && !profile
+        && manifest.is_up_to_date(
+            Stage::Commits,
+            commits_key,
+            &outputs(&commits_file, &witness_columns_file),
         );
-        println!("Wrote commits.bin.");
+    if constants_up_to_date && commits_up_to_date {
+        println!("constants.bin and commits.bin are up to date.");
     } else {
-        println!("Not writing constants.bin because not all declared constants are defined (or there are none).");
-        success = false;
+        let (constants, degree) = constant_evaluator::generate_with_cache(
+            &analyzed,
+            Some(&output_dir.join("constants_cache")),
+        );
+        if analyzed.constant_count() == constants.len() {
+            if constants_up_to_date {
+                println!("constants.bin is up to date.");
+            } else {
+                manifest.start(Stage::Constants);
+                write_polys_file(
+                    &mut BufWriter::new(&mut fs::File::create(&constants_file).unwrap()),
+                    degree,
+                    &constants,
+                );
+                println!("Wrote constants.bin.");
+                if let Some(path) = &fixed_columns_file {
+                    export_columns(path, format.unwrap(), degree, &constants);
+                }
+                manifest.record(Stage::Constants, keys.pil);
+            }
+            if commits_up_to_date {
+                println!("commits.bin is up to date.");
+            } else {
+                manifest.start(Stage::Commits);
+                // The file is also read to write the columns of machines into place.
+                let mut commits_writer = fs::File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&commits_file)
+                    .unwrap();
+                if lookup_coverage {
+                    // The coverage report needs all values, so we keep them in memory.
+                    let commits = commit_evaluator::generate(
+                        &analyzed,
+                        degree,
+                        &constants,
+                        query_callback,
+                        verbose,
+                    );
+                    write_polys_file(&mut BufWriter::new(&mut commits_writer), degree, &commits);
+                    report_unused_lookup_rows(&analyzed, degree, &constants, &commits);
+                } else {
+                    let mut trace_writer =
+                        trace_file.map(|path| BufWriter::new(fs::File::create(path).unwrap()));
+                    let mut profile = profile.then(Profile::default);
+                    write_commits_file(
+                        &mut commits_writer,
+                        &analyzed,
+                        degree,
+                        &constants,
+                        query_callback,
+                        Reports {
+                            verbose,
+                            trace: trace_writer
+                                .as_mut()
+                                .map(|file| TraceWriter::new(file))
+                                .as_mut(),
+                            profile: profile.as_mut(),
+                        },
+                    );
+                    if let Some(path) = trace_file {
+                        println!("Wrote {}.", path.display());
+                    }
+                    if let Some(profile) = profile {
+                        print!("{profile}");
+                    }
+                }
+                drop(commits_writer);
+                println!("Wrote commits.bin.");
+                if let Some(path) = &witness_columns_file {
+                    // The witness is read back because it is not kept in memory.
+                    let names = analyzed
+                        .committed_polys_in_source_order()
+                        .into_iter()
+                        .map(|(poly, _)| poly.absolute_name.as_str())
+                        .collect::<Vec<_>>();
+                    let commits =
+                        read_polys_file(&fs::read(&commits_file).unwrap(), degree, &names);
+                    export_columns(path, format.unwrap(), degree, &commits);
+                }
+                manifest.record(Stage::Commits, commits_key);
+            }
+        } else {
+            println!("Not writing constants.bin because not all declared constants are defined (or there are none).");
+            manifest.record(Stage::Constants, None);
+            manifest.record(Stage::Commits, None);
+            success = false;
+        }
     }
-    let json_out = json_exporter::export(analyzed);
     let json_file = format!("{file_name}.json");
-    json_out
-        .write(&mut fs::File::create(output_dir.join(&json_file)).unwrap())
-        .unwrap();
-    println!("Wrote {json_file}.");
+    let json_key = keys
+        .pil
+        .map(|pil| combine_hashes([pil, group_by_machine as u64]));
+    if manifest.is_up_to_date(Stage::Json, json_key, &[&output_dir.join(&json_file)]) {
+        println!("{json_file} is up to date.");
+    } else {
+        manifest.start(Stage::Json);
+        let json_out = if group_by_machine {
+            json_exporter::export_grouped_by_machine(&analyzed)
+        } else {
+            json_exporter::export(&analyzed)
+        };
+        json_out
+            .write(&mut fs::File::create(output_dir.join(&json_file)).unwrap())
+            .unwrap();
+        manifest.constraint_system_hash = Some(analyzer::hash::constraint_system_hash(&analyzed));
+        manifest.record(Stage::Json, json_key);
+        println!("Wrote {json_file}.");
+    }
+    manifest.store();
+    println!("Wrote manifest.json.");
     success
 }
 
+/// Returns the hash of the PIL source, together with the field it is compiled for
+/// and the number format.
+fn pil_hash(pil: &str) -> u64 {
+    combine_hashes([stable_hash(pil.as_bytes()), field_hash()])
+}
+
+/// Returns the hash of the field and the active number format, which both
+/// change the PIL output and the exported columns.
+fn field_hash() -> u64 {
+    let format = number_format().map(|f| f.to_string()).unwrap_or_default();
+    stable_hash(format!("{} {format}", field_mod()).as_bytes())
+}
+
+fn inputs_hash(inputs: &InputChannels) -> u64 {
+    let inputs = inputs
+        .iter()
+        .sorted()
+        .map(|(channel, values)| format!("{channel}: {}", values.iter().format(", ")))
+        .join("\n");
+    stable_hash(inputs.as_bytes())
+}
+
+fn report_unused_lookup_rows(
+    analyzed: &analyzer::Analyzed,
+    degree: DegreeType,
+    constants: &[(&str, Vec<AbstractNumberType>)],
+    commits: &[(&str, Vec<AbstractNumberType>)],
+) {
+    let unused = lookup_coverage::unused_lookup_rows(analyzed, degree, constants, commits);
+    if unused.is_empty() {
+        println!("All rows of all fixed lookup tables are used.");
+    }
+    for UnusedLookupRows {
+        identity,
+        table_size,
+        rows,
+        unevaluated_rows,
+    } in unused
+    {
+        println!(
+            "{} of {table_size} table rows are never matched by the lookup in {}:{}:\n    {identity}\n  Unused rows: {}",
+            rows.len(),
+            identity.source.file,
+            identity.source.line,
+            rows.iter().map(|r| r.to_string()).join(", ")
+        );
+        if !unevaluated_rows.is_empty() {
+            println!(
+                "  The left-hand side cannot be evaluated in the rows {}, which might match some of them.",
+                unevaluated_rows.iter().map(|r| r.to_string()).join(", ")
+            );
+        }
+    }
+}
+
+/// The outputs of a stage: The file in the format of pilcom and the export
+/// in another format, if requested.
+fn outputs<'a>(file: &'a Path, export: &'a Option<PathBuf>) -> Vec<&'a Path> {
+    [file].into_iter().chain(export.as_deref()).collect()
+}
+
+fn export_columns(
+    path: &Path,
+    format: ColumnFormat,
+    degree: DegreeType,
+    columns: &[(&str, Vec<AbstractNumberType>)],
+) {
+    let mut file = BufWriter::new(fs::File::create(path).unwrap());
+    column_file::write_columns(&mut file, format, degree, columns);
+    println!("Wrote {}.", path.display());
+}
+
 fn write_polys_file(
     file: &mut impl Write,
     degree: DegreeType,
@@ -145,13 +811,780 @@ fn write_polys_file(
 ) {
     for i in 0..degree as usize {
         for (_name, constant) in polys {
-            let mut v = constant[i].clone();
-            if v.sign() == Sign::Minus {
-                // This hardcodes the goldilocks field
-                v += 0xffffffff00000001u64;
+            write_value(file, &constant[i]);
+        }
+    }
+}
+
+/// Reads the values of the given columns from data in the format of `write_polys_file`.
+fn read_polys_file<'a>(
+    data: &[u8],
+    degree: DegreeType,
+    names: &[&'a str],
+) -> Vec<(&'a str, Vec<AbstractNumberType>)> {
+    let value_size = field_byte_size();
+    assert_eq!(
+        data.len(),
+        degree as usize * names.len() * value_size,
+        "Expected {degree} rows of {} columns.",
+        names.len()
+    );
+    let mut polys = names
+        .iter()
+        .map(|name| (*name, Vec::with_capacity(degree as usize)))
+        .collect::<Vec<_>>();
+    for (i, value) in data.chunks(value_size).enumerate() {
+        polys[i % names.len()].1.push(from_field_bytes(value));
+    }
+    polys
+}
+
+/// The number of rows that are read back at once to write the columns of machines into place.
+const MACHINE_CHUNK_ROWS: u64 = 4096;
+
+/// Generates the committed polynomials and writes them in the same format as
+/// `write_polys_file`, but row by row, so that the whole trace is never kept in memory.
+/// The columns of machines are only known at the end and are written into place afterwards,
+/// in one sequential pass over chunks of rows.
+fn write_commits_file<F: Read + Write + Seek>(
+    file: &mut F,
+    analyzed: &analyzer::Analyzed,
+    degree: DegreeType,
+    constants: &[(&str, Vec<AbstractNumberType>)],
+    query_callback: Option<impl FnMut(&str) -> Option<AbstractNumberType>>,
+    reports: Reports,
+) {
+    let start = file.stream_position().unwrap();
+    let column_count = analyzed.commitment_count() as u64;
+    let mut writer = BufWriter::new(&mut *file);
+    let machine_values = commit_evaluator::generate_rows(
+        analyzed,
+        degree,
+        constants,
+        query_callback,
+        reports,
+        |row_values| {
+            for v in &row_values {
+                write_value(&mut writer, v);
+            }
+        },
+    );
+    writer.flush().unwrap();
+    drop(writer);
+    write_machine_columns(
+        file,
+        start,
+        degree,
+        column_count,
+        &machine_values,
+        MACHINE_CHUNK_ROWS,
+    );
+    let row_size = column_count * field_byte_size() as u64;
+    file.seek(SeekFrom::Start(start + degree * row_size))
+        .unwrap();
+}
+
+/// Writes the values of the given columns into the rows written at `start`, reading and
+/// writing back `chunk_rows` rows at a time.
+fn write_machine_columns<F: Read + Write + Seek>(
+    file: &mut F,
+    start: u64,
+    degree: DegreeType,
+    column_count: u64,
+    columns: &[(usize, Vec<AbstractNumberType>)],
+    chunk_rows: u64,
+) {
+    if columns.is_empty() {
+        return;
+    }
+    let value_size = field_byte_size() as u64;
+    let row_size = column_count * value_size;
+    let mut chunk = vec![];
+    for chunk_start in (0..degree).step_by(chunk_rows as usize) {
+        let rows = chunk_rows.min(degree - chunk_start);
+        let offset = start + chunk_start * row_size;
+        chunk.resize((rows * row_size) as usize, 0);
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.read_exact(&mut chunk).unwrap();
+        for (col, values) in columns {
+            for row in 0..rows {
+                let position = ((row * column_count + *col as u64) * value_size) as usize;
+                let value = to_field_bytes(&values[(chunk_start + row) as usize]);
+                chunk[position..position + value.len()].copy_from_slice(&value);
+            }
+        }
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&chunk).unwrap();
+    }
+}
+
+fn write_value(file: &mut impl Write, value: &AbstractNumberType) {
+    file.write_all(&to_field_bytes(value)).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::number::{with_number_format, NumberFormat, GOLDILOCKS_MOD};
+
+    use super::*;
+
+    #[test]
+    fn streamed_commits_match_in_memory_commits() {
+        // The memory accesses are handled by a machine, whose columns are written last.
+        let src = fs::read_to_string("tests/mem_read_write.asm").unwrap();
+        let pil = asm_compiler::compile(None, &src).unwrap();
+        let analyzed = analyzer::analyze_string(&format!("{pil}"));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+
+        let commits =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let mut in_memory = vec![];
+        write_polys_file(&mut in_memory, degree, &commits);
+
+        let mut streamed = Cursor::new(vec![]);
+        write_commits_file(
+            &mut streamed,
+            &analyzed,
+            degree,
+            &constants,
+            no_callback(),
+            Reports::default(),
+        );
+        assert_eq!(streamed.position() as usize, in_memory.len());
+        assert_eq!(streamed.into_inner(), in_memory);
+    }
+
+    #[test]
+    fn machine_columns_in_chunks() {
+        let value = |row: u64, col: u64| AbstractNumberType::from(10 * row + col);
+        let (degree, column_count) = (7, 3);
+        let mut file = Cursor::new(vec![]);
+        file.write_all(b"header").unwrap();
+        for row in 0..degree {
+            for col in 0..column_count {
+                let v = if col == 1 { 0.into() } else { value(row, col) };
+                write_value(&mut file, &v);
             }
-            file.write_all(&abstract_to_degree(&v).to_le_bytes())
+        }
+        let machine_column = (0..degree).map(|row| value(row, 1)).collect();
+        write_machine_columns(
+            &mut file,
+            6,
+            degree,
+            column_count,
+            &[(1, machine_column)],
+            3,
+        );
+
+        let data = file.into_inner();
+        assert_eq!(&data[..6], b"header");
+        let columns = read_polys_file(&data[6..], degree, &["a", "b", "c"]);
+        for (col, (_, values)) in columns.iter().enumerate() {
+            let expected = (0..degree)
+                .map(|row| value(row, col as u64))
+                .collect::<Vec<_>>();
+            assert_eq!(*values, expected);
+        }
+    }
+
+    #[test]
+    fn reproducible_commits() {
+        let src = fs::read_to_string("tests/mem_read_write.asm").unwrap();
+        let pil = asm_compiler::compile(None, &src).unwrap();
+        let analyzed = analyzer::analyze_string(&format!("{pil}"));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let write = || {
+            let mut file = Cursor::new(vec![]);
+            write_commits_file(
+                &mut file,
+                &analyzed,
+                degree,
+                &constants,
+                no_callback(),
+                Reports::default(),
+            );
+            file.into_inner()
+        };
+        assert_eq!(write(), write());
+    }
+
+    #[test]
+    fn trace_file() {
+        let analyzed = analyzer::analyze(Path::new("tests/fibonacci.pil"));
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let mut trace = vec![];
+        write_commits_file(
+            &mut Cursor::new(vec![]),
+            &analyzed,
+            degree,
+            &constants,
+            no_callback(),
+            Reports {
+                trace: Some(&mut TraceWriter::new(&mut trace)),
+                ..Default::default()
+            },
+        );
+        let trace = String::from_utf8(trace).unwrap();
+        let mut lines = trace.lines();
+        assert_eq!(lines.next(), Some("row,Fibonacci.x,Fibonacci.y"));
+        assert_eq!(lines.nth(2), Some("2,2,3"));
+        assert_eq!(lines.count() as u64, degree - 3);
+    }
+
+    #[test]
+    fn check_asm_witness() {
+        let inputs = |values: [i32; 6]| default_channel(&values);
+        assert_eq!(
+            check_witness("tests/simple_sum.asm", inputs([16, 4, 1, 2, 8, 5])),
+            Ok(())
+        );
+        let err = check_witness("tests/simple_sum.asm", inputs([15, 4, 1, 2, 8, 5])).unwrap_err();
+        assert_eq!(err.row, 25);
+        assert!(err.failed_identities[0].contains("Assembly.XIsZero"));
+        // The free input is not needed in this row, so no query is answered.
+        assert_eq!(err.unknown_columns, ["Assembly.X_free_value"]);
+    }
+
+    #[test]
+    fn asm_to_memory() {
+        let inputs = default_channel(&[16, 4, 1, 2, 8, 5]);
+        let result = compile_asm_to_memory("tests/simple_sum.asm", inputs, false, false).unwrap();
+        assert_eq!(result.degree, 1024);
+        assert_eq!(result.constants.len(), result.analyzed.constant_count());
+        assert!(format!("{}", result.pil).contains("namespace Assembly(1024);"));
+        let (_, a) = result
+            .commits
+            .iter()
+            .find(|(name, _)| name == "Assembly.A")
+            .unwrap();
+        // The sum is computed and then the expected sum is subtracted.
+        assert!(a.contains(&16.into()));
+        assert_eq!(a.last(), Some(&0.into()));
+    }
+
+    #[test]
+    fn asm_to_memory_with_errors() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let file = dir.join("invalid.asm");
+        fs::write(
+            &file,
+            "reg pc[@pc];\nreg X[<=];\nreg A;\nundefined_instr;\n",
+        )
+        .unwrap();
+        let errors =
+            compile_asm_to_memory(file.to_str().unwrap(), InputChannels::new(), false, false)
+                .err()
+                .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message(), "Unknown instruction undefined_instr.");
+
+        let options = CompileOptions::default();
+        assert_eq!(
+            compile_asm(file.to_str().unwrap(), InputChannels::new(), &dir, &options),
+            Err(format!(
+                "{}:4:1: Unknown instruction undefined_instr.",
+                file.display()
+            ))
+        );
+        assert!(!dir.join("invalid.pil").exists());
+    }
+
+    #[test]
+    fn asm_without_overwrite() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let pil_file = dir.join("simple_sum.pil");
+        fs::write(&pil_file, "// existing\n").unwrap();
+        let inputs = default_channel(&[16, 4, 1, 2, 8, 5]);
+        let compile = |force_overwrite| {
+            let options = CompileOptions {
+                force_overwrite,
+                ..Default::default()
+            };
+            compile_asm("tests/simple_sum.asm", inputs.clone(), &dir, &options)
+        };
+        assert_eq!(
+            compile(false),
+            Err(format!(
+                "Target file {} already exists. Not overwriting.",
+                pil_file.display()
+            ))
+        );
+        assert_eq!(fs::read_to_string(&pil_file).unwrap(), "// existing\n");
+        assert!(!dir.join("commits.bin").exists());
+        assert_eq!(compile(true), Ok(()));
+        assert!(dir.join("commits.bin").exists());
+    }
+
+    #[test]
+    fn unused_inputs() {
+        let inputs = default_channel(&[16, 4, 1, 2, 8, 5, 9, 9]);
+        let result = compile_asm_to_memory("tests/simple_sum.asm", inputs, false, false).unwrap();
+        assert_eq!(result.unused_inputs, [("input".to_string(), vec![6, 7])]);
+        let inputs = default_channel(&[16, 4, 1, 2, 8, 5]);
+        let result = compile_asm_to_memory("tests/simple_sum.asm", inputs, false, false).unwrap();
+        assert!(result.unused_inputs.is_empty());
+    }
+
+    #[test]
+    fn missing_inputs() {
+        let err =
+            check_witness("tests/simple_sum.asm", default_channel(&[16, 4, 1, 2])).unwrap_err();
+        assert!(err
+            .reasons
+            .iter()
+            .any(|r| r.starts_with("Input 5 of channel \"input\" was read")));
+    }
+
+    #[test]
+    fn missing_channel() {
+        let inputs = [("stdin".to_string(), vec![3.into()])].into();
+        let err = check_witness("tests/input_channels.asm", inputs).unwrap_err();
+        assert!(err.reasons.contains(
+            &"Input 1 of channel \"oracle\" was read, but no inputs were given for the channel."
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn negative_input_index() {
+        let inputs = default_channel(&[1, 2]);
+        let usage = InputUsage::new(&inputs);
+        let mut callback = inputs_query_callback(inputs, usage.clone());
+        assert_eq!(callback(r#"0, 3, 3, "input", 1"#), Some(2.into()));
+        assert_eq!(callback(r#"0, 3, 3, "input", -1"#), None);
+        assert_eq!(
+            usage.borrow().missing(),
+            ["Input -1 of channel \"input\" was read, but only 2 inputs were given."]
+        );
+    }
+
+    #[test]
+    fn coroutines() {
+        let result =
+            compile_asm_to_memory("tests/coroutines.asm", InputChannels::new(), false, false)
+                .unwrap();
+        let column = |name| &result.commits.iter().find(|(n, _)| n == name).unwrap().1;
+        let last = |name| column(name).last().unwrap().clone();
+        assert_eq!(last("Assembly.A"), 3.into());
+        assert_eq!(last("Assembly.B"), 14.into());
+        // The lines executed, switching between the coroutines at each yield.
+        // The label of the second coroutine is line 6.
+        assert_eq!(
+            column("Assembly.pc")[..11],
+            [0, 1, 2, 6, 7, 8, 3, 4, 9, 10, 5].map(Into::into)
+        );
+    }
+
+    #[test]
+    fn conditional_lookup() {
+        let result = compile_asm_to_memory(
+            "tests/conditional_lookup.asm",
+            InputChannels::new(),
+            false,
+            false,
+        )
+        .unwrap();
+        let column = |name| &result.commits.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(column("Assembly.A")[..5], [0, 3, 3, 9, 9].map(Into::into));
+        assert_eq!(column("Assembly.B")[..5], [0, 0, 9, 9, 81].map(Into::into));
+    }
+
+    #[test]
+    fn input_channels() {
+        let inputs = [("stdin", vec![3]), ("oracle", vec![0, 5])]
+            .into_iter()
+            .map(|(channel, values)| {
+                (
+                    channel.to_string(),
+                    values.into_iter().map(Into::into).collect(),
+                )
+            })
+            .collect();
+        let result =
+            compile_asm_to_memory("tests/input_channels.asm", inputs, false, false).unwrap();
+        let (_, a) = result
+            .commits
+            .iter()
+            .find(|(name, _)| name == "Assembly.A")
+            .unwrap();
+        assert!(a.contains(&8.into()));
+    }
+
+    #[test]
+    fn jump_table() {
+        for (case, expected) in [(0, 10), (1, 20), (2, 30)] {
+            let result = compile_asm_to_memory(
+                "tests/jump_table.asm",
+                default_channel(&[case]),
+                false,
+                false,
+            )
+            .unwrap();
+            let (_, a) = result
+                .commits
+                .iter()
+                .find(|(name, _)| name == "Assembly.A")
                 .unwrap();
+            assert_eq!(a.last(), Some(&expected.into()));
+        }
+    }
+
+    #[test]
+    fn bitwise() {
+        for (a, b, expected) in [
+            (1, 2, (0, 3)),
+            (2, 1, (2, 1)),
+            (3, 0, (2, 1)),
+            (2, 0, (0, 2)),
+        ] {
+            let result =
+                compile_asm_to_memory("tests/bitwise.asm", default_channel(&[a, b]), false, false)
+                    .unwrap();
+            let last = |column: &str| {
+                let (_, values) = result
+                    .commits
+                    .iter()
+                    .find(|(name, _)| name == column)
+                    .unwrap();
+                values.last().unwrap().clone()
+            };
+            assert_eq!(last("Assembly.A"), expected.0.into());
+            assert_eq!(last("Assembly.B"), expected.1.into());
+        }
+    }
+
+    #[test]
+    fn comparison() {
+        for (a, b, expected) in [
+            (1, 2, (1, 0, 1)),
+            (2, 1, (0, 0, 3)),
+            (3, 3, (0, 1, 2)),
+            (0, 15, (1, 0, 1)),
+        ] {
+            let result = compile_asm_to_memory(
+                "tests/comparison.asm",
+                default_channel(&[a, b]),
+                false,
+                false,
+            )
+            .unwrap();
+            let last = |column: &str| {
+                let (_, values) = result
+                    .commits
+                    .iter()
+                    .find(|(name, _)| name == column)
+                    .unwrap();
+                values.last().unwrap().clone()
+            };
+            assert_eq!(last("Assembly.L"), expected.0.into());
+            assert_eq!(last("Assembly.E"), expected.1.into());
+            assert_eq!(last("Assembly.G"), expected.2.into());
+        }
+    }
+
+    #[test]
+    fn incremental_compilation() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let source = dir.join("sum.asm");
+        fs::copy("tests/simple_sum.asm", &source).unwrap();
+        let compile = |inputs: [i32; 6]| {
+            compile_asm(
+                source.to_str().unwrap(),
+                default_channel(&inputs),
+                &dir,
+                &CompileOptions {
+                    incremental: true,
+                    force_overwrite: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let marker = b"not regenerated".to_vec();
+        let mark = |file: &str| fs::write(dir.join(file), &marker).unwrap();
+        let is_marked = |file: &str| fs::read(dir.join(file)).unwrap() == marker;
+        let outputs = ["constants.bin", "commits.bin", "sum.pil.json"];
+        let inputs = [16, 4, 1, 2, 8, 5];
+
+        compile(inputs);
+        outputs.into_iter().for_each(mark);
+        compile(inputs);
+        for file in outputs {
+            assert!(is_marked(file), "{file} was regenerated");
         }
+
+        // Only the witness depends on the inputs.
+        let inputs = [7, 3, 1, 2, 4, 0];
+        compile(inputs);
+        assert!(!is_marked("commits.bin"));
+        assert!(is_marked("constants.bin"));
+        assert!(is_marked("sum.pil.json"));
+
+        // A comment changes the source, but not the PIL.
+        let asm = fs::read_to_string(&source).unwrap();
+        fs::write(&source, asm + "\n// comment\n").unwrap();
+        mark("commits.bin");
+        compile(inputs);
+        for file in outputs {
+            assert!(is_marked(file), "{file} was regenerated");
+        }
+
+        // The PIL file is only regenerated if the source changes.
+        let pil_file = dir.join("sum.pil");
+        let pil = fs::read_to_string(&pil_file).unwrap() + "// kept\n";
+        fs::write(&pil_file, &pil).unwrap();
+        compile(inputs);
+        assert_eq!(fs::read_to_string(&pil_file).unwrap(), pil);
+        assert!(!is_marked("constants.bin"));
+
+        // Missing outputs are regenerated.
+        fs::remove_file(dir.join("commits.bin")).unwrap();
+        compile(inputs);
+        assert!(!is_marked("commits.bin"));
+    }
+
+    #[test]
+    fn incremental_compilation_with_number_format() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let source = dir.join("sum.asm");
+        fs::copy("tests/simple_sum.asm", &source).unwrap();
+        let compile = || {
+            compile_asm(
+                source.to_str().unwrap(),
+                default_channel(&[16, 4, 1, 2, 8, 5]),
+                &dir,
+                &CompileOptions {
+                    incremental: true,
+                    format: Some(ColumnFormat::Csv),
+                    force_overwrite: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let pil = || fs::read_to_string(dir.join("sum.pil")).unwrap();
+        let csv = || fs::read_to_string(dir.join("fixed_columns.csv")).unwrap();
+        let unsigned_minus_one = (GOLDILOCKS_MOD - 1).to_string();
+
+        compile();
+        assert!(pil().contains("pol constant p_X_read_free = [1, 0, 0, 1, 0, 0, 0, -1, 0];"));
+        // The number format changes the PIL and the exported columns,
+        // so no stage is up to date.
+        with_number_format(NumberFormat::Unsigned, compile);
+        assert!(pil().contains(&format!(
+            "pol constant p_X_read_free = [1, 0, 0, 1, 0, 0, 0, {unsigned_minus_one}, 0];"
+        )));
+        assert!(csv().contains(&unsigned_minus_one));
+        compile();
+        assert!(pil().contains("pol constant p_X_read_free = [1, 0, 0, 1, 0, 0, 0, -1, 0];"));
+    }
+
+    #[test]
+    fn export_columns() {
+        let pil_file = Path::new("tests/fibonacci.pil");
+        let analyzed = analyzer::analyze(pil_file);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let commits =
+            commit_evaluator::generate(&analyzed, degree, &constants, no_callback(), false);
+        let named = |columns: &[(&str, Vec<AbstractNumberType>)]| {
+            columns
+                .iter()
+                .map(|(name, values)| (name.to_string(), values.clone()))
+                .collect::<Vec<_>>()
+        };
+        for format in [ColumnFormat::Bin, ColumnFormat::Csv] {
+            let dir = mktemp::Temp::new_dir().unwrap();
+            let options = CompileOptions {
+                format: Some(format),
+                ..Default::default()
+            };
+            assert!(compile_pil(pil_file, &dir, no_callback(), &options).unwrap());
+            let read = |file: &str| {
+                column_file::read_columns(&fs::read(dir.join(format!("{file}.{format}"))).unwrap())
+            };
+            assert_eq!(read("fixed_columns"), named(&constants));
+            assert_eq!(read("witness_columns"), named(&commits));
+            let witness_file = dir.join(format!("witness_columns.{format}"));
+            assert_eq!(verify_witness(pil_file, &dir, &witness_file), Ok(()));
+        }
+    }
+
+    #[test]
+    fn conflicting_options() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let pil_file = Path::new("tests/fibonacci.pil");
+        let options = CompileOptions {
+            lookup_coverage: true,
+            profile: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            compile_pil(pil_file, &dir, no_callback(), &options),
+            Err(
+                "The profile cannot be created together with the lookup coverage report."
+                    .to_string()
+            )
+        );
+        let pil = parser::parse_file_with_includes(pil_file).unwrap();
+        assert!(compile_pil_ast(&pil, "fibonacci.pil", &dir, no_callback(), &options).is_err());
+        assert!(!dir.join("constants.bin").exists());
+    }
+
+    #[test]
+    fn eliminate_witnesses() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let pil_file = dir.join("sum.pil");
+        fs::write(
+            &pil_file,
+            r#"
+            constant %N = 8;
+            namespace Fibonacci(%N);
+                col fixed ISLAST(i) { 1 - (i - (%N - 1)) / (i - (%N - 1)) };
+                col witness x, sum, y;
+                ISLAST * (y' - 1) = 0;
+                ISLAST * (x' - 1) = 0;
+                sum = x + y;
+                (1-ISLAST) * (x' - y) = 0;
+                (1-ISLAST) * (y' - sum) = 0;
+            "#,
+        )
+        .unwrap();
+        let compile = |eliminate_witnesses| {
+            let options = CompileOptions {
+                incremental: true,
+                eliminate_witnesses,
+                ..Default::default()
+            };
+            assert!(compile_pil(&pil_file, &dir, no_callback(), &options).unwrap());
+            fs::metadata(dir.join("commits.bin")).unwrap().len()
+        };
+        // Each column has 8 rows of 8 bytes.
+        assert_eq!(compile(false), 3 * 8 * 8);
+        // The option is part of the stage keys.
+        assert_eq!(compile(true), 2 * 8 * 8);
+        let json = fs::read_to_string(dir.join("sum.pil.json")).unwrap();
+        assert!(json.contains(r#""nCommitments":2"#));
+    }
+
+    #[test]
+    fn deduplicate_witnesses() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let pil_file = dir.join("counters.pil");
+        fs::write(
+            &pil_file,
+            r#"
+            constant %N = 8;
+            namespace Global(%N);
+                col fixed ISLAST(i) { 1 - (i - (%N - 1)) / (i - (%N - 1)) };
+            namespace A(%N);
+                col witness x;
+                (1 - Global.ISLAST) * (x' - x - 1) = 0;
+                Global.ISLAST * x' = 0;
+            namespace B(%N);
+                col witness y;
+                (1 - Global.ISLAST) * (y' - y - 1) = 0;
+                Global.ISLAST * y' = 0;
+            "#,
+        )
+        .unwrap();
+        let compile = |deduplicate_witnesses| {
+            let options = CompileOptions {
+                incremental: true,
+                deduplicate_witnesses,
+                ..Default::default()
+            };
+            assert!(compile_pil(&pil_file, &dir, no_callback(), &options).unwrap());
+            fs::metadata(dir.join("commits.bin")).unwrap().len()
+        };
+        assert_eq!(compile(false), 2 * 8 * 8);
+        assert_eq!(compile(true), 8 * 8);
+        let json = fs::read_to_string(dir.join("counters.pil.json")).unwrap();
+        assert!(json.contains(r#""nCommitments":1"#));
+    }
+
+    #[test]
+    fn json_grouped_by_machine() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let pil_file = Path::new("tests/fibonacci.pil");
+        let json = |group_by_machine| {
+            let options = CompileOptions {
+                incremental: true,
+                group_by_machine,
+                ..Default::default()
+            };
+            assert!(compile_pil(pil_file, &dir, no_callback(), &options).unwrap());
+            json::parse(&fs::read_to_string(dir.join("fibonacci.pil.json")).unwrap()).unwrap()
+        };
+        assert!(json(false)["machines"].is_null());
+        // The option is part of the key of the json stage.
+        let grouped = json(true);
+        assert!(grouped["polIdentities"].is_null());
+        assert!(!grouped["machines"]["Fibonacci"].is_null());
+    }
+
+    #[test]
+    fn verify_external_witness() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let pil_file = Path::new("tests/fibonacci.pil");
+        let options = CompileOptions::default();
+        assert!(compile_pil(pil_file, &dir, no_callback(), &options).unwrap());
+        let analyzed = analyzer::analyze(pil_file);
+        let (constants, degree) = constant_evaluator::generate(&analyzed);
+        let mut trace = vec![];
+        write_commits_file(
+            &mut Cursor::new(vec![]),
+            &analyzed,
+            degree,
+            &constants,
+            no_callback(),
+            Reports {
+                trace: Some(&mut TraceWriter::new(&mut trace)),
+                ..Default::default()
+            },
+        );
+        let witness_csv = dir.join("witness.csv");
+        fs::write(&witness_csv, &trace).unwrap();
+        assert_eq!(verify_witness(pil_file, &dir, &witness_csv), Ok(()));
+
+        // Change y in row 2 from 3 to 4.
+        let trace = String::from_utf8(trace)
+            .unwrap()
+            .replacen("\n2,2,3\n", "\n2,2,4\n", 1);
+        fs::write(&witness_csv, trace).unwrap();
+        let violations = verify_witness(pil_file, &dir, &witness_csv).unwrap_err();
+        let rows = violations.iter().map(|v| v.row).collect::<Vec<_>>();
+        assert_eq!(rows, [1, 2, 2]);
+        // (1 - ISLAST) * (y' - (x + y)) on row 1
+        assert_eq!(violations[0].left, [1.into()]);
+        assert_eq!(violations[0].right, [0.into()]);
+    }
+
+    #[test]
+    fn interactive_inputs() {
+        let mut output = vec![];
+        let mut callback = interactive_query_callback("x\n7\n".as_bytes(), &mut output);
+        assert_eq!(callback(r#"0, 3, 3, "input", 1"#), Some(7.into()));
+        // The answer is reused, the query is in the wrong row or at the end of the input.
+        assert_eq!(callback(r#"1, 3, 3, "input", 1"#), Some(7.into()));
+        assert_eq!(callback(r#"2, 4, 3, "input", 2"#), None);
+        assert_eq!(callback(r#"3, 3, 3, "input", 2"#), None);
+        assert_eq!(callback(r#"4, 3, 3, "input", 3"#), None);
+        drop(callback);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Free input at step 0, pc 3: channel \"input\", index 1: Not a number: x\n\
+            Free input at step 0, pc 3: channel \"input\", index 1: \
+            Free input at step 3, pc 3: channel \"input\", index 2: \n"
+        );
+    }
+
+    fn default_channel(values: &[i32]) -> InputChannels {
+        [(
+            asm_compiler::DEFAULT_INPUT_CHANNEL.to_string(),
+            values.iter().map(|&v| v.into()).collect(),
+        )]
+        .into()
     }
 }
@@ -0,0 +1,194 @@
+//! Files with the values of fixed or witness columns that record the names of the
+//! columns, in contrast to constants.bin and commits.bin, which only contain the values.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::commit_evaluator::{read_trace, TraceWriter};
+use crate::number::{
+    clamp, field_byte_size, field_mod, from_field_bytes, to_field_bytes, AbstractNumberType,
+    DegreeType,
+};
+
+/// The start of files in the binary format.
+const MAGIC: &[u8; 8] = b"powdrcol";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnFormat {
+    /// A header with the degree, the field modulus and the column names, followed by the
+    /// values column by column. All numbers are little-endian, the values have the
+    /// byte size of the field.
+    Bin,
+    /// The format of execution traces, see `commit_evaluator::TraceWriter`.
+    Csv,
+}
+
+impl ColumnFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ColumnFormat::Bin => "bin",
+            ColumnFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for ColumnFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bin" => Ok(ColumnFormat::Bin),
+            "csv" => Ok(ColumnFormat::Csv),
+            _ => Err(format!("Unknown format {s}, expected bin or csv.")),
+        }
+    }
+}
+
+impl Display for ColumnFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Writes the first `degree` values of the columns in the given format.
+pub fn write_columns<N: AsRef<str>>(
+    file: &mut impl Write,
+    format: ColumnFormat,
+    degree: DegreeType,
+    columns: &[(N, Vec<AbstractNumberType>)],
+) {
+    match format {
+        ColumnFormat::Bin => {
+            file.write_all(MAGIC).unwrap();
+            file.write_all(&degree.to_le_bytes()).unwrap();
+            write_bytes(file, &field_mod().to_bytes_le().1);
+            file.write_all(&(columns.len() as u32).to_le_bytes())
+                .unwrap();
+            for (name, _) in columns {
+                write_bytes(file, name.as_ref().as_bytes());
+            }
+            for (_, values) in columns {
+                for value in &values[..degree as usize] {
+                    file.write_all(&to_field_bytes(value)).unwrap();
+                }
+            }
+        }
+        ColumnFormat::Csv => {
+            let mut trace = TraceWriter::new(file);
+            trace.write_header(columns.iter().map(|(name, _)| name.as_ref()));
+            for row in 0..degree {
+                let values = columns
+                    .iter()
+                    .map(|(_, values)| Some(clamp(values[row as usize].clone())))
+                    .collect::<Vec<_>>();
+                trace.write_row(row, &values);
+            }
+        }
+    }
+}
+
+/// Reads columns written by `write_columns` in any of the formats.
+/// Panics if the data is invalid or, for the binary format, if it was written
+/// for a different field than the active one.
+pub fn read_columns(data: &[u8]) -> Vec<(String, Vec<AbstractNumberType>)> {
+    let Some(mut data) = data.strip_prefix(MAGIC) else {
+        return read_trace(std::str::from_utf8(data).expect("Invalid column file."));
+    };
+    let degree = u64::from_le_bytes(read_array(&mut data));
+    let modulus = AbstractNumberType::from_bytes_le(num_bigint::Sign::Plus, read_bytes(&mut data));
+    assert_eq!(
+        modulus,
+        field_mod(),
+        "The columns were written for the field modulus {modulus}, but the active field has modulus {}.",
+        field_mod()
+    );
+    let column_count = u32::from_le_bytes(read_array(&mut data));
+    let names = (0..column_count)
+        .map(|_| String::from_utf8(read_bytes(&mut data).to_vec()).expect("Invalid column name."))
+        .collect::<Vec<_>>();
+    let value_size = field_byte_size();
+    assert_eq!(
+        data.len(),
+        names.len() * degree as usize * value_size,
+        "Expected {degree} values for each of the {column_count} columns."
+    );
+    let mut values = data.chunks(value_size).map(from_field_bytes);
+    names
+        .into_iter()
+        .map(|name| (name, values.by_ref().take(degree as usize).collect()))
+        .collect()
+}
+
+/// Writes the length of `bytes` as u32 followed by the bytes.
+fn write_bytes(file: &mut impl Write, bytes: &[u8]) {
+    file.write_all(&(bytes.len() as u32).to_le_bytes()).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+fn read_array<const N: usize>(data: &mut &[u8]) -> [u8; N] {
+    assert!(data.len() >= N, "Unexpected end of column file.");
+    let (bytes, rest) = data.split_at(N);
+    *data = rest;
+    bytes.try_into().unwrap()
+}
+
+fn read_bytes<'a>(data: &mut &'a [u8]) -> &'a [u8] {
+    let len = u32::from_le_bytes(read_array(data)) as usize;
+    assert!(data.len() >= len, "Unexpected end of column file.");
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use crate::number::{with_named_field, FieldId};
+
+    use super::*;
+
+    fn columns() -> Vec<(&'static str, Vec<AbstractNumberType>)> {
+        vec![
+            ("Main.x", vec![1.into(), 2.into(), 3.into()]),
+            ("Main.y", vec![(-1).into(), 0.into(), 7.into()]),
+        ]
+    }
+
+    fn write(format: ColumnFormat) -> Vec<u8> {
+        let mut out = vec![];
+        write_columns(&mut out, format, 3, &columns());
+        out
+    }
+
+    #[test]
+    fn write_and_read() {
+        let expected = columns()
+            .into_iter()
+            .map(|(name, values)| {
+                let values = values.into_iter().map(clamp).collect();
+                (name.to_string(), values)
+            })
+            .collect::<Vec<_>>();
+        for format in [ColumnFormat::Bin, ColumnFormat::Csv] {
+            assert_eq!(read_columns(&write(format)), expected);
+        }
+        let bin = write(ColumnFormat::Bin);
+        // Magic, degree, modulus, column count, names and 6 values of 8 bytes.
+        assert_eq!(bin.len(), 8 + 8 + (4 + 8) + 4 + 2 * (4 + 6) + 6 * 8);
+        assert_eq!(bin[8..16], 3u64.to_le_bytes());
+    }
+
+    #[test]
+    #[should_panic = "The columns were written for the field modulus"]
+    fn read_in_other_field() {
+        let bin = write(ColumnFormat::Bin);
+        with_named_field(FieldId::Bn254, || read_columns(&bin));
+    }
+
+    #[test]
+    #[should_panic = "Expected 3 values for each of the 2 columns."]
+    fn read_truncated() {
+        let bin = write(ColumnFormat::Bin);
+        read_columns(&bin[..bin.len() - 1]);
+    }
+}
@@ -0,0 +1,214 @@
+//! The manifest of an output directory. Besides the hash of the constraint system,
+//! it records a hash of the inputs of each stage of the last compilation into the
+//! directory, so that stages whose inputs did not change can be skipped.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::utils::stable_hash;
+
+const FILE_NAME: &str = "manifest.json";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Compiling the .asm file to PIL.
+    Asm,
+    /// Exporting the analyzed PIL to json.
+    Json,
+    /// Generating the fixed columns.
+    Constants,
+    /// Generating the witness columns.
+    Commits,
+}
+
+impl Stage {
+    const ALL: [Stage; 4] = [Stage::Asm, Stage::Json, Stage::Constants, Stage::Commits];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Asm => "asm",
+            Stage::Json => "json",
+            Stage::Constants => "constants",
+            Stage::Commits => "commits",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Manifest {
+    /// The output directory the manifest is stored in.
+    dir: PathBuf,
+    /// Whether stages whose inputs did not change are skipped.
+    skip_unchanged: bool,
+    pub constraint_system_hash: Option<u64>,
+    /// The hash of the inputs of each stage that completed successfully.
+    stages: BTreeMap<Stage, u64>,
+    /// Modification time (in nanoseconds since the epoch), size and content hash of source files.
+    files: BTreeMap<String, (u128, u64, u64)>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the output directory. Returns an empty manifest
+    /// if there is none or it cannot be read.
+    pub fn load(output_dir: &Path, skip_unchanged: bool) -> Self {
+        let mut manifest = Manifest {
+            dir: output_dir.to_path_buf(),
+            skip_unchanged,
+            ..Default::default()
+        };
+        let Some(content) = fs::read_to_string(output_dir.join(FILE_NAME))
+            .ok()
+            .and_then(|content| json::parse(&content).ok())
+        else {
+            return manifest;
+        };
+        manifest.constraint_system_hash = parse_hash(&content["constraintSystemHash"]);
+        for stage in Stage::ALL {
+            if let Some(hash) = parse_hash(&content["stages"][stage.name()]) {
+                manifest.stages.insert(stage, hash);
+            }
+        }
+        for (file, entry) in content["files"].entries() {
+            if let (Some(modified), Some(size), Some(hash)) = (
+                entry["modified"].as_str().and_then(|m| m.parse().ok()),
+                entry["size"].as_u64(),
+                parse_hash(&entry["hash"]),
+            ) {
+                manifest
+                    .files
+                    .insert(file.to_string(), (modified, size, hash));
+            }
+        }
+        manifest
+    }
+
+    pub fn store(&self) {
+        let mut content = json::object! {};
+        if let Some(hash) = self.constraint_system_hash {
+            content["constraintSystemHash"] = format_hash(hash).into();
+        }
+        content["stages"] = json::object! {};
+        for (stage, hash) in &self.stages {
+            content["stages"][stage.name()] = format_hash(*hash).into();
+        }
+        content["files"] = json::object! {};
+        for (file, (modified, size, hash)) in &self.files {
+            content["files"][file.as_str()] = json::object! {
+                modified: modified.to_string(),
+                size: *size,
+                hash: format_hash(*hash),
+            };
+        }
+        content
+            .write(&mut fs::File::create(self.dir.join(FILE_NAME)).unwrap())
+            .unwrap();
+    }
+
+    /// Returns true if unchanged stages are skipped, the stage was last run
+    /// with inputs of the hash `key` and all its outputs still exist.
+    /// A stage without key is never up to date.
+    pub fn is_up_to_date(&self, stage: Stage, key: Option<u64>, outputs: &[&Path]) -> bool {
+        self.skip_unchanged
+            && key.is_some()
+            && self.stages.get(&stage) == key.as_ref()
+            && outputs.iter().all(|output| output.exists())
+    }
+
+    /// Marks the stage as not completed before its outputs are overwritten,
+    /// so that it is not skipped next time if it fails.
+    pub fn start(&mut self, stage: Stage) {
+        if self.stages.remove(&stage).is_some() {
+            self.store();
+        }
+    }
+
+    /// Records that the stage completed successfully with inputs of the hash `key`,
+    /// or, if there is no key, that it cannot be skipped next time.
+    pub fn record(&mut self, stage: Stage, key: Option<u64>) {
+        match key {
+            Some(key) => self.stages.insert(stage, key),
+            None => self.stages.remove(&stage),
+        };
+    }
+
+    /// Returns the hash of the contents of a source file. The file is only read
+    /// if its modification time or size differ from the ones recorded in the manifest.
+    pub fn file_hash(&mut self, path: &Path) -> u64 {
+        let name = path.to_str().unwrap().to_string();
+        let metadata = fs::metadata(path).ok().and_then(|metadata| {
+            let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+            Some((modified.as_nanos(), metadata.len()))
+        });
+        if let (Some(metadata), Some((modified, size, hash))) = (metadata, self.files.get(&name)) {
+            if metadata == (*modified, *size) {
+                return *hash;
+            }
+        }
+        let hash = stable_hash(&fs::read(path).unwrap());
+        if let Some((modified, size)) = metadata {
+            self.files.insert(name, (modified, size, hash));
+        }
+        hash
+    }
+}
+
+/// Combines the hashes of several inputs into one.
+pub fn combine_hashes(hashes: impl IntoIterator<Item = u64>) -> u64 {
+    stable_hash(
+        &hashes
+            .into_iter()
+            .flat_map(|hash| hash.to_le_bytes())
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn format_hash(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+fn parse_hash(value: &json::JsonValue) -> Option<u64> {
+    u64::from_str_radix(value.as_str()?, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn store_and_load() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let source = dir.join("source.asm");
+        fs::write(&source, "reg pc[@pc];").unwrap();
+
+        let mut manifest = Manifest::load(&dir, true);
+        assert!(!manifest.is_up_to_date(Stage::Asm, Some(1), &[]));
+        let hash = manifest.file_hash(&source);
+        manifest.constraint_system_hash = Some(7);
+        manifest.record(Stage::Asm, Some(1));
+        manifest.record(Stage::Commits, Some(2));
+        manifest.record(Stage::Commits, None);
+        manifest.store();
+
+        let mut manifest = Manifest::load(&dir, true);
+        assert_eq!(manifest.constraint_system_hash, Some(7));
+        assert!(manifest.is_up_to_date(Stage::Asm, Some(1), &[&source]));
+        assert!(!manifest.is_up_to_date(Stage::Asm, Some(2), &[&source]));
+        assert!(!manifest.is_up_to_date(Stage::Asm, None, &[&source]));
+        assert!(!manifest.is_up_to_date(Stage::Asm, Some(1), &[&dir.join("missing")]));
+        assert!(!manifest.is_up_to_date(Stage::Commits, Some(2), &[]));
+        assert_eq!(manifest.file_hash(&source), hash);
+
+        fs::write(&source, "reg pc[@pc_readable];").unwrap();
+        assert_ne!(manifest.file_hash(&source), hash);
+
+        manifest.start(Stage::Asm);
+        assert!(!Manifest::load(&dir, true).is_up_to_date(Stage::Asm, Some(1), &[&source]));
+
+        manifest.record(Stage::Asm, Some(1));
+        manifest.store();
+        let manifest = Manifest::load(&dir, false);
+        assert!(!manifest.is_up_to_date(Stage::Asm, Some(1), &[&source]));
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::number::AbstractNumberType;
+
+/// On-disk cache for the values of fixed columns.
+/// Each column is stored in its own file that is named after a hash (the key)
+/// of everything its values depend on.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Self {
+        Cache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// @returns the cached values for the key, if present and readable.
+    pub fn load(&self, key: u64) -> Option<Vec<AbstractNumberType>> {
+        let mut reader = BufReader::new(fs::File::open(self.file(key)).ok()?);
+        let len = read_u32(&mut reader)?;
+        (0..len)
+            .map(|_| {
+                let mut bytes = vec![0u8; read_u32(&mut reader)?];
+                reader.read_exact(&mut bytes).ok()?;
+                Some(AbstractNumberType::from_signed_bytes_le(&bytes))
+            })
+            .collect()
+    }
+
+    /// Stores the values under the key. Failures are reported but not fatal.
+    pub fn store(&self, key: u64, values: &[AbstractNumberType]) {
+        let result = fs::create_dir_all(&self.dir).and_then(|_| {
+            let mut writer = BufWriter::new(fs::File::create(self.file(key))?);
+            writer.write_all(&(values.len() as u32).to_le_bytes())?;
+            for v in values {
+                let bytes = v.to_signed_bytes_le();
+                writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                writer.write_all(&bytes)?;
+            }
+            writer.flush()
+        });
+        if let Err(err) = result {
+            eprintln!("Could not write fixed column cache file: {err}");
+        }
+    }
+
+    /// Removes all cache files whose key is not in `used`.
+    pub fn remove_unused(&self, used: &HashSet<u64>) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let key = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| u64::from_str_radix(s, 16).ok());
+            if path.extension().and_then(|e| e.to_str()) == Some("bin")
+                && !key.map(|k| used.contains(&k)).unwrap_or(false)
+            {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    fn file(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.bin"))
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> Option<usize> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes).ok()?;
+    Some(u32::from_le_bytes(bytes) as usize)
+}
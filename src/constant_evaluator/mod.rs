@@ -1,16 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
+use crate::analyzer::util::expr_any;
 use crate::analyzer::{
-    Analyzed, BinaryOperator, Expression, FunctionValueDefinition, UnaryOperator,
+    Analyzed, BinaryOperator, Expression, FunctionValueDefinition, PaddingStrategy, Polynomial,
+    UnaryOperator,
 };
-use crate::number::{abstract_to_degree, AbstractNumberType, DegreeType};
+use crate::number::{abstract_to_degree, field_mod, AbstractNumberType, DegreeType, NumberContext};
+use crate::utils::stable_hash;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use self::cache::Cache;
+
+mod cache;
 
 /// Generates the constant polynomial values for all constant polynomials
 /// that are defined (and not just declared).
+/// Polynomials that do not depend on each other and the rows of each polynomial
+/// are evaluated in parallel on the current rayon thread pool, in the field
+/// active on the calling thread. The result does not depend on the number of threads.
 /// @returns the values (in source order) and the degree of the polynomials.
 pub fn generate(analyzed: &Analyzed) -> (Vec<(&str, Vec<AbstractNumberType>)>, DegreeType) {
+    generate_with_cache(analyzed, None)
+}
+
+/// Generates the constant polynomial values like `generate`, but if a cache directory
+/// is given, loads the values of polynomials from there if their definition (including
+/// the definitions of the polynomials and constants they reference), the degree and the
+/// field did not change. Newly evaluated polynomials are stored in the cache directory
+/// and outdated entries are removed.
+pub fn generate_with_cache<'a>(
+    analyzed: &'a Analyzed,
+    cache_dir: Option<&Path>,
+) -> (Vec<(&'a str, Vec<AbstractNumberType>)>, DegreeType) {
+    let cache = cache_dir.map(Cache::new);
+    let context = NumberContext::current();
+    let mut keys = HashMap::new();
     let mut degree = None;
-    let mut other_constants = HashMap::new();
+    let mut remaining = vec![];
     for (poly, value) in analyzed.constant_polys_in_source_order() {
         if let Some(value) = value {
             if let Some(degree) = degree {
@@ -18,10 +46,56 @@ pub fn generate(analyzed: &Analyzed) -> (Vec<(&str, Vec<AbstractNumberType>)>, D
             } else {
                 degree = Some(poly.degree);
             }
-            let values = generate_values(analyzed, poly.degree, value, &other_constants);
-            other_constants.insert(&poly.absolute_name, values);
+            remaining.push((poly, value));
         }
     }
+    let mut other_constants = HashMap::new();
+    while !remaining.is_empty() {
+        // Evaluate all polynomials whose dependencies are already evaluated.
+        let (ready, blocked): (Vec<_>, Vec<_>) = remaining.into_iter().partition(|(_, value)| {
+            referenced_polys(value)
+                .iter()
+                .all(|name| other_constants.contains_key(name.as_str()))
+        });
+        assert!(
+            !ready.is_empty(),
+            "Fixed columns depend on undefined or cyclically defined columns: {}",
+            blocked
+                .iter()
+                .map(|(poly, _)| &poly.absolute_name)
+                .join(", ")
+        );
+        for (poly, value) in &ready {
+            let key = cache_key(analyzed, poly, value, &keys);
+            keys.insert(poly.absolute_name.as_str(), key);
+        }
+        let values = ready
+            .par_iter()
+            .map(|(poly, value)| {
+                context.run(|| {
+                    let name = poly.absolute_name.as_str();
+                    let cache = cache.as_ref().map(|cache| (cache, keys[name]));
+                    let cached = cache
+                        .and_then(|(cache, key)| cache.load(key))
+                        .filter(|values| values.len() as DegreeType == poly.degree);
+                    let values = cached.unwrap_or_else(|| {
+                        let values =
+                            generate_values(analyzed, poly.degree, value, &other_constants);
+                        if let Some((cache, key)) = cache {
+                            cache.store(key, &values);
+                        }
+                        values
+                    });
+                    (name, values)
+                })
+            })
+            .collect::<Vec<_>>();
+        other_constants.extend(values);
+        remaining = blocked;
+    }
+    if let Some(cache) = &cache {
+        cache.remove_unused(&keys.values().cloned().collect::<HashSet<_>>());
+    }
     let mut values = Vec::new();
     for (poly, _) in analyzed.constant_polys_in_source_order() {
         if let Some(v) = other_constants.get_mut(poly.absolute_name.as_str()) {
@@ -31,6 +105,68 @@ pub fn generate(analyzed: &Analyzed) -> (Vec<(&str, Vec<AbstractNumberType>)>, D
     (values, degree.unwrap_or_default())
 }
 
+/// @returns the names of the polynomials called in the definition.
+fn referenced_polys(definition: &FunctionValueDefinition) -> Vec<String> {
+    referenced_names(definition, |e| match e {
+        Expression::FunctionCall(name, _) => Some(name),
+        _ => None,
+    })
+}
+
+fn referenced_names(
+    definition: &FunctionValueDefinition,
+    name: impl Fn(&Expression) -> Option<&String>,
+) -> Vec<String> {
+    let mut names = vec![];
+    for e in definition_expressions(definition) {
+        expr_any(e, &mut |e| {
+            names.extend(name(e).cloned());
+            false
+        });
+    }
+    names.into_iter().unique().collect()
+}
+
+fn definition_expressions(definition: &FunctionValueDefinition) -> &[Expression] {
+    match definition {
+        FunctionValueDefinition::Mapping(e) | FunctionValueDefinition::Query(e) => {
+            std::slice::from_ref(e)
+        }
+        FunctionValueDefinition::Array(items, _) => &items[..],
+    }
+}
+
+/// Computes the key of a polynomial in the cache, which covers everything
+/// its values depend on, where referenced polynomials are covered by their keys.
+fn cache_key(
+    analyzed: &Analyzed,
+    poly: &Polynomial,
+    definition: &FunctionValueDefinition,
+    keys: &HashMap<&str, u64>,
+) -> u64 {
+    let constants = referenced_names(definition, |e| match e {
+        Expression::Constant(name) => Some(name),
+        _ => None,
+    })
+    .into_iter()
+    .map(|name| format!("{name} = {}", analyzed.constants[&name]));
+    let polys = referenced_polys(definition)
+        .into_iter()
+        .map(|name| format!("{name}: {:016x}", keys[name.as_str()]));
+    let text = format!(
+        "field {}; degree {}; {}{}; {}",
+        field_mod(),
+        poly.degree,
+        match definition {
+            FunctionValueDefinition::Array(_, padding) => format!("array{padding} "),
+            _ => String::new(),
+        },
+        definition_expressions(definition).iter().format(", "),
+        constants.chain(polys).format(", ")
+    );
+    stable_hash(text.as_bytes())
+}
+
 fn generate_values(
     analyzed: &Analyzed,
     degree: DegreeType,
@@ -38,26 +174,41 @@ fn generate_values(
     other_constants: &HashMap<&str, Vec<AbstractNumberType>>,
 ) -> Vec<AbstractNumberType> {
     match body {
-        FunctionValueDefinition::Mapping(body) => (0..degree)
-            .map(|i| {
-                Evaluator {
-                    analyzed,
-                    variables: &[i.into()],
-                    other_constants,
-                }
-                .evaluate(body)
-            })
-            .collect(),
-        FunctionValueDefinition::Array(values) => {
+        FunctionValueDefinition::Mapping(body) => {
+            let context = NumberContext::current();
+            (0..degree)
+                .into_par_iter()
+                .map_init(
+                    || context.enter(),
+                    |_, i| {
+                        Evaluator {
+                            analyzed,
+                            variables: &[i.into()],
+                            other_constants,
+                        }
+                        .evaluate(body)
+                    },
+                )
+                .collect()
+        }
+        FunctionValueDefinition::Array(values, padding) => {
             let evaluator = Evaluator {
                 analyzed,
                 variables: &[],
                 other_constants,
             };
             let mut values: Vec<_> = values.iter().map(|v| evaluator.evaluate(v)).collect();
-            // TODO we fill with zeros - should we warn? should we repeat?
             if degree as usize > values.len() {
-                values.resize(degree as usize, 0.into())
+                match padding {
+                    PaddingStrategy::Cyclic if !values.is_empty() => {
+                        values = values.into_iter().cycle().take(degree as usize).collect()
+                    }
+                    PaddingStrategy::LastValue => {
+                        let last = values.last().cloned().unwrap_or_default();
+                        values.resize(degree as usize, last)
+                    }
+                    _ => values.resize(degree as usize, 0.into()),
+                }
             }
             values
         }
@@ -117,8 +268,10 @@ impl<'a> Evaluator<'a> {
             BinaryOperator::Mod => left % right,
             BinaryOperator::BinaryAnd => left & right,
             BinaryOperator::BinaryOr => left | right,
+            BinaryOperator::BinaryXor => left ^ right,
             BinaryOperator::ShiftLeft => left << abstract_to_degree(&right),
             BinaryOperator::ShiftRight => left >> abstract_to_degree(&right),
+            op => op.compare(&left, &right),
         }
     }
 
@@ -131,13 +284,17 @@ impl<'a> Evaluator<'a> {
         match op {
             UnaryOperator::Plus => v,
             UnaryOperator::Minus => -v,
+            UnaryOperator::LogicalNot => unreachable!("Logical not is lowered by the analyzer."),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::analyzer::analyze_string;
+    use std::path::Path;
+
+    use crate::analyzer::{analyze, analyze_string};
+    use crate::number::{with_named_field, FieldId};
 
     use super::*;
 
@@ -285,4 +442,87 @@ mod test {
             )
         );
     }
+
+    #[test]
+    pub fn array_padding() {
+        let src = r#"
+            namespace F(7);
+            col fixed zero = [1, 2, 3];
+            col fixed last = [1, 2, 3]...;
+            col fixed cyclic = [1, 2, 3]*;
+            col fixed empty_cyclic = []*;
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, _) = generate(&analyzed);
+        assert_eq!(
+            constants,
+            vec![
+                ("F.zero", convert(vec![1, 2, 3, 0, 0, 0, 0])),
+                ("F.last", convert(vec![1, 2, 3, 3, 3, 3, 3])),
+                ("F.cyclic", convert(vec![1, 2, 3, 1, 2, 3, 1])),
+                ("F.empty_cyclic", convert(vec![0; 7])),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn parallel_evaluation_is_deterministic() {
+        let analyzed = analyze(Path::new("tests/independent_constants.pil"));
+        let parallel = generate(&analyzed);
+        let sequential = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| generate(&analyzed));
+        assert_eq!(parallel.0.len(), 6);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    pub fn reference_to_later_column() {
+        let src = r#"
+            constant %N = 4;
+            namespace F(%N);
+            col fixed doub(i) { seq(i) * 2 };
+            col fixed seq(i) { i };
+        "#;
+        let analyzed = analyze_string(src);
+        let (constants, _) = generate(&analyzed);
+        assert_eq!(constants[0], ("F.doub", convert(vec![0, 2, 4, 6])));
+    }
+
+    #[test]
+    pub fn cached_evaluation() {
+        let src = r#"
+            constant %N = 4;
+            namespace F(%N);
+            col fixed seq(i) { i };
+        "#;
+        let analyzed = analyze_string(src);
+        let cache_dir = mktemp::Temp::new_dir().unwrap();
+        let cache_files = || {
+            std::fs::read_dir(&cache_dir)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect::<Vec<_>>()
+        };
+        let (constants, _) = generate_with_cache(&analyzed, Some(&cache_dir));
+        assert_eq!(constants, vec![("F.seq", convert(vec![0, 1, 2, 3]))]);
+        let files = cache_files();
+        assert_eq!(files.len(), 1);
+
+        // Values are loaded from the cache if the definition did not change.
+        let key = u64::from_str_radix(files[0].file_stem().unwrap().to_str().unwrap(), 16);
+        Cache::new(&cache_dir).store(key.unwrap(), &convert(vec![7, 7, -7, 7]));
+        let (constants, _) = generate_with_cache(&analyzed, Some(&cache_dir));
+        assert_eq!(constants, vec![("F.seq", convert(vec![7, 7, -7, 7]))]);
+
+        // A different field invalidates the cache.
+        let (constants, _) = with_named_field(FieldId::Bn254, || {
+            generate_with_cache(&analyzed, Some(&cache_dir))
+        });
+        assert_eq!(constants, vec![("F.seq", convert(vec![0, 1, 2, 3]))]);
+        assert_eq!(cache_files().len(), 1);
+        assert_ne!(cache_files(), files);
+    }
 }
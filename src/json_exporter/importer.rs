@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::analyzer::{
+    Analyzed, BinaryOperator, Expression, FunctionValueDefinition, Identity, IdentityKind,
+    Polynomial, PolynomialReference, PolynomialType, PublicDeclaration, SelectedExpressions,
+    SourceRef, StatementIdentifier, UnaryOperator,
+};
+
+use super::schema::{ExpressionJson, PilJson, JSON_FORMAT_VERSION};
+
+/// Reconstructs the analyzed PIL from the output of `export` or `export_versioned`.
+/// The values of fixed columns and constants are not part of the exported format,
+/// so fixed columns are imported without definition.
+/// Panics if the JSON is not in the exported format.
+pub fn import(json: &str) -> Analyzed {
+    let mut value: serde_json::Value =
+        serde_json::from_str(json).unwrap_or_else(|err| panic!("Invalid JSON: {err}"));
+    let version = value
+        .as_object_mut()
+        .expect("Expected a JSON object.")
+        .entry("version")
+        .or_insert(JSON_FORMAT_VERSION.into());
+    assert_eq!(
+        *version, JSON_FORMAT_VERSION,
+        "Unsupported version of the JSON format."
+    );
+    let pil = PilJson::deserialize(value)
+        .unwrap_or_else(|err| panic!("JSON does not conform to the schema: {err}"));
+    Importer::new(&pil).import()
+}
+
+struct Importer<'a> {
+    pil: &'a PilJson,
+    /// Names of committed and constant polynomials by type, ID and array length.
+    polys: Vec<(PolynomialType, u64, u64, &'a str)>,
+    /// Names of intermediate polynomials by expression ID.
+    intermediates: HashMap<u64, &'a str>,
+    /// Names of public declarations by ID.
+    publics: HashMap<u64, &'a str>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(pil: &'a PilJson) -> Self {
+        let mut polys = vec![];
+        let mut intermediates = HashMap::new();
+        for (name, reference) in &pil.references {
+            match polynomial_type(&reference.poly_type) {
+                PolynomialType::Intermediate => {
+                    intermediates.insert(reference.id, name.as_str());
+                }
+                poly_type => polys.push((
+                    poly_type,
+                    reference.id,
+                    reference.len.unwrap_or(1),
+                    name.as_str(),
+                )),
+            }
+        }
+        Importer {
+            pil,
+            polys,
+            intermediates,
+            publics: pil
+                .publics
+                .iter()
+                .map(|p| (p.id, p.name.as_str()))
+                .collect(),
+        }
+    }
+
+    fn import(&self) -> Analyzed {
+        let pil = self.pil;
+        // The source order is reconstructed from the IDs: First the committed and constant
+        // polynomials, then the public declarations and finally the intermediate polynomials
+        // and identities in the order of their expressions.
+        let mut source_order = vec![];
+        let mut definitions = HashMap::new();
+        let mut intermediate_expressions = vec![];
+        for (name, reference) in &pil.references {
+            let poly_type = polynomial_type(&reference.poly_type);
+            let value = if poly_type == PolynomialType::Intermediate {
+                intermediate_expressions.push((reference.id, name));
+                Some(FunctionValueDefinition::Mapping(
+                    self.expression(&pil.expressions[reference.id as usize]),
+                ))
+            } else {
+                source_order.push((poly_type as usize, reference.id, name));
+                None
+            };
+            definitions.insert(
+                name.clone(),
+                (
+                    Polynomial {
+                        id: reference.id,
+                        source: unknown_source(),
+                        absolute_name: name.clone(),
+                        poly_type,
+                        degree: reference.pol_deg,
+                        length: reference.len,
+                    },
+                    value,
+                ),
+            );
+        }
+        source_order.sort();
+        let mut source_order = source_order
+            .into_iter()
+            .map(|(_, _, name)| StatementIdentifier::Definition(name.clone()))
+            .collect::<Vec<_>>();
+        // Intermediate polynomials are numbered in the order of their expressions.
+        intermediate_expressions.sort();
+        for (id, (_, name)) in intermediate_expressions.iter().enumerate() {
+            definitions.get_mut(*name).unwrap().0.id = id as u64;
+        }
+
+        let public_declarations = pil
+            .publics
+            .iter()
+            .map(|public| {
+                let polynomial = match public.pol_type.as_str() {
+                    "cmP" => self.reference("cm", public.pol_id, false),
+                    "constP" => self.reference("const", public.pol_id, false),
+                    "imP" => self.reference("exp", public.pol_id, false),
+                    t => panic!("Invalid polynomial type {t}"),
+                };
+                (
+                    public.name.clone(),
+                    PublicDeclaration {
+                        id: public.id,
+                        source: unknown_source(),
+                        name: public.name.clone(),
+                        polynomial,
+                        index: public.idx,
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let mut publics = pil.publics.iter().collect::<Vec<_>>();
+        publics.sort_by_key(|p| p.id);
+        source_order.extend(
+            publics
+                .into_iter()
+                .map(|p| StatementIdentifier::PublicDeclaration(p.name.clone())),
+        );
+
+        let mut identities = pil
+            .pol_identities
+            .iter()
+            .map(|id| {
+                let left = SelectedExpressions {
+                    selector: Some(self.expression_by_id(id.e)),
+                    expressions: vec![],
+                };
+                (
+                    id.e,
+                    (IdentityKind::Polynomial, &id.file_name, id.line),
+                    left,
+                    Default::default(),
+                )
+            })
+            .chain(
+                [
+                    (IdentityKind::Plookup, &pil.plookup_identities),
+                    (IdentityKind::Permutation, &pil.permutation_identities),
+                ]
+                .into_iter()
+                .flat_map(|(kind, identities)| {
+                    identities.iter().map(move |id| {
+                        let first_expression =
+                            id.f.iter().chain(&id.sel_f).chain(&id.t).chain(&id.sel_t);
+                        (
+                            *first_expression.min().unwrap(),
+                            (kind, &id.file_name, id.line),
+                            self.selected_expressions(id.sel_f, &id.f),
+                            self.selected_expressions(id.sel_t, &id.t),
+                        )
+                    })
+                }),
+            )
+            .chain(pil.connection_identities.iter().map(|id| {
+                (
+                    *id.pols.iter().chain(&id.connections).min().unwrap(),
+                    (IdentityKind::Connect, &id.file_name, id.line),
+                    self.selected_expressions(None, &id.pols),
+                    self.selected_expressions(None, &id.connections),
+                )
+            }))
+            .collect::<Vec<_>>();
+        identities.sort_by_key(|(first_expression, ..)| *first_expression);
+
+        let mut ids_per_kind = HashMap::new();
+        let identities = identities
+            .into_iter()
+            .map(|(first_expression, (kind, file, line), left, right)| {
+                let id = ids_per_kind.entry(kind).or_insert(0);
+                let identity = Identity {
+                    id: *id,
+                    kind,
+                    source: SourceRef {
+                        file: file.clone(),
+                        line,
+                    },
+                    namespace: identity_namespace(&left, &right),
+                    left,
+                    right,
+                };
+                *id += 1;
+                (first_expression, identity)
+            })
+            .collect::<Vec<_>>();
+
+        // Merge the identities and intermediate polynomials by their expression IDs.
+        let mut items = identities
+            .iter()
+            .enumerate()
+            .map(|(i, (first_expression, _))| {
+                (*first_expression as u64, StatementIdentifier::Identity(i))
+            })
+            .chain(
+                intermediate_expressions
+                    .into_iter()
+                    .map(|(id, name)| (id, StatementIdentifier::Definition(name.clone()))),
+            )
+            .collect::<Vec<_>>();
+        items.sort_by_key(|(id, _)| *id);
+        source_order.extend(items.into_iter().map(|(_, item)| item));
+
+        Analyzed {
+            constants: Default::default(),
+            definitions,
+            public_declarations,
+            identities: identities
+                .into_iter()
+                .map(|(_, identity)| identity)
+                .collect(),
+            source_order,
+        }
+    }
+
+    fn selected_expressions(&self, selector: Option<usize>, ids: &[usize]) -> SelectedExpressions {
+        SelectedExpressions {
+            selector: selector.map(|id| self.expression_by_id(id)),
+            expressions: ids.iter().map(|id| self.expression_by_id(*id)).collect(),
+        }
+    }
+
+    fn expression_by_id(&self, id: usize) -> Expression {
+        self.expression(&self.pil.expressions[id])
+    }
+
+    fn expression(&self, e: &ExpressionJson) -> Expression {
+        let values = || e.values.as_ref().expect("Expected operands.");
+        match e.op.as_str() {
+            "cm" | "const" | "exp" => Expression::PolynomialReference(self.reference(
+                &e.op,
+                e.id.expect("Expected an ID."),
+                e.next.unwrap_or_default(),
+            )),
+            "public" => Expression::PublicReference(
+                self.publics[&e.id.expect("Expected an ID.")].to_string(),
+            ),
+            "number" => Expression::Number(
+                e.value
+                    .as_ref()
+                    .and_then(|v| v.parse().ok())
+                    .expect("Expected a number."),
+            ),
+            "neg" => Expression::UnaryOperation(
+                UnaryOperator::Minus,
+                Box::new(self.expression(&values()[0])),
+            ),
+            op => {
+                let op = match op {
+                    "add" => BinaryOperator::Add,
+                    "sub" => BinaryOperator::Sub,
+                    "mul" => BinaryOperator::Mul,
+                    "pow" => BinaryOperator::Pow,
+                    _ => panic!("Unknown operation {op}"),
+                };
+                Expression::BinaryOperation(
+                    Box::new(self.expression(&values()[0])),
+                    op,
+                    Box::new(self.expression(&values()[1])),
+                )
+            }
+        }
+    }
+
+    /// Turns an ID in the exported format (including the array offset) into a reference.
+    fn reference(&self, op: &str, id: u64, next: bool) -> PolynomialReference {
+        if op == "exp" {
+            return PolynomialReference {
+                name: self.intermediates[&id].to_string(),
+                index: None,
+                next,
+            };
+        }
+        let poly_type = match op {
+            "cm" => PolynomialType::Committed,
+            "const" => PolynomialType::Constant,
+            _ => panic!("Invalid polynomial reference type {op}"),
+        };
+        let (start, name) = self
+            .polys
+            .iter()
+            .find(|(t, start, len, _)| *t == poly_type && (*start..start + len).contains(&id))
+            .map(|(_, start, _, name)| (*start, *name))
+            .unwrap_or_else(|| panic!("Unknown polynomial: {op} {id}"));
+        let is_array = self.pil.references[name].is_array;
+        PolynomialReference {
+            name: name.to_string(),
+            index: is_array.then_some(id - start),
+            next,
+        }
+    }
+}
+
+fn polynomial_type(t: &str) -> PolynomialType {
+    match t {
+        "cmP" => PolynomialType::Committed,
+        "constP" => PolynomialType::Constant,
+        "imP" => PolynomialType::Intermediate,
+        _ => panic!("Invalid polynomial type {t}"),
+    }
+}
+
+fn unknown_source() -> SourceRef {
+    SourceRef {
+        file: String::new(),
+        line: 0,
+    }
+}
+
+/// The namespace is not exported, so we use the namespace of the first referenced polynomial.
+fn identity_namespace(left: &SelectedExpressions, right: &SelectedExpressions) -> String {
+    left.selector
+        .iter()
+        .chain(&left.expressions)
+        .chain(&right.selector)
+        .chain(&right.expressions)
+        .find_map(first_namespace)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn first_namespace(e: &Expression) -> Option<&str> {
+    match e {
+        Expression::PolynomialReference(poly) => {
+            poly.name.split_once('.').map(|(namespace, _)| namespace)
+        }
+        Expression::BinaryOperation(left, _, right) => {
+            first_namespace(left).or_else(|| first_namespace(right))
+        }
+        Expression::UnaryOperation(_, e) => first_namespace(e),
+        _ => None,
+    }
+}
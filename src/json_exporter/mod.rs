@@ -1,16 +1,21 @@
 use std::cmp;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use json::{object, JsonValue};
 
 use crate::analyzer::{
-    Analyzed, BinaryOperator, Expression, FunctionValueDefinition, IdentityKind,
+    Analyzed, BinaryOperator, Expression, FunctionValueDefinition, Identity, IdentityKind,
     PolynomialReference, PolynomialType, StatementIdentifier, UnaryOperator,
 };
 
 use self::expression_counter::compute_intermediate_expression_ids;
+use self::schema::{PilJson, JSON_FORMAT_VERSION};
 
 mod expression_counter;
+mod importer;
+pub mod schema;
+
+pub use importer::import;
 
 struct Exporter<'a> {
     analyzed: &'a Analyzed,
@@ -22,12 +27,33 @@ struct Exporter<'a> {
 }
 
 pub fn export(analyzed: &Analyzed) -> JsonValue {
+    export_with_grouping(analyzed, false)
+}
+
+/// Exports like `export`, but adds a "version" field and serializes through
+/// the structs in `schema`, which fixes the layout of the output.
+/// Panics if the exported JSON does not conform to the schema.
+pub fn export_versioned(analyzed: &Analyzed) -> String {
+    let mut json = export(analyzed);
+    json["version"] = JSON_FORMAT_VERSION.into();
+    let pil: PilJson = serde_json::from_str(&json.dump())
+        .unwrap_or_else(|err| panic!("Exported JSON does not conform to the schema: {err}"));
+    serde_json::to_string(&pil).unwrap()
+}
+
+/// Exports like `export`, but instead of flat lists of identities, the identities
+/// are grouped by the namespace (machine) they are declared in under the key "machines".
+/// Identities that reference columns of other namespaces are listed under "crossMachine".
+pub fn export_grouped_by_machine(analyzed: &Analyzed) -> JsonValue {
+    export_with_grouping(analyzed, true)
+}
+
+fn export_with_grouping(analyzed: &Analyzed, group_by_machine: bool) -> JsonValue {
     let mut exporter = Exporter::new(analyzed);
     let mut publics = Vec::new();
-    let mut pol_identities = Vec::new();
-    let mut plookup_identities = Vec::new();
-    let mut permutation_identities = Vec::new();
-    let mut connection_identities = Vec::new();
+    let mut identities = IdentityLists::default();
+    let mut machines: BTreeMap<String, IdentityLists> = BTreeMap::new();
+    let mut cross_machine = IdentityLists::default();
     for item in &analyzed.source_order {
         match item {
             StatementIdentifier::Definition(name) => {
@@ -71,45 +97,39 @@ pub fn export(analyzed: &Analyzed) -> JsonValue {
                     exporter.extract_expression_opt(&identity.left.selector, selector_degree);
                 let right = exporter.extract_expression_vec(&identity.right.expressions, 1);
                 let sel_right = exporter.extract_expression_opt(&identity.right.selector, 1);
-                match identity.kind {
-                    IdentityKind::Polynomial => pol_identities.push(object! {
+                let json = match identity.kind {
+                    IdentityKind::Polynomial => object! {
                         e: sel_left.unwrap(),
                         fileName: file_name,
                         line: line
-                    }),
-                    IdentityKind::Plookup => {
-                        plookup_identities.push(object! {
-                            selF: sel_left,
-                            f: left,
-                            selT: sel_right,
-                            t: right,
-                            fileName: file_name,
-                            line: line
-                        });
-                    }
-                    IdentityKind::Permutation => {
-                        permutation_identities.push(object! {
-                            selF: sel_left,
-                            f: left,
-                            selT: sel_right,
-                            t: right,
-                            fileName: file_name,
-                            line: line
-                        });
-                    }
-                    IdentityKind::Connect => {
-                        connection_identities.push(object! {
-                            pols: left,
-                            connections: right,
-                            fileName: file_name,
-                            line: line
-                        });
-                    }
-                }
+                    },
+                    IdentityKind::Plookup | IdentityKind::Permutation => object! {
+                        selF: sel_left,
+                        f: left,
+                        selT: sel_right,
+                        t: right,
+                        fileName: file_name,
+                        line: line
+                    },
+                    IdentityKind::Connect => object! {
+                        pols: left,
+                        connections: right,
+                        fileName: file_name,
+                        line: line
+                    },
+                };
+                let target = if !group_by_machine {
+                    &mut identities
+                } else if is_cross_machine(identity) {
+                    &mut cross_machine
+                } else {
+                    machines.entry(identity.namespace.clone()).or_default()
+                };
+                target.push(identity.kind, json);
             }
         }
     }
-    object! {
+    let mut result = object! {
         nCommitments: analyzed.commitment_count(),
         nQ: exporter.number_q,
         nIm: analyzed.intermediate_count(),
@@ -117,10 +137,84 @@ pub fn export(analyzed: &Analyzed) -> JsonValue {
         publics: publics,
         references: exporter.references(),
         expressions: exporter.expressions,
-        polIdentities: pol_identities,
-        plookupIdentities: plookup_identities,
-        permutationIdentities: permutation_identities,
-        connectionIdentities: connection_identities,
+    };
+    if group_by_machine {
+        result["machines"] = machines
+            .into_iter()
+            .map(|(namespace, identities)| (namespace, identities.into_json()))
+            .collect::<BTreeMap<_, _>>()
+            .into();
+        result["crossMachine"] = cross_machine.into_json();
+    } else {
+        for (key, value) in identities.into_json().entries_mut() {
+            result[key] = value.take();
+        }
+    }
+    result
+}
+
+/// The exported identities, separated by kind.
+#[derive(Default)]
+struct IdentityLists {
+    polynomial: Vec<JsonValue>,
+    plookup: Vec<JsonValue>,
+    permutation: Vec<JsonValue>,
+    connection: Vec<JsonValue>,
+}
+
+impl IdentityLists {
+    fn push(&mut self, kind: IdentityKind, identity: JsonValue) {
+        match kind {
+            IdentityKind::Polynomial => &mut self.polynomial,
+            IdentityKind::Plookup => &mut self.plookup,
+            IdentityKind::Permutation => &mut self.permutation,
+            IdentityKind::Connect => &mut self.connection,
+        }
+        .push(identity)
+    }
+
+    fn into_json(self) -> JsonValue {
+        object! {
+            polIdentities: self.polynomial,
+            plookupIdentities: self.plookup,
+            permutationIdentities: self.permutation,
+            connectionIdentities: self.connection,
+        }
+    }
+}
+
+/// @returns true if the identity references a polynomial outside of
+/// the namespace it is declared in.
+fn is_cross_machine(identity: &Identity) -> bool {
+    let mut namespaces = BTreeSet::new();
+    for selected in [&identity.left, &identity.right] {
+        for e in selected.selector.iter().chain(selected.expressions.iter()) {
+            collect_referenced_namespaces(e, &mut namespaces);
+        }
+    }
+    namespaces.iter().any(|n| *n != identity.namespace)
+}
+
+fn collect_referenced_namespaces<'a>(e: &'a Expression, namespaces: &mut BTreeSet<&'a str>) {
+    match e {
+        Expression::PolynomialReference(poly) => {
+            if let Some((namespace, _)) = poly.name.split_once('.') {
+                namespaces.insert(namespace);
+            }
+        }
+        Expression::Tuple(items) | Expression::FunctionCall(_, items) => items
+            .iter()
+            .for_each(|e| collect_referenced_namespaces(e, namespaces)),
+        Expression::BinaryOperation(left, _, right) => {
+            collect_referenced_namespaces(left, namespaces);
+            collect_referenced_namespaces(right, namespaces);
+        }
+        Expression::UnaryOperation(_, e) => collect_referenced_namespaces(e, namespaces),
+        Expression::Constant(_)
+        | Expression::LocalVariableReference(_)
+        | Expression::PublicReference(_)
+        | Expression::Number(_)
+        | Expression::String(_) => {}
     }
 }
 
@@ -270,8 +364,15 @@ impl<'a> Exporter<'a> {
                     BinaryOperator::Mod
                     | BinaryOperator::BinaryAnd
                     | BinaryOperator::BinaryOr
+                    | BinaryOperator::BinaryXor
                     | BinaryOperator::ShiftLeft
-                    | BinaryOperator::ShiftRight => {
+                    | BinaryOperator::ShiftRight
+                    | BinaryOperator::Less
+                    | BinaryOperator::LessEqual
+                    | BinaryOperator::Greater
+                    | BinaryOperator::GreaterEqual
+                    | BinaryOperator::Equal
+                    | BinaryOperator::NotEqual => {
                         panic!("Operator {op:?} not supported on polynomials.")
                     }
                 };
@@ -298,6 +399,9 @@ impl<'a> Exporter<'a> {
                         },
                         deps,
                     ),
+                    UnaryOperator::LogicalNot => {
+                        panic!("Logical not should have been replaced by the analyzer.")
+                    }
                 }
             }
             Expression::FunctionCall(_, _) => {
@@ -473,4 +577,85 @@ mod test {
         compare_export_file_ignore_idq_hex("tests/polygon-hermez/rom.pil");
         compare_export_file_ignore_idq_hex("tests/polygon-hermez/main.pil");
     }
+
+    #[test]
+    fn grouped_by_machine() {
+        let src = r#"
+            namespace Main(8);
+                col fixed A(i) { i };
+                col witness x, y;
+                x' = x + 1;
+                y = x * 2;
+                { x } in { A };
+                { y } in { Byte.BYTE };
+            namespace Byte(8);
+                col fixed BYTE(i) { i & 0xff };
+                col witness z;
+                z = BYTE;
+        "#;
+        let analyzed = analyzer::analyze_string(src);
+        let grouped = export_grouped_by_machine(&analyzed);
+        assert!(grouped["polIdentities"].is_null());
+        let machines = &grouped["machines"];
+        assert_eq!(machines.len(), 2);
+        assert_eq!(machines["Main"]["polIdentities"].len(), 2);
+        assert_eq!(machines["Main"]["plookupIdentities"].len(), 1);
+        assert_eq!(machines["Byte"]["polIdentities"].len(), 1);
+        assert_eq!(machines["Byte"]["plookupIdentities"].len(), 0);
+        let cross = &grouped["crossMachine"]["plookupIdentities"];
+        assert_eq!(cross.len(), 1);
+        assert_eq!(cross[0]["line"], 8);
+
+        // The flat export contains the same identities and expressions.
+        let flat = export(&analyzed);
+        assert!(flat["machines"].is_null());
+        assert_eq!(flat["polIdentities"].len(), 3);
+        assert_eq!(flat["plookupIdentities"].len(), 2);
+        assert_eq!(flat["expressions"], grouped["expressions"]);
+    }
+
+    #[test]
+    fn export_then_import() {
+        for file in [
+            "tests/polygon-hermez/config.pil",
+            "tests/polygon-hermez/global.pil",
+            "tests/polygon-hermez/arith.pil",
+            "tests/polygon-hermez/keccakf.pil",
+            "tests/polygon-hermez/main.pil",
+        ] {
+            let analyzed = analyzer::analyze(Path::new(file));
+            let exported = export(&analyzed);
+            let imported = import(&exported.dump());
+            assert_eq!(export(&imported), exported, "{file}");
+            assert_eq!(imported.definitions.len(), analyzed.definitions.len());
+            for (imported, original) in imported.identities.iter().zip(&analyzed.identities) {
+                assert_eq!(imported.kind, original.kind);
+                assert_eq!(imported.source, original.source);
+            }
+            assert_eq!(
+                import(&export_versioned(&analyzed)).source_order.len(),
+                analyzed.source_order.len()
+            );
+        }
+    }
+
+    #[test]
+    fn versioned_round_trip() {
+        for file in [
+            "tests/polygon-hermez/config.pil",
+            "tests/polygon-hermez/global.pil",
+            "tests/polygon-hermez/arith.pil",
+            "tests/polygon-hermez/main.pil",
+        ] {
+            let analyzed = analyzer::analyze(Path::new(file));
+            let versioned = export_versioned(&analyzed);
+            let parsed: PilJson = serde_json::from_str(&versioned).unwrap();
+            assert_eq!(parsed.version, JSON_FORMAT_VERSION);
+            assert_eq!(serde_json::to_string(&parsed).unwrap(), versioned);
+
+            let mut expected = export(&analyzed);
+            expected["version"] = JSON_FORMAT_VERSION.into();
+            assert_eq!(json::parse(&versioned).unwrap(), expected);
+        }
+    }
 }
@@ -0,0 +1,110 @@
+//! Typed description of the JSON format produced by the exporter (the format of pilcom).
+//! The structs reject unknown fields, so that changes to the layout are detected.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The version of the layout described by the structs in this module.
+/// It has to be increased with every change to the layout.
+pub const JSON_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PilJson {
+    pub version: u32,
+    pub n_commitments: u64,
+    #[serde(rename = "nQ")]
+    pub n_q: u64,
+    pub n_im: u64,
+    pub n_constants: u64,
+    pub publics: Vec<PublicJson>,
+    pub references: BTreeMap<String, ReferenceJson>,
+    pub expressions: Vec<ExpressionJson>,
+    pub pol_identities: Vec<PolIdentityJson>,
+    pub plookup_identities: Vec<LookupIdentityJson>,
+    pub permutation_identities: Vec<LookupIdentityJson>,
+    pub connection_identities: Vec<ConnectionIdentityJson>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PublicJson {
+    pub name: String,
+    pub pol_id: u64,
+    /// One of "cmP", "constP" and "imP".
+    pub pol_type: String,
+    pub idx: u64,
+    pub id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ReferenceJson {
+    /// One of "cmP", "constP" and "imP".
+    #[serde(rename = "type")]
+    pub poly_type: String,
+    pub id: u64,
+    pub pol_deg: u64,
+    pub is_array: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub len: Option<u64>,
+}
+
+/// An expression node. Which of the optional fields are present depends on `op`:
+/// References ("cm", "const", "exp") have `id` and `next`, "public" has `id`,
+/// "number" has `value` and operators have `values`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ExpressionJson {
+    pub op: String,
+    pub deg: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<bool>,
+    /// The decimal representation of a number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<ExpressionJson>>,
+    #[serde(default, rename = "idQ", skip_serializing_if = "Option::is_none")]
+    pub id_q: Option<u64>,
+    /// IDs of the intermediate polynomials the expression depends on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deps: Option<Vec<u64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PolIdentityJson {
+    /// ID of the expression that has to be zero.
+    pub e: usize,
+    pub file_name: String,
+    pub line: usize,
+}
+
+/// A plookup or permutation identity. The fields are expression IDs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LookupIdentityJson {
+    #[serde(rename = "selF")]
+    pub sel_f: Option<usize>,
+    pub f: Vec<usize>,
+    #[serde(rename = "selT")]
+    pub sel_t: Option<usize>,
+    pub t: Vec<usize>,
+    #[serde(rename = "fileName")]
+    pub file_name: String,
+    pub line: usize,
+}
+
+/// A connection identity. The fields are expression IDs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ConnectionIdentityJson {
+    pub pols: Vec<usize>,
+    pub connections: Vec<usize>,
+    pub file_name: String,
+    pub line: usize,
+}
@@ -1,3 +1,8 @@
+use std::cell::RefCell;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::thread::LocalKey;
+
 use num_bigint::Sign;
 
 /// The abstract type of numbers to be computed with.
@@ -21,10 +26,507 @@ pub fn is_zero(x: &AbstractNumberType) -> bool {
 
 pub const GOLDILOCKS_MOD: u64 = 0xffffffff00000001u64;
 
-pub fn format_number(x: &AbstractNumberType) -> String {
-    if *x > (GOLDILOCKS_MOD / 2).into() {
-        format!("{}", GOLDILOCKS_MOD - x)
+pub const BN254_MOD: &str =
+    "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+/// The fields that can be selected by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldId {
+    Goldilocks,
+    Bn254,
+}
+
+impl FieldId {
+    pub fn modulus(&self) -> AbstractNumberType {
+        match self {
+            FieldId::Goldilocks => GOLDILOCKS_MOD.into(),
+            FieldId::Bn254 => AbstractNumberType::parse_bytes(BN254_MOD.as_bytes(), 10).unwrap(),
+        }
+    }
+}
+
+impl FromStr for FieldId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "goldilocks" => Ok(FieldId::Goldilocks),
+            "bn254" => Ok(FieldId::Bn254),
+            _ => Err(format!("Unknown field {s}, expected goldilocks or bn254.")),
+        }
+    }
+}
+
+impl Display for FieldId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldId::Goldilocks => write!(f, "goldilocks"),
+            FieldId::Bn254 => write!(f, "bn254"),
+        }
+    }
+}
+
+/// How field elements are written in PIL, column files and messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// The canonical representative in `[0, p)`.
+    Unsigned,
+    /// The representative of smallest absolute value in `(-p/2, p/2]`, like `-1` for `p - 1`.
+    Signed,
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unsigned" => Ok(NumberFormat::Unsigned),
+            "signed" => Ok(NumberFormat::Signed),
+            _ => Err(format!(
+                "Unknown number format {s}, expected unsigned or signed."
+            )),
+        }
+    }
+}
+
+impl Display for NumberFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberFormat::Unsigned => write!(f, "unsigned"),
+            NumberFormat::Signed => write!(f, "signed"),
+        }
+    }
+}
+
+thread_local! {
+    /// The modulus of the field all computations are performed in.
+    /// If it is not set, the Goldilocks field is used.
+    static FIELD_MOD: RefCell<Option<AbstractNumberType>> = const { RefCell::new(None) };
+    /// The format numbers are written in. If it is not set, numbers in PIL and
+    /// column files are written as they are and messages use the signed format.
+    static NUMBER_FORMAT: RefCell<Option<NumberFormat>> = const { RefCell::new(None) };
+}
+
+/// Returns the modulus of the currently active field.
+pub fn field_mod() -> AbstractNumberType {
+    FIELD_MOD.with(|m| m.borrow().clone().unwrap_or_else(|| GOLDILOCKS_MOD.into()))
+}
+
+/// Returns the modulus of the field if one was explicitly activated (instead of the default).
+pub fn active_field_mod() -> Option<AbstractNumberType> {
+    FIELD_MOD.with(|m| m.borrow().clone())
+}
+
+/// Returns the number of bytes needed to store a field element of the currently active field.
+pub fn field_byte_size() -> usize {
+    field_mod().bits().div_ceil(8) as usize
+}
+
+/// Runs `f` with the given field modulus active and restores the previous one afterwards,
+/// also if `f` panics. The modulus is only active on the current thread, use
+/// `NumberContext` to activate it on other threads.
+pub fn with_field_mod<T>(modulus: AbstractNumberType, f: impl FnOnce() -> T) -> T {
+    let _restore = Restore::set(&FIELD_MOD, Some(modulus));
+    f()
+}
+
+/// Runs `f` with the given number format active and restores the previous one afterwards,
+/// also if `f` panics. Like the field modulus, the format is only active on the current thread.
+pub fn with_number_format<T>(format: NumberFormat, f: impl FnOnce() -> T) -> T {
+    let _restore = Restore::set(&NUMBER_FORMAT, Some(format));
+    f()
+}
+
+/// Restores the previous value of a thread-local setting when dropped.
+struct Restore<T: 'static> {
+    key: &'static LocalKey<RefCell<Option<T>>>,
+    previous: Option<T>,
+}
+
+impl<T> Restore<T> {
+    fn set(key: &'static LocalKey<RefCell<Option<T>>>, value: Option<T>) -> Self {
+        let previous = key.with(|v| v.replace(value));
+        Restore { key, previous }
+    }
+}
+
+impl<T> Drop for Restore<T> {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        self.key.with(|v| *v.borrow_mut() = previous);
+    }
+}
+
+/// The field modulus and number format active on a thread.
+/// Parallel computations capture the context of the calling thread
+/// and run their work items in it on the worker threads.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NumberContext {
+    field_mod: Option<AbstractNumberType>,
+    number_format: Option<NumberFormat>,
+}
+
+impl NumberContext {
+    /// Returns the context active on the current thread.
+    pub fn current() -> Self {
+        NumberContext {
+            field_mod: active_field_mod(),
+            number_format: number_format(),
+        }
+    }
+
+    /// Runs `f` with this context active and restores the previous one afterwards.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _guard = self.enter();
+        f()
+    }
+
+    /// Activates this context on the current thread until the returned guard is dropped,
+    /// which restores the previous one. Useful to activate the context once per
+    /// worker instead of once per work item, e.g. with rayon's `map_init`.
+    pub fn enter(&self) -> NumberContextGuard {
+        NumberContextGuard {
+            _field_mod: Restore::set(&FIELD_MOD, self.field_mod.clone()),
+            _number_format: Restore::set(&NUMBER_FORMAT, self.number_format),
+        }
+    }
+}
+
+/// Keeps a `NumberContext` active on the current thread, see `NumberContext::enter`.
+pub struct NumberContextGuard {
+    _field_mod: Restore<AbstractNumberType>,
+    _number_format: Restore<NumberFormat>,
+}
+
+/// Returns the number format if one was activated.
+pub fn number_format() -> Option<NumberFormat> {
+    NUMBER_FORMAT.with(|m| *m.borrow())
+}
+
+/// Runs `f` with the given field active.
+pub fn with_named_field<T>(field: FieldId, f: impl FnOnce() -> T) -> T {
+    with_field_mod(field.modulus(), f)
+}
+
+/// Reduces a number to its canonical representative in the field, i.e. into the range `[0, p)`.
+pub fn clamp(x: AbstractNumberType) -> AbstractNumberType {
+    let modulus = field_mod();
+    let x = x % &modulus;
+    if x.sign() == Sign::Minus {
+        x + modulus
     } else {
-        format!("{x}")
+        x
+    }
+}
+
+/// Converts a number to the little-endian bytes of its canonical representative
+/// in the currently active field, padded to `field_byte_size()` bytes.
+pub fn to_field_bytes(x: &AbstractNumberType) -> Vec<u8> {
+    let (_, mut bytes) = clamp(x.clone()).to_bytes_le();
+    bytes.resize(field_byte_size(), 0);
+    bytes
+}
+
+/// Converts the little-endian bytes of a field element of the currently active field
+/// back to a number. Panics if the bytes do not encode a canonical field element.
+pub fn from_field_bytes(bytes: &[u8]) -> AbstractNumberType {
+    assert_eq!(
+        bytes.len(),
+        field_byte_size(),
+        "Expected {} bytes for a field element, but got {}.",
+        field_byte_size(),
+        bytes.len()
+    );
+    let x = AbstractNumberType::from_bytes_le(Sign::Plus, bytes);
+    assert!(
+        x < field_mod(),
+        "Value {x} is not smaller than the field modulus."
+    );
+    x
+}
+
+/// Reduces a number modulo `modulus` to the representative of smallest absolute value,
+/// i.e. into the range `(-p/2, p/2]`.
+pub fn reduce_signed(x: AbstractNumberType, modulus: &AbstractNumberType) -> AbstractNumberType {
+    let x = x % modulus;
+    let x = if x.sign() == Sign::Minus {
+        x + modulus
+    } else {
+        x
+    };
+    if x > modulus / 2 {
+        x - modulus
+    } else {
+        x
+    }
+}
+
+/// Computes `x**y mod m`.
+pub fn pow(
+    mut x: AbstractNumberType,
+    mut y: AbstractNumberType,
+    m: AbstractNumberType,
+) -> AbstractNumberType {
+    assert!(y >= 0.into());
+    if y == 0.into() {
+        return 1.into();
+    }
+    let mut r: AbstractNumberType = 1.into();
+    while y >= 2.into() {
+        if y.bit(0) {
+            r = (r * x.clone()) % m.clone();
+        }
+        x = (x.clone() * x) % m.clone();
+        y = y.clone() >> 1;
+    }
+    (r * x) % m
+}
+
+/// Parses a number like a literal in the source language: decimal, hexadecimal (`0x...`)
+/// or binary (`0b...`), with optional `_` separators between the digits.
+/// A leading `-` denotes the signed form of a field element (see `NumberFormat::Signed`).
+pub fn parse_number(s: &str) -> Result<AbstractNumberType, String> {
+    let (negative, literal) = match s.strip_prefix('-') {
+        Some(literal) => (true, literal),
+        None => (false, s),
+    };
+    let (radix, digits) = if let Some(digits) = literal.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = literal.strip_prefix("0b") {
+        (2, digits)
+    } else {
+        (10, literal)
+    };
+    let valid = digits.chars().next().is_some_and(|c| c.is_digit(radix))
+        && digits.chars().all(|c| c == '_' || c.is_digit(radix));
+    if !valid {
+        return Err(format!("Invalid number: \"{s}\""));
+    }
+    let value = AbstractNumberType::parse_bytes(digits.replace('_', "").as_bytes(), radix).unwrap();
+    Ok(if negative { -value } else { value })
+}
+
+/// Parses a number like `parse_number` and checks that it is an element of the active
+/// field in unsigned or signed form, i.e. that its absolute value is less than the modulus.
+pub fn parse_field_element(s: &str) -> Result<AbstractNumberType, String> {
+    let value = parse_number(s)?;
+    let modulus = field_mod();
+    if value.magnitude() >= modulus.magnitude() {
+        return Err(format!(
+            "{s} is not a field element, its absolute value has to be less than {modulus}."
+        ));
+    }
+    Ok(value)
+}
+
+/// Computes the multiplicative inverse of `x` modulo the prime `m`.
+pub fn inv(x: AbstractNumberType, m: AbstractNumberType) -> AbstractNumberType {
+    pow(x, m.clone() - 2, m)
+}
+
+/// Formats a field element for messages, in the active number format or signed by default.
+pub fn format_number(x: &AbstractNumberType) -> String {
+    format_number_as(x, number_format().unwrap_or(NumberFormat::Signed))
+}
+
+/// Formats a number in PIL or a column file. If a number format is active, it is
+/// reduced into the range of the format, otherwise it is written as it is.
+pub fn display_number(x: &AbstractNumberType) -> String {
+    match number_format() {
+        Some(format) => format_number_as(x, format),
+        None => x.to_string(),
+    }
+}
+
+/// Formats the field element `x` in the given format.
+pub fn format_number_as(x: &AbstractNumberType, format: NumberFormat) -> String {
+    match format {
+        NumberFormat::Unsigned => clamp(x.clone()).to_string(),
+        NumberFormat::Signed => reduce_signed(x.clone(), &field_mod()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_fields() {
+        let p: AbstractNumberType = GOLDILOCKS_MOD.into();
+        assert_eq!(clamp((-1).into()), p.clone() - 1);
+        with_field_mod(17.into(), || {
+            assert_eq!(clamp((-1).into()), 16.into());
+            with_named_field(FieldId::Bn254, || {
+                assert_eq!(clamp((-1).into()), FieldId::Bn254.modulus() - 1);
+                assert_eq!(field_byte_size(), 32);
+            });
+            assert_eq!(field_mod(), 17.into());
+        });
+        assert_eq!(field_mod(), p);
+        assert_eq!(field_byte_size(), 8);
+    }
+
+    #[test]
+    fn field_restored_on_panic() {
+        let p: AbstractNumberType = GOLDILOCKS_MOD.into();
+        with_field_mod(17.into(), || {
+            let result = std::panic::catch_unwind(|| {
+                with_named_field(FieldId::Bn254, || panic!("inner"));
+            });
+            assert!(result.is_err());
+            assert_eq!(field_mod(), 17.into());
+        });
+        assert_eq!(field_mod(), p);
+        assert_eq!(active_field_mod(), None);
+    }
+
+    #[test]
+    fn context_on_other_thread() {
+        let context = with_field_mod(17.into(), || {
+            with_number_format(NumberFormat::Unsigned, NumberContext::current)
+        });
+        std::thread::spawn(move || {
+            assert_eq!(active_field_mod(), None);
+            context.run(|| {
+                assert_eq!(clamp((-1).into()), 16.into());
+                assert_eq!(number_format(), Some(NumberFormat::Unsigned));
+            });
+            assert_eq!(NumberContext::current(), NumberContext::default());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn signed_reduction() {
+        let m = 17.into();
+        assert_eq!(reduce_signed(30.into(), &m), (-4).into());
+        assert_eq!(reduce_signed((-30).into(), &m), 4.into());
+        assert_eq!(reduce_signed(8.into(), &m), 8.into());
+        assert_eq!(reduce_signed(9.into(), &m), (-8).into());
+        assert_eq!(active_field_mod(), None);
+        assert_eq!(with_field_mod(m.clone(), active_field_mod), Some(m));
+    }
+
+    #[test]
+    fn number_formats() {
+        let p: AbstractNumberType = GOLDILOCKS_MOD.into();
+        let minus_one = p.clone() - 1;
+        let half = p.clone() / 2;
+        assert_eq!(display_number(&(-1).into()), "-1");
+        assert_eq!(display_number(&minus_one), minus_one.to_string());
+        assert_eq!(format_number(&minus_one), "-1");
+        with_number_format(NumberFormat::Unsigned, || {
+            assert_eq!(display_number(&(-1).into()), minus_one.to_string());
+            assert_eq!(format_number(&(-1).into()), minus_one.to_string());
+            assert_eq!(display_number(&(p.clone() + 2)), "2");
+            with_number_format(NumberFormat::Signed, || {
+                assert_eq!(display_number(&minus_one), "-1");
+                assert_eq!(display_number(&half), half.to_string());
+                assert_eq!(display_number(&(half.clone() + 1)), format!("-{}", half));
+            });
+            assert_eq!(number_format(), Some(NumberFormat::Unsigned));
+        });
+        assert_eq!(number_format(), None);
+        assert_eq!("signed".parse(), Ok(NumberFormat::Signed));
+        assert_eq!(
+            NumberFormat::Unsigned.to_string().parse(),
+            Ok(NumberFormat::Unsigned)
+        );
+        assert!("hex".parse::<NumberFormat>().is_err());
+    }
+
+    #[test]
+    fn field_bytes() {
+        let p: AbstractNumberType = GOLDILOCKS_MOD.into();
+        assert_eq!(to_field_bytes(&1.into()), [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            to_field_bytes(&(-1).into()),
+            to_field_bytes(&(p.clone() - 1))
+        );
+        assert_eq!(to_field_bytes(&p), [0; 8]);
+        assert_eq!(to_field_bytes(&(p.clone() + 2)), to_field_bytes(&2.into()));
+        for x in [0.into(), 1.into(), p.clone() - 1, p.clone() / 2] {
+            assert_eq!(from_field_bytes(&to_field_bytes(&x)), x);
+        }
+        assert_eq!(from_field_bytes(&to_field_bytes(&(-5).into())), p - 5);
+
+        with_named_field(FieldId::Bn254, || {
+            let p = FieldId::Bn254.modulus();
+            let bytes = to_field_bytes(&(-1).into());
+            assert_eq!(bytes.len(), 32);
+            assert_eq!(from_field_bytes(&bytes), p.clone() - 1);
+            assert_eq!(from_field_bytes(&to_field_bytes(&p)), 0.into());
+        });
+    }
+
+    #[test]
+    #[should_panic = "is not smaller than the field modulus"]
+    fn non_canonical_field_bytes() {
+        from_field_bytes(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    #[should_panic = "Expected 8 bytes for a field element, but got 4."]
+    fn wrong_field_byte_count() {
+        from_field_bytes(&[1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse_number("1_000"), Ok(1000.into()));
+        assert_eq!(parse_number("0xdead_beef"), Ok(0xdeadbeefu32.into()));
+        assert_eq!(parse_number("0b1010"), Ok(10.into()));
+        assert_eq!(parse_number("-0xff"), Ok((-255).into()));
+        let p: AbstractNumberType = GOLDILOCKS_MOD.into();
+        assert_eq!(parse_number(&format!("{}", p.clone() - 1)), Ok(p - 1));
+        for invalid in [
+            "", "-", "0x", "_1", "0x_1", "12a", "0b12", "+1", " 1", "--1",
+        ] {
+            assert_eq!(
+                parse_number(invalid),
+                Err(format!("Invalid number: \"{invalid}\""))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_field_elements() {
+        let p: AbstractNumberType = GOLDILOCKS_MOD.into();
+        let max = format!("{}", p.clone() - 1);
+        assert_eq!(parse_field_element(&max), Ok(p.clone() - 1));
+        assert_eq!(parse_field_element(&format!("-{max}")), Ok(1 - p.clone()));
+        for invalid in [format!("{p}"), format!("-{p}")] {
+            assert_eq!(
+                parse_field_element(&invalid),
+                Err(format!(
+                    "{invalid} is not a field element, its absolute value has to be less than {p}."
+                ))
+            );
+        }
+        assert_eq!(
+            parse_field_element("0x"),
+            Err("Invalid number: \"0x\"".to_string())
+        );
+        // Values of a larger field are rejected in the active one.
+        let q = FieldId::Bn254.modulus();
+        assert!(parse_field_element(&format!("{}", q.clone() - 1)).is_err());
+        assert!(
+            with_named_field(FieldId::Bn254, || parse_field_element(&format!(
+                "{}",
+                q - 1
+            )))
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn parse_field_id() {
+        assert_eq!("bn254".parse(), Ok(FieldId::Bn254));
+        assert_eq!(
+            FieldId::Goldilocks.to_string().parse(),
+            Ok(FieldId::Goldilocks)
+        );
+        assert!("babybear".parse::<FieldId>().is_err());
     }
 }
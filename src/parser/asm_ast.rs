@@ -1,3 +1,5 @@
+use crate::number::AbstractNumberType;
+
 use super::ast::{Expression, SelectedExpressions, Statement};
 
 #[derive(Debug, PartialEq, Eq)]
@@ -5,6 +7,14 @@ pub struct ASMFile(pub Vec<ASMStatement>);
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ASMStatement {
+    /// The number of rows and whether it is padded to the next power of two.
+    Degree(usize, AbstractNumberType, bool),
+    /// The number of bits of the operands of bitwise operations.
+    BitwiseWidth(usize, AbstractNumberType),
+    /// The number of bits of the operands of `<`, `<=`, `>` and `>=`.
+    ComparisonWidth(usize, AbstractNumberType),
+    /// The largest absolute value of coefficients that is not reported if no field is active.
+    CoefficientBound(usize, AbstractNumberType),
     RegisterDeclaration(usize, String, Option<RegisterFlag>),
     InstructionDeclaration(
         usize,
@@ -12,7 +22,12 @@ pub enum ASMStatement {
         Vec<InstructionParam>,
         Vec<InstructionBodyElement>,
     ),
+    /// A macro that expands to the given instruction body elements, with the
+    /// parameters replaced by the arguments.
+    MacroDefinition(usize, String, Vec<String>, Vec<InstructionBodyElement>),
     InlinePil(usize, Vec<Statement>),
+    /// Inline PIL from the gadget library, given by name and arguments.
+    Gadget(usize, String, Vec<Expression>),
     Assignment(usize, Vec<String>, Option<String>, Box<Expression>),
     Instruction(usize, String, Vec<Expression>),
     Label(usize, String),
@@ -21,7 +36,11 @@ pub enum ASMStatement {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum RegisterFlag {
     IsPC,
+    /// The program counter, which can also be read in assignments.
+    IsReadablePC,
     IsDefaultAssignment,
+    /// The register `call` stores the return address in and `ret` jumps to.
+    IsReturnAddress,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -38,6 +57,10 @@ pub struct InstructionParam {
 pub enum InstructionBodyElement {
     Expression(Expression),
     PlookupIdentity(SelectedExpressions, PlookupOperator, SelectedExpressions),
+    /// A witness column that is only declared if the instruction is used.
+    LocalColumn(String),
+    /// A call to a macro, which is expanded before the instruction is converted.
+    MacroCall(String, Vec<Expression>),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
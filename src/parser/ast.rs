@@ -3,6 +3,24 @@ use crate::number::AbstractNumberType;
 #[derive(Debug, PartialEq, Eq)]
 pub struct PILFile(pub Vec<Statement>);
 
+/// A PIL file that keeps the comments, so that it can be printed again
+/// without losing them.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct CommentedPILFile {
+    pub statements: Vec<CommentedStatement>,
+    /// The comments after the last statement.
+    pub trailing_comments: Vec<String>,
+}
+
+/// A statement with the comments on the lines before it and
+/// the comment following it on the same line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommentedStatement {
+    pub leading_comments: Vec<String>,
+    pub statement: Statement,
+    pub trailing_comment: Option<String>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Statement {
     /// File name
@@ -47,7 +65,9 @@ pub enum Expression {
     BinaryOperation(Box<Expression>, BinaryOperator, Box<Expression>),
     UnaryOperation(UnaryOperator, Box<Expression>),
     FunctionCall(String, Vec<Expression>),
-    FreeInput(Box<Expression>),
+    /// A free input `${ input }` of an assembly program, optionally followed
+    /// by the keys of its query: `${ input; key, ... }`.
+    FreeInput(Box<Expression>, Option<Vec<Expression>>),
 }
 
 #[derive(Debug, PartialEq, Eq, Default, Clone)]
@@ -68,6 +88,8 @@ pub struct PolynomialReference {
 pub enum UnaryOperator {
     Plus,
     Minus,
+    /// `!x`, which is `1 - x` for boolean `x`.
+    LogicalNot,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -80,8 +102,61 @@ pub enum BinaryOperator {
     Pow,
     BinaryAnd,
     BinaryOr,
+    BinaryXor,
     ShiftLeft,
     ShiftRight,
+    /// The comparisons are 1 if they hold and 0 otherwise.
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl BinaryOperator {
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Less
+                | BinaryOperator::LessEqual
+                | BinaryOperator::Greater
+                | BinaryOperator::GreaterEqual
+                | BinaryOperator::Equal
+                | BinaryOperator::NotEqual
+        )
+    }
+
+    /// Evaluates the comparison on two numbers, 1 if it holds and 0 otherwise.
+    /// Panics if the operator is not a comparison.
+    pub fn compare(
+        &self,
+        left: &AbstractNumberType,
+        right: &AbstractNumberType,
+    ) -> AbstractNumberType {
+        let holds = match self {
+            BinaryOperator::Less => left < right,
+            BinaryOperator::LessEqual => left <= right,
+            BinaryOperator::Greater => left > right,
+            BinaryOperator::GreaterEqual => left >= right,
+            BinaryOperator::Equal => left == right,
+            BinaryOperator::NotEqual => left != right,
+            _ => panic!("{self} is not a comparison."),
+        };
+        u32::from(holds).into()
+    }
+}
+
+/// How an array of values shorter than the degree is extended.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum PaddingStrategy {
+    /// Fill the remaining rows with zeros (`[1, 2]`).
+    #[default]
+    Zero,
+    /// Repeat the last value (`[1, 2]...`).
+    LastValue,
+    /// Repeat the whole array (`[1, 2]*`).
+    Cyclic,
 }
 
 /// The definition of a function (excluding its name):
@@ -90,8 +165,8 @@ pub enum BinaryOperator {
 pub enum FunctionDefinition {
     /// Parameter-value-mapping.
     Mapping(Vec<String>, Expression),
-    /// Array of values.
-    Array(Vec<Expression>),
+    /// Array of values and how it is extended to the degree.
+    Array(Vec<Expression>, PaddingStrategy),
     /// Prover query.
     Query(Vec<String>, Expression),
 }
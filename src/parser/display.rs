@@ -1,5 +1,6 @@
 use std::fmt::{Display, Formatter, Result};
 
+use crate::number::display_number;
 use crate::utils::quote;
 
 use super::ast::*;
@@ -15,6 +16,24 @@ impl Display for PILFile {
     }
 }
 
+impl Display for CommentedPILFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        for s in &self.statements {
+            for c in &s.leading_comments {
+                writeln!(f, "{c}")?;
+            }
+            match &s.trailing_comment {
+                Some(c) => writeln!(f, "{} {c}", s.statement)?,
+                None => writeln!(f, "{}", s.statement)?,
+            }
+        }
+        for c in &self.trailing_comments {
+            writeln!(f, "{c}")?;
+        }
+        Ok(())
+    }
+}
+
 impl Display for Statement {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
@@ -94,8 +113,8 @@ impl Display for FunctionDefinition {
             FunctionDefinition::Mapping(params, body) => {
                 write!(f, "({}) {{ {body} }}", params.join(", "))
             }
-            FunctionDefinition::Array(values) => {
-                write!(f, " = [{}]", format_expressions(values))
+            FunctionDefinition::Array(values, padding) => {
+                write!(f, " = [{}]{padding}", format_expressions(values))
             }
             FunctionDefinition::Query(params, value) => {
                 write!(f, "({}) query {value}", params.join(", "),)
@@ -104,6 +123,16 @@ impl Display for FunctionDefinition {
     }
 }
 
+impl Display for PaddingStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            PaddingStrategy::Zero => Ok(()),
+            PaddingStrategy::LastValue => write!(f, "..."),
+            PaddingStrategy::Cyclic => write!(f, "*"),
+        }
+    }
+}
+
 impl Display for SelectedExpressions {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(
@@ -132,13 +161,16 @@ impl Display for Expression {
             Expression::Constant(name) => write!(f, "{name}"),
             Expression::PolynomialReference(reference) => write!(f, "{reference}"),
             Expression::PublicReference(name) => write!(f, "{name}"),
-            Expression::Number(value) => write!(f, "{value}"),
+            Expression::Number(value) => write!(f, "{}", display_number(value)),
             Expression::String(value) => write!(f, "\"{value}\""), // TODO quote?
             Expression::Tuple(items) => write!(f, "({})", format_expressions(items)),
             Expression::BinaryOperation(left, op, right) => write!(f, "({left} {op} {right})"),
             Expression::UnaryOperation(op, exp) => write!(f, "{op}{exp}"),
             Expression::FunctionCall(fun, args) => write!(f, "{fun}({})", format_expressions(args)),
-            Expression::FreeInput(input) => write!(f, "${{ {input} }}"),
+            Expression::FreeInput(input, None) => write!(f, "${{ {input} }}"),
+            Expression::FreeInput(input, Some(keys)) => {
+                write!(f, "${{ {input}; {} }}", format_expressions(keys))
+            }
         }
     }
 }
@@ -190,8 +222,15 @@ impl Display for BinaryOperator {
                 BinaryOperator::Pow => "**",
                 BinaryOperator::BinaryAnd => "&",
                 BinaryOperator::BinaryOr => "|",
+                BinaryOperator::BinaryXor => "^",
                 BinaryOperator::ShiftLeft => "<<",
                 BinaryOperator::ShiftRight => ">>",
+                BinaryOperator::Less => "<",
+                BinaryOperator::LessEqual => "<=",
+                BinaryOperator::Greater => ">",
+                BinaryOperator::GreaterEqual => ">=",
+                BinaryOperator::Equal => "==",
+                BinaryOperator::NotEqual => "!=",
             }
         )
     }
@@ -205,6 +244,7 @@ impl Display for UnaryOperator {
             match self {
                 UnaryOperator::Minus => "-",
                 UnaryOperator::Plus => "+",
+                UnaryOperator::LogicalNot => "!",
             }
         )
     }
@@ -248,6 +288,15 @@ public out = y(%last_row);"#;
         assert_eq!(input.trim(), printed.trim());
     }
 
+    #[test]
+    fn reparse_array_padding() {
+        let input = r#"pol constant x = [1, 2];
+pol constant y = [1, 2]...;
+pol constant z = [1, 2]*;"#;
+        let printed = format!("{}", parser::parse(Some("input"), input).unwrap());
+        assert_eq!(input.trim(), printed.trim());
+    }
+
     #[test]
     fn reparse_strings_and_tuples() {
         let input = r#"constant %N = ("abc", 3);"#;
@@ -1,3 +1,8 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 use codespan_reporting::files::SimpleFiles;
 use codespan_reporting::term;
@@ -7,6 +12,7 @@ use lalrpop_util::*;
 pub mod asm_ast;
 pub mod ast;
 pub mod display;
+pub mod pretty;
 
 lalrpop_mod!(
     #[allow(clippy::all)]
@@ -15,47 +21,241 @@ lalrpop_mod!(
 );
 
 #[derive(Debug)]
-pub struct ParseError<'a> {
+pub struct ParseError {
     start: usize,
     end: usize,
     file_name: String,
-    contents: &'a str,
+    contents: String,
     message: String,
 }
 
-impl<'a> ParseError<'a> {
+impl ParseError {
+    /// Creates an error that is reported at a location in the input
+    /// and was found after the input was parsed.
+    pub fn new(
+        file_name: Option<&str>,
+        contents: &str,
+        start: usize,
+        end: usize,
+        message: String,
+    ) -> Self {
+        ParseError {
+            start,
+            end,
+            file_name: file_name.unwrap_or("input").to_string(),
+            contents: contents.to_string(),
+            message,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the 1-based line and column of the start of the error in `source`,
+    /// which is the input the error was reported for.
+    /// Columns count characters, not bytes.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let mut offset = self.start.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let prefix = &source[..offset];
+        let line_start = prefix.rfind('\n').map_or(0, |i| i + 1);
+        (
+            prefix.matches('\n').count() + 1,
+            prefix[line_start..].chars().count() + 1,
+        )
+    }
+
     pub fn output_to_stderr(&self) {
+        self.output_diagnostic(Diagnostic::error())
+    }
+
+    /// Outputs the message as a warning instead of an error.
+    pub fn output_as_warning_to_stderr(&self) {
+        self.output_diagnostic(Diagnostic::warning())
+    }
+
+    fn output_diagnostic(&self, diagnostic: Diagnostic<usize>) {
         let config = term::Config::default();
         let mut files = SimpleFiles::new();
-        let file_id = files.add(&self.file_name, self.contents);
-        let diagnostic = Diagnostic::error()
+        let file_id = files.add(&self.file_name, &self.contents);
+        let (line, column) = self.line_col(&self.contents);
+        let diagnostic = diagnostic
             .with_message(&self.message)
-            .with_labels(vec![Label::primary(file_id, self.start..self.end)]);
+            .with_labels(vec![Label::primary(file_id, self.start..self.end)])
+            .with_notes(vec![format!("at {}:{line}:{column}", self.file_name)]);
         let mut writer = StandardStream::stderr(ColorChoice::Always);
         term::emit(&mut writer, &config, &files, &diagnostic).unwrap()
     }
 }
 
-pub fn parse<'a>(file_name: Option<&str>, input: &'a str) -> Result<ast::PILFile, ParseError<'a>> {
-    powdr::PILFileParser::new()
-        .parse(input)
-        .map_err(|err| handle_error(err, file_name, input))
+/// Formats the error as `file:line:column: message`, without the source excerpt
+/// of `output_to_stderr`.
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (line, column) = self.line_col(&self.contents);
+        write!(f, "{}:{line}:{column}: {}", self.file_name, self.message)
+    }
+}
+
+pub fn parse(file_name: Option<&str>, input: &str) -> Result<ast::PILFile, ParseError> {
+    parse_with_error_recovery(file_name, input).map_err(|mut errors| errors.remove(0))
 }
 
-pub fn parse_asm<'a>(
+pub fn parse_asm(file_name: Option<&str>, input: &str) -> Result<asm_ast::ASMFile, ParseError> {
+    parse_asm_with_error_recovery(file_name, input).map_err(|mut errors| errors.remove(0))
+}
+
+/// Like `parse`, but continues after the next ";" if a statement is invalid
+/// and returns all errors in the order of their location.
+pub fn parse_with_error_recovery(
     file_name: Option<&str>,
-    input: &'a str,
-) -> Result<asm_ast::ASMFile, ParseError<'a>> {
-    powdr::ASMFileParser::new()
-        .parse(input)
-        .map_err(|err| handle_error(err, file_name, input))
+    input: &str,
+) -> Result<ast::PILFile, Vec<ParseError>> {
+    let mut errors = vec![];
+    let result = powdr::PILFileParser::new().parse(&mut errors, input);
+    collect_errors(result, errors, file_name, input)
 }
 
-fn handle_error<'a>(
+/// Like `parse_asm`, but continues after the next ";" if a statement is invalid
+/// and returns all errors in the order of their location.
+pub fn parse_asm_with_error_recovery(
+    file_name: Option<&str>,
+    input: &str,
+) -> Result<asm_ast::ASMFile, Vec<ParseError>> {
+    let mut errors = vec![];
+    let result = powdr::ASMFileParser::new().parse(&mut errors, input);
+    collect_errors(result, errors, file_name, input)
+}
+
+/// Combines the errors the parser recovered from with the error it stopped at, if any.
+fn collect_errors<T>(
+    result: Result<T, lalrpop_util::ParseError<usize, lexer::Token, &str>>,
+    recovered: Vec<ErrorRecovery<usize, lexer::Token, &str>>,
+    file_name: Option<&str>,
+    input: &str,
+) -> Result<T, Vec<ParseError>> {
+    let mut errors = recovered
+        .into_iter()
+        .map(|recovery| handle_error(recovery.error, file_name, input))
+        .collect::<Vec<_>>();
+    match result {
+        Ok(result) if errors.is_empty() => Ok(result),
+        Ok(_) => Err(errors),
+        Err(err) => {
+            errors.push(handle_error(err, file_name, input));
+            Err(errors)
+        }
+    }
+}
+
+/// Parses a PIL file and keeps the comments. A comment that follows a statement on the
+/// same line is attached to that statement, all other comments to the statement after them.
+/// Comments inside a statement are attached to the next statement.
+pub fn parse_with_comments(
+    file_name: Option<&str>,
+    input: &str,
+) -> Result<ast::CommentedPILFile, ParseError> {
+    let statements = parse(file_name, input)?.0;
+    let mut comments = find_comments(input).into_iter().peekable();
+    let mut result = ast::CommentedPILFile::default();
+    for statement in statements {
+        let start = statement_start(&statement);
+        if let Some(previous) = result.statements.last_mut() {
+            previous.trailing_comment = comments
+                .next_if(|(offset, _)| *offset < start && !starts_line(input, *offset))
+                .map(|(_, comment)| comment);
+        }
+        let mut leading_comments = vec![];
+        while let Some((_, comment)) = comments.next_if(|(offset, _)| *offset < start) {
+            leading_comments.push(comment);
+        }
+        result.statements.push(ast::CommentedStatement {
+            leading_comments,
+            statement,
+            trailing_comment: None,
+        });
+    }
+    if let Some(previous) = result.statements.last_mut() {
+        previous.trailing_comment = comments
+            .next_if(|(offset, _)| !starts_line(input, *offset))
+            .map(|(_, comment)| comment);
+    }
+    result.trailing_comments = comments.map(|(_, comment)| comment).collect();
+    Ok(result)
+}
+
+/// Returns the `//` and `/* */` comments in the input with their offsets,
+/// skipping over string literals.
+fn find_comments(input: &str) -> Vec<(usize, String)> {
+    let mut comments = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        match (c, chars.peek().map(|(_, c)| *c)) {
+            ('"', _) => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            ('/', Some('/')) => {
+                let end = input[offset..]
+                    .find(['\n', '\r'])
+                    .map_or(input.len(), |i| offset + i);
+                comments.push((offset, input[offset..end].trim_end().to_string()));
+                while chars.next_if(|(i, _)| *i < end).is_some() {}
+            }
+            ('/', Some('*')) => {
+                let end = input[offset + 2..]
+                    .find("*/")
+                    .map_or(input.len(), |i| offset + 2 + i + 2);
+                comments.push((offset, input[offset..end].to_string()));
+                while chars.next_if(|(i, _)| *i < end).is_some() {}
+            }
+            _ => {}
+        }
+    }
+    comments
+}
+
+/// Returns true if only whitespace precedes the offset on its line.
+fn starts_line(input: &str, offset: usize) -> bool {
+    let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+    input[line_start..offset].trim().is_empty()
+}
+
+fn statement_start(statement: &ast::Statement) -> usize {
+    use ast::Statement::*;
+    match statement {
+        Include(start, _)
+        | Namespace(start, _, _)
+        | PolynomialDefinition(start, _, _)
+        | PublicDeclaration(start, _, _, _)
+        | PolynomialConstantDeclaration(start, _)
+        | PolynomialConstantDefinition(start, _, _)
+        | PolynomialCommitDeclaration(start, _, _)
+        | PolynomialIdentity(start, _)
+        | PlookupIdentity(start, _, _)
+        | PermutationIdentity(start, _, _)
+        | ConnectIdentity(start, _, _)
+        | ConstantDefinition(start, _, _)
+        | MacroDefinition(start, _, _, _, _)
+        | FunctionCall(start, _, _) => *start,
+    }
+}
+
+fn handle_error(
     err: lalrpop_util::ParseError<usize, lexer::Token, &str>,
     file_name: Option<&str>,
-    input: &'a str,
-) -> ParseError<'a> {
+    input: &str,
+) -> ParseError {
     let (&start, &end) = match &err {
         lalrpop_util::ParseError::InvalidToken { location } => (location, location),
         lalrpop_util::ParseError::UnrecognizedEOF {
@@ -75,11 +275,111 @@ fn handle_error<'a>(
         start,
         end,
         file_name: file_name.unwrap_or("input").to_string(),
-        contents: input,
+        contents: input.to_string(),
         message: format!("{err}"),
     }
 }
 
+/// Parses a PIL file and replaces all `include` statements by the statements of the
+/// included file, recursively. Paths are resolved relative to the including file.
+/// As in the analyzer, a file is only included once, but include cycles are errors.
+/// Note that the source offsets of inlined statements refer to the file they come from.
+pub fn parse_file_with_includes(path: &Path) -> Result<ast::PILFile, ParseError> {
+    parse_file_with_includes_and_comments(path)
+        .map(|file| ast::PILFile(file.statements.into_iter().map(|s| s.statement).collect()))
+}
+
+/// Like `parse_file_with_includes`, but keeps the comments. The comments of an
+/// include statement and those at the end of an included file are attached to
+/// the statement after them.
+pub fn parse_file_with_includes_and_comments(
+    path: &Path,
+) -> Result<ast::CommentedPILFile, ParseError> {
+    let file_name = path.to_str().unwrap();
+    let contents = fs::read_to_string(path).map_err(|err| {
+        ParseError::new(
+            Some(file_name),
+            "",
+            0,
+            0,
+            format!("Could not read file: {err}"),
+        )
+    })?;
+    let path = path.canonicalize().unwrap();
+    let mut included = [path.clone()].into();
+    let mut result = ast::CommentedPILFile::default();
+    inline_includes(
+        &path,
+        file_name,
+        &contents,
+        &mut vec![path.clone()],
+        &mut included,
+        &mut result,
+    )?;
+    Ok(result)
+}
+
+fn inline_includes(
+    path: &Path,
+    file_name: &str,
+    contents: &str,
+    stack: &mut Vec<PathBuf>,
+    included: &mut HashSet<PathBuf>,
+    result: &mut ast::CommentedPILFile,
+) -> Result<(), ParseError> {
+    let file = parse_with_comments(Some(file_name), contents)?;
+    for mut commented in file.statements {
+        // Comments that are not attached yet are moved to this statement.
+        result
+            .trailing_comments
+            .append(&mut commented.leading_comments);
+        let ast::Statement::Include(start, include) = &commented.statement else {
+            commented.leading_comments = std::mem::take(&mut result.trailing_comments);
+            result.statements.push(commented);
+            continue;
+        };
+        result
+            .trailing_comments
+            .extend(commented.trailing_comment.clone());
+        let error =
+            |message: String| ParseError::new(Some(file_name), contents, *start, *start, message);
+        let include_path = path
+            .parent()
+            .unwrap()
+            .join(include)
+            .canonicalize()
+            .map_err(|err| error(format!("Could not read included file {include}: {err}")))?;
+        if let Some(pos) = stack.iter().position(|p| *p == include_path) {
+            return Err(error(format!(
+                "Include cycle: {}",
+                stack[pos..]
+                    .iter()
+                    .chain([&include_path])
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            )));
+        }
+        if !included.insert(include_path.clone()) {
+            continue;
+        }
+        let include_contents = fs::read_to_string(&include_path)
+            .map_err(|err| error(format!("Could not read included file {include}: {err}")))?;
+        stack.push(include_path.clone());
+        inline_includes(
+            &include_path,
+            include_path.to_str().unwrap(),
+            &include_contents,
+            stack,
+            included,
+            result,
+        )?;
+        stack.pop();
+    }
+    result.trailing_comments.extend(file.trailing_comments);
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::fs;
@@ -89,12 +389,53 @@ mod test {
 
     #[test]
     fn empty() {
-        assert!(powdr::PILFileParser::new().parse("").is_ok());
+        assert!(powdr::PILFileParser::new().parse(&mut vec![], "").is_ok());
+    }
+
+    #[test]
+    fn error_line_col() {
+        let input = "pol commit t;\npol commit x;\n  t = x x;";
+        let err = parse(None, input).unwrap_err();
+        assert_eq!(err.line_col(input), (3, 9));
+    }
+
+    #[test]
+    fn all_errors() {
+        let input = "pol commit t;\nt = t t;\npol commit x;\nx = = 1;\nx = t;";
+        let errors = parse_with_error_recovery(None, input).unwrap_err();
+        let locations = errors
+            .iter()
+            .map(|err| err.line_col(input))
+            .collect::<Vec<_>>();
+        assert_eq!(locations, [(2, 7), (4, 5)]);
+        assert_eq!(parse(None, input).unwrap_err().line_col(input), (2, 7));
+
+        let asm = "reg pc[@pc];\nreg X[<=]\nreg A;\nA <=X= 1 +;\nA <=X= 2;";
+        let errors = parse_asm_with_error_recovery(None, asm).unwrap_err();
+        let locations = errors
+            .iter()
+            .map(|err| err.line_col(asm))
+            .collect::<Vec<_>>();
+        assert_eq!(locations, [(3, 1), (4, 11)]);
+        assert!(parse_asm_with_error_recovery(None, "reg A;\nA <=X= 2;").is_ok());
+    }
+
+    #[test]
+    fn error_line_col_multi_byte() {
+        // "é" and "ü" take two bytes each, but count as a single column.
+        let input = "// café\n// süß ü\npol commit t;\n/* é */ t = t t;";
+        let err = parse(None, input).unwrap_err();
+        assert_eq!(err.line_col(input), (4, 15));
+        let start = input.find('ß').unwrap();
+        let err = ParseError::new(None, input, start, start, String::new());
+        assert_eq!(err.line_col(input), (2, 6));
     }
 
     #[test]
     fn simple_include() {
-        let parsed = powdr::PILFileParser::new().parse("include \"x\";").unwrap();
+        let parsed = powdr::PILFileParser::new()
+            .parse(&mut vec![], "include \"x\";")
+            .unwrap();
         assert_eq!(
             parsed,
             PILFile(vec![Statement::Include(0, "x".to_string())])
@@ -104,7 +445,7 @@ mod test {
     #[test]
     fn start_offsets() {
         let parsed = powdr::PILFileParser::new()
-            .parse("include \"x\"; pol commit t;")
+            .parse(&mut vec![], "include \"x\"; pol commit t;")
             .unwrap();
         assert_eq!(
             parsed,
@@ -122,9 +463,46 @@ mod test {
         );
     }
 
+    #[test]
+    fn keep_comments() {
+        let input = r#"// The main namespace.
+namespace Main(8);
+/* A multi-line
+   comment. */
+pol commit x; // after x
+pol constant y = [1]; /* "not a string */
+pol commit s(i) query ("// not a comment", i);
+// trailing
+"#;
+        let parsed = parse_with_comments(None, input).unwrap();
+        assert_eq!(
+            parsed.statements[0].leading_comments,
+            ["// The main namespace."]
+        );
+        assert_eq!(
+            parsed.statements[1].trailing_comment.as_deref(),
+            Some("// after x")
+        );
+        assert!(parsed.statements[3].leading_comments.is_empty());
+        assert_eq!(
+            format!("{parsed}"),
+            r#"// The main namespace.
+namespace Main(8);
+/* A multi-line
+   comment. */
+pol commit x; // after x
+pol constant y = [1]; /* "not a string */
+pol commit s(i) query ("// not a comment", i);
+// trailing
+"#
+        );
+    }
+
     #[test]
     fn simple_plookup() {
-        let parsed = powdr::PILFileParser::new().parse("f in g;").unwrap();
+        let parsed = powdr::PILFileParser::new()
+            .parse(&mut vec![], "f in g;")
+            .unwrap();
         assert_eq!(
             parsed,
             PILFile(vec![Statement::PlookupIdentity(
@@ -188,7 +566,7 @@ mod test {
     #[test]
     fn simple_macro() {
         let parsed = powdr::PILFileParser::new()
-            .parse("macro f(x) { x in g; x + 1 };")
+            .parse(&mut vec![], "macro f(x) { x in g; x + 1 };")
             .unwrap();
         assert_eq!(
             parsed,
@@ -227,6 +605,72 @@ mod test {
         );
     }
 
+    #[test]
+    fn hex_and_binary_literals() {
+        let input = "constant %A = 0xdead_beef;
+constant %B = 0b1010_0101;
+constant %C = -0xff;
+constant %D = 0x1_0000_0000_0000_0000_0000_0000_0000_0000;
+constant %E = 1_000;";
+        let printed = format!("{}", parse(Some("input"), input).unwrap());
+        assert_eq!(
+            printed.trim(),
+            "constant %A = 3735928559;
+constant %B = 165;
+constant %C = -255;
+constant %D = 340282366920938463463374607431768211456;
+constant %E = 1000;"
+        );
+    }
+
+    #[test]
+    fn inline_includes() {
+        let temp_dir = mktemp::Temp::new_dir().unwrap();
+        let write = |name: &str, contents: &str| {
+            fs::create_dir_all(temp_dir.join(name).parent().unwrap()).unwrap();
+            fs::write(temp_dir.join(name), contents).unwrap();
+        };
+        write(
+            "main.pil",
+            "include \"sub/a.pil\"; include \"b.pil\"; pol commit x;",
+        );
+        write("sub/a.pil", "include \"../b.pil\"; pol commit a;");
+        write("b.pil", "pol commit b;");
+        let parsed = parse_file_with_includes(&temp_dir.join("main.pil")).unwrap();
+        assert_eq!(
+            format!("{parsed}"),
+            "pol commit b;\npol commit a;\npol commit x;\n"
+        );
+
+        write(
+            "main.pil",
+            "// a\ninclude \"sub/a.pil\";\n// x\npol commit x;\n// end",
+        );
+        write("b.pil", "// b\npol commit b; // b!\n// end of b");
+        let parsed = parse_file_with_includes_and_comments(&temp_dir.join("main.pil")).unwrap();
+        assert_eq!(
+            format!("{parsed}"),
+            "// a\n// b\npol commit b; // b!\n// end of b\npol commit a;\n// x\npol commit x;\n// end\n"
+        );
+
+        write("b.pil", "include \"main.pil\";");
+        let err = parse_file_with_includes(&temp_dir.join("main.pil")).unwrap_err();
+        let path = |name: &str| {
+            let path = temp_dir.join(name).canonicalize().unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        assert_eq!(
+            err.message(),
+            format!(
+                "Include cycle: {} -> {} -> {} -> {}",
+                path("main.pil"),
+                path("sub/a.pil"),
+                path("b.pil"),
+                path("main.pil")
+            )
+        );
+    }
+
     #[test]
     fn parse_example_asm_files() {
         parse_asm_file("tests/simple_sum.asm");
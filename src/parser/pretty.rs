@@ -0,0 +1,362 @@
+//! Formats PIL files canonically: expressions only have the parentheses that the
+//! precedence of their operators requires, and chains of binary operations that
+//! do not fit the line width are broken into one operand per line.
+
+use crate::number::display_number;
+use crate::utils::quote;
+
+use super::ast::*;
+
+/// Formats the file with a maximum line width of `width`.
+/// Lines can be longer if they contain no binary operation that can be broken.
+pub fn format_pil(file: &CommentedPILFile, width: usize) -> String {
+    let printer = Printer { width };
+    let mut result = String::new();
+    for s in &file.statements {
+        for c in &s.leading_comments {
+            result += &format!("{c}\n");
+        }
+        result += &printer.statement(&s.statement, 0);
+        if let Some(c) = &s.trailing_comment {
+            result += &format!(" {c}");
+        }
+        result += "\n";
+    }
+    for c in &file.trailing_comments {
+        result += &format!("{c}\n");
+    }
+    result
+}
+
+struct Printer {
+    width: usize,
+}
+
+impl Printer {
+    /// Formats a statement that starts at column `indent`.
+    fn statement(&self, statement: &Statement, indent: usize) -> String {
+        let expression = |prefix: &str, e: &Expression, suffix: &str| {
+            format!(
+                "{prefix}{}{suffix}",
+                self.expression(e, indent + prefix.len(), indent)
+            )
+        };
+        match statement {
+            Statement::Include(_, path) => format!("include {};", quote(path)),
+            Statement::Namespace(_, name, poly_length) => {
+                format!("namespace {name}({});", flat(poly_length))
+            }
+            Statement::PolynomialDefinition(_, name, value) => {
+                expression(&format!("pol {name} = "), value, ";")
+            }
+            Statement::PublicDeclaration(_, name, poly, index) => {
+                format!("public {name} = {}({});", reference(poly), flat(index))
+            }
+            Statement::PolynomialConstantDeclaration(_, names) => {
+                format!("pol constant {};", format_names(names))
+            }
+            Statement::PolynomialConstantDefinition(_, name, definition) => {
+                self.function_definition(&format!("pol constant {name}"), definition, indent)
+            }
+            Statement::PolynomialCommitDeclaration(_, names, None) => {
+                format!("pol commit {};", format_names(names))
+            }
+            Statement::PolynomialCommitDeclaration(_, names, Some(definition)) => self
+                .function_definition(
+                    &format!("pol commit {}", format_names(names)),
+                    definition,
+                    indent,
+                ),
+            Statement::PolynomialIdentity(_, e) => {
+                let (left, right) = match e {
+                    Expression::BinaryOperation(left, BinaryOperator::Sub, right) => {
+                        (left.as_ref(), right.as_ref())
+                    }
+                    _ => (e, &Expression::Number(0.into())),
+                };
+                let left = self.expression(left, indent, indent);
+                let column = end_column(&left, indent) + " = ".len();
+                format!("{left} = {};", self.expression(right, column, indent))
+            }
+            Statement::PlookupIdentity(_, left, right) => {
+                format!("{} in {};", selected(left), selected(right))
+            }
+            Statement::PermutationIdentity(_, left, right) => {
+                format!("{} is {};", selected(left), selected(right))
+            }
+            Statement::ConnectIdentity(_, left, right) => format!(
+                "{{ {} }} connect {{ {} }};",
+                flat_list(left),
+                flat_list(right)
+            ),
+            Statement::ConstantDefinition(_, name, value) => {
+                expression(&format!("constant {name} = "), value, ";")
+            }
+            Statement::MacroDefinition(_, name, params, statements, body) => {
+                let prefix = format!("macro {name}({}) {{", params.join(", "));
+                let inner = indent + 4;
+                let lines = statements
+                    .iter()
+                    .map(|s| self.statement(s, inner))
+                    .chain(body.iter().map(|e| self.expression(e, inner, inner)))
+                    .collect::<Vec<_>>();
+                let inline = format!("{prefix} {} }};", lines.join(""));
+                if lines.len() <= 1 && !inline.contains('\n') && indent + inline.len() <= self.width
+                {
+                    inline
+                } else {
+                    let separator = format!("\n{}", " ".repeat(inner));
+                    format!(
+                        "{prefix}{separator}{}\n{}}};",
+                        lines.join(&separator),
+                        " ".repeat(indent)
+                    )
+                }
+            }
+            Statement::FunctionCall(_, name, args) => format!("{name}({});", flat_list(args)),
+        }
+    }
+
+    fn function_definition(
+        &self,
+        prefix: &str,
+        definition: &FunctionDefinition,
+        indent: usize,
+    ) -> String {
+        match definition {
+            FunctionDefinition::Mapping(params, body) => {
+                let prefix = format!("{prefix}({}) {{ ", params.join(", "));
+                let body = self.expression(body, indent + prefix.len(), indent);
+                format!("{prefix}{body} }};")
+            }
+            FunctionDefinition::Array(values, padding) => {
+                format!("{prefix} = [{}]{padding};", flat_list(values))
+            }
+            FunctionDefinition::Query(params, value) => {
+                let prefix = format!("{prefix}({}) query ", params.join(", "));
+                let value = self.expression(value, indent + prefix.len(), indent);
+                format!("{prefix}{value};")
+            }
+        }
+    }
+
+    /// Formats an expression that starts at `column`. If it does not fit the width,
+    /// the operands of its outermost chain of binary operations are put on separate
+    /// lines, indented by four spaces more than `indent`.
+    fn expression(&self, e: &Expression, column: usize, indent: usize) -> String {
+        let formatted = flat(e);
+        if column + formatted.len() <= self.width {
+            return formatted;
+        }
+        let Expression::BinaryOperation(..) = e else {
+            return formatted;
+        };
+        let (first, rest) = chain(e);
+        let inner = indent + 4;
+        let mut result = self.operand(first, left_precedence(e), column, inner);
+        for (op, operand) in rest {
+            let prefix = format!("{op} ");
+            let operand = self.operand(operand, right_precedence(*op), inner + prefix.len(), inner);
+            result += &format!("\n{}{prefix}{operand}", " ".repeat(inner));
+        }
+        result
+    }
+
+    /// Formats an operand, in parentheses if its precedence is lower than `min_precedence`.
+    fn operand(&self, e: &Expression, min_precedence: u8, column: usize, indent: usize) -> String {
+        if precedence(e) < min_precedence {
+            format!("({})", self.expression(e, column + 1, indent))
+        } else {
+            self.expression(e, column, indent)
+        }
+    }
+}
+
+/// The precedence of the outermost operation of the expression, higher binds stronger.
+fn precedence(e: &Expression) -> u8 {
+    match e {
+        Expression::BinaryOperation(_, op, _) => match op {
+            BinaryOperator::Less
+            | BinaryOperator::LessEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterEqual
+            | BinaryOperator::Equal
+            | BinaryOperator::NotEqual => 1,
+            BinaryOperator::BinaryOr => 2,
+            BinaryOperator::BinaryXor => 3,
+            BinaryOperator::BinaryAnd => 4,
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => 5,
+            BinaryOperator::Add | BinaryOperator::Sub => 6,
+            BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Mod => 7,
+            BinaryOperator::Pow => 8,
+        },
+        Expression::UnaryOperation(..) => 9,
+        // Negative numbers are printed with a minus sign.
+        Expression::Number(n) if display_number(n).starts_with('-') => 9,
+        _ => TERM,
+    }
+}
+
+/// The precedence of expressions that never need parentheses.
+const TERM: u8 = 10;
+
+/// The minimal precedence of the left operand of the binary operation `e`.
+/// Comparisons do not associate, so their left operand cannot be a comparison.
+fn left_precedence(e: &Expression) -> u8 {
+    match e {
+        Expression::BinaryOperation(_, op, _) if op.is_comparison() => precedence(e) + 1,
+        _ => precedence(e),
+    }
+}
+
+/// The minimal precedence of the right operand of `op`. All other binary operators
+/// are left-associative and the exponent can only be a term.
+fn right_precedence(op: BinaryOperator) -> u8 {
+    match op {
+        BinaryOperator::Pow => TERM,
+        _ => {
+            precedence(&Expression::BinaryOperation(
+                Box::new(Expression::Number(0.into())),
+                op,
+                Box::new(Expression::Number(0.into())),
+            )) + 1
+        }
+    }
+}
+
+/// Splits a binary operation into its first operand and the operators and operands
+/// that follow, as long as the operators have the same precedence.
+fn chain(e: &Expression) -> (&Expression, Vec<(&BinaryOperator, &Expression)>) {
+    match e {
+        Expression::BinaryOperation(left, op, right) if precedence(left) == left_precedence(e) => {
+            let (first, mut rest) = chain(left);
+            rest.push((op, right));
+            (first, rest)
+        }
+        Expression::BinaryOperation(left, op, right) => (left, vec![(op, right)]),
+        _ => (e, vec![]),
+    }
+}
+
+/// Formats the expression on a single line.
+fn flat(e: &Expression) -> String {
+    let operand = |e: &Expression, min_precedence: u8| {
+        if precedence(e) < min_precedence {
+            format!("({})", flat(e))
+        } else {
+            flat(e)
+        }
+    };
+    match e {
+        Expression::Constant(name) => name.clone(),
+        Expression::PolynomialReference(r) => reference(r),
+        Expression::PublicReference(name) => name.clone(),
+        Expression::Number(value) => display_number(value),
+        Expression::String(value) => format!("\"{value}\""),
+        Expression::Tuple(items) => format!("({})", flat_list(items)),
+        Expression::BinaryOperation(left, op, right) => format!(
+            "{} {op} {}",
+            operand(left, left_precedence(e)),
+            operand(right, right_precedence(*op))
+        ),
+        Expression::UnaryOperation(op, inner) => format!("{op}{}", operand(inner, TERM)),
+        Expression::FunctionCall(fun, args) => format!("{fun}({})", flat_list(args)),
+        Expression::FreeInput(input, None) => format!("${{ {} }}", flat(input)),
+        Expression::FreeInput(input, Some(keys)) => {
+            format!("${{ {}; {} }}", flat(input), flat_list(keys))
+        }
+    }
+}
+
+fn flat_list(expressions: &[Expression]) -> String {
+    expressions.iter().map(flat).collect::<Vec<_>>().join(", ")
+}
+
+fn reference(r: &PolynomialReference) -> String {
+    format!(
+        "{}{}{}{}",
+        r.namespace
+            .as_ref()
+            .map(|n| format!("{n}."))
+            .unwrap_or_default(),
+        r.name,
+        r.index
+            .as_ref()
+            .map(|i| format!("[{}]", flat(i)))
+            .unwrap_or_default(),
+        if r.next { "'" } else { "" }
+    )
+}
+
+fn selected(s: &SelectedExpressions) -> String {
+    format!(
+        "{}{{ {} }}",
+        s.selector
+            .as_ref()
+            .map(|s| format!("{} ", flat(s)))
+            .unwrap_or_default(),
+        flat_list(&s.expressions)
+    )
+}
+
+fn format_names(names: &[PolynomialName]) -> String {
+    names
+        .iter()
+        .map(|n| {
+            let size = n.array_size.as_ref().map(|s| format!("[{}]", flat(s)));
+            format!("{}{}", n.name, size.unwrap_or_default())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The column after the last character of `s`, if it starts at `column`.
+fn end_column(s: &str, column: usize) -> usize {
+    match s.rfind('\n') {
+        Some(i) => s.len() - i - 1,
+        None => column + s.len(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::format_pil;
+    use crate::parser;
+
+    #[test]
+    fn wide_expression() {
+        let input = fs::read_to_string("tests/wide_expression.pil").unwrap();
+        let expected = fs::read_to_string("tests/wide_expression.formatted.pil").unwrap();
+        let file = parser::parse_with_comments(None, &input).unwrap();
+        let formatted = format_pil(&file, 60);
+        assert_eq!(formatted, expected);
+
+        // Formatting keeps the meaning and is idempotent.
+        let reparsed = parser::parse_with_comments(None, &formatted).unwrap();
+        assert_eq!(
+            parser::parse(None, &formatted).unwrap().to_string(),
+            parser::parse(None, &input).unwrap().to_string()
+        );
+        assert_eq!(format_pil(&reparsed, 60), formatted);
+    }
+
+    #[test]
+    fn parentheses() {
+        let input = "constant %x = (((1 + 2) + (3 + 4)) * -(5 - 6)) ** (2 ** 3);
+constant %y = ((1 << 2) | (3 & 4)) - (-1 ** 2);
+constant %z = ((1 | 2) < 3) == (4 >= (5 != 6));
+constant %w = ((1 ^ (2 & 3)) | 4) ^ (5 | 6);
+";
+        let file = parser::parse_with_comments(None, input).unwrap();
+        assert_eq!(
+            format_pil(&file, 100),
+            "constant %x = ((1 + 2 + (3 + 4)) * -(5 - 6)) ** (2 ** 3);
+constant %y = (1 << 2 | 3 & 4) - -1 ** 2;
+constant %z = (1 | 2 < 3) == (4 >= (5 != 6));
+constant %w = (1 ^ 2 & 3 | 4) ^ (5 | 6);
+"
+        );
+    }
+}
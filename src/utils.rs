@@ -23,6 +23,14 @@ pub fn indent(input: &str, indentation: &str) -> String {
     }
 }
 
+/// A hash function (64 bit FNV-1a) whose output only depends on the input bytes
+/// and is thus stable across runs, platforms and compiler versions.
+pub fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::{compute_line_starts, offset_to_line};
@@ -0,0 +1,16 @@
+// Combines the first two inputs with bitwise operations on 2 bits.
+// Input: a, b (both less than 4)
+
+bitwise_width 2;
+degree 16;
+
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+
+A <=X= ${ ("input", 0) };
+B <=X= ${ ("input", 1) };
+B <=X= A | B;
+A <=X= A & B - 1;
+B <=X= A ^ B;
@@ -0,0 +1,28 @@
+// Computes 4 * input by calling a subroutine that doubles A twice.
+
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg RA[@ret]; // "@ret" makes RA the return address register and defines "call l" and "ret".
+
+pil{
+    col witness XInv;
+    col witness XIsZero;
+    XIsZero  = 1 - X * XInv;
+    XIsZero * X = 0;
+    XIsZero * (1 - XIsZero) = 0;
+}
+
+instr assert_zero <=X= a { XIsZero = 1 }
+instr jmp l: label { pc' = l }
+
+A <=X= ${ ("input", 0) };
+call double;
+call double;
+assert_zero A - 4 * ${ ("input", 0) };
+end::
+jmp end;
+
+double::
+A <=X= A + A;
+ret;
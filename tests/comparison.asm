@@ -0,0 +1,19 @@
+// Compares the first two inputs on 4 bits.
+// Input: a, b (both less than 16)
+
+comparison_width 4;
+degree 32;
+
+reg pc[@pc];
+reg X[<=];
+reg A;
+reg B;
+reg L;
+reg E;
+reg G;
+
+A <=X= ${ ("input", 0) };
+B <=X= ${ ("input", 1) };
+L <=X= A < B;
+E <=X= A == B;
+G <=X= 2 * (A >= B) + (A != B);
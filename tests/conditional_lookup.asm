@@ -0,0 +1,24 @@
+// Squares a value with a lookup into a table of squares, but only if the
+// first argument of the instruction is 1. Otherwise, the value is copied.
+degree 16;
+
+reg pc[@pc];
+reg X[<=];
+reg Y[<=];
+reg A;
+reg B;
+
+pil{
+    col fixed SQUARE_IN(i) { i };
+    col fixed SQUARE(i) { i * i };
+}
+
+instr square_if c, <=X= a, b <=Y= {
+    c { X, Y } in { SQUARE_IN, SQUARE },
+    (1 - c) * (Y - X) = 0
+}
+
+A <=X= 3;
+square_if 1, A, B;
+square_if 0, B, A;
+square_if 1, A, B;
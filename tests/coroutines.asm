@@ -0,0 +1,24 @@
+// Two coroutines that take turns: The second program counter "co"
+// stores the line at which the other coroutine continues.
+reg pc[@pc];
+reg co[@pc];
+reg X[<=];
+reg A;
+reg B;
+
+instr start l: label { co' = l }
+instr yield { pc' = co, co' = pc + 1 }
+instr loop { pc' = pc }
+
+start second;
+A <=X= 1;
+yield;
+A <=X= A + 2;
+yield;
+loop;
+
+second::
+B <=X= A + 10;
+yield;
+B <=X= B + A;
+yield;
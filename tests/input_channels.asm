@@ -0,0 +1,22 @@
+// Adds inputs read from two different channels.
+
+reg pc[@pc];
+reg X[<=];
+reg A;
+
+pil{
+    col witness XInv;
+    col witness XIsZero;
+    XIsZero  = 1 - X * XInv;
+    XIsZero * X = 0;
+    XIsZero * (1 - XIsZero) = 0;
+}
+
+instr assert_zero <=X= a { XIsZero = 1 }
+instr jmp l: label { pc' = l }
+
+A <=X= ${ ("stdin", 0) };
+A <=X= A + ${ ("oracle", 1) };
+assert_zero A - 8;
+end::
+jmp end;
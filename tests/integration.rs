@@ -1,7 +1,7 @@
 use std::{fs, path::Path, process::Command};
 
 use itertools::Itertools;
-use powdr::compiler;
+use powdr::compiler::{self, CompileOptions};
 use powdr::number::AbstractNumberType;
 
 fn verify_pil(file_name: &str, query_callback: Option<fn(&str) -> Option<AbstractNumberType>>) {
@@ -13,8 +13,10 @@ fn verify_pil(file_name: &str, query_callback: Option<fn(&str) -> Option<Abstrac
     assert!(compiler::compile_pil(
         &input_file,
         &temp_dir,
-        query_callback
-    ));
+        query_callback,
+        &CompileOptions::default()
+    )
+    .unwrap());
     verify(file_name, &temp_dir);
 }
 
@@ -42,8 +44,9 @@ fn verify_asm(file_name: &str, inputs: Vec<AbstractNumberType>) {
             }
             None
         }),
-        false
-    ));
+        &CompileOptions::default()
+    )
+    .unwrap());
     verify(pil_file_name, &temp_dir);
 }
 
@@ -142,7 +145,27 @@ fn palindrome() {
     );
 }
 
+#[test]
+fn call_ret() {
+    verify_asm("call_ret.asm", vec![7.into()]);
+}
+
+#[test]
+fn test_memory() {
+    verify_pil("memory.pil", None);
+}
+
 #[test]
 fn test_mem_read_write() {
     verify_asm("mem_read_write.asm", Default::default());
 }
+
+#[test]
+fn jump_table() {
+    verify_asm("jump_table.asm", vec![1.into()]);
+}
+
+#[test]
+fn bitwise() {
+    verify_asm("bitwise.asm", vec![1.into(), 2.into()]);
+}
@@ -0,0 +1,27 @@
+// Jumps to the case selected by the first input through a jump table,
+// by assigning the computed line to the program counter.
+// Input: case (0, 1 or 2)
+
+reg pc[@pc];
+reg X[<=];
+reg A;
+
+instr jmp l: label { pc' = l }
+
+A <=X= ${ ("input", 0) };
+pc <=X= A + 2;
+ jmp zero;
+ jmp one;
+ jmp two;
+
+zero::
+ A <=X= 10;
+ jmp end;
+one::
+ A <=X= 20;
+ jmp end;
+two::
+ A <=X= 30;
+
+end::
+ jmp end;